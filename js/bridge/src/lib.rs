@@ -1,5 +1,5 @@
 use spiral_rs::client::*;
-use spiral_rs::params::Params;
+use spiral_rs::params::intern_params;
 use spiral_rs::util::*;
 
 use std::convert::TryInto;
@@ -18,16 +18,12 @@ macro_rules! console_log {
     ($($t:tt)*) => (log(&format_args!($($t)*).to_string()))
 }
 
-pub struct ApiClientObj<'a> {
-    pub params: &'a Params,
-    pub c: Client<'a>,
-}
-
-// Container class for a static lifetime ApiClientObj
-// Avoids a lifetime in the return signature of bound Rust functions
+// Container class for a client holding its own parameters
+// Avoids a lifetime in the return signature of bound Rust functions;
+// the parameters are interned, so dropping the last client frees them
 #[wasm_bindgen]
 pub struct ApiClient {
-    client: &'static mut ApiClientObj<'static>,
+    client: SharedClient,
 }
 
 #[wasm_bindgen]
@@ -37,11 +33,8 @@ pub fn initialize_client(json_params: Option<String>) -> ApiClient {
         cfg = json_params.unwrap();
     }
 
-    let params = Box::leak(Box::new(params_from_json(&cfg)));
-    let client = Box::leak(Box::new(ApiClientObj {
-        params,
-        c: Client::init(params),
-    }));
+    let params = intern_params(params_from_json(&cfg));
+    let client = SharedClient::init(params);
 
     ApiClient { client }
 }
@@ -55,7 +48,6 @@ pub fn generate_keys(
     let seed_val = (*seed).try_into().unwrap();
     let result = c
         .client
-        .c
         .generate_keys_optional(seed_val, generate_pub_params)?
         .into_boxed_slice();
     Some(result)
@@ -64,19 +56,19 @@ pub fn generate_keys(
 #[wasm_bindgen]
 pub fn generate_query(c: &mut ApiClient, id: &str, idx_target: usize) -> Box<[u8]> {
     c.client
-        .c
+        .client()
         .generate_full_query(id, idx_target)
         .into_boxed_slice()
 }
 
 #[wasm_bindgen]
 pub fn decode_response(c: &mut ApiClient, data: Box<[u8]>) -> Box<[u8]> {
-    c.client.c.decode_response(&*data).into_boxed_slice()
+    c.client.client().decode_response(&*data).into_boxed_slice()
 }
 
 #[wasm_bindgen]
 pub fn get_row(c: &mut ApiClient, key: &str) -> u32 {
-    spiral_rs::key_value::row_from_key(c.client.params, key) as u32
+    spiral_rs::key_value::row_from_key(c.client.params(), key) as u32
 }
 
 #[wasm_bindgen]
@@ -1,75 +1,170 @@
 use bzip2_rs::DecoderReader;
-use std::{collections::HashMap, io::Read};
+use std::{collections::HashMap, io::Read, time::Duration};
 
 use crate::error::Error;
 use base64::{engine::general_purpose, Engine as _};
-use reqwest::multipart::{Form, Part};
-use serde::{Deserialize, Serialize};
+use rand::Rng;
+use reqwest::{
+    multipart::{Form, Part},
+    RequestBuilder, Response, StatusCode,
+};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde_json::Value;
 use spiral_rs::{
-    client::Client,
-    key_value::{extract_result_impl, row_from_key, varint_decode},
-    params::Params,
-    util::params_from_json_obj,
+    arith::log2_ceil,
+    client::{choose_batch_shape, plan_batches, Client, Seed, SharedClient},
+    key_value::{extract_result_impl, varint_decode, varint_encode, KeyLayout},
+    params::{intern_params, Params},
+    util::try_params_from_json_obj,
 };
 
-/// HTTP GET request to the given URL with the given API key.
-pub(crate) async fn http_get_string(url: &str, api_key: &str) -> Result<String, Error> {
-    let req = reqwest::Client::new().get(url).header("x-api-key", api_key);
-    let res = req.send().await?.error_for_status()?.text().await?;
-    Ok(res)
+/// The default timeout for a single HTTP request, including reading the response.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The default timeout for connecting to the server.
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The default number of times an idempotent request is retried.
+const DEFAULT_MAX_RETRIES: u32 = 3;
+
+/// The default delay before the first retry. Each further retry doubles it.
+const DEFAULT_INITIAL_BACKOFF: Duration = Duration::from_millis(250);
+
+/// An HTTP client for the Blyss API, which reuses its connections across requests.
+#[derive(Debug, Clone)]
+pub(crate) struct HttpClient {
+    client: reqwest::Client,
+    timeout: Duration,
+    max_retries: u32,
+    initial_backoff: Duration,
 }
 
-/// HTTP POST request with binary body to the given URL with the given API key.
-pub(crate) async fn http_post_bytes(
-    url: &str,
-    api_key: &str,
-    data: Vec<u8>,
-) -> Result<Vec<u8>, Error> {
-    let req = reqwest::Client::new()
-        .post(url)
-        .body(data)
-        .header("Content-Type", "application/octet-stream")
-        .header("x-api-key", api_key);
-    let res = req.send().await?.error_for_status()?;
-    let resp_body = res.bytes().await?;
-    Ok(resp_body.to_vec())
-}
-
-/// HTTP POST request with string body to the given URL with the given API key.
-pub(crate) async fn http_post_string(
-    url: &str,
-    api_key: &str,
-    data: String,
-) -> Result<String, Error> {
-    let req = reqwest::Client::new()
-        .post(url)
-        .body(data)
-        .header("x-api-key", api_key);
-    let res = req.send().await?.error_for_status()?.text().await?;
-    Ok(res)
+impl Default for HttpClient {
+    fn default() -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            timeout: DEFAULT_TIMEOUT,
+            max_retries: DEFAULT_MAX_RETRIES,
+            initial_backoff: DEFAULT_INITIAL_BACKOFF,
+        }
+    }
 }
 
-/// HTTP POST request to the given URL with the given API key.
-pub(crate) async fn http_post_form_data(
-    url: &str,
-    api_key: &str,
-    data: Vec<u8>,
-    fields: HashMap<String, String>,
-) -> Result<Vec<u8>, Error> {
-    let mut form_data = Form::new();
-    for (key, value) in fields {
-        form_data = form_data.text(key, value);
+/// Whether a response with the given status may succeed if the request is retried.
+fn is_retryable_status(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+impl HttpClient {
+    /// Send the given request, and fail with `Error::ApiError` if the response is not a success.
+    ///
+    /// Idempotent requests are retried with exponential backoff on connection failures,
+    /// timeouts, server errors, and rate limiting.
+    async fn send(&self, req: RequestBuilder, idempotent: bool) -> Result<Response, Error> {
+        let mut req = req.timeout(self.timeout);
+        let mut retries_left = if idempotent { self.max_retries } else { 0 };
+        let mut backoff = self.initial_backoff;
+        loop {
+            // bodies that are streamed, like multipart forms, cannot be retried
+            let retry = if retries_left > 0 {
+                req.try_clone()
+            } else {
+                None
+            };
+            let result = req.send().await;
+            let retryable = match &result {
+                Ok(res) => is_retryable_status(res.status()),
+                Err(err) => err.is_connect() || err.is_timeout(),
+            };
+            match retry {
+                Some(next_req) if retryable => {
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                    retries_left -= 1;
+                    req = next_req;
+                }
+                _ => {
+                    let res = result?;
+                    if !res.status().is_success() {
+                        return Err(Error::ApiError(
+                            res.status().as_u16().to_string(),
+                            res.url().path().to_owned(),
+                        ));
+                    }
+                    return Ok(res);
+                }
+            }
+        }
+    }
+
+    /// HTTP GET request to the given URL with the given API key.
+    pub(crate) async fn get_string(&self, url: &str, api_key: &str) -> Result<String, Error> {
+        let req = self.client.get(url).header("x-api-key", api_key);
+        let res = self.send(req, true).await?.text().await?;
+        Ok(res)
+    }
+
+    /// HTTP POST request with binary body to the given URL with the given API key.
+    /// Only idempotent requests are retried.
+    pub(crate) async fn post_bytes(
+        &self,
+        url: &str,
+        api_key: &str,
+        data: Vec<u8>,
+        idempotent: bool,
+    ) -> Result<Vec<u8>, Error> {
+        let req = self
+            .client
+            .post(url)
+            .body(data)
+            .header("Content-Type", "application/octet-stream")
+            .header("x-api-key", api_key);
+        let res = self.send(req, idempotent).await?;
+        let resp_body = res.bytes().await?;
+        Ok(resp_body.to_vec())
     }
-    form_data = form_data.part("file", Part::bytes(data));
 
-    let req = reqwest::Client::new()
-        .post(url)
-        .multipart(form_data)
-        .header("x-api-key", api_key);
-    let res = req.send().await?.error_for_status()?;
-    let resp_body = res.bytes().await?;
-    Ok(resp_body.to_vec())
+    /// HTTP POST request with string body to the given URL with the given API key.
+    /// Only idempotent requests are retried.
+    pub(crate) async fn post_string(
+        &self,
+        url: &str,
+        api_key: &str,
+        data: String,
+        idempotent: bool,
+    ) -> Result<String, Error> {
+        let req = self
+            .client
+            .post(url)
+            .body(data)
+            .header("x-api-key", api_key);
+        let res = self.send(req, idempotent).await?.text().await?;
+        Ok(res)
+    }
+
+    /// HTTP POST request to the given URL with the given API key. Never retried.
+    pub(crate) async fn post_form_data(
+        &self,
+        url: &str,
+        api_key: &str,
+        data: Vec<u8>,
+        fields: HashMap<String, String>,
+    ) -> Result<Vec<u8>, Error> {
+        let mut form_data = Form::new();
+        for (key, value) in fields {
+            form_data = form_data.text(key, value);
+        }
+        form_data = form_data.part("file", Part::bytes(data));
+
+        let req = self
+            .client
+            .post(url)
+            .multipart(form_data)
+            .header("x-api-key", api_key);
+        let res = self.send(req, false).await?;
+        let resp_body = res.bytes().await?;
+        Ok(resp_body.to_vec())
+    }
 }
 
 /// Decompress the given data using bzip2.
@@ -100,31 +195,91 @@ fn serialize_chunks(data: &[Vec<u8>]) -> Vec<u8> {
 /// - for each chunk:
 ///   - 8 bytes: chunk length (u64 LE)
 ///   - (chunk data)
-fn deserialize_chunks(data: &[u8]) -> Vec<Vec<u8>> {
-    let mut chunks = Vec::new();
+///
+/// Fails with `Error::MalformedResponse` if `data` is truncated or has trailing bytes.
+fn deserialize_chunks(data: &[u8]) -> Result<Vec<Vec<u8>>, Error> {
+    let truncated = || Error::MalformedResponse("truncated response".to_owned());
     let mut offset = 0;
-    let num_chunks = u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap());
-    offset += 8;
+    let read_u64 = |offset: &mut usize| -> Result<u64, Error> {
+        let bytes = data.get(*offset..*offset + 8).ok_or_else(truncated)?;
+        *offset += 8;
+        Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+    };
+
+    let num_chunks = read_u64(&mut offset)?;
+    let mut chunks = Vec::new();
     for _ in 0..num_chunks {
-        let chunk_len = u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap());
-        offset += 8;
-        chunks.push(data[offset..offset + chunk_len as usize].to_vec());
-        offset += chunk_len as usize;
+        let chunk_len = usize::try_from(read_u64(&mut offset)?).map_err(|_| truncated())?;
+        let end = offset.checked_add(chunk_len).ok_or_else(truncated)?;
+        chunks.push(data.get(offset..end).ok_or_else(truncated)?.to_vec());
+        offset = end;
+    }
+    if offset != data.len() {
+        return Err(Error::MalformedResponse(format!(
+            "{} trailing bytes in response",
+            data.len() - offset
+        )));
+    }
+    Ok(chunks)
+}
+
+/// Version of the `/private-read` wire format spoken to servers other than the Blyss service.
+///
+/// The request is `application/octet-stream`, in the following format (all integers u64 LE):
+/// - 8 bytes: wire version
+/// - 8 bytes: number of queries
+/// - for each query:
+///   - 8 bytes: query length
+///   - (setup UUID, followed by the serialized query)
+///
+/// The response has the same format, with one serialized response per query.
+pub const WIRE_VERSION: u64 = 1;
+
+/// Serialize queries into a versioned `/private-read` request body.
+fn serialize_read_request(queries: &[Vec<u8>]) -> Vec<u8> {
+    let mut serialized = u64::to_le_bytes(WIRE_VERSION).to_vec();
+    serialized.extend(serialize_chunks(queries));
+    serialized
+}
+
+/// Deserialize a versioned `/private-read` response body, checking its version and length.
+fn deserialize_read_response(data: &[u8]) -> Result<Vec<Vec<u8>>, Error> {
+    let (version, chunks) = data
+        .split_first_chunk::<8>()
+        .ok_or_else(|| Error::MalformedResponse("truncated response".to_owned()))?;
+    let version = u64::from_le_bytes(*version);
+    if version != WIRE_VERSION {
+        return Err(Error::MalformedResponse(format!(
+            "unsupported wire version {}",
+            version
+        )));
     }
-    chunks
+    deserialize_chunks(chunks)
 }
 
 /// Split the given data into metadata and the rest of the data.
-fn split_metadata(data: &[u8]) -> (&[u8], &[u8]) {
-    let (value, bytes_used) = varint_decode(data);
-    let metadata_len = value as usize;
-    if metadata_len == 0 {
-        return (&[], data);
+///
+/// Fails with `Error::MalformedResponse` if the metadata length is truncated, or longer
+/// than the data.
+fn split_metadata(data: &[u8]) -> Result<(&[u8], &[u8]), Error> {
+    let malformed = || Error::MalformedResponse("truncated value metadata".to_owned());
+    // `varint_decode` reads up to and including the first byte without the continuation bit
+    if !data.iter().any(|&b| b & 0x80 == 0) {
+        return Err(malformed());
     }
-    let metadata = &data[bytes_used..bytes_used + metadata_len];
-    let data = &data[bytes_used + metadata_len..];
+    let (metadata_len, bytes_used) = varint_decode(data);
+    let metadata_end = bytes_used.checked_add(metadata_len).ok_or_else(malformed)?;
+    let metadata = data.get(bytes_used..metadata_end).ok_or_else(malformed)?;
+
+    Ok((metadata, &data[metadata_end..]))
+}
 
-    (metadata, data)
+/// Prefix the given data with the given metadata, as expected by `split_metadata`.
+fn wrap_metadata(metadata: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut wrapped = varint_encode(metadata.len() as u64);
+    wrapped.extend(metadata);
+    wrapped.extend(data);
+    wrapped
 }
 
 /// Return whether the given data is all zeros.
@@ -133,8 +288,68 @@ fn is_all_zeros(decrypted: &[u8]) -> bool {
 }
 
 /// Fetch the metadata from the given URL.
-pub(crate) async fn get_meta(url: &str, api_key: &str) -> Result<String, Error> {
-    http_get_string(&format!("{}/meta", url), api_key).await
+pub(crate) async fn get_meta(http: &HttpClient, url: &str, api_key: &str) -> Result<String, Error> {
+    http.get_string(&format!("{}/meta", url), api_key).await
+}
+
+/// Maximum size of the JSON body of a single write request.
+const MAX_WRITE_BATCH_BYTES: usize = 4_000_000;
+
+/// Split the given JSON key-value pairs into batches of at most `MAX_WRITE_BATCH_BYTES`.
+/// A single pair larger than the limit is sent in its own batch.
+fn batch_kv_pairs(kv_pairs: Vec<(String, Value)>) -> Vec<serde_json::Map<String, Value>> {
+    let mut batches = Vec::new();
+    let mut batch = serde_json::Map::new();
+    let mut batch_bytes = 0;
+    for (key, value) in kv_pairs {
+        // "key":"value", with the value base64-encoded
+        let pair_bytes = key.len() + value.as_str().map_or(4, |v| v.len()) + 6;
+        if !batch.is_empty() && batch_bytes + pair_bytes > MAX_WRITE_BATCH_BYTES {
+            batches.push(std::mem::take(&mut batch));
+            batch_bytes = 0;
+        }
+        batch.insert(key, value);
+        batch_bytes += pair_bytes;
+    }
+    if !batch.is_empty() {
+        batches.push(batch);
+    }
+    batches
+}
+
+/// Post the given JSON key-value pairs to the bucket's write endpoint, in batches.
+/// A `null` value deletes the key. Returns the version of the bucket after the last batch.
+async fn write_kv_pairs(
+    http: &HttpClient,
+    url: &str,
+    api_key: &str,
+    kv_pairs: Vec<(String, Value)>,
+) -> Result<u64, Error> {
+    let mut version = None;
+    for batch in batch_kv_pairs(kv_pairs) {
+        let body = serde_json::to_string(&batch)?;
+        let resp = http
+            .post_string(&format!("{}/write", url), api_key, body, false)
+            .await?;
+        version = serde_json::from_str::<Value>(&resp)
+            .ok()
+            .and_then(|v| v.get("version").and_then(Value::as_u64));
+    }
+
+    // Servers that do not report the version in the write response report it in the metadata
+    match version {
+        Some(version) => Ok(version),
+        None => get_version(http, url, api_key).await,
+    }
+}
+
+/// Fetch the current version of the bucket at the given URL.
+async fn get_version(http: &HttpClient, url: &str, api_key: &str) -> Result<u64, Error> {
+    let metadata = get_meta(http, url, api_key).await?;
+    serde_json::from_str::<Value>(&metadata)?
+        .get("global_version")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| Error::MalformedResponse("missing global_version".to_owned()))
 }
 
 fn is_blyss_url(url: &str) -> bool {
@@ -146,9 +361,16 @@ struct PrelimSetupBody {
     length: usize,
 }
 
-async fn perform_setup(url: &str, api_key: &str, setup_data: Vec<u8>) -> Result<String, Error> {
+async fn perform_setup(
+    http: &HttpClient,
+    url: &str,
+    api_key: &str,
+    setup_data: Vec<u8>,
+) -> Result<String, Error> {
     if !is_blyss_url(url) {
-        let setup_resp = http_post_bytes(&format!("{}/setup", url), api_key, setup_data).await?;
+        let setup_resp = http
+            .post_bytes(&format!("{}/setup", url), api_key, setup_data, false)
+            .await?;
         let setup_resp_str = String::from_utf8(setup_resp)?;
         let uuid = serde_json::from_str::<Value>(&setup_resp_str)?
             .get("uuid")
@@ -162,8 +384,9 @@ async fn perform_setup(url: &str, api_key: &str, setup_data: Vec<u8>) -> Result<
     let prelim_setup_body = serde_json::to_string(&PrelimSetupBody {
         length: setup_data.len(),
     })?;
-    let setup_resp =
-        http_post_string(&format!("{}/setup", url), api_key, prelim_setup_body).await?;
+    let setup_resp = http
+        .post_string(&format!("{}/setup", url), api_key, prelim_setup_body, false)
+        .await?;
     let setup_resp_value: Value = serde_json::from_str(&setup_resp)?;
     let fields: HashMap<String, String> = serde_json::from_value(
         setup_resp_value
@@ -174,7 +397,8 @@ async fn perform_setup(url: &str, api_key: &str, setup_data: Vec<u8>) -> Result<
     let s3_url: String =
         serde_json::from_value(setup_resp_value.get("url").ok_or(Error::Unknown)?.clone())?;
 
-    http_post_form_data(&s3_url, api_key, setup_data, fields).await?;
+    http.post_form_data(&s3_url, api_key, setup_data, fields)
+        .await?;
 
     let uuid = setup_resp_value
         .get("uuid")
@@ -185,20 +409,89 @@ async fn perform_setup(url: &str, api_key: &str, setup_data: Vec<u8>) -> Result<
     Ok(uuid)
 }
 
+/// Decrypt a single response, and extract the value of the given key from it, if present.
+fn decode_value<'a>(
+    client: &Client<'a>,
+    params: &Params,
+    key: &str,
+    chunk: &[u8],
+) -> Result<Option<Vec<u8>>, Error> {
+    let p_bits = log2_ceil(params.pt_modulus) as usize;
+    let decrypted = client
+        .decode_response(chunk)
+        .to_vec(p_bits, params.modp_words_per_chunk());
+    if is_all_zeros(&decrypted) {
+        return Ok(None);
+    }
+    let decompressed = decompress(&decrypted)?;
+    let result = extract_result_impl(key, &decompressed);
+    match result {
+        Ok(result) => Ok(Some(split_metadata(&result)?.1.to_vec())),
+        Err(_) => Ok(None),
+    }
+}
+
 /// Privately read the given keys from the given URL, using the given API key.
+///
+/// Every candidate row of every key (see `KeyLayout::candidate_rows`) is queried in a single request.
+/// When there are many rows, they are fetched with batch queries (see `choose_batch_shape`), so
+/// the client uploads one query, and the server makes one pass over the database, per batch
+/// rather than per row. The shape of the request depends only on the number of rows: if the
+/// rows do not fit the batches, which is vanishingly rare, this fails with
+/// `Error::BatchOverflow` rather than fetch them some other way.
+#[allow(clippy::too_many_arguments)]
 async fn private_read<'a>(
+    http: &HttpClient,
     client: &Client<'a>,
     params: &Params,
+    key_layout: KeyLayout,
     uuid: &str,
     url: &str,
     api_key: &str,
     keys: &[String],
 ) -> Result<Vec<Vec<u8>>, Error> {
-    let queries: Vec<_> = keys
+    let candidate_rows: Vec<_> = keys
         .iter()
-        .map(|key| {
-            let idx_target = row_from_key(&params, key);
-            let query = client.generate_query(idx_target);
+        .map(|key| key_layout.candidate_rows(params, key))
+        .collect();
+    let rows: Vec<_> = candidate_rows.iter().flatten().copied().collect();
+    // whether to batch, and the shape of the batches, must not depend on the rows themselves
+    let (num_batches, num_partitions) = choose_batch_shape(params, rows.len());
+    let resp_chunks = if num_partitions > 1 && !is_blyss_url(url) {
+        let batches = plan_batches(params, num_batches, num_partitions, &rows)
+            .ok_or(Error::BatchOverflow(rows.len()))?;
+        private_read_batched(http, client, uuid, url, api_key, &batches).await?
+    } else {
+        private_read_rows(http, client, uuid, url, api_key, &rows).await?
+    };
+
+    let mut results = Vec::new();
+    for (key, rows) in keys.iter().zip(candidate_rows.iter()) {
+        let mut value = None;
+        for row in rows {
+            if value.is_none() {
+                value = decode_value(client, params, key, &resp_chunks[row])?;
+            }
+        }
+        results.push(value.unwrap_or_default());
+    }
+
+    Ok(results)
+}
+
+/// Fetch the responses for the given rows with one query per row.
+async fn private_read_rows<'a>(
+    http: &HttpClient,
+    client: &Client<'a>,
+    uuid: &str,
+    url: &str,
+    api_key: &str,
+    rows: &[usize],
+) -> Result<HashMap<usize, Vec<u8>>, Error> {
+    let queries: Vec<_> = rows
+        .iter()
+        .map(|idx_target| {
+            let query = client.generate_query(*idx_target);
             let query_data = query.serialize();
             let uuid_and_query_data: Vec<_> = (uuid.as_bytes().to_vec().into_iter())
                 .chain(query_data)
@@ -206,31 +499,108 @@ async fn private_read<'a>(
             uuid_and_query_data
         })
         .collect();
-    let full_query_data = serialize_chunks(&queries);
+    let read_url = format!("{}/private-read", url);
+    let resp_chunks = if is_blyss_url(url) {
+        let full_query_data = serialize_chunks(&queries);
+        let resp_data_b64 = http
+            .post_bytes(&read_url, api_key, full_query_data, true)
+            .await?;
+        let resp_data = general_purpose::STANDARD.decode(resp_data_b64)?;
+        deserialize_chunks(&resp_data)?
+    } else {
+        let full_query_data = serialize_read_request(&queries);
+        let resp_data = http
+            .post_bytes(&read_url, api_key, full_query_data, true)
+            .await?;
+        deserialize_read_response(&resp_data)?
+    };
+    if resp_chunks.len() != queries.len() {
+        return Err(Error::MalformedResponse(format!(
+            "got {} results for {} queries",
+            resp_chunks.len(),
+            queries.len()
+        )));
+    }
 
-    let resp_data_b64 =
-        http_post_bytes(&format!("{}/private-read", url), api_key, full_query_data).await?;
-    let resp_data = general_purpose::STANDARD.decode(resp_data_b64)?;
-    let resp_chunks = deserialize_chunks(&resp_data);
+    Ok(rows.iter().copied().zip(resp_chunks).collect())
+}
 
-    let mut results = Vec::new();
-    for (i, chunk) in resp_chunks.iter().enumerate() {
-        let decrypted = client.decode_response(&chunk);
-        if is_all_zeros(&decrypted) {
-            results.push(vec![]);
-            continue;
-        }
-        let decompressed = decompress(&decrypted)?;
-        let result = extract_result_impl(&keys[i], &decompressed);
-        if let Ok(result) = result {
-            let (_metadata, data) = split_metadata(&result);
-            results.push(data.to_vec());
-        } else {
-            results.push(vec![]);
-        }
+/// Fetch the responses for the targets of the given batches (see `plan_batches`), with one batch
+/// query per batch.
+async fn private_read_batched<'a>(
+    http: &HttpClient,
+    client: &Client<'a>,
+    uuid: &str,
+    url: &str,
+    api_key: &str,
+    batches: &[Vec<Option<usize>>],
+) -> Result<HashMap<usize, Vec<u8>>, Error> {
+    let num_partitions = batches[0].len();
+    let queries: Vec<_> = batches
+        .iter()
+        .map(|targets| client.generate_full_batch_query(uuid, targets))
+        .collect();
+
+    let read_url = format!("{}/private-read-batch", url);
+    let full_query_data = serialize_read_request(&queries);
+    let resp_data = http
+        .post_bytes(&read_url, api_key, full_query_data, true)
+        .await?;
+    let resp_chunks = deserialize_read_response(&resp_data)?;
+    if resp_chunks.len() != batches.len() * num_partitions {
+        return Err(Error::MalformedResponse(format!(
+            "got {} results for {} batches of {} queries",
+            resp_chunks.len(),
+            batches.len(),
+            num_partitions
+        )));
     }
 
-    Ok(results)
+    let targets = batches.iter().flatten();
+    Ok(targets
+        .zip(resp_chunks)
+        .filter_map(|(target, chunk)| target.map(|row| (row, chunk)))
+        .collect())
+}
+
+/// Whether the given error is the server reporting that it does not know a setup UUID.
+///
+/// The server reports unknown UUIDs as 410 Gone, unlike missing buckets, which are 404.
+fn is_unknown_uuid(err: &Error) -> bool {
+    match err {
+        Error::ApiError(status, _) => status == StatusCode::GONE.as_str(),
+        _ => false,
+    }
+}
+
+fn serialize_seed<S: Serializer>(seed: &Seed, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&hex::encode(seed))
+}
+
+fn deserialize_seed<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Seed, D::Error> {
+    let seed_hex = String::deserialize(deserializer)?;
+    let seed = hex::decode(seed_hex).map_err(serde::de::Error::custom)?;
+    seed.try_into()
+        .map_err(|_| serde::de::Error::custom("seed must be 32 bytes"))
+}
+
+/// The identity of a client that has been set up with a bucket.
+///
+/// Exported by `ApiClient::identity()` and passed to `ApiClient::resume()`,
+/// so that a later session can make private reads without uploading its keys again.
+/// The seed is secret: anyone holding it can decrypt the client's reads.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientIdentity {
+    /// The seed the client's keys are generated from.
+    #[serde(
+        serialize_with = "serialize_seed",
+        deserialize_with = "deserialize_seed"
+    )]
+    pub seed: Seed,
+    /// The UUID the server assigned to the client's public parameters.
+    pub uuid: String,
+    /// The hash of the parameters the keys were generated for.
+    pub params_hash: String,
 }
 
 /// A client for a single, existing Blyss bucket.
@@ -239,52 +609,202 @@ pub struct ApiClient {
     pub url: String,
 
     api_key: String,
-    params: &'static Params,
-    client: Client<'static>,
+    http: HttpClient,
+    key_layout: KeyLayout,
+    client: SharedClient,
+    seed: Option<Seed>,
     uuid: Option<String>,
 }
 
+/// A builder for an `ApiClient`, to configure its HTTP requests.
+///
+/// Created with `ApiClient::builder`.
+#[derive(Debug, Clone)]
+pub struct ApiClientBuilder {
+    url: String,
+    api_key: String,
+    timeout: Duration,
+    connect_timeout: Duration,
+    max_retries: u32,
+    initial_backoff: Duration,
+    http_client: Option<reqwest::Client>,
+}
+
+impl ApiClientBuilder {
+    /// Set the API key to access the bucket with. Defaults to none.
+    pub fn api_key(mut self, api_key: &str) -> Self {
+        self.api_key = api_key.to_owned();
+        self
+    }
+
+    /// Set the timeout for a single HTTP request, including reading the response. Defaults to 60 seconds.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Set the timeout for connecting to the server. Defaults to 10 seconds.
+    ///
+    /// Not used with a client given to `http_client`, which has its own connect timeout.
+    pub fn connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = connect_timeout;
+        self
+    }
+
+    /// Set the number of times idempotent requests, like reads of the metadata and
+    /// private reads, are retried after a failure that may be transient. Defaults to 3.
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Set the delay before the first retry. Each further retry doubles it. Defaults to 250 ms.
+    pub fn initial_backoff(mut self, initial_backoff: Duration) -> Self {
+        self.initial_backoff = initial_backoff;
+        self
+    }
+
+    /// Use the given HTTP client, to share its connection pool with other API clients.
+    pub fn http_client(mut self, http_client: reqwest::Client) -> Self {
+        self.http_client = Some(http_client);
+        self
+    }
+
+    /// Create the API client, fetching the metadata of the bucket.
+    pub async fn build(self) -> Result<ApiClient, Error> {
+        let client = match self.http_client {
+            Some(client) => client,
+            None => reqwest::Client::builder()
+                .connect_timeout(self.connect_timeout)
+                .build()?,
+        };
+        let http = HttpClient {
+            client,
+            timeout: self.timeout,
+            max_retries: self.max_retries,
+            initial_backoff: self.initial_backoff,
+        };
+        ApiClient::with_http(&self.url, &self.api_key, http).await
+    }
+}
+
 impl ApiClient {
-    /// Create a new API client for the given URL and API key.
+    /// Create a new API client for the given URL and API key, with the default HTTP configuration.
     ///
     /// The URL should be the URL of the bucket, e.g. `https://beta.api.blyss.dev/global.abc123`.
     pub async fn new(url: &str, api_key: &str) -> Result<Self, Error> {
-        let metadata = get_meta(url, api_key).await?;
-        let params_value = serde_json::from_str::<Value>(&metadata)?
-            .get("pir_scheme")
-            .ok_or(Error::Unknown)?
-            .clone();
-        let params = params_from_json_obj(&params_value);
-        let boxed_params = Box::leak(Box::new(params)); // TODO: avoid this
+        Self::builder(url).api_key(api_key).build().await
+    }
 
+    /// Start building an API client for the given URL, to configure its HTTP requests.
+    pub fn builder(url: &str) -> ApiClientBuilder {
+        ApiClientBuilder {
+            url: url.to_owned(),
+            api_key: String::new(),
+            timeout: DEFAULT_TIMEOUT,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            max_retries: DEFAULT_MAX_RETRIES,
+            initial_backoff: DEFAULT_INITIAL_BACKOFF,
+            http_client: None,
+        }
+    }
+
+    async fn with_http(url: &str, api_key: &str, http: HttpClient) -> Result<Self, Error> {
+        let metadata = serde_json::from_str::<Value>(&get_meta(&http, url, api_key).await?)?;
+        let params_value = metadata.get("pir_scheme").ok_or(Error::Unknown)?;
+        // buckets that do not name a scheme are served with Spiral
+        match params_value.get("scheme").and_then(Value::as_str) {
+            None | Some("spiral") => {}
+            Some(scheme) => return Err(Error::UnsupportedScheme(scheme.to_owned())),
+        }
+        let params = intern_params(try_params_from_json_obj(params_value)?);
+        // buckets that do not advertise a layout use a single row per key
+        let key_layout = match metadata.get("key_layout") {
+            Some(layout) => serde_json::from_value(layout.clone())?,
+            None => KeyLayout::Single,
+        };
+        if !key_layout.is_valid() {
+            return Err(Error::MalformedResponse(format!(
+                "unsupported key layout: {:?}",
+                key_layout
+            )));
+        }
         Ok(Self {
             url: url.to_string(),
             api_key: api_key.to_string(),
-            params: boxed_params,
-            client: Client::init(boxed_params),
+            http,
+            key_layout,
+            client: SharedClient::init(params),
+            seed: None,
             uuid: None,
         })
     }
 
+    /// The HTTP client this client makes its requests with.
+    pub(crate) fn http(&self) -> &HttpClient {
+        &self.http
+    }
+
     /// Returns whether the client has been set up for private reads.
     fn has_set_up(&self) -> bool {
         self.uuid.is_some()
     }
 
-    /// Prepare the client for private reads. This must be called before calling private_read().
+    /// Prepare the client for private reads. This must be called before calling private_read(),
+    /// unless the client has resumed an identity with resume().
     pub async fn setup(&mut self) -> Result<(), Error> {
-        let setup = self.client.generate_keys();
+        let seed = rand::thread_rng().gen();
+        self.setup_with_seed(seed).await
+    }
+
+    /// Generate keys from the given seed, and upload the public parameters to the server.
+    async fn setup_with_seed(&mut self, seed: Seed) -> Result<(), Error> {
+        let setup = self.client.generate_keys_from_seed(seed);
         let setup_data = setup.serialize();
 
-        let uuid = perform_setup(&self.url, &self.api_key, setup_data).await?;
+        let uuid = perform_setup(&self.http, &self.url, &self.api_key, setup_data).await?;
 
+        self.seed = Some(seed);
         self.uuid = Some(uuid);
 
         Ok(())
     }
 
+    /// Export the identity of this client, so that a later session can resume() it.
+    ///
+    /// Returns `None` if the client has not been set up.
+    pub fn identity(&self) -> Option<ClientIdentity> {
+        Some(ClientIdentity {
+            seed: self.seed?,
+            uuid: self.uuid.clone()?,
+            params_hash: self.client.params().hash(),
+        })
+    }
+
+    /// Resume an identity exported by identity(), instead of calling setup().
+    ///
+    /// The keys are regenerated from the identity's seed, and nothing is uploaded.
+    /// If the server no longer knows the identity's UUID, the next private_read()
+    /// sets the client up again, and the identity should be exported again.
+    ///
+    /// # Errors
+    /// - `Error::IdentityMismatch` - If the identity was made for a bucket with different parameters.
+    pub fn resume(&mut self, identity: &ClientIdentity) -> Result<(), Error> {
+        if identity.params_hash != self.client.params().hash() {
+            return Err(Error::IdentityMismatch);
+        }
+        self.client.generate_secret_keys_from_seed(identity.seed);
+        self.seed = Some(identity.seed);
+        self.uuid = Some(identity.uuid.clone());
+
+        Ok(())
+    }
+
     /// Privately read the given keys from the bucket.
-    /// Must call setup() before calling this.
+    /// Must call setup() or resume() before calling this.
+    ///
+    /// If the server does not know the client's UUID, the client's public parameters
+    /// are uploaded again, under a new UUID, and the read is retried.
     ///
     /// # Arguments
     /// - `keys` - The keys to read.
@@ -294,15 +814,27 @@ impl ApiClient {
     /// If a key does not exist, the corresponding value will be an empty vector.
     ///
     /// # Errors
-    /// - `Error::NeedSetup` - If setup() has not been called.
-    pub async fn private_read(&self, keys: &[String]) -> Result<Vec<Vec<u8>>, Error> {
+    /// - `Error::NeedSetup` - If neither setup() nor resume() has been called.
+    pub async fn private_read(&mut self, keys: &[String]) -> Result<Vec<Vec<u8>>, Error> {
         if !self.has_set_up() {
             return Err(Error::NeedSetup);
         }
 
+        match self.private_read_once(keys).await {
+            Err(err) if is_unknown_uuid(&err) => {
+                self.setup_with_seed(self.seed.unwrap()).await?;
+                self.private_read_once(keys).await
+            }
+            result => result,
+        }
+    }
+
+    async fn private_read_once(&self, keys: &[String]) -> Result<Vec<Vec<u8>>, Error> {
         private_read(
-            &self.client,
-            &self.params,
+            &self.http,
+            self.client.client(),
+            self.client.params(),
+            self.key_layout,
             self.uuid.as_ref().unwrap(),
             &self.url,
             &self.api_key,
@@ -310,4 +842,478 @@ impl ApiClient {
         )
         .await
     }
+
+    /// Write the given key-value pairs to the bucket.
+    /// Large writes are split into several requests.
+    ///
+    /// # Arguments
+    /// - `kv_pairs` - The keys and values to write. Existing values are overwritten.
+    ///
+    /// # Returns
+    /// The version of the bucket after the write.
+    pub async fn write(&self, kv_pairs: &HashMap<String, Vec<u8>>) -> Result<u64, Error> {
+        let mut pairs: Vec<_> = kv_pairs
+            .iter()
+            .map(|(key, value)| {
+                let wrapped = wrap_metadata(&[], value);
+                let value_b64 = general_purpose::STANDARD.encode(wrapped);
+                (key.clone(), Value::String(value_b64))
+            })
+            .collect();
+        pairs.sort_by(|a, b| a.0.cmp(&b.0));
+
+        write_kv_pairs(&self.http, &self.url, &self.api_key, pairs).await
+    }
+
+    /// Delete the given keys from the bucket.
+    ///
+    /// # Returns
+    /// The version of the bucket after the delete.
+    pub async fn delete(&self, keys: &[String]) -> Result<u64, Error> {
+        let pairs = keys.iter().map(|key| (key.clone(), Value::Null)).collect();
+
+        write_kv_pairs(&self.http, &self.url, &self.api_key, pairs).await
+    }
+
+    /// Fetch the metadata of the bucket.
+    pub async fn meta(&self) -> Result<Value, Error> {
+        let metadata = get_meta(&self.http, &self.url, &self.api_key).await?;
+        Ok(serde_json::from_str(&metadata)?)
+    }
+
+    /// Fetch the current version of the bucket.
+    pub async fn version(&self) -> Result<u64, Error> {
+        get_version(&self.http, &self.url, &self.api_key).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use actix_web::{web, App, HttpServer};
+    use spiral_rs::key_value::row_from_key;
+    use spiral_server::{
+        bucket::{Bucket, BucketConfig, Buckets},
+        routes::configure,
+    };
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    const PARAMS_JSON: &str = r#"{
+        "n": 2,
+        "nu_1": 6,
+        "nu_2": 2,
+        "p": 256,
+        "q2_bits": 20,
+        "t_gsw": 8,
+        "t_conv": 4,
+        "t_exp_left": 8,
+        "t_exp_right": 8,
+        "instances": 1,
+        "db_item_size": 8192
+    }"#;
+
+    /// Starts a server with a single bucket, returning the bucket URL.
+    async fn start_server(bucket_name: &str) -> (String, Arc<Bucket>) {
+        let config = BucketConfig::from_params_json(PARAMS_JSON).unwrap();
+        start_server_with_config(bucket_name, &config).await
+    }
+
+    async fn start_server_with_config(
+        bucket_name: &str,
+        config: &BucketConfig,
+    ) -> (String, Arc<Bucket>) {
+        let buckets = Buckets::new(None);
+        let bucket = buckets
+            .create(bucket_name, config)
+            .unwrap()
+            .into_spiral()
+            .unwrap();
+        let state = web::Data::new(buckets);
+
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(state.clone())
+                .app_data(web::PayloadConfig::new(1usize << 32))
+                .configure(configure)
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let port = server.addrs()[0].port();
+        tokio::spawn(server.run());
+
+        (format!("http://127.0.0.1:{}/{}", port, bucket_name), bucket)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn private_read_round_trip() {
+        let (url, bucket) = start_server("round-trip").await;
+        let apple = wrap_metadata(b"{}", b"red");
+        let banana = wrap_metadata(b"{}", b"yellow");
+        bucket
+            .write(&[("apple", &apple), ("banana", &banana)])
+            .unwrap();
+
+        let mut client = ApiClient::new(&url, "").await.unwrap();
+        assert!(matches!(
+            client.private_read(&["apple".to_owned()]).await,
+            Err(Error::NeedSetup)
+        ));
+        client.setup().await.unwrap();
+
+        let keys = ["apple", "missing", "banana"].map(|k| k.to_owned());
+        let results = client.private_read(&keys).await.unwrap();
+        assert_eq!(results, vec![b"red".to_vec(), vec![], b"yellow".to_vec()]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn many_keys_are_read_with_batch_queries() {
+        let (url, bucket) = start_server("batched").await;
+        let apple = wrap_metadata(b"{}", b"red");
+        let banana = wrap_metadata(b"{}", b"yellow");
+        bucket
+            .write(&[("apple", &apple), ("banana", &banana)])
+            .unwrap();
+
+        let mut client = ApiClient::new(&url, "").await.unwrap();
+        client.setup().await.unwrap();
+        let num_keys = 256;
+        let (_, num_partitions) = choose_batch_shape(client.client.params(), num_keys);
+        assert!(num_partitions > 1);
+
+        let mut keys: Vec<_> = (2..num_keys).map(|i| format!("missing-{}", i)).collect();
+        keys.insert(0, "apple".to_owned());
+        keys.insert(100, "banana".to_owned());
+        let results = client.private_read(&keys).await.unwrap();
+        assert_eq!(results.len(), num_keys);
+        assert_eq!(results[0], b"red");
+        assert_eq!(results[100], b"yellow");
+        assert!(results
+            .iter()
+            .enumerate()
+            .all(|(i, result)| i == 0 || i == 100 || result.is_empty()));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn private_read_json_round_trip() {
+        let (url, bucket) = start_server("json").await;
+        let apple = wrap_metadata(b"{}", b"red");
+        bucket.write(&[("apple", &apple)]).unwrap();
+
+        let mut client = ApiClient::new(&url, "").await.unwrap();
+        let setup = client.client.generate_keys();
+        let setup_b64 = general_purpose::STANDARD.encode(setup.serialize());
+        let setup_resp = client
+            .http
+            .post_string(
+                &format!("{}/setup", url),
+                "",
+                serde_json::to_string(&setup_b64).unwrap(),
+                false,
+            )
+            .await
+            .unwrap();
+        let uuid = serde_json::from_str::<Value>(&setup_resp).unwrap()["uuid"]
+            .as_str()
+            .unwrap()
+            .to_owned();
+
+        let query = client
+            .client
+            .client()
+            .generate_query(row_from_key(client.client.params(), "apple"));
+        let query_b64 =
+            general_purpose::STANDARD.encode([uuid.as_bytes(), &query.serialize()].concat());
+        let body = serde_json::json!({ "version": WIRE_VERSION, "queries": [query_b64] });
+        let resp = reqwest::Client::new()
+            .post(format!("{}/private-read", url))
+            .header("Content-Type", "application/json")
+            .body(body.to_string())
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        let resp: Value = serde_json::from_str(&resp).unwrap();
+        assert_eq!(resp["version"], WIRE_VERSION);
+
+        let result_b64 = resp["results"][0].as_str().unwrap();
+        let result = general_purpose::STANDARD.decode(result_b64).unwrap();
+        let params = client.client.params();
+        let p_bits = log2_ceil(params.pt_modulus) as usize;
+        let decrypted = client
+            .client
+            .client()
+            .decode_response(&result)
+            .to_vec(p_bits, params.modp_words_per_chunk());
+        let value = extract_result_impl("apple", &decompress(&decrypted).unwrap()).unwrap();
+        assert_eq!(split_metadata(&value).unwrap().1, b"red");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn resumed_identity_skips_setup() {
+        let (url, bucket) = start_server("identity").await;
+        let apple = wrap_metadata(b"{}", b"red");
+        bucket.write(&[("apple", &apple)]).unwrap();
+        let keys = ["apple".to_owned()];
+
+        let mut client = ApiClient::new(&url, "").await.unwrap();
+        assert!(client.identity().is_none());
+        client.setup().await.unwrap();
+        let identity = client.identity().unwrap();
+        let identity_json = serde_json::to_string(&identity).unwrap();
+
+        // a new session reads with the same UUID, without uploading its keys
+        let mut resumed = ApiClient::new(&url, "").await.unwrap();
+        let identity: ClientIdentity = serde_json::from_str(&identity_json).unwrap();
+        resumed.resume(&identity).unwrap();
+        assert_eq!(resumed.identity(), Some(identity.clone()));
+        assert_eq!(
+            resumed.private_read(&keys).await.unwrap(),
+            vec![b"red".to_vec()]
+        );
+
+        // a forgotten UUID falls back to a fresh setup with the same keys
+        assert!(bucket.pub_params.remove(&identity.uuid).unwrap());
+        assert_eq!(
+            resumed.private_read(&keys).await.unwrap(),
+            vec![b"red".to_vec()]
+        );
+        let new_identity = resumed.identity().unwrap();
+        assert_ne!(new_identity.uuid, identity.uuid);
+        assert_eq!(new_identity.seed, identity.seed);
+
+        let mismatched = ClientIdentity {
+            params_hash: "00".to_owned(),
+            ..identity
+        };
+        assert!(matches!(
+            resumed.resume(&mismatched),
+            Err(Error::IdentityMismatch)
+        ));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn write_and_delete_round_trip() {
+        let (url, _bucket) = start_server("write").await;
+        let mut client = ApiClient::new(&url, "").await.unwrap();
+        client.setup().await.unwrap();
+        assert_eq!(client.version().await.unwrap(), 0);
+
+        let kv_pairs = HashMap::from([
+            ("apple".to_owned(), b"red".to_vec()),
+            ("banana".to_owned(), b"yellow".to_vec()),
+        ]);
+        assert_eq!(client.write(&kv_pairs).await.unwrap(), 1);
+        assert_eq!(client.meta().await.unwrap()["global_version"], 1);
+
+        let keys = ["apple", "banana"].map(|k| k.to_owned());
+        let results = client.private_read(&keys).await.unwrap();
+        assert_eq!(results, vec![b"red".to_vec(), b"yellow".to_vec()]);
+
+        assert_eq!(client.delete(&keys[..1]).await.unwrap(), 2);
+        let results = client.private_read(&keys).await.unwrap();
+        assert_eq!(results, vec![vec![], b"yellow".to_vec()]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn cuckoo_bucket_round_trip() {
+        let mut config = BucketConfig::from_params_json(PARAMS_JSON).unwrap();
+        config.key_layout = KeyLayout::Cuckoo { num_hashes: 2 };
+        let (url, _bucket) = start_server_with_config("cuckoo", &config).await;
+        let mut client = ApiClient::new(&url, "").await.unwrap();
+        assert_eq!(client.key_layout, config.key_layout);
+        client.setup().await.unwrap();
+
+        let kv_pairs: HashMap<_, _> = (0..20)
+            .map(|i| (format!("key-{}", i), format!("value-{}", i).into_bytes()))
+            .collect();
+        client.write(&kv_pairs).await.unwrap();
+
+        let mut keys: Vec<_> = kv_pairs.keys().cloned().collect();
+        keys.push("missing".to_owned());
+        let results = client.private_read(&keys).await.unwrap();
+        for (key, result) in keys.iter().zip(results.iter()) {
+            assert_eq!(result, kv_pairs.get(key).unwrap_or(&vec![]));
+        }
+    }
+
+    /// Starts a server whose `/flaky` path fails with 503 for its first `failures` requests,
+    /// and whose `/slow` path takes a second to answer, returning its URL and request count.
+    async fn start_flaky_server(failures: usize) -> (String, Arc<AtomicUsize>) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let server = HttpServer::new(move || {
+            let counter = counter.clone();
+            App::new()
+                .route(
+                    "/flaky",
+                    web::route().to(move || {
+                        let n = counter.fetch_add(1, Ordering::SeqCst);
+                        async move {
+                            if n < failures {
+                                actix_web::HttpResponse::ServiceUnavailable().finish()
+                            } else {
+                                actix_web::HttpResponse::Ok().body("ok")
+                            }
+                        }
+                    }),
+                )
+                .route(
+                    "/slow",
+                    web::get().to(|| async {
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                        "slow"
+                    }),
+                )
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let port = server.addrs()[0].port();
+        tokio::spawn(server.run());
+
+        (format!("http://127.0.0.1:{}", port), requests)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn idempotent_requests_are_retried() {
+        let (url, requests) = start_flaky_server(2).await;
+        let http = HttpClient {
+            initial_backoff: Duration::from_millis(1),
+            ..HttpClient::default()
+        };
+
+        let flaky = format!("{}/flaky", url);
+        assert_eq!(http.get_string(&flaky, "").await.unwrap(), "ok");
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+
+        // writes are never retried
+        requests.store(0, Ordering::SeqCst);
+        let result = http.post_string(&flaky, "", String::new(), false).await;
+        assert!(matches!(
+            result,
+            Err(Error::ApiError(status, path)) if status == "503" && path == "/flaky"
+        ));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        // retries give up
+        requests.store(0, Ordering::SeqCst);
+        let http = HttpClient {
+            max_retries: 1,
+            ..http
+        };
+        assert!(matches!(
+            http.get_string(&flaky, "").await,
+            Err(Error::ApiError(status, _)) if status == "503"
+        ));
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+        let http = HttpClient {
+            timeout: Duration::from_millis(100),
+            max_retries: 0,
+            ..http
+        };
+        match http.get_string(&format!("{}/slow", url), "").await {
+            Err(Error::HTTPError(err)) => assert!(err.is_timeout()),
+            result => panic!("expected a timeout, got {:?}", result),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn builder_configures_clients() {
+        let (url, bucket) = start_server("builder").await;
+        let apple = wrap_metadata(b"{}", b"red");
+        bucket.write(&[("apple", &apple)]).unwrap();
+
+        let shared = reqwest::Client::new();
+        let mut client = ApiClient::builder(&url)
+            .timeout(Duration::from_secs(30))
+            .max_retries(1)
+            .http_client(shared.clone())
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(client.http().max_retries, 1);
+        client.setup().await.unwrap();
+        let results = client.private_read(&["apple".to_owned()]).await.unwrap();
+        assert_eq!(results, vec![b"red".to_vec()]);
+
+        // API failures carry their status and path
+        let missing = url.replace("builder", "missing");
+        let result = ApiClient::builder(&missing)
+            .http_client(shared)
+            .build()
+            .await;
+        assert!(matches!(
+            result,
+            Err(Error::ApiError(status, path)) if status == "404" && path == "/missing/meta"
+        ));
+        assert!(!is_unknown_uuid(&Error::ApiError(
+            "404".to_owned(),
+            "/missing/private-read".to_owned()
+        )));
+    }
+
+    #[test]
+    fn large_writes_are_batched() {
+        let big = Value::String("a".repeat(MAX_WRITE_BATCH_BYTES / 2));
+        let pairs = vec![
+            ("a".to_owned(), big.clone()),
+            ("b".to_owned(), big.clone()),
+            ("c".to_owned(), Value::Null),
+            (
+                "d".to_owned(),
+                Value::String("a".repeat(MAX_WRITE_BATCH_BYTES * 2)),
+            ),
+        ];
+        let batches = batch_kv_pairs(pairs);
+        let keys: Vec<Vec<_>> = batches
+            .iter()
+            .map(|b| b.keys().cloned().collect())
+            .collect();
+        assert_eq!(keys, vec![vec!["a"], vec!["b", "c"], vec!["d"]]);
+    }
+
+    #[test]
+    fn truncated_responses_are_rejected() {
+        let chunks = vec![b"first".to_vec(), b"second".to_vec()];
+        let response = serialize_chunks(&chunks);
+        assert_eq!(deserialize_chunks(&response).unwrap(), chunks);
+        for len in 0..response.len() {
+            assert!(matches!(
+                deserialize_chunks(&response[..len]),
+                Err(Error::MalformedResponse(_))
+            ));
+        }
+        let mut trailing = response.clone();
+        trailing.push(0);
+        assert!(deserialize_chunks(&trailing).is_err());
+
+        // a chunk length that overflows the offset
+        let mut huge = 1u64.to_le_bytes().to_vec();
+        huge.extend(u64::MAX.to_le_bytes());
+        assert!(deserialize_chunks(&huge).is_err());
+
+        let versioned = serialize_read_request(&chunks);
+        assert_eq!(deserialize_read_response(&versioned).unwrap(), chunks);
+        assert!(deserialize_read_response(&versioned[..4]).is_err());
+
+        let value = wrap_metadata(b"meta", b"data");
+        assert_eq!(
+            split_metadata(&value).unwrap(),
+            (&b"meta"[..], &b"data"[..])
+        );
+        for len in 0..value.len() - 4 {
+            assert!(split_metadata(&value[..len]).is_err());
+        }
+        assert!(split_metadata(&[0xff; 4]).is_err());
+    }
 }
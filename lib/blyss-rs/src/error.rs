@@ -1,3 +1,4 @@
+use spiral_rs::params::ParamsError;
 use thiserror::Error;
 
 /// Error type for Blyss.
@@ -24,9 +25,37 @@ pub enum Error {
     /// A wrapped io::Error.
     #[error("IO error: {0}")]
     IOError(#[from] std::io::Error),
+    /// Scheme parameters that are malformed or inconsistent.
+    #[error("Invalid parameters: {0}")]
+    InvalidParams(#[from] ParamsError),
+    /// A response from the server that could not be parsed.
+    #[error("Malformed response: {0}")]
+    MalformedResponse(String),
+    /// Rows to read that do not fit the batch queries picked for their number; this only
+    /// happens with negligible probability.
+    #[error("The {0} rows to read do not fit in the batch queries")]
+    BatchOverflow(usize),
     /// An error caused by failing to call `setup()` before using `private_read()`.
     #[error("Must call setup() before using private_read()")]
     NeedSetup,
+    /// A client identity that was exported for a bucket with different parameters.
+    #[error("Client identity does not match the bucket's parameters")]
+    IdentityMismatch,
+    /// A Merkle tree node that is not a 256-bit big-endian hex string.
+    #[error("Invalid Merkle tree node: {0}")]
+    InvalidNode(String),
+    /// An identity commitment that is not in the Merkle tree.
+    #[error("Identity not found: {0}")]
+    IdentityNotFound(String),
+    /// A subtree from a private read that is inconsistent with the Merkle tree.
+    #[error("Inconsistent subtree: {0}")]
+    InconsistentSubtree(String),
+    /// A Merkle proof that does not lead to the expected root.
+    #[error("Merkle proof does not lead to root {0}")]
+    ProofMismatch(String),
+    /// A bucket served with a PIR scheme this client cannot decode.
+    #[error("Unsupported PIR scheme: {0}")]
+    UnsupportedScheme(String),
     /// An unknown error.
     #[error("Unknown error")]
     Unknown,
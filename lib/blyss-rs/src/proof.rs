@@ -1,9 +1,11 @@
 use crate::{
-    api::{http_get_string, ApiClient},
+    api::{ApiClient, HttpClient},
     error::Error,
 };
 use ruint::aliases::U256;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, HashMap};
 
 /// A configuration for performing Merkle proof lookups using Blyss.
 ///
@@ -27,7 +29,7 @@ pub struct LookupCfg {
 impl LookupCfg {
     /// Fetch a `LookupCfg` from the given URL to a JSON object.
     pub async fn from_url(url: &str) -> Result<LookupCfg, Error> {
-        let val = http_get_string(url, "").await?;
+        let val = HttpClient::default().get_string(url, "").await?;
         let cfg: LookupCfg = serde_json::from_str(&val)?;
         Ok(cfg)
     }
@@ -56,19 +58,117 @@ impl ProofStep {
     }
 }
 
+/// A hash function combining two sibling nodes of a Merkle tree into their parent.
+pub trait MerkleHasher {
+    /// Hash the given left and right children into their parent.
+    fn hash2(&self, left: U256, right: U256) -> U256;
+}
+
+/// The Poseidon hash used by Semaphore and World ID trees,
+/// i.e. the circom Poseidon over the BN254 scalar field.
+///
+/// Requires the `poseidon` feature.
+#[cfg(any(feature = "poseidon", test))]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PoseidonHasher;
+
+#[cfg(any(feature = "poseidon", test))]
+impl MerkleHasher for PoseidonHasher {
+    fn hash2(&self, left: U256, right: U256) -> U256 {
+        use ark_bn254::Fr;
+        use ark_ff::{BigInteger, PrimeField};
+        use light_poseidon::{Poseidon, PoseidonHasher as _};
+
+        let to_field = |value: U256| Fr::from_be_bytes_mod_order(&value.to_be_bytes::<32>());
+        let mut poseidon = Poseidon::<Fr>::new_circom(2).unwrap();
+        let hash = poseidon.hash(&[to_field(left), to_field(right)]).unwrap();
+        U256::from_be_slice(&hash.into_bigint().to_bytes_be())
+    }
+}
+
+/// SHA-256 of the concatenated big-endian children.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sha256Hasher;
+
+impl MerkleHasher for Sha256Hasher {
+    fn hash2(&self, left: U256, right: U256) -> U256 {
+        let mut hasher = Sha256::new();
+        hasher.update(left.to_be_bytes::<32>());
+        hasher.update(right.to_be_bytes::<32>());
+        U256::from_be_bytes::<32>(hasher.finalize().into())
+    }
+}
+
 /// Convert the given BE hex string to a `U256`.
 fn to_u256(value: &str) -> U256 {
     U256::from_be_bytes::<32>(hex::decode(&value[2..]).unwrap().try_into().unwrap())
 }
 
-/// Get the indices of the subtrees needed to construct a Merkle proof for the given identity index.
-fn get_subtree_indices(lookup_cfg: &LookupCfg, identity_idx: usize) -> Vec<String> {
-    let mut keys_to_fetch = Vec::new();
+/// Parse the given BE hex string, with a `0x` prefix, as a `U256`.
+fn parse_node(value: &str) -> Result<U256, Error> {
+    value
+        .strip_prefix("0x")
+        .and_then(|value| hex::decode(value).ok())
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(U256::from_be_bytes::<32>)
+        .ok_or_else(|| Error::InvalidNode(value.to_owned()))
+}
+
+/// Hash the given node up through the steps of a Merkle proof.
+fn fold_proof<H: MerkleHasher>(hasher: &H, node: U256, proof: &[ProofStep]) -> Result<U256, Error> {
+    let mut cur_hash = node;
+    for step in proof {
+        let step_hash = parse_node(&step.value)?;
+        cur_hash = if step.pos == 0 {
+            hasher.hash2(step_hash, cur_hash)
+        } else {
+            hasher.hash2(cur_hash, step_hash)
+        };
+    }
+    Ok(cur_hash)
+}
+
+/// Compute the root that the given Merkle proof leads to from the given leaf.
+pub fn compute_root<H: MerkleHasher>(
+    hasher: &H,
+    leaf: &str,
+    proof: &[ProofStep],
+) -> Result<U256, Error> {
+    fold_proof(hasher, parse_node(leaf)?, proof)
+}
+
+/// Verify that the given Merkle proof leads from the given leaf to the given root.
+///
+/// # Errors
+/// - `Error::ProofMismatch` - If the proof leads to a different root.
+/// - `Error::InvalidNode` - If the leaf, root, or a step is not a 256-bit hex string.
+pub fn verify_proof<H: MerkleHasher>(
+    hasher: &H,
+    leaf: &str,
+    proof: &[ProofStep],
+    root: &str,
+) -> Result<(), Error> {
+    if compute_root(hasher, leaf, proof)? != parse_node(root)? {
+        return Err(Error::ProofMismatch(root.to_owned()));
+    }
+    Ok(())
+}
+
+/// A subtree on the path from an identity to the cap of the Merkle tree.
+struct SubtreePosition {
+    /// The key of the subtree in the bucket.
+    key: String,
+    /// The index of the identity's ancestor among the leaves of the subtree.
+    idx_within_subtree: usize,
+}
+
+/// Get the levels of the roots of the subtrees on the path from any identity to the cap,
+/// from the leaves up.
+fn get_subtree_levels(lookup_cfg: &LookupCfg) -> Vec<usize> {
+    let mut levels = Vec::new();
     let mut cur_level = lookup_cfg.tree_height - lookup_cfg.subtree_height;
     while cur_level >= lookup_cfg.cap_height - 1 {
-        let idx_within_level = identity_idx >> (lookup_cfg.tree_height - 1 - cur_level);
-        let key = format!("{}-{}", cur_level, idx_within_level);
-        keys_to_fetch.push(key);
+        levels.push(cur_level);
 
         if cur_level >= lookup_cfg.subtree_height {
             cur_level -= lookup_cfg.subtree_height - 1;
@@ -77,7 +177,69 @@ fn get_subtree_indices(lookup_cfg: &LookupCfg, identity_idx: usize) -> Vec<Strin
         }
     }
 
-    keys_to_fetch
+    levels
+}
+
+/// Get the key in the bucket of the subtree rooted at the given node.
+fn subtree_key(level: usize, idx_within_level: usize) -> String {
+    format!("{}-{}", level, idx_within_level)
+}
+
+/// Get the subtrees needed to construct a Merkle proof for the given identity index, from the leaves up.
+fn get_subtree_positions(lookup_cfg: &LookupCfg, identity_idx: usize) -> Vec<SubtreePosition> {
+    get_subtree_levels(lookup_cfg)
+        .into_iter()
+        .map(|cur_level| {
+            let idx_within_level = identity_idx >> (lookup_cfg.tree_height - 1 - cur_level);
+            let idx_within_subtree = (identity_idx
+                >> (lookup_cfg.tree_height - 1 - (cur_level + lookup_cfg.subtree_height - 1)))
+                - idx_within_level * (1 << (lookup_cfg.subtree_height - 1));
+            SubtreePosition {
+                key: subtree_key(cur_level, idx_within_level),
+                idx_within_subtree,
+            }
+        })
+        .collect()
+}
+
+/// Get the indices of the subtrees needed to construct a Merkle proof for the given identity index.
+fn get_subtree_indices(lookup_cfg: &LookupCfg, identity_idx: usize) -> Vec<String> {
+    get_subtree_positions(lookup_cfg, identity_idx)
+        .into_iter()
+        .map(|position| position.key)
+        .collect()
+}
+
+/// Get the keys of the subtrees on the paths from the given identity indices, each once,
+/// padded with other subtrees so that the number of keys only depends on `num_identities`.
+///
+/// At each level, there are as many keys as there would be if every identity had its own
+/// subtree there, so the server does not learn how many identities share subtrees.
+fn get_padded_subtree_keys(
+    lookup_cfg: &LookupCfg,
+    num_identities: usize,
+    identity_idxs: &[usize],
+) -> Vec<String> {
+    let mut keys = Vec::new();
+    for level in get_subtree_levels(lookup_cfg) {
+        let mut idxs_within_level: BTreeSet<usize> = identity_idxs
+            .iter()
+            .map(|identity_idx| identity_idx >> (lookup_cfg.tree_height - 1 - level))
+            .collect();
+        let subtrees_in_level = 1usize.checked_shl(level as u32).unwrap_or(usize::MAX);
+        let num_keys = num_identities.min(subtrees_in_level);
+        let mut dummy_idx = 0;
+        while idxs_within_level.len() < num_keys {
+            idxs_within_level.insert(dummy_idx);
+            dummy_idx += 1;
+        }
+        keys.extend(
+            idxs_within_level
+                .into_iter()
+                .map(|idx_within_level| subtree_key(level, idx_within_level)),
+        );
+    }
+    keys
 }
 
 /// Get the Merkle proof for the given index in the given tree.
@@ -103,34 +265,25 @@ fn construct_merkle_proof(
     identity_idx: usize,
     subtrees: &[Vec<String>],
 ) -> Vec<ProofStep> {
-    let mut cur_level = lookup_cfg.tree_height - lookup_cfg.subtree_height;
-    let mut outer_idx = 0;
-
     let mut proof = Vec::new();
-    while cur_level >= lookup_cfg.cap_height - 1 {
-        let subtree = &subtrees[outer_idx];
-        outer_idx += 1;
-        let idx_within_level = identity_idx >> (lookup_cfg.tree_height - 1 - cur_level);
-        let idx_within_subtree = (identity_idx
-            >> (lookup_cfg.tree_height - 1 - (cur_level + lookup_cfg.subtree_height - 1)))
-            - idx_within_level * (1 << (lookup_cfg.subtree_height - 1));
-
-        let proof_part = get_subproof(subtree, lookup_cfg.subtree_height, idx_within_subtree);
+    for (position, subtree) in get_subtree_positions(lookup_cfg, identity_idx)
+        .iter()
+        .zip(subtrees)
+    {
+        let proof_part = get_subproof(
+            subtree,
+            lookup_cfg.subtree_height,
+            position.idx_within_subtree,
+        );
         proof.extend(proof_part.into_iter());
-
-        if cur_level >= lookup_cfg.subtree_height {
-            cur_level -= lookup_cfg.subtree_height - 1;
-        } else {
-            break;
-        }
     }
 
     proof
 }
 
 /// Fetch the cap of the Merkle tree.
-async fn get_cap(url: &str) -> Result<Vec<String>, Error> {
-    let val = http_get_string(url, "").await?;
+async fn get_cap(http: &HttpClient, url: &str) -> Result<Vec<String>, Error> {
+    let val = http.get_string(url, "").await?;
     let cap: Vec<String> = serde_json::from_str(&val)?;
     Ok(cap)
 }
@@ -141,61 +294,271 @@ fn get_idx_within_cap(identity_idx: usize, tree_height: usize, cap_height: usize
     idx_within_level
 }
 
-/// Fetch the Merkle proof for the given identity index using Blyss.
-async fn fetch_merkle_proof_at_idx(
-    client: &mut ApiClient,
-    lookup_cfg: &LookupCfg,
-    identity_idx: usize,
-) -> Result<Vec<ProofStep>, Error> {
-    let cap = get_cap(&lookup_cfg.cap_url).await?;
-    let subtrees_to_query = get_subtree_indices(lookup_cfg, identity_idx);
-    let subtrees = client.private_read(&subtrees_to_query).await?;
-    let mut subtrees_as_strs = Vec::new();
-    for s in subtrees {
-        let s: Vec<String> = serde_json::from_slice(&s)?;
-        subtrees_as_strs.push(s);
+/// Check that the given identity index is a leaf of the Merkle tree.
+fn check_identity_idx(lookup_cfg: &LookupCfg, identity_idx: usize) -> Result<usize, Error> {
+    if identity_idx >= 1 << (lookup_cfg.tree_height - 1) {
+        return Err(Error::MalformedResponse(format!(
+            "identity index {} is outside a tree of height {}",
+            identity_idx, lookup_cfg.tree_height
+        )));
     }
-    let mut proof = construct_merkle_proof(lookup_cfg, identity_idx, &subtrees_as_strs);
-    let cap_proof_part = get_subproof(
-        &cap,
-        lookup_cfg.cap_height,
-        get_idx_within_cap(identity_idx, lookup_cfg.tree_height, lookup_cfg.cap_height),
-    );
-    proof.extend(cap_proof_part.into_iter());
+    Ok(identity_idx)
+}
 
-    Ok(proof)
+/// Check that the given subtree is a full tree of the configured height.
+fn check_subtree(lookup_cfg: &LookupCfg, key: &str, subtree: &[String]) -> Result<(), Error> {
+    if subtree.len() != (1 << lookup_cfg.subtree_height) - 1 {
+        return Err(Error::InconsistentSubtree(key.to_owned()));
+    }
+    Ok(())
 }
 
-/// Get the index for the given identity commitment.
-async fn fetch_idx_for_identity(
-    client: &mut ApiClient,
-    identity_commitment: &str,
-) -> Result<usize, Error> {
-    let result = client
-        .private_read(&[identity_commitment.to_owned()])
-        .await?;
-    let index: usize = serde_json::from_slice(&result[0])?;
+/// Check that the given cap is a full tree of the configured height.
+fn check_cap(lookup_cfg: &LookupCfg, cap: &[String]) -> Result<(), Error> {
+    if cap.len() != (1 << lookup_cfg.cap_height) - 1 {
+        return Err(Error::MalformedResponse(format!(
+            "cap has {} nodes, expected a tree of height {}",
+            cap.len(),
+            lookup_cfg.cap_height
+        )));
+    }
+    Ok(())
+}
 
-    Ok(index)
+/// The subtrees on the path from an identity to the cap of the Merkle tree.
+struct FetchedPath {
+    identity_idx: usize,
+    /// The subtrees, from the leaves up.
+    subtrees: Vec<Vec<String>>,
 }
 
-/// Fetch the Merkle proof for the given identity commitment using Blyss, with the given lookup configuration.
-async fn private_fetch_merkle_proof_with_cfg(
-    identity_commitment: &str,
-    lookup_cfg: &LookupCfg,
-) -> Result<Vec<ProofStep>, Error> {
+impl FetchedPath {
+    /// Construct the Merkle proof for the identity, through the given cap.
+    ///
+    /// # Errors
+    /// - `Error::InconsistentSubtree` - If a subtree is not a full tree of the configured height.
+    /// - `Error::MalformedResponse` - If the identity index or the cap do not fit the tree.
+    fn proof(&self, lookup_cfg: &LookupCfg, cap: &[String]) -> Result<Vec<ProofStep>, Error> {
+        check_identity_idx(lookup_cfg, self.identity_idx)?;
+        for (key, subtree) in get_subtree_indices(lookup_cfg, self.identity_idx)
+            .iter()
+            .zip(&self.subtrees)
+        {
+            check_subtree(lookup_cfg, key, subtree)?;
+        }
+        check_cap(lookup_cfg, cap)?;
+        let mut proof = construct_merkle_proof(lookup_cfg, self.identity_idx, &self.subtrees);
+        let cap_proof_part = get_subproof(
+            cap,
+            lookup_cfg.cap_height,
+            get_idx_within_cap(
+                self.identity_idx,
+                lookup_cfg.tree_height,
+                lookup_cfg.cap_height,
+            ),
+        );
+        proof.extend(cap_proof_part.into_iter());
+        Ok(proof)
+    }
+
+    /// Check that the path leads from the given leaf to the root of the given cap.
+    ///
+    /// Each subtree must contain the node below it on the path (the leaf, for the lowest subtree),
+    /// and hash up to its own root. The highest subtree's root must be in the cap.
+    ///
+    /// # Errors
+    /// - `Error::InconsistentSubtree` - If a subtree from the private read breaks the path.
+    /// - `Error::ProofMismatch` - If the cap does not hash to its own root.
+    /// - `Error::MalformedResponse` - If the identity index or the cap do not fit the tree.
+    fn verify<H: MerkleHasher>(
+        &self,
+        hasher: &H,
+        lookup_cfg: &LookupCfg,
+        cap: &[String],
+        leaf: &str,
+    ) -> Result<(), Error> {
+        check_identity_idx(lookup_cfg, self.identity_idx)?;
+        let mut node = parse_node(leaf)?;
+        let positions = get_subtree_positions(lookup_cfg, self.identity_idx);
+        let leaves_start = (1 << (lookup_cfg.subtree_height - 1)) - 1;
+        for (position, subtree) in positions.iter().zip(&self.subtrees) {
+            let inconsistent = || Error::InconsistentSubtree(position.key.clone());
+            check_subtree(lookup_cfg, &position.key, subtree)?;
+            if parse_node(&subtree[leaves_start + position.idx_within_subtree])? != node {
+                return Err(inconsistent());
+            }
+            let subproof = get_subproof(
+                subtree,
+                lookup_cfg.subtree_height,
+                position.idx_within_subtree,
+            );
+            node = fold_proof(hasher, node, &subproof)?;
+            if parse_node(&subtree[0])? != node {
+                return Err(inconsistent());
+            }
+        }
+
+        check_cap(lookup_cfg, cap)?;
+        let idx_within_cap = get_idx_within_cap(
+            self.identity_idx,
+            lookup_cfg.tree_height,
+            lookup_cfg.cap_height,
+        );
+        let cap_leaf = &cap[(1 << (lookup_cfg.cap_height - 1)) - 1 + idx_within_cap];
+        if parse_node(cap_leaf)? != node {
+            return Err(match positions.last() {
+                Some(position) => Error::InconsistentSubtree(position.key.clone()),
+                None => Error::ProofMismatch(cap[0].clone()),
+            });
+        }
+        let cap_subproof = get_subproof(cap, lookup_cfg.cap_height, idx_within_cap);
+        if fold_proof(hasher, node, &cap_subproof)? != parse_node(&cap[0])? {
+            return Err(Error::ProofMismatch(cap[0].clone()));
+        }
+
+        Ok(())
+    }
+}
+
+/// Normalize the given identity commitment to a lowercase hex string, with a `0x` prefix.
+fn normalize_identity_commitment(identity_commitment: &str) -> String {
     let mut owned_ic = identity_commitment.to_owned();
     if !owned_ic.starts_with("0x") {
         owned_ic = format!("0x{}", identity_commitment);
     }
-    owned_ic = owned_ic.to_lowercase();
+    owned_ic.to_lowercase()
+}
+
+/// Get the indices for the given identity commitments, in a single private read.
+async fn fetch_idx_for_identities(
+    client: &mut ApiClient,
+    identity_commitments: &[String],
+) -> Result<Vec<Result<usize, Error>>, Error> {
+    let results = client.private_read(identity_commitments).await?;
+    let indices = identity_commitments
+        .iter()
+        .zip(results)
+        .map(|(identity_commitment, result)| {
+            if result.is_empty() {
+                return Err(Error::IdentityNotFound(identity_commitment.clone()));
+            }
+            let index: usize = serde_json::from_slice(&result)?;
+            Ok(index)
+        })
+        .collect();
+
+    Ok(indices)
+}
+
+/// Privately fetch the subtrees on the paths from the given identity indices, in a single private read.
+///
+/// Subtrees shared by several identities are only read once, and the read is padded so that its
+/// size only depends on the number of identities (see `get_padded_subtree_keys`).
+async fn fetch_paths_at_indices(
+    client: &mut ApiClient,
+    lookup_cfg: &LookupCfg,
+    indices: Vec<Result<usize, Error>>,
+) -> Result<Vec<Result<FetchedPath, Error>>, Error> {
+    let indices: Vec<_> = indices
+        .into_iter()
+        .map(|identity_idx| check_identity_idx(lookup_cfg, identity_idx?))
+        .collect();
+    let identity_idxs: Vec<usize> = indices.iter().flatten().copied().collect();
+    let keys = get_padded_subtree_keys(lookup_cfg, indices.len(), &identity_idxs);
+    let subtrees = if keys.is_empty() {
+        Vec::new()
+    } else {
+        client.private_read(&keys).await?
+    };
+    let subtrees: HashMap<_, _> = keys.iter().zip(subtrees).collect();
+
+    let paths = indices
+        .into_iter()
+        .map(|identity_idx| {
+            let identity_idx = identity_idx?;
+            let mut subtrees_as_strs = Vec::new();
+            for key in get_subtree_indices(lookup_cfg, identity_idx) {
+                let s = &subtrees[&key];
+                if s.is_empty() {
+                    return Err(Error::InconsistentSubtree(key));
+                }
+                let s: Vec<String> = serde_json::from_slice(s)?;
+                check_subtree(lookup_cfg, &key, &s)?;
+                subtrees_as_strs.push(s);
+            }
+            Ok(FetchedPath {
+                identity_idx,
+                subtrees: subtrees_as_strs,
+            })
+        })
+        .collect();
+
+    Ok(paths)
+}
+
+/// Privately fetch the paths from the given (normalized) identity commitments to the cap,
+/// with the given lookup configuration, and fetch the cap.
+///
+/// The identities' indices are read in one private read, and their subtrees in another.
+/// The cap is fetched once per call, and not cached across calls, since it changes
+/// whenever the tree is updated.
+/// The outer error is for failures of the whole batch, and the inner ones for single identities.
+async fn fetch_paths_with_cfg(
+    identity_commitments: &[String],
+    lookup_cfg: &LookupCfg,
+) -> Result<(Vec<String>, Vec<Result<FetchedPath, Error>>), Error> {
+    if identity_commitments.is_empty() {
+        return Ok((Vec::new(), Vec::new()));
+    }
 
     let mut client = ApiClient::new(&lookup_cfg.bucket_url, &lookup_cfg.api_key).await?;
+    let cap = get_cap(client.http(), &lookup_cfg.cap_url).await?;
     client.setup().await?;
 
-    let index = fetch_idx_for_identity(&mut client, &owned_ic).await?;
-    let proof = fetch_merkle_proof_at_idx(&mut client, lookup_cfg, index).await?;
-    Ok(proof)
+    let indices = fetch_idx_for_identities(&mut client, identity_commitments).await?;
+    let paths = fetch_paths_at_indices(&mut client, lookup_cfg, indices).await?;
+    Ok((cap, paths))
+}
+
+/// Fetch the Merkle proofs for the given identity commitments using Blyss, with the given lookup configuration.
+///
+/// If a hasher is given, each proof is verified against the cap.
+async fn private_fetch_merkle_proofs_with_cfg<H: MerkleHasher>(
+    identity_commitments: &[String],
+    lookup_cfg: &LookupCfg,
+    hasher: Option<&H>,
+) -> Result<Vec<Result<Vec<ProofStep>, Error>>, Error> {
+    let owned_ics: Vec<_> = identity_commitments
+        .iter()
+        .map(|ic| normalize_identity_commitment(ic))
+        .collect();
+    let (cap, paths) = fetch_paths_with_cfg(&owned_ics, lookup_cfg).await?;
+
+    let proofs = owned_ics
+        .iter()
+        .zip(paths)
+        .map(|(owned_ic, path)| {
+            let path = path?;
+            if let Some(hasher) = hasher {
+                path.verify(hasher, lookup_cfg, &cap, owned_ic)?;
+            }
+            path.proof(lookup_cfg, &cap)
+        })
+        .collect();
+
+    Ok(proofs)
+}
+
+/// Fetch the Merkle proof for the given identity commitment using Blyss, with the given lookup configuration.
+async fn private_fetch_merkle_proof_with_cfg<H: MerkleHasher>(
+    identity_commitment: &str,
+    lookup_cfg: &LookupCfg,
+    hasher: Option<&H>,
+) -> Result<Vec<ProofStep>, Error> {
+    let identity_commitments = [identity_commitment.to_owned()];
+    let mut proofs =
+        private_fetch_merkle_proofs_with_cfg(&identity_commitments, lookup_cfg, hasher).await?;
+    proofs.pop().unwrap()
 }
 
 /// Privately fetch the Merkle proof for the given identity commitment using Blyss.
@@ -208,33 +571,201 @@ pub async fn private_fetch_merkle_proof(
     lookup_cfg_url: &str,
 ) -> Result<Vec<ProofStep>, Error> {
     let lookup_cfg = LookupCfg::from_url(lookup_cfg_url).await?;
-    private_fetch_merkle_proof_with_cfg(identity_commitment, &lookup_cfg).await
+    private_fetch_merkle_proof_with_cfg::<Sha256Hasher>(identity_commitment, &lookup_cfg, None)
+        .await
+}
+
+/// Privately fetch the Merkle proof for the given identity commitment using Blyss,
+/// and verify it against the cap of the tree.
+///
+/// # Arguments
+/// - `lookup_cfg_url` - A URL pointing to the JSON lookup configuration (see `LookupCfg`).
+/// - `identity_commitment` - The identity commitment (as a big-endian hex string) to fetch the Merkle proof for.
+/// - `hasher` - The hash function of the tree, e.g. `PoseidonHasher` for Semaphore and World ID trees.
+///
+/// # Errors
+/// - `Error::InconsistentSubtree` - If a subtree from the private read does not lead from the identity to the cap.
+/// - `Error::ProofMismatch` - If the cap does not hash to its own root.
+pub async fn private_fetch_verified_merkle_proof<H: MerkleHasher>(
+    identity_commitment: &str,
+    lookup_cfg_url: &str,
+    hasher: &H,
+) -> Result<Vec<ProofStep>, Error> {
+    let lookup_cfg = LookupCfg::from_url(lookup_cfg_url).await?;
+    private_fetch_merkle_proof_with_cfg(identity_commitment, &lookup_cfg, Some(hasher)).await
+}
+
+/// Privately fetch the Merkle proofs for many identity commitments using Blyss.
+///
+/// The indices of all the identities are fetched in one private read, and the subtrees on their
+/// paths in another, reading subtrees shared by several identities only once.
+/// The cap is fetched once for the whole batch; it is not cached between calls.
+///
+/// # Arguments
+/// - `identity_commitments` - The identity commitments (as big-endian hex strings) to fetch Merkle proofs for.
+/// - `lookup_cfg_url` - A URL pointing to the JSON lookup configuration (see `LookupCfg`).
+///
+/// # Returns
+/// The proof, or the error fetching it, for each identity commitment, in order.
+/// The outer error is for failures of the whole batch, like the bucket being unreachable.
+/// An identity commitment that is not in the tree gets `Error::IdentityNotFound`.
+pub async fn private_fetch_merkle_proofs(
+    identity_commitments: &[String],
+    lookup_cfg_url: &str,
+) -> Result<Vec<Result<Vec<ProofStep>, Error>>, Error> {
+    let lookup_cfg = LookupCfg::from_url(lookup_cfg_url).await?;
+    private_fetch_merkle_proofs_with_cfg::<Sha256Hasher>(identity_commitments, &lookup_cfg, None)
+        .await
+}
+
+/// Privately fetch the Merkle proofs for many identity commitments using Blyss,
+/// as `private_fetch_merkle_proofs`, and verify each against the cap of the tree.
+///
+/// A proof that does not verify gets the error `private_fetch_verified_merkle_proof` would return.
+pub async fn private_fetch_verified_merkle_proofs<H: MerkleHasher>(
+    identity_commitments: &[String],
+    lookup_cfg_url: &str,
+    hasher: &H,
+) -> Result<Vec<Result<Vec<ProofStep>, Error>>, Error> {
+    let lookup_cfg = LookupCfg::from_url(lookup_cfg_url).await?;
+    private_fetch_merkle_proofs_with_cfg(identity_commitments, &lookup_cfg, Some(hasher)).await
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    use semaphore::poseidon;
+    use actix_web::{web, App, HttpServer};
+    use spiral_server::{
+        bucket::{BucketConfig, Buckets},
+        routes::configure,
+    };
+    use std::collections::HashSet;
 
     fn to_str(value: &U256) -> String {
         format!("0x{}", hex::encode(value.to_be_bytes::<32>()))
     }
 
-    fn verify_proof(input: &str, proof: &[ProofStep], root: &str) {
-        let mut cur_hash = to_u256(&input);
-        for step in proof.iter() {
-            let step_hash = step.u256();
+    const TEST_CFG: LookupCfg = LookupCfg {
+        bucket_url: String::new(),
+        api_key: String::new(),
+        cap_url: String::new(),
+        subtree_height: 3,
+        cap_height: 3,
+        tree_height: 7,
+    };
+
+    /// Build a full SHA-256 tree of the given height, as its levels from the root down.
+    fn build_tree(tree_height: usize) -> Vec<Vec<String>> {
+        let leaves = (0..1u64 << (tree_height - 1))
+            .map(|i| to_str(&U256::from(i * 7 + 1)))
+            .collect();
+        let mut levels: Vec<Vec<String>> = vec![leaves];
+        while levels[0].len() > 1 {
+            let parents = levels[0]
+                .chunks(2)
+                .map(|pair| to_str(&Sha256Hasher.hash2(to_u256(&pair[0]), to_u256(&pair[1]))))
+                .collect();
+            levels.insert(0, parents);
+        }
+        levels
+    }
+
+    /// Get the subtree of the given height rooted at the given node, in the bucket's layout.
+    fn subtree_at(levels: &[Vec<String>], level: usize, idx: usize, height: usize) -> Vec<String> {
+        (0..height)
+            .flat_map(|l| levels[level + l][idx << l..(idx + 1) << l].iter().cloned())
+            .collect()
+    }
+
+    /// Get the path from the given identity index, as a bucket built from `levels` would serve it.
+    fn path_at(levels: &[Vec<String>], identity_idx: usize) -> FetchedPath {
+        let subtrees = get_subtree_indices(&TEST_CFG, identity_idx)
+            .iter()
+            .map(|key| {
+                let (level, idx) = key.split_once('-').unwrap();
+                subtree_at(
+                    levels,
+                    level.parse().unwrap(),
+                    idx.parse().unwrap(),
+                    TEST_CFG.subtree_height,
+                )
+            })
+            .collect();
+        FetchedPath {
+            identity_idx,
+            subtrees,
+        }
+    }
+
+    fn cap_of(levels: &[Vec<String>]) -> Vec<String> {
+        subtree_at(levels, 0, 0, TEST_CFG.cap_height)
+    }
+
+    const PARAMS_JSON: &str = r#"{
+        "n": 2,
+        "nu_1": 6,
+        "nu_2": 2,
+        "p": 256,
+        "q2_bits": 20,
+        "t_gsw": 8,
+        "t_conv": 4,
+        "t_exp_left": 8,
+        "t_exp_right": 8,
+        "instances": 1,
+        "db_item_size": 8192
+    }"#;
+
+    /// Starts a server with a bucket holding the identity indices and subtrees of the given tree,
+    /// which also serves the cap, returning the lookup configuration for it.
+    async fn start_lookup_server(levels: &[Vec<String>]) -> LookupCfg {
+        let buckets = Buckets::new(None);
+        let config = BucketConfig::from_params_json(PARAMS_JSON).unwrap();
+        buckets.create("tree", &config).unwrap();
+        let state = web::Data::new(buckets);
+        let cap_json = serde_json::to_string(&cap_of(levels)).unwrap();
 
-            let new_hash = if step.pos == 0 {
-                poseidon::hash2(step_hash, cur_hash)
-            } else {
-                poseidon::hash2(cur_hash, step_hash)
-            };
+        let server = HttpServer::new(move || {
+            let cap_json = cap_json.clone();
+            App::new()
+                .app_data(state.clone())
+                .app_data(web::PayloadConfig::new(1usize << 32))
+                .route(
+                    "/cap",
+                    web::get().to(move || {
+                        let cap_json = cap_json.clone();
+                        async move { cap_json }
+                    }),
+                )
+                .configure(configure)
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let port = server.addrs()[0].port();
+        tokio::spawn(server.run());
 
-            cur_hash = new_hash;
+        let lookup_cfg = LookupCfg {
+            bucket_url: format!("http://127.0.0.1:{}/tree", port),
+            cap_url: format!("http://127.0.0.1:{}/cap", port),
+            ..TEST_CFG
+        };
+        let leaves = &levels[TEST_CFG.tree_height - 1];
+        let mut kv_pairs = HashMap::new();
+        for (identity_idx, leaf) in leaves.iter().enumerate() {
+            kv_pairs.insert(leaf.clone(), identity_idx.to_string().into_bytes());
+            let path = path_at(levels, identity_idx);
+            for (key, subtree) in get_subtree_indices(&TEST_CFG, identity_idx)
+                .into_iter()
+                .zip(path.subtrees)
+            {
+                kv_pairs.insert(key, serde_json::to_vec(&subtree).unwrap());
+            }
         }
-        assert_eq!(to_str(&cur_hash), root);
+        let client = ApiClient::new(&lookup_cfg.bucket_url, "").await.unwrap();
+        client.write(&kv_pairs).await.unwrap();
+
+        lookup_cfg
     }
 
     #[test]
@@ -344,9 +875,228 @@ mod tests {
 
         let root = "0x205aff5d8fc468b111f6fba374f5ba3bdaf02b37a741fd675fac334350f19880";
         verify_proof(
+            &PoseidonHasher,
             "0x0000000000000000000000000000000000000000000000000000000000000000",
             &sample_proof,
             root,
-        );
+        )
+        .unwrap();
+        assert!(matches!(
+            verify_proof(&PoseidonHasher, root, &sample_proof, root),
+            Err(Error::ProofMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn subtree_keys_are_padded_to_the_number_of_identities() {
+        // identities sharing all their subtrees, and identities sharing none
+        let clustered = [0, 1, 2];
+        let spread = [0, 20, 40];
+        let clustered_keys = get_padded_subtree_keys(&TEST_CFG, 3, &clustered);
+        let spread_keys = get_padded_subtree_keys(&TEST_CFG, 3, &spread);
+        assert_eq!(clustered_keys.len(), spread_keys.len());
+
+        for (idxs, keys) in [(&clustered, &clustered_keys), (&spread, &spread_keys)] {
+            let keys: HashSet<_> = keys.iter().collect();
+            assert_eq!(keys.len(), clustered_keys.len());
+            for identity_idx in idxs.iter() {
+                for key in get_subtree_indices(&TEST_CFG, *identity_idx) {
+                    assert!(keys.contains(&key));
+                }
+            }
+        }
+
+        // identities that were not found still count, and levels with fewer subtrees are full
+        let keys = get_padded_subtree_keys(&TEST_CFG, 8, &[0]);
+        assert_eq!(keys.len(), 8 + 4);
+        assert_eq!(keys.iter().collect::<HashSet<_>>().len(), keys.len());
+    }
+
+    #[test]
+    fn fetched_paths_verify_against_cap() {
+        let levels = build_tree(TEST_CFG.tree_height);
+        let cap = cap_of(&levels);
+        let root = &levels[0][0];
+        for identity_idx in [0, 13, 63] {
+            let leaf = &levels[TEST_CFG.tree_height - 1][identity_idx];
+            let path = path_at(&levels, identity_idx);
+            assert_eq!(path.subtrees.len(), 2);
+            path.verify(&Sha256Hasher, &TEST_CFG, &cap, leaf).unwrap();
+
+            let proof = path.proof(&TEST_CFG, &cap).unwrap();
+            assert_eq!(proof.len(), TEST_CFG.tree_height - 1);
+            verify_proof(&Sha256Hasher, leaf, &proof, root).unwrap();
+        }
+    }
+
+    #[test]
+    fn inconsistent_subtrees_are_detected() {
+        let levels = build_tree(TEST_CFG.tree_height);
+        let cap = cap_of(&levels);
+        let identity_idx = 13;
+        let leaf = &levels[TEST_CFG.tree_height - 1][identity_idx];
+        let keys = get_subtree_indices(&TEST_CFG, identity_idx);
+        let zero = to_str(&U256::ZERO);
+
+        // a sibling on the path, in each subtree
+        let positions = get_subtree_positions(&TEST_CFG, identity_idx);
+        for (i, key) in keys.iter().enumerate() {
+            let mut path = path_at(&levels, identity_idx);
+            let sibling =
+                (1 << (TEST_CFG.subtree_height - 1)) - 1 + (positions[i].idx_within_subtree ^ 1);
+            path.subtrees[i][sibling] = zero.clone();
+            assert!(matches!(
+                path.verify(&Sha256Hasher, &TEST_CFG, &cap, leaf),
+                Err(Error::InconsistentSubtree(k)) if &k == key
+            ));
+        }
+
+        // the wrong identity, or a subtree from elsewhere in the tree
+        let path = path_at(&levels, identity_idx);
+        let other_leaf = &levels[TEST_CFG.tree_height - 1][identity_idx + 1];
+        assert!(matches!(
+            path.verify(&Sha256Hasher, &TEST_CFG, &cap, other_leaf),
+            Err(Error::InconsistentSubtree(k)) if k == keys[0]
+        ));
+        let mut path = path_at(&levels, identity_idx);
+        path.subtrees[1] = path_at(&levels, 63).subtrees[1].clone();
+        assert!(matches!(
+            path.verify(&Sha256Hasher, &TEST_CFG, &cap, leaf),
+            Err(Error::InconsistentSubtree(k)) if k == keys[1]
+        ));
+
+        // a truncated subtree, or a cap that does not hash to its root
+        let mut path = path_at(&levels, identity_idx);
+        path.subtrees[0].pop();
+        assert!(matches!(
+            path.verify(&Sha256Hasher, &TEST_CFG, &cap, leaf),
+            Err(Error::InconsistentSubtree(k)) if k == keys[0]
+        ));
+        let path = path_at(&levels, identity_idx);
+        let mut cap = cap;
+        cap[0] = zero;
+        assert!(matches!(
+            path.verify(&Sha256Hasher, &TEST_CFG, &cap, leaf),
+            Err(Error::ProofMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn malformed_paths_are_rejected() {
+        let levels = build_tree(TEST_CFG.tree_height);
+        let cap = cap_of(&levels);
+        let leaf = &levels[TEST_CFG.tree_height - 1][13];
+
+        let mut path = path_at(&levels, 13);
+        path.identity_idx = 1 << (TEST_CFG.tree_height - 1);
+        assert!(matches!(
+            path.proof(&TEST_CFG, &cap),
+            Err(Error::MalformedResponse(_))
+        ));
+        assert!(matches!(
+            path.verify(&Sha256Hasher, &TEST_CFG, &cap, leaf),
+            Err(Error::MalformedResponse(_))
+        ));
+
+        let mut path = path_at(&levels, 13);
+        path.subtrees[1].truncate(1);
+        let key = get_subtree_indices(&TEST_CFG, 13).remove(1);
+        assert!(matches!(
+            path.proof(&TEST_CFG, &cap),
+            Err(Error::InconsistentSubtree(k)) if k == key
+        ));
+
+        let path = path_at(&levels, 13);
+        assert!(matches!(
+            path.proof(&TEST_CFG, &cap[..1]),
+            Err(Error::MalformedResponse(_))
+        ));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn batch_fetches_share_subtrees() {
+        let levels = build_tree(TEST_CFG.tree_height);
+        let lookup_cfg = start_lookup_server(&levels).await;
+        let cap = cap_of(&levels);
+        let leaves = &levels[TEST_CFG.tree_height - 1];
+
+        let identities = [13, 14, 0, 13, 63];
+        let mut identity_commitments: Vec<_> =
+            identities.iter().map(|i| leaves[*i].clone()).collect();
+        // commitments are normalized before lookup
+        identity_commitments[1] = identity_commitments[1][2..].to_uppercase();
+        identity_commitments.push(to_str(&U256::from(2)));
+
+        let keys: HashSet<_> = identities
+            .iter()
+            .flat_map(|i| get_subtree_indices(&TEST_CFG, *i))
+            .collect();
+        assert!(keys.len() < identities.len() * 2);
+
+        let proofs = private_fetch_merkle_proofs_with_cfg(
+            &identity_commitments,
+            &lookup_cfg,
+            Some(&Sha256Hasher),
+        )
+        .await
+        .unwrap();
+        assert_eq!(proofs.len(), identity_commitments.len());
+        for (identity_idx, proof) in identities.iter().zip(&proofs) {
+            let proof = proof.as_ref().unwrap();
+            let expected = path_at(&levels, *identity_idx)
+                .proof(&TEST_CFG, &cap)
+                .unwrap();
+            assert_eq!(
+                serde_json::to_value(proof).unwrap(),
+                serde_json::to_value(expected).unwrap()
+            );
+            verify_proof(&Sha256Hasher, &leaves[*identity_idx], proof, &levels[0][0]).unwrap();
+        }
+        assert!(matches!(
+            proofs.last().unwrap(),
+            Err(Error::IdentityNotFound(ic)) if ic == &to_str(&U256::from(2))
+        ));
+
+        // a bad subtree only fails the identities below it
+        let bad_key = get_subtree_indices(&TEST_CFG, 63).remove(0);
+        let mut bad_subtree = path_at(&levels, 63).subtrees.remove(0);
+        bad_subtree[0] = to_str(&U256::ZERO);
+        let client = ApiClient::new(&lookup_cfg.bucket_url, "").await.unwrap();
+        client
+            .write(&HashMap::from([(
+                bad_key.clone(),
+                serde_json::to_vec(&bad_subtree).unwrap(),
+            )]))
+            .await
+            .unwrap();
+        let proofs = private_fetch_merkle_proofs_with_cfg(
+            &[leaves[13].clone(), leaves[63].clone()],
+            &lookup_cfg,
+            Some(&Sha256Hasher),
+        )
+        .await
+        .unwrap();
+        assert!(proofs[0].is_ok());
+        assert!(matches!(&proofs[1], Err(Error::InconsistentSubtree(key)) if key == &bad_key));
+
+        // a truncated subtree, or an index outside the tree, fails even without verification
+        bad_subtree.pop();
+        client
+            .write(&HashMap::from([
+                (bad_key.clone(), serde_json::to_vec(&bad_subtree).unwrap()),
+                (leaves[5].clone(), b"64".to_vec()),
+            ]))
+            .await
+            .unwrap();
+        let proofs = private_fetch_merkle_proofs_with_cfg::<Sha256Hasher>(
+            &[leaves[13].clone(), leaves[63].clone(), leaves[5].clone()],
+            &lookup_cfg,
+            None,
+        )
+        .await
+        .unwrap();
+        assert!(proofs[0].is_ok());
+        assert!(matches!(&proofs[1], Err(Error::InconsistentSubtree(key)) if key == &bad_key));
+        assert!(matches!(&proofs[2], Err(Error::MalformedResponse(_))));
     }
 }
@@ -61,6 +61,13 @@ fn post_data_to_server(data: Vec<u8>, server_url: &str) -> Vec<u8> {
         .timeout(Duration::from_secs(360))
         .build()
         .unwrap();
+
+    // a doublepir-server takes the query directly
+    if server_url.ends_with("/answer") {
+        let resp = http_client.post(server_url).body(data).send().unwrap();
+        return resp.error_for_status().unwrap().bytes().unwrap().to_vec();
+    }
+
     let resp = http_client
         .post(server_url)
         .body(Vec::<u8>::new())
@@ -98,23 +105,27 @@ fn main() {
     let bits_per_entry: u64 = args[2].parse().unwrap();
     let data_file_name: String = args[3].parse().unwrap();
     let server_url: String = args[4].parse().unwrap();
-    let password: String = args[5].parse().unwrap();
-    let k: usize = args[6].parse().unwrap();
-    assert_eq!(bits_per_entry, 1);
-
-    // let rng = thread_rng();
-    // let num_queries = 2;
-    // let indices_to_query: Vec<usize> = rng
-    //     .sample_iter::<usize, _>(Standard)
-    //     .take(num_queries)
-    //     .map(|x| x % num_entries)
-    //     .collect();
-    let indices_to_query =
-        get_password_bloom_indices(&password, k, f64::log2(num_entries as f64).floor() as usize);
+
+    // either `--index [index ...]`, to print entries, or `[password] [k]`, to check
+    // a password against a Bloom filter of 1-bit entries
+    let password = if args[5] == "--index" {
+        None
+    } else {
+        assert_eq!(bits_per_entry, 1, "Bloom filters have 1-bit entries");
+        Some(args[5].clone())
+    };
+    let indices_to_query: Vec<u64> = match &password {
+        None => args[6..].iter().map(|i| i.parse().unwrap()).collect(),
+        Some(password) => {
+            let k: usize = args[6].parse().unwrap();
+            get_password_bloom_indices(password, k, f64::log2(num_entries as f64).floor() as usize)
+        }
+    };
     println!("Querying {:?}", indices_to_query);
 
-    let params = DoublePirClient::params_from_file(&format!("{}.params", data_file_name));
-    let dbinfo = DoublePirClient::dbinfo_from_file(&format!("{}.dbinfo", data_file_name));
+    let artifact_file_name = DoublePirServer::artifact_file_name(&data_file_name);
+    let params = DoublePirClient::params_from_file(&artifact_file_name).unwrap();
+    let dbinfo = DoublePirClient::dbinfo_from_file(&artifact_file_name).unwrap();
     let mut client = DoublePirClient::with_params(&params, &dbinfo);
     println!(
         "Loaded. Params: {:?} {:?}",
@@ -123,7 +134,7 @@ fn main() {
     );
 
     // Client loads the hint
-    client.load_hint_from_file(&format!("{}.hint", data_file_name));
+    client.load_hint_from_file(&artifact_file_name).unwrap();
 
     // Client generate the queries
     let (queries, client_states, query_plan) = client.generate_query_batch(&indices_to_query);
@@ -155,13 +166,15 @@ fn main() {
         println!("{:?}", String::from_utf8(answer.clone()).unwrap());
     }
 
-    let answer_data = if server_url.starts_with("http://localhost") {
-        answer
-    } else {
-        general_purpose::STANDARD.decode(&answer).unwrap()
-    };
+    let answer_data =
+        if server_url.starts_with("http://localhost") || server_url.ends_with("/answer") {
+            answer
+        } else {
+            general_purpose::STANDARD.decode(&answer).unwrap()
+        };
 
     // Client decodes the answer
+    let mut in_filter = true;
     for (batch_idx, client_state) in client_states.iter().enumerate() {
         let planned_query = query_plan[batch_idx];
         if planned_query.is_none() {
@@ -174,23 +187,17 @@ fn main() {
 
         println!("retrieved query {} (batch: {})", index_to_query, batch_idx);
 
-        let result = u64::from_ne_bytes(
-            client
-                .decode_response_impl(
-                    &answer_data,
-                    index_to_query_in_batch,
-                    batch_idx,
-                    &client_state,
-                )
-                .as_slice()
-                .try_into()
-                .unwrap(),
+        let result = client.decode_entry(
+            &answer_data,
+            index_to_query_in_batch,
+            batch_idx,
+            &client_state,
         );
+        println!("Got {}", bytes_to_hex_upper(&result));
+        in_filter &= result[0] == 1;
+    }
 
-        let corr_result = 1; //(index_to_query % 2) as u64;
-
-        // Check if correct
-        println!("Got {}, expected {}", result, corr_result);
-        assert_eq!(result, corr_result);
+    if password.is_some() {
+        println!("Password in filter: {}", in_filter);
     }
 }
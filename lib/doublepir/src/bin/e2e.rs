@@ -1,13 +1,14 @@
 use std::{env, ops::AddAssign, time::Instant};
 
 use doublepir_rs::{
+    database::{entry_at, entry_to_bytes, DbStorage},
     doublepir::*,
     matrix::Matrix,
     pir::PirServer,
     serializer::{DeserializeSlice, Serialize},
     util::checksum_u32,
 };
-// use rand::thread_rng;
+use rand::{thread_rng, Rng};
 
 pub fn round_to_multiple(x: u64, delta: u64) -> u64 {
     let v = (x + delta / 2) / delta;
@@ -19,25 +20,19 @@ fn main() {
     let num_entries: u64 = args[1].parse().unwrap();
     let bits_per_entry: u64 = args[2].parse().unwrap();
     let data_file_name: String = args[3].parse().unwrap();
-    assert_eq!(bits_per_entry, 1);
-
-    // let mut rng = thread_rng();
-    // let index_to_query = rng.gen::<usize>() % num_entries;
-    let indices_to_query = vec![
-        42589995014,
-        6766736080,
-        42461265606,
-        29863406032,
-        59769687430,
-        33912068661,
-        37794679486,
-        43925156523,
-    ];
-    let num_chunks = indices_to_query.len();
+
+    let artifact_file_name = DoublePirServer::artifact_file_name(&data_file_name);
+    let params = DoublePirClient::params_from_file(&artifact_file_name).unwrap();
+    let dbinfo = DoublePirClient::dbinfo_from_file(&artifact_file_name).unwrap();
+
+    // each query in the batch needs at least one row of entries
+    let num_chunks = usize::min(8, params.l / dbinfo.ne);
+    let mut rng = thread_rng();
+    let indices_to_query: Vec<u64> = (0..num_chunks)
+        .map(|_| rng.gen::<u64>() % num_entries)
+        .collect();
     println!("Querying {:?}", indices_to_query);
 
-    let params = DoublePirClient::params_from_file(&format!("{}.params", data_file_name));
-    let dbinfo = DoublePirClient::dbinfo_from_file(&format!("{}.dbinfo", data_file_name));
     let mut client = DoublePirClient::with_params(&params, &dbinfo);
     println!(
         "Loaded. Params: {:?} {:?}",
@@ -46,17 +41,22 @@ fn main() {
     );
 
     // Client loads the hint
-    client.load_hint_from_file(&format!("{}.hint", data_file_name));
+    client.load_hint_from_file(&artifact_file_name).unwrap();
 
     // Client generate the queries
     let (queries, client_states, query_plan) = client.generate_query_batch(&indices_to_query);
 
     let query_bytes = queries.serialize();
 
+    // The expected entries
+    let data = std::fs::read(&data_file_name).unwrap();
+
     // Server processes the queries
     let mut server = DoublePirServer::new(num_entries, bits_per_entry as usize);
     println!("{:?} {:?}", *server.params_ref(), *server.dbinfo_ref());
-    server.restore_from_files(&data_file_name, true, true);
+    server
+        .restore_from_files_with(&data_file_name, true, DbStorage::Map)
+        .unwrap();
 
     let start = Instant::now();
     let mut responses = Vec::new();
@@ -64,7 +64,7 @@ fn main() {
         let db_rows = server.db_ref().num_rows();
         let db_cols = server.db_ref().num_cols();
         let total_sz_bytes = db_rows * db_cols * 4;
-        let batch_sz = db_rows / num_chunks;
+        let batch_sz = server.dbinfo_ref().batch_rows(db_rows, num_chunks);
         let batch_sz_bytes = batch_sz * db_cols * 4;
 
         let start = (chunk_idx - 1) * batch_sz_bytes;
@@ -76,12 +76,7 @@ fn main() {
 
         println!("at chunk {} getting slice: {}-{}", chunk_idx, start, end);
 
-        let data_slc_u8 = &server.db_ref().raw_data[start..end];
-        let data_slc = unsafe {
-            let ptr = data_slc_u8.as_ptr() as *const u32;
-            let slice: &[u32] = std::slice::from_raw_parts(ptr, data_slc_u8.len() / 4);
-            slice
-        };
+        let data_slc = &server.db_ref().raw_data.as_u32_slice()[start / 4..end / 4];
 
         println!("checksum u32 {}", checksum_u32(data_slc));
 
@@ -121,23 +116,20 @@ fn main() {
 
         println!("retrieved query {} (batch: {})", index_to_query, batch_idx);
 
-        let result = u64::from_ne_bytes(
-            client
-                .decode_response_impl(
-                    &answer_bytes,
-                    index_to_query_in_batch,
-                    batch_idx,
-                    &client_state,
-                )
-                .as_slice()
-                .try_into()
-                .unwrap(),
+        let result = client.decode_entry(
+            &answer_bytes,
+            index_to_query_in_batch,
+            batch_idx,
+            &client_state,
         );
 
-        let corr_result = 1; //(index_to_query % 2) as u64;
+        let corr_result = entry_to_bytes(
+            entry_at(&data, bits_per_entry, index_to_query as usize),
+            bits_per_entry,
+        );
 
         // Check if correct
-        println!("Got {}, expected {}", result, corr_result);
+        println!("Got {:?}, expected {:?}", result, corr_result);
         assert_eq!(result, corr_result);
     }
 }
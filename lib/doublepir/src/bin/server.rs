@@ -0,0 +1,29 @@
+use std::env;
+
+use actix_web::{web, App, HttpServer};
+use doublepir_rs::http::{configure, AppState};
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    // [num_entries] [bits_per_entry] [data_file_name] [port]
+    let args: Vec<String> = env::args().collect();
+    let num_entries: u64 = args[1].parse().unwrap();
+    let bits_per_entry: usize = args[2].parse().unwrap();
+    let data_file_name: String = args[3].parse().unwrap();
+    let port = args.get(4).map(String::as_str).unwrap_or("8008");
+
+    // expects the files written by `preprocess`
+    let state =
+        web::Data::new(AppState::from_files(num_entries, bits_per_entry, &data_file_name).unwrap());
+    println!("Loaded {}, listening on {}", data_file_name, port);
+
+    HttpServer::new(move || {
+        App::new()
+            .app_data(state.clone())
+            .app_data(web::PayloadConfig::new(1usize << 32))
+            .configure(configure)
+    })
+    .bind(("0.0.0.0", port.parse().unwrap()))?
+    .run()
+    .await
+}
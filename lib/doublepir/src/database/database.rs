@@ -1,23 +1,10 @@
-use std::slice;
-
 use crate::{
     arith::arith::{base_p, reconstruct_from_base_p},
     matrix::{Matrix, MatrixRef, SquishParams, Squishable},
     params::Params,
 };
 
-fn bits_from_byte(byte: u8) -> [u8; 8] {
-    [
-        byte & (1),
-        (byte & (1 << 1)) >> 1,
-        (byte & (1 << 2)) >> 2,
-        (byte & (1 << 3)) >> 3,
-        (byte & (1 << 4)) >> 4,
-        (byte & (1 << 5)) >> 5,
-        (byte & (1 << 6)) >> 6,
-        (byte & (1 << 7)) >> 7,
-    ]
-}
+use super::{entries_from_bytes, RawData};
 
 /// Structure specifying the layout of the database.
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -87,6 +74,20 @@ impl DbInfo {
         assert!(db_elems <= params.l * params.m);
         info
     }
+
+    /// Returns how many of the `num_rows` DB rows each of `num_batches` queries in a
+    /// batch covers; the last query also covers the remaining rows. This is a multiple
+    /// of `ne`, so that no entry is split across two batches.
+    pub fn batch_rows(&self, num_rows: usize, num_batches: usize) -> usize {
+        let batch_rows = num_rows / num_batches / self.ne * self.ne;
+        assert!(
+            batch_rows > 0,
+            "{} rows are too few for {} queries in a batch",
+            num_rows,
+            num_batches
+        );
+        batch_rows
+    }
 }
 
 /// The PIR database and its layout information.
@@ -97,7 +98,7 @@ pub struct Db {
 
     pub db_rows: usize,
     pub db_cols: usize,
-    pub raw_data: Vec<u8>,
+    pub raw_data: RawData,
 }
 
 impl Db {
@@ -113,7 +114,7 @@ impl Db {
             data: dummy,
             db_rows: 0,
             db_cols: 0,
-            raw_data: Vec::new(),
+            raw_data: RawData::default(),
         }
     }
 
@@ -135,11 +136,7 @@ impl Db {
 
     pub fn get_nice_slice(&self) -> &[u32] {
         if self.raw_data.len() > 0 {
-            unsafe {
-                let ptr = self.raw_data.as_ptr() as *const u32;
-                let slice: &[u32] = slice::from_raw_parts(ptr, self.raw_data.len() / 4);
-                slice
-            }
+            self.raw_data.as_u32_slice()
         } else {
             &self.data.data
         }
@@ -165,13 +162,13 @@ impl Db {
     /// The current contents of the database will be completely overridden.
     /// The iterator `data` should yield at least `num_entries` items, each a
     /// value of at most `bits_per_entry` bits.
-    pub fn load_data<I: Iterator<Item = u8>>(
+    pub fn load_data<T: Into<u64>, I: Iterator<Item = T>>(
         &mut self,
         bits_per_entry: u64,
         params: &Params,
         data: I,
     ) {
-        let mut iter = data.enumerate().peekable();
+        let mut iter = data.map(Into::into).enumerate().peekable();
         self.data = Matrix::new(params.l, params.m);
 
         if self.info.packing > 0 {
@@ -196,7 +193,7 @@ impl Db {
                     // let elem = u64::from_ne_bytes(elem_ref.as_slice().try_into().unwrap());
                     let row = (i / params.m) * (self.info.ne as usize) + (j as usize);
                     let col = i % (params.m as usize);
-                    self.data[row][col] = base_p(self.info.p, elem as u64, j as u64) as u32;
+                    self.data[row][col] = base_p(self.info.p, elem, j as u64) as u32;
                 }
             }
         }
@@ -205,52 +202,21 @@ impl Db {
         self.data -= (params.p / 2) as u32;
     }
 
+    /// Loads the database from a file of entries, in the format read by `entries_from_bytes`.
     pub fn load_data_fast(&mut self, bits_per_entry: u64, params: &Params, data_fname: &str) {
         let raw_data = std::fs::read(data_fname).unwrap();
-        let mut iter = raw_data
-            .into_iter()
-            .flat_map(bits_from_byte)
-            .enumerate()
-            .peekable();
-        self.data = Matrix::new(params.l, params.m);
-        println!("allocated");
-
-        if self.info.packing > 0 {
-            // Pack multiple DB elems into each Z_p elem
-            let mut at = 0;
-            let mut cur = 0u32;
-            let mut coeff = 1u32;
-            while let Some((i, elem)) = iter.next() {
-                cur += (elem as u32) * coeff;
-                coeff *= 1 << bits_per_entry;
-                if ((i + 1) % (self.info.packing as usize) == 0) || (iter.peek().is_none()) {
-                    self.data[at / params.m][at % params.m] = cur as u32;
-                    at += 1;
-                    cur = 0;
-                    coeff = 1
-                }
-            }
-        } else {
-            // Use multiple Z_p elems to represent each DB elem
-            for (i, elem) in iter {
-                for j in 0..self.info.ne {
-                    // let elem = u64::from_ne_bytes(elem_ref.as_slice().try_into().unwrap());
-                    let row = (i / params.m) * (self.info.ne as usize) + (j as usize);
-                    let col = i % (params.m as usize);
-                    self.data[row][col] = base_p(self.info.p, elem as u64, j as u64) as u32;
-                }
-            }
-        }
-
-        // Map DB elems to [-p/2; p/2]
-        self.data -= (params.p / 2) as u32;
+        self.load_data(
+            bits_per_entry,
+            params,
+            entries_from_bytes(&raw_data, bits_per_entry),
+        );
     }
 
     /// Creates a new database, filled data from the given iterator.
     ///
     /// The iterator `data` should yield at least `num_entries` items, each a
     /// value of at most `bits_per_entry` bits.
-    pub fn with_data<I: Iterator<Item = u8>>(
+    pub fn with_data<T: Into<u64>, I: Iterator<Item = T>>(
         num_entries: u64,
         bits_per_entry: u64,
         params: &Params,
@@ -261,8 +227,55 @@ impl Db {
         db
     }
 
-    fn _set(&mut self, _: u64, _: u64, _: &Params) {
-        todo!("Eventually add updates");
+    fn get_nice_slice_mut(&mut self) -> &mut [u32] {
+        if !self.raw_data.is_empty() {
+            self.raw_data.as_u32_slice_mut()
+        } else {
+            &mut self.data.data
+        }
+    }
+
+    /// Sets entry `i` of a squished database to `val`.
+    ///
+    /// Returns the Z_p elems that changed, as (row, unsquished col, old value, new value),
+    /// with values in [0, p).
+    pub fn set_entry(&mut self, i: u64, val: u64) -> Vec<(usize, usize, u32, u32)> {
+        assert!(i < self.info.num_entries);
+        assert!(val < (1 << self.info.bits_per_entry));
+        assert!(self.info.orig_cols > 0, "database must be squished");
+
+        let info = self.info;
+        let (delta, basis) = (info.squish_params.delta, info.squish_params.basis as usize);
+        let mask = (1u32 << basis) - 1;
+        let num_cols = self.num_cols();
+        let data = self.get_nice_slice_mut();
+
+        // the index of the entry's first Z_p elem
+        let elem = if info.packing > 0 {
+            (i / info.packing as u64) as usize
+        } else {
+            i as usize
+        };
+        let (row, col) = (elem / info.orig_cols, elem % info.orig_cols);
+        let shift = (col % delta) * basis;
+
+        let mut changes = Vec::with_capacity(info.ne);
+        for j in 0..info.ne {
+            let idx = (row * info.ne + j) * num_cols + col / delta;
+            let old = (data[idx] >> shift) & mask;
+            let new = if info.packing > 0 {
+                let entry_shift = info.bits_per_entry * (i % info.packing as u64);
+                let entry_mask = ((1u64 << info.bits_per_entry) - 1) << entry_shift;
+                ((old as u64 & !entry_mask) | (val << entry_shift)) as u32
+            } else {
+                base_p(info.p, val, j as u64) as u32
+            };
+            if new != old {
+                data[idx] = (data[idx] & !(mask << shift)) | (new << shift);
+                changes.push((row * info.ne + j, col, old, new));
+            }
+        }
+        changes
     }
 
     /// Squishes the database, compressing it for faster processing in-memory.
@@ -306,18 +319,16 @@ impl Db {
     pub fn get_elem(&self, i: usize) -> u64 {
         assert!(i < self.info.num_entries as usize);
 
-        let mut col = i % self.data.cols;
-        let mut row = i / self.data.cols;
-        let mut orig_col = 0;
-
+        // the index of the entry's first Z_p elem
+        let mut new_i = i;
         if self.info.packing > 0 {
-            let new_i = i / self.info.packing as usize;
-            col = new_i % self.data.cols;
-            row = new_i / self.data.cols;
+            new_i = i / self.info.packing as usize;
         }
+        let mut col = new_i % self.data.cols;
+        let mut row = new_i / self.data.cols;
+        let mut orig_col = 0;
 
         if self.info.squish_params.delta > 0 && self.info.orig_cols > 0 {
-            let new_i = i / self.info.packing as usize;
             col = new_i % self.info.orig_cols;
             row = new_i / self.info.orig_cols;
 
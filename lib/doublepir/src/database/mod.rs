@@ -1,3 +1,7 @@
 mod database;
+mod records;
+mod storage;
 
 pub use database::*;
+pub use records::*;
+pub use storage::*;
@@ -0,0 +1,79 @@
+//! The input format for database entries.
+//!
+//! A database file is a stream of `bits_per_entry`-bit entries, packed least
+//! significant bit first. With 1-bit entries, this is a bit array; when
+//! `bits_per_entry` is a multiple of 8, each entry is a fixed-width little-endian
+//! record of `bits_per_entry / 8` bytes.
+
+/// The number of bytes used to return a single entry.
+pub fn bytes_per_entry(bits_per_entry: u64) -> usize {
+    ((bits_per_entry + 7) / 8) as usize
+}
+
+/// Returns the `i`-th entry packed in `data`.
+pub fn entry_at(data: &[u8], bits_per_entry: u64, i: usize) -> u64 {
+    assert!(bits_per_entry > 0 && bits_per_entry < 64);
+    let mut val = 0u64;
+    for b in 0..bits_per_entry as usize {
+        let bit = i * bits_per_entry as usize + b;
+        val |= (((data[bit / 8] >> (bit % 8)) & 1) as u64) << b;
+    }
+    val
+}
+
+/// Iterates over the entries packed in `data`. Trailing bits that do not make up a
+/// whole entry are ignored.
+pub fn entries_from_bytes(data: &[u8], bits_per_entry: u64) -> impl Iterator<Item = u64> + '_ {
+    let num_entries = data.len() * 8 / bits_per_entry as usize;
+    (0..num_entries).map(move |i| entry_at(data, bits_per_entry, i))
+}
+
+/// Packs entries in the format read by `entries_from_bytes`.
+pub fn entries_to_bytes<I: Iterator<Item = u64>>(entries: I, bits_per_entry: u64) -> Vec<u8> {
+    assert!(bits_per_entry > 0 && bits_per_entry < 64);
+    let mut out = Vec::new();
+    let mut bit = 0;
+    for val in entries {
+        for b in 0..bits_per_entry as usize {
+            if bit % 8 == 0 {
+                out.push(0);
+            }
+            out[bit / 8] |= (((val >> b) & 1) as u8) << (bit % 8);
+            bit += 1;
+        }
+    }
+    out
+}
+
+/// Encodes a single entry as `bytes_per_entry` little-endian bytes.
+pub fn entry_to_bytes(val: u64, bits_per_entry: u64) -> Vec<u8> {
+    val.to_le_bytes()[..bytes_per_entry(bits_per_entry)].to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_round_trip_at_any_width() {
+        for bits_per_entry in [1, 3, 8, 12, 16, 33, 63] {
+            let max = (1u64 << bits_per_entry) - 1;
+            let entries: Vec<u64> = (0..50u64)
+                .map(|i| (i * 0x9e37_79b9_7f4a_7c15) & max)
+                .collect();
+            let data = entries_to_bytes(entries.iter().copied(), bits_per_entry);
+            assert_eq!(data.len(), (50 * bits_per_entry as usize + 7) / 8);
+            let decoded: Vec<u64> = entries_from_bytes(&data, bits_per_entry).collect();
+            assert_eq!(decoded[..50], entries[..]);
+        }
+
+        // 1-bit entries are read least significant bit first
+        let bits: Vec<u64> = entries_from_bytes(&[0b0000_0101], 1).collect();
+        assert_eq!(bits, vec![1, 0, 1, 0, 0, 0, 0, 0]);
+        // whole-byte entries are little-endian records
+        let records: Vec<u64> = entries_from_bytes(&[0x34, 0x12, 0x78, 0x56], 16).collect();
+        assert_eq!(records, vec![0x1234, 0x5678]);
+        assert_eq!(entry_to_bytes(0x1234, 16), vec![0x34, 0x12]);
+        assert_eq!(entry_to_bytes(0x1234, 13), vec![0x34, 0x12]);
+    }
+}
@@ -0,0 +1,156 @@
+//! Storage for a preprocessed database restored from disk.
+//!
+//! The database can either be read into memory, or mapped from its artifact file.
+//! Mapping starts instantly regardless of the database size, and the pages are
+//! shared with any other process serving the same file. Mappings are copy-on-write:
+//! `Db::set_entry` only copies the pages it touches, and never changes the file.
+
+use std::{
+    fmt::Debug,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    mem::size_of,
+    ops::Deref,
+    slice,
+};
+
+use memmap2::{MmapMut, MmapOptions};
+
+/// The alignment of mappable data within a file.
+pub const PAGE_SIZE: u64 = 4096;
+
+/// How `DoublePirServer::restore_from_files` loads the database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DbStorage {
+    /// Do not load the database.
+    Skip,
+    /// Read the database into memory.
+    Read,
+    /// Map the database from its file.
+    Map,
+}
+
+/// The squished database, as stored in an artifact file.
+pub enum RawData {
+    Owned(Vec<u32>),
+    Mapped(MmapMut),
+}
+
+impl RawData {
+    /// Reads `len` bytes of `file`, starting at `offset`, into memory.
+    pub fn read(mut file: &File, offset: u64, len: usize) -> io::Result<Self> {
+        assert_eq!(len % size_of::<u32>(), 0);
+        let mut data = vec![0u32; len / size_of::<u32>()];
+        let bytes = unsafe { slice::from_raw_parts_mut(data.as_mut_ptr() as *mut u8, len) };
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(bytes)?;
+        Ok(RawData::Owned(data))
+    }
+
+    /// Maps `len` bytes of `file`, starting at `offset`, copy-on-write.
+    ///
+    /// The offset must be a multiple of `PAGE_SIZE`. The file must not be modified
+    /// while it is mapped.
+    pub fn map(file: &File, offset: u64, len: usize) -> io::Result<Self> {
+        assert_eq!(len % size_of::<u32>(), 0);
+        assert_eq!(offset % PAGE_SIZE, 0);
+        let mmap = unsafe { MmapOptions::new().offset(offset).len(len).map_copy(file)? };
+        // mappings are page-aligned, which is enough for any SIMD load
+        assert_eq!(mmap.as_ptr() as usize % 64, 0);
+        Ok(RawData::Mapped(mmap))
+    }
+
+    pub fn load(file: &File, offset: u64, len: usize, storage: DbStorage) -> io::Result<Self> {
+        match storage {
+            DbStorage::Skip => Ok(Self::default()),
+            DbStorage::Read => Self::read(file, offset, len),
+            DbStorage::Map => Self::map(file, offset, len),
+        }
+    }
+
+    pub fn is_mapped(&self) -> bool {
+        matches!(self, RawData::Mapped(_))
+    }
+
+    pub fn as_u32_slice(&self) -> &[u32] {
+        match self {
+            RawData::Owned(data) => data,
+            RawData::Mapped(mmap) => unsafe {
+                slice::from_raw_parts(mmap.as_ptr() as *const u32, mmap.len() / 4)
+            },
+        }
+    }
+
+    pub fn as_u32_slice_mut(&mut self) -> &mut [u32] {
+        match self {
+            RawData::Owned(data) => data,
+            RawData::Mapped(mmap) => unsafe {
+                slice::from_raw_parts_mut(mmap.as_mut_ptr() as *mut u32, mmap.len() / 4)
+            },
+        }
+    }
+}
+
+impl Default for RawData {
+    fn default() -> Self {
+        RawData::Owned(Vec::new())
+    }
+}
+
+impl Deref for RawData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            RawData::Owned(data) => unsafe {
+                slice::from_raw_parts(data.as_ptr() as *const u8, data.len() * 4)
+            },
+            RawData::Mapped(mmap) => mmap,
+        }
+    }
+}
+
+impl PartialEq for RawData {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_u32_slice() == other.as_u32_slice()
+    }
+}
+
+impl Debug for RawData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RawData")
+            .field("mapped", &self.is_mapped())
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mapped_and_read_data_agree() {
+        let path = std::env::temp_dir().join(format!("doublepir-storage-{}", std::process::id()));
+        let words: Vec<u32> = (0..4096u32).map(|i| i.wrapping_mul(2654435761)).collect();
+        let bytes: Vec<u8> = words.iter().flat_map(|w| w.to_ne_bytes()).collect();
+        let mut contents = vec![0xffu8; PAGE_SIZE as usize];
+        contents.extend(&bytes);
+        std::fs::write(&path, &contents).unwrap();
+
+        let file = File::open(&path).unwrap();
+        let read = RawData::load(&file, PAGE_SIZE, bytes.len(), DbStorage::Read).unwrap();
+        let mut mapped = RawData::load(&file, PAGE_SIZE, bytes.len(), DbStorage::Map).unwrap();
+        assert!(mapped.is_mapped());
+        assert_eq!(read.as_u32_slice(), &words[..]);
+        assert_eq!(&*mapped, &bytes[..]);
+        assert_eq!(read, mapped);
+
+        // writes stay private to the mapping
+        mapped.as_u32_slice_mut()[0] ^= 1;
+        assert_ne!(read, mapped);
+        assert_eq!(std::fs::read(&path).unwrap(), contents);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -4,12 +4,15 @@ use crate::{
     database::*,
     params::Params,
     pir::*,
-    serializer::{DeserializeSlice, Serialize, State},
+    serializer::{Artifact, ArtifactError, DeserializeSlice, Serialize, State},
 };
 
-use crate::doublepir;
+use crate::doublepir::{self, HintDelta};
 
-use std::{fmt::Debug, future::Future};
+use std::{
+    fmt::{Debug, Display},
+    future::Future,
+};
 
 pub struct DoublePirClient {
     num_entries: u64,
@@ -18,8 +21,28 @@ pub struct DoublePirClient {
     shared_state: State,
     db_info: DbInfo,
     hint: State,
+    hint_version: u64,
+}
+
+/// A hint delta that does not apply to the version of the hint the client holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HintVersionMismatch {
+    pub hint_version: u64,
+    pub from_version: u64,
+}
+
+impl Display for HintVersionMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "hint delta applies to version {}, but the hint is at version {}",
+            self.from_version, self.hint_version
+        )
+    }
 }
 
+impl std::error::Error for HintVersionMismatch {}
+
 impl PirClient for DoublePirClient {
     fn new(num_entries: u64, bits_per_entry: usize) -> Self {
         let bits_per_entry_u64 = bits_per_entry as u64;
@@ -39,11 +62,12 @@ impl PirClient for DoublePirClient {
             shared_state,
             db_info,
             hint,
+            hint_version: 0,
         }
     }
 
     fn load_hint(&mut self, hint: &[u8]) {
-        self.hint = State::deserialize(hint);
+        self.load_hint_at_version(hint, 0);
     }
 
     fn generate_query(&self, index: u64) -> (Vec<u8>, Vec<u8>) {
@@ -86,6 +110,7 @@ impl DoublePirClient {
             shared_state,
             db_info: *db_info,
             hint,
+            hint_version: 0,
         }
     }
 
@@ -99,6 +124,7 @@ impl DoublePirClient {
             shared_state,
             db_info: *db_info,
             hint,
+            hint_version: 0,
         }
     }
 
@@ -121,17 +147,69 @@ impl DoublePirClient {
             .collect()
     }
 
-    pub fn load_hint_from_file(&mut self, hint_file_name: &str) {
-        self.hint = State::deserialize(&std::fs::read(hint_file_name).unwrap());
+    /// Loads the hint, and its version, from an artifact written by
+    /// `DoublePirServer::save_to_files`.
+    ///
+    /// Fails if the artifact is corrupted, or is for a different database than this client's.
+    pub fn load_hint_from_file(&mut self, artifact_file_name: &str) -> Result<(), ArtifactError> {
+        let artifact = Artifact::open(artifact_file_name)?;
+        if artifact.params()?.to_string() != self.params.to_string()
+            || artifact.dbinfo()? != self.db_info
+        {
+            return Err(ArtifactError::Mismatch(format!(
+                "{} is for a different database",
+                artifact_file_name
+            )));
+        }
+        let hint = artifact.hint()?;
+        if !doublepir::hint_has_shape(&hint, &self.params, &self.db_info) {
+            return Err(ArtifactError::Mismatch(format!(
+                "the hint in {} does not match its parameters",
+                artifact_file_name
+            )));
+        }
+        self.hint = hint;
+        self.hint_version = artifact.layout()?.hint_version;
+        Ok(())
+    }
+
+    /// Loads a hint, as published by the server at the given hint version.
+    pub fn load_hint_at_version(&mut self, hint: &[u8], version: u64) {
+        self.hint = State::deserialize(hint);
+        self.hint_version = version;
+    }
+
+    pub fn hint_version(&self) -> u64 {
+        self.hint_version
+    }
+
+    /// Applies a hint delta published by the server, which must start from the
+    /// version of the hint this client holds.
+    pub fn apply_hint_delta(&mut self, delta: &HintDelta) -> Result<(), HintVersionMismatch> {
+        if delta.from_version != self.hint_version {
+            return Err(HintVersionMismatch {
+                hint_version: self.hint_version,
+                from_version: delta.from_version,
+            });
+        }
+        doublepir::apply_hint_delta(
+            &mut self.hint,
+            &delta.rows,
+            &delta.diffs,
+            &self.shared_state,
+            &self.params,
+            &self.db_info,
+        );
+        self.hint_version = delta.to_version;
+        Ok(())
     }
 
-    pub fn params_from_file(params_file_name: &str) -> Params {
-        let str_bytes = std::fs::read(params_file_name).unwrap();
-        Params::from_string(std::str::from_utf8(&str_bytes).unwrap())
+    pub fn params_from_file(artifact_file_name: &str) -> Result<Params, ArtifactError> {
+        Artifact::open(artifact_file_name)?.params()
     }
 
-    pub fn dbinfo_from_file(dbinfo_file_name: &str) -> DbInfo {
-        DbInfo::deserialize(&std::fs::read(dbinfo_file_name).unwrap())
+    pub fn dbinfo_from_file(artifact_file_name: &str) -> Result<DbInfo, ArtifactError> {
+        Artifact::open(artifact_file_name)?.dbinfo()
     }
 
     pub fn num_entries(&self) -> u64 {
@@ -171,19 +249,33 @@ impl DoublePirClient {
         result.to_ne_bytes().to_vec()
     }
 
+    /// Decodes the entry at `index` from an answer, as `bytes_per_entry` little-endian bytes.
+    pub fn decode_entry(
+        &self,
+        response: &[u8],
+        index: u64,
+        query_index: usize,
+        client_query_data: &[u8],
+    ) -> Vec<u8> {
+        let result = self.decode_response_impl(response, index, query_index, client_query_data);
+        let val = u64::from_ne_bytes(result.as_slice().try_into().unwrap());
+        entry_to_bytes(val, self.db_info.bits_per_entry)
+    }
+
     pub fn generate_query_plan(&self, indices: &[u64]) -> (Vec<Option<(u64, u64)>>, Vec<u64>) {
         let params = self.params_ref();
         let dbinfo = self.dbinfo_ref();
 
         let batch_num = indices.len();
-        let batch_sz = params.l / batch_num;
-        let batch_sz_words = batch_sz * params.m * dbinfo.packing;
+        let batch_sz = dbinfo.batch_rows(params.l, batch_num);
+        // entries are either packed into one row, or spread over `ne` rows
+        let entries_per_row = params.m * usize::max(dbinfo.packing, 1);
+        let batch_sz_words = batch_sz / dbinfo.ne * entries_per_row;
         let mut query_plan = vec![None; batch_num];
 
         for (query_idx, i) in indices.iter().enumerate() {
-            let db_elem = *i / (dbinfo.packing as u64);
-            let row = db_elem / (params.m as u64);
-            let batch = row / (batch_sz as u64);
+            let row = (*i / entries_per_row as u64) * dbinfo.ne as u64;
+            let batch = u64::min(row / (batch_sz as u64), batch_num as u64 - 1);
             let idx_within_batch = *i;
 
             println!("gave {} batch {} (row = {})", idx_within_batch, batch, row);
@@ -1,6 +1,14 @@
-use std::{future::Future, time::Instant};
-
-use crate::{database::*, info, matrix::*, params::Params, serializer::State, util::*};
+use std::{collections::BTreeMap, future::Future, time::Instant};
+
+use crate::{
+    arith::arith::{base_p, reconstruct_from_base_p},
+    database::*,
+    info,
+    matrix::*,
+    params::Params,
+    serializer::State,
+    util::*,
+};
 
 // Ratio between first-level DB and second-level DB
 const COMP_RATIO: usize = 64;
@@ -72,6 +80,12 @@ where
     vec![a_1, a_2]
 }
 
+/// Returns whether `hint` has the shape `setup` gives the hint for the given database.
+pub fn hint_has_shape(hint: &State, params: &Params, info: &DbInfo) -> bool {
+    let hint_rows = params.n * params.delta() as usize * info.x;
+    hint.len() == 1 && hint[0].rows == hint_rows && hint[0].cols == params.n
+}
+
 /// Returns (server_state, hint)
 pub fn setup(db: &mut Db, shared: &State, params: &Params) -> (State, State) {
     let a_1 = shared[0].clone();
@@ -258,7 +272,7 @@ pub fn answer(
     let mut a_1 = Matrix::new(0, 0);
     let num_queries = queries.len();
     println!("got num_queries: {}", num_queries);
-    let mut batch_sz = db.num_rows() / num_queries;
+    let mut batch_sz = db.info.batch_rows(db.num_rows(), num_queries);
 
     let mut last = 0;
     // selects a column from each batch of rows
@@ -458,6 +472,112 @@ pub fn recover(
     return Db::reconstruct_elem(vals, i, info);
 }
 
+/// A change to the hint, published by the server after updating the database.
+///
+/// Clients holding the hint at version `from_version` can apply it to bring their
+/// hint to `to_version`, instead of downloading the whole hint again.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HintDelta {
+    pub from_version: u64,
+    pub to_version: u64,
+    /// The DB rows whose product with A_1 changed.
+    pub rows: Vec<u64>,
+    /// For each row in `rows`, the change in its expanded product with A_1,
+    /// as a (rows.len(), n * delta) matrix.
+    pub diffs: Matrix,
+}
+
+/// Sets the given (index, value) entries of a squished database, and updates the
+/// server state and hint to match what `setup` would compute from the new data.
+///
+/// Returns the touched DB rows and the change in their expanded product with A_1,
+/// which `apply_hint_delta` applies to a client's hint.
+pub fn update(
+    db: &mut Db,
+    changes: &[(u64, u64)],
+    server: &mut State,
+    hint: &mut State,
+    shared: &State,
+    params: &Params,
+) -> (Vec<u64>, Matrix) {
+    let a_1 = &shared[0];
+
+    // change in DB * A_1, for each touched row
+    let mut row_diffs = BTreeMap::<usize, Vec<u32>>::new();
+    for &(i, val) in changes {
+        for (row, col, old, new) in db.set_entry(i, val) {
+            let d = new.wrapping_sub(old);
+            let row_diff = row_diffs.entry(row).or_insert_with(|| vec![0u32; params.n]);
+            for k in 0..params.n {
+                row_diff[k] = row_diff[k].wrapping_add(d.wrapping_mul(a_1[col][k]));
+            }
+        }
+    }
+
+    // h_1 holds the expanded digits of (DB * A_1)^T, offset by p/2 and squished
+    let h_1 = &mut server[0];
+    let (basis, squishing) = (SquishParams::default().basis, SquishParams::default().delta);
+    let mask = (1u32 << basis) - 1;
+    let delta = params.delta() as usize;
+    let x = db.info.x;
+
+    let mut rows = Vec::with_capacity(row_diffs.len());
+    let mut diffs = Matrix::new(row_diffs.len(), params.n * delta);
+    for (at, (row, row_diff)) in row_diffs.into_iter().enumerate() {
+        let (col, offset) = (row / x, (row % x) * params.n * delta);
+        let (squished_col, shift) = (col / squishing, (col % squishing) as u64 * basis);
+        for k in 0..params.n {
+            let idxs: Vec<usize> = (0..delta)
+                .map(|f| (offset + k * delta + f) * h_1.cols + squished_col)
+                .collect();
+            let digits: Vec<u64> = idxs
+                .iter()
+                .map(|&idx| ((h_1.data[idx] >> shift) & mask) as u64)
+                .collect();
+            let old = reconstruct_from_base_p(params.p, &digits) as u32;
+            let new = old.wrapping_add(row_diff[k]) as u64;
+            for (f, &idx) in idxs.iter().enumerate() {
+                let digit = base_p(params.p, new, f as u64) as u32;
+                diffs[at][k * delta + f] = digit.wrapping_sub(digits[f] as u32);
+                h_1.data[idx] = (h_1.data[idx] & !(mask << shift)) | (digit << shift);
+            }
+        }
+        rows.push(row as u64);
+    }
+
+    apply_hint_delta(hint, &rows, &diffs, shared, params, &db.info);
+    (rows, diffs)
+}
+
+/// Adds the change in the expanded product of the given DB rows with A_1 to the hint.
+pub fn apply_hint_delta(
+    offline: &mut State,
+    rows: &[u64],
+    diffs: &Matrix,
+    shared: &State,
+    params: &Params,
+    info: &DbInfo,
+) {
+    assert_eq!(rows.len(), diffs.rows);
+    let h_2 = &mut offline[0];
+    let a_2 = &shared[1];
+    let nd = params.n * params.delta() as usize;
+    assert_eq!(diffs.cols, nd);
+
+    for (at, &row) in rows.iter().enumerate() {
+        let (col, offset) = (row as usize / info.x, (row as usize % info.x) * nd);
+        for r in 0..nd {
+            let d = diffs[at][r];
+            if d == 0 {
+                continue;
+            }
+            for k in 0..params.n {
+                h_2[offset + r][k] = h_2[offset + r][k].wrapping_add(d.wrapping_mul(a_2[col][k]));
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::{ops::AddAssign, time::Instant};
@@ -465,6 +585,11 @@ mod tests {
     use rand::{distributions::Standard, thread_rng, Rng};
 
     use super::*;
+    use crate::{
+        doublepir::{DoublePirClient, DoublePirServer, HintVersionMismatch},
+        pir::{PirClient, PirServer},
+        serializer::{DeserializeSlice, Serialize},
+    };
 
     #[test]
     fn simple_end_to_end_test() {
@@ -523,6 +648,139 @@ mod tests {
         assert_eq!(result as u8, corr_val);
     }
 
+    /// Queries a random entry of a random database of the given shape.
+    fn end_to_end_with_width(num_entries: usize, bits_per_entry: u64) {
+        let max = (1u64 << bits_per_entry) - 1;
+        let vals: Vec<u64> = thread_rng()
+            .sample_iter(Standard)
+            .map(|x: u64| x & max)
+            .take(num_entries)
+            .collect();
+        let index_to_query = thread_rng().gen::<usize>() % num_entries;
+
+        let params = pick_params(num_entries as u64, bits_per_entry, SEC_PARAM, LOGQ);
+        let mut db = Db::with_data(
+            num_entries as u64,
+            bits_per_entry,
+            &params,
+            vals.iter().copied(),
+        );
+        println!("params: {:?} info: {:?}", params, db.info);
+
+        let shared_state = init(&db.info, &params);
+        let (server_state, hint) = setup(&mut db, &shared_state, &params);
+        assert_eq!(db.get_elem(index_to_query), vals[index_to_query]);
+
+        let (client_state, query) = query(index_to_query as u64, &shared_state, &params, &db.info);
+        let answer = answer(
+            &db,
+            &vec![query.clone()],
+            &server_state,
+            &shared_state,
+            &params,
+            None,
+            None,
+        );
+        let result = recover(
+            index_to_query as u64,
+            0,
+            &hint,
+            &query,
+            &answer,
+            &shared_state,
+            &client_state,
+            &params,
+            &db.info,
+        );
+        assert_eq!(result, vals[index_to_query]);
+    }
+
+    #[test]
+    fn entries_narrower_than_plaintext_modulus() {
+        for bits_per_entry in [2, 4] {
+            end_to_end_with_width(1 << 20, bits_per_entry);
+        }
+    }
+
+    #[test]
+    fn entries_wider_than_plaintext_modulus() {
+        for bits_per_entry in [16, 32] {
+            end_to_end_with_width(1 << 18, bits_per_entry);
+        }
+    }
+
+    /// Updates a few entries of a server, and checks that the result matches a fresh setup
+    /// over the new data, and that a client applying the published delta reads the new values.
+    fn update_with_width(num_entries: usize, bits_per_entry: u64) {
+        let max = (1u64 << bits_per_entry) - 1;
+        let mut vals: Vec<u64> = thread_rng()
+            .sample_iter(Standard)
+            .map(|x: u64| x & max)
+            .take(num_entries)
+            .collect();
+
+        let mut server = DoublePirServer::new(num_entries as u64, bits_per_entry as usize);
+        server.load_data(vals.iter().copied());
+        let mut client = DoublePirClient::with_params(server.params_ref(), server.dbinfo_ref());
+        client.load_hint(&server.get_hint());
+
+        // neighbouring entries share a DB row
+        let mut rng = thread_rng();
+        let i = rng.gen::<u64>() % (num_entries as u64 - 1);
+        let changes = vec![
+            (i, !vals[i as usize] & max),
+            (i + 1, rng.gen::<u64>() & max),
+            (
+                rng.gen::<u64>() % num_entries as u64,
+                rng.gen::<u64>() & max,
+            ),
+        ];
+        for &(i, val) in changes.iter() {
+            vals[i as usize] = val;
+        }
+
+        let delta = server.update(&changes);
+        assert_eq!((delta.from_version, delta.to_version), (0, 1));
+        let delta = HintDelta::deserialize(&delta.serialize());
+        client.apply_hint_delta(&delta).unwrap();
+        assert_eq!(client.hint_version(), 1);
+        assert_eq!(
+            client.apply_hint_delta(&delta),
+            Err(HintVersionMismatch {
+                hint_version: 1,
+                from_version: 0
+            })
+        );
+
+        let mut fresh = DoublePirServer::new(num_entries as u64, bits_per_entry as usize);
+        fresh.load_data(vals.iter().copied());
+        assert_eq!(server.db_ref().data, fresh.db_ref().data);
+        assert_eq!(server.server_state_ref(), fresh.server_state_ref());
+        assert_eq!(server.get_hint(), fresh.get_hint());
+
+        for &(i, _) in changes.iter() {
+            let (queries, client_states, query_plan) = client.generate_query_batch(&[i]);
+            let response = server.answer(&queries.serialize());
+            let (_, index_in_batch) = query_plan[0].unwrap();
+            let result =
+                client.decode_response_impl(&response, index_in_batch, 0, &client_states[0]);
+            assert_eq!(
+                u64::from_ne_bytes(result.try_into().unwrap()),
+                vals[i as usize]
+            );
+        }
+    }
+
+    #[test]
+    fn updates_to_packed_entries_match_fresh_setup() {
+        update_with_width(1 << 20, 1);
+    }
+
+    #[test]
+    fn updates_to_wide_entries_match_fresh_setup() {
+        update_with_width(1 << 18, 16);
+    }
+
     #[test]
     fn batched_end_to_end_test() {
         let num_entries = 1 << 24;
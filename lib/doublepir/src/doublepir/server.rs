@@ -4,16 +4,10 @@ use crate::{
     matrix::Matrix,
     params::Params,
     pir::*,
-    serializer::{Deserialize, DeserializeSlice, Serialize, State},
+    serializer::{Artifact, ArtifactError, DbLayout, DeserializeSlice, Serialize, State},
 };
 
-use std::{
-    fmt::Debug,
-    fs::{self, File},
-    io::Write,
-    slice,
-    time::Instant,
-};
+use std::{fmt::Debug, time::Instant};
 
 pub struct DoublePirServer {
     num_entries: u64,
@@ -23,6 +17,7 @@ pub struct DoublePirServer {
     db: Db,
     pub server_state: State,
     hint: State,
+    hint_version: u64,
     pub adjustments: Vec<u32>,
 }
 
@@ -39,6 +34,11 @@ impl DoublePirServer {
         &self.hint
     }
 
+    /// The version of the hint, bumped by every `update`.
+    pub fn hint_version(&self) -> u64 {
+        self.hint_version
+    }
+
     pub fn params_ref(&self) -> &Params {
         &self.params
     }
@@ -47,15 +47,9 @@ impl DoublePirServer {
         &self.db.info
     }
 
-    pub fn get_file_names(fname_base: &str) -> (String, String, String, String, String, String) {
-        (
-            format!("{}.hint", &fname_base),
-            format!("{}.state", &fname_base),
-            format!("{}.dbp", &fname_base),
-            format!("{}.dbinfo", &fname_base),
-            format!("{}.params", &fname_base),
-            format!("{}.txt", &fname_base),
-        )
+    /// The name of the artifact written by `save_to_files`.
+    pub fn artifact_file_name(fname_base: &str) -> String {
+        format!("{}.dpir", fname_base)
     }
 
     pub fn restore_from_files(
@@ -63,98 +57,103 @@ impl DoublePirServer {
         fname_base: &str,
         load_server_state: bool,
         load_db_data: bool,
-    ) {
-        let (hint_fname, server_state_fname, db_fname, dbinfo_fname, _params_fname, txt_fname) =
-            Self::get_file_names(fname_base);
-
-        println!("1");
-        std::io::stdout().flush().unwrap();
-
-        self.hint = State::deserialize(&std::fs::read(hint_fname).unwrap());
-        println!("2");
-        std::io::stdout().flush().unwrap();
+    ) -> Result<(), ArtifactError> {
+        let db_storage = if load_db_data {
+            DbStorage::Read
+        } else {
+            DbStorage::Skip
+        };
+        self.restore_from_files_with(fname_base, load_server_state, db_storage)
+    }
 
-        if load_server_state {
-            self.server_state = State::deserialize_iter(
-                &mut std::fs::read(server_state_fname).unwrap().into_iter(),
-            );
+    /// Restores a server saved by `save_to_files`, loading the database as given by `db_storage`.
+    ///
+    /// Fails if the artifact is corrupted, or was not preprocessed for this server's
+    /// number of entries and bits per entry.
+    pub fn restore_from_files_with(
+        &mut self,
+        fname_base: &str,
+        load_server_state: bool,
+        db_storage: DbStorage,
+    ) -> Result<(), ArtifactError> {
+        let artifact = Artifact::open(Self::artifact_file_name(fname_base))?;
+
+        // the parameters are picked from the database shape
+        let params = artifact.params()?;
+        let dbinfo = artifact.dbinfo()?;
+        if params.to_string() != self.params.to_string()
+            || dbinfo.num_entries != self.num_entries
+            || dbinfo.bits_per_entry != self.bits_per_entry as u64
+        {
+            return Err(ArtifactError::Mismatch(format!(
+                "{} was not preprocessed for {} entries of {} bits",
+                fname_base, self.num_entries, self.bits_per_entry
+            )));
         }
 
-        println!("3");
-        std::io::stdout().flush().unwrap();
-
-        let dbinfo = DbInfo::deserialize(&std::fs::read(dbinfo_fname).unwrap());
-        println!("4");
-        std::io::stdout().flush().unwrap();
-
-        let raw_data = if load_db_data {
-            // let f = File::open(db_fname).unwrap();
-            // let mut reader = BufReader::new(f);
-            // let mut b: [u8; 4] = unsafe { mem::uninitialized() };
-            // reader.bytes().map.collect()
-            let start = Instant::now();
-            let val = fs::read(db_fname).unwrap();
-            println!("load took: {} us", start.elapsed().as_micros());
-            val
+        let layout = artifact.layout()?;
+        let hint = artifact.hint()?;
+        if !hint_has_shape(&hint, &self.params, &dbinfo) {
+            return Err(ArtifactError::Mismatch(format!(
+                "the hint in {} does not match its parameters",
+                fname_base
+            )));
+        }
+        let server_state = if load_server_state {
+            let server_state = artifact.server_state()?;
+            if server_state.len() != 2 || server_state[0].rows != hint[0].rows {
+                return Err(ArtifactError::Mismatch(format!(
+                    "the server state in {} does not match its hint",
+                    fname_base
+                )));
+            }
+            server_state
         } else {
-            Vec::new()
+            State::new()
         };
-        println!("5");
-        std::io::stdout().flush().unwrap();
-
-        let txt_bytes = std::fs::read(txt_fname).unwrap();
-        println!("6");
-        std::io::stdout().flush().unwrap();
 
-        let txt_val = std::str::from_utf8(&txt_bytes).unwrap();
-        println!("7");
-        std::io::stdout().flush().unwrap();
-
-        let parts: Vec<&str> = txt_val.split(",").collect();
-        let db_rows = parts[0].parse::<usize>().unwrap();
-        let db_cols = parts[1].parse::<usize>().unwrap();
-        println!("8");
-        std::io::stdout().flush().unwrap();
+        let start = Instant::now();
+        let raw_data = artifact.data(db_storage)?;
+        println!("load took: {} us", start.elapsed().as_micros());
+        if db_storage != DbStorage::Skip && raw_data.len() != layout.rows * layout.cols * 4 {
+            return Err(ArtifactError::Mismatch(format!(
+                "the data in {} does not match its layout",
+                fname_base
+            )));
+        }
 
+        self.hint = hint;
+        self.hint_version = layout.hint_version;
+        if load_server_state {
+            self.server_state = server_state;
+        }
         // don't want to do a copy here
         self.db = Db {
             info: dbinfo,
             data: Matrix::new(1, 1),
-            db_rows,
-            db_cols,
+            db_rows: layout.rows,
+            db_cols: layout.cols,
             raw_data,
-        }
+        };
+        Ok(())
     }
 
-    pub fn save_to_files(&mut self, fname_base: &str) {
-        let (hint_fname, server_state_fname, db_fname, dbinfo_fname, params_fname, txt_fname) =
-            Self::get_file_names(fname_base);
-
-        let mut out_file = File::create(&hint_fname).unwrap();
-        out_file.write_all(&self.hint.serialize()).unwrap();
-
-        let mut out_file = File::create(&server_state_fname).unwrap();
-        out_file.write_all(&self.server_state.serialize()).unwrap();
-
-        let mut out_file = File::create(&dbinfo_fname).unwrap();
-        out_file.write_all(&self.db.info.serialize()).unwrap();
-
-        let mut out_file = File::create(&params_fname).unwrap();
-        out_file
-            .write_all(&self.params.to_string().as_bytes())
-            .unwrap();
-
-        let mut out_file = File::create(&db_fname).unwrap();
-        let data = unsafe {
-            let ptr = self.db.data.data.as_ptr() as *const u8;
-            let slice: &[u8] = slice::from_raw_parts(ptr, self.db.data.data.len() * 4);
-            slice
+    /// Saves the server to a single artifact, named by `artifact_file_name`.
+    pub fn save_to_files(&self, fname_base: &str) -> Result<(), ArtifactError> {
+        let layout = DbLayout {
+            rows: self.db.num_rows(),
+            cols: self.db.num_cols(),
+            hint_version: self.hint_version,
         };
-        out_file.write_all(&data).unwrap();
-
-        let mut out_file = File::create(&txt_fname).unwrap();
-        write!(out_file, "{},{}", self.db.data.rows, self.db.data.cols).unwrap();
-        out_file.flush().unwrap();
+        Artifact::write(
+            Self::artifact_file_name(fname_base),
+            &self.params,
+            &self.db.info,
+            &layout,
+            &self.hint,
+            &self.server_state,
+            self.db.get_nice_slice(),
+        )
     }
 
     pub fn load_data_fast(&mut self, data_fname: &str) {
@@ -162,6 +161,7 @@ impl DoublePirServer {
             .load_data_fast(self.bits_per_entry as u64, &self.params, data_fname);
         println!("loaded!");
         (self.server_state, self.hint) = setup(&mut self.db, &self.shared_state, &self.params);
+        self.hint_version = 0;
     }
 
     pub fn answer_inline(&self, query: &[u8], data: &[u32], chunk_idx: Option<usize>) -> Vec<u8> {
@@ -179,6 +179,31 @@ impl DoublePirServer {
         response.serialize()
     }
 
+    /// Sets the given (index, value) entries of the database, and updates the server
+    /// state and hint to match.
+    ///
+    /// Returns the delta clients apply to their hint with `DoublePirClient::apply_hint_delta`.
+    /// A restored server must have loaded its server state and DB data.
+    pub fn update(&mut self, changes: &[(u64, u64)]) -> HintDelta {
+        assert!(!self.server_state.is_empty(), "server state is not loaded");
+        let (rows, diffs) = update(
+            &mut self.db,
+            changes,
+            &mut self.server_state,
+            &mut self.hint,
+            &self.shared_state,
+            &self.params,
+        );
+        let from_version = self.hint_version;
+        self.hint_version += 1;
+        HintDelta {
+            from_version,
+            to_version: self.hint_version,
+            rows,
+            diffs,
+        }
+    }
+
     pub fn generate_adjustments(params: &Params, shared_state: &State) -> Vec<u32> {
         let mut out = Vec::new();
         let ratio = params.p / 2;
@@ -217,15 +242,17 @@ impl PirServer for DoublePirServer {
             shared_state,
             server_state,
             hint,
+            hint_version: 0,
             adjustments,
         }
     }
 
-    fn load_data<I: Iterator<Item = u8>>(&mut self, data: I) {
+    fn load_data<T: Into<u64>, I: Iterator<Item = T>>(&mut self, data: I) {
         self.db
             .load_data(self.bits_per_entry as u64, &self.params, data);
         println!("loaded!");
         (self.server_state, self.hint) = setup(&mut self.db, &self.shared_state, &self.params);
+        self.hint_version = 0;
     }
 
     fn get_hint(&self) -> Vec<u8> {
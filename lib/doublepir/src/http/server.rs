@@ -0,0 +1,275 @@
+//! HTTP routes serving a preprocessed DoublePIR database.
+//!
+//! - `GET /meta` returns the number of entries and bits per entry, the parameters
+//!   (in the form written to `.params`) and the base64-encoded `DbInfo`, which a
+//!   client passes to `DoublePirClient::with_params`.
+//! - `GET /hint` returns the serialized hint.
+//! - `POST /answer` takes a serialized query batch and returns the serialized answer, or
+//!   400 Bad Request if the body is not a query batch of the shape the database expects.
+
+use std::fmt::Display;
+
+use actix_web::{error::ErrorBadRequest, get, post, web, HttpResponse};
+use base64::{engine::general_purpose, Engine};
+use serde_json::json;
+
+use crate::{
+    database::DbStorage,
+    doublepir::DoublePirServer,
+    pir::PirServer,
+    serializer::{ArtifactError, Serialize},
+};
+
+/// A request body that is not a serialized batch of DoublePIR queries of the shape the
+/// database expects.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidQuery(pub String);
+
+impl Display for InvalidQuery {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidQuery {}
+
+pub struct AppState {
+    server: DoublePirServer,
+    hint: Vec<u8>,
+    meta: String,
+}
+
+impl AppState {
+    pub fn new(server: DoublePirServer) -> Self {
+        let hint = server.get_hint();
+        let dbinfo = server.dbinfo_ref();
+        let meta = json!({
+            "scheme": "doublepir",
+            "num_entries": dbinfo.num_entries,
+            "bits_per_entry": dbinfo.bits_per_entry,
+            "params": server.params_ref().to_string(),
+            "dbinfo": general_purpose::STANDARD.encode(server.dbinfo_ref().serialize()),
+        })
+        .to_string();
+        Self { server, hint, meta }
+    }
+
+    /// Loads a database saved by `DoublePirServer::save_to_files`, mapping it from its file.
+    pub fn from_files(
+        num_entries: u64,
+        bits_per_entry: usize,
+        fname_base: &str,
+    ) -> Result<Self, ArtifactError> {
+        let mut server = DoublePirServer::new(num_entries, bits_per_entry);
+        server.restore_from_files_with(fname_base, true, DbStorage::Map)?;
+        Ok(Self::new(server))
+    }
+
+    pub fn server(&self) -> &DoublePirServer {
+        &self.server
+    }
+
+    pub fn hint(&self) -> &[u8] {
+        &self.hint
+    }
+
+    /// The JSON served at `/meta`.
+    pub fn meta(&self) -> &str {
+        &self.meta
+    }
+
+    /// Checks that `query` is a serialized batch of DoublePIR queries of the shape this
+    /// database expects, since the DoublePIR deserializer panics on malformed input.
+    pub fn check_query(&self, query: &[u8]) -> Result<(), InvalidQuery> {
+        let params = self.server.params_ref();
+        let info = self.server.dbinfo_ref();
+        let delta = info.squish_params.delta;
+
+        let round_up = |n: usize| (n + delta - 1) / delta * delta;
+
+        // the (rows, cols) of each matrix in a query
+        let mut dims = vec![(round_up(params.m), 1)];
+        let query2_dims = (round_up(params.l / info.x), 1);
+        dims.resize(1 + info.ne / info.x, query2_dims);
+        let query_bytes = 4 + dims.iter().map(|(r, c)| 8 + 4 * r * c).sum::<usize>();
+
+        let num_queries = query.len().saturating_sub(4) / query_bytes;
+        let max_queries = self.server.db_ref().num_rows() / info.ne;
+        if num_queries == 0
+            || num_queries > max_queries
+            || query.len() != 4 + num_queries * query_bytes
+        {
+            return Err(InvalidQuery(format!(
+                "bad doublepir query length {}",
+                query.len()
+            )));
+        }
+
+        let read_u32 = |offset: usize| {
+            u32::from_be_bytes(query[offset..offset + 4].try_into().unwrap()) as usize
+        };
+        let mut well_formed = read_u32(0) == num_queries;
+        for i in 0..num_queries {
+            let mut offset = 4 + i * query_bytes;
+            well_formed &= read_u32(offset) == dims.len();
+            offset += 4;
+            for (rows, cols) in dims.iter() {
+                well_formed &= read_u32(offset) == *rows && read_u32(offset + 4) == *cols;
+                offset += 8 + 4 * rows * cols;
+            }
+        }
+        if !well_formed {
+            return Err(InvalidQuery("bad doublepir query shape".to_owned()));
+        }
+        Ok(())
+    }
+
+    /// Answers a serialized batch of DoublePIR queries, after checking it with `check_query`.
+    pub fn answer(&self, query: &[u8]) -> Result<Vec<u8>, InvalidQuery> {
+        self.check_query(query)?;
+        let raw_data = &self.server.db_ref().raw_data;
+        if raw_data.is_empty() {
+            return Ok(self.server.answer(query));
+        }
+
+        // restored databases are only held as raw data
+        Ok(self
+            .server
+            .answer_inline(query, raw_data.as_u32_slice(), None))
+    }
+}
+
+#[get("/meta")]
+async fn get_meta(state: web::Data<AppState>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(state.meta.clone())
+}
+
+#[get("/hint")]
+async fn get_hint(state: web::Data<AppState>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("application/octet-stream")
+        .body(state.hint.clone())
+}
+
+#[post("/answer")]
+async fn post_answer(
+    body: web::Bytes,
+    state: web::Data<AppState>,
+) -> actix_web::Result<HttpResponse> {
+    let response = web::block(move || state.answer(&body))
+        .await?
+        .map_err(ErrorBadRequest)?;
+    Ok(HttpResponse::Ok()
+        .content_type("application/octet-stream")
+        .body(response))
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_meta).service(get_hint).service(post_answer);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use actix_web::{http::StatusCode, test, App};
+    use rand::{distributions::Standard, thread_rng, Rng};
+
+    use super::*;
+    use crate::{
+        database::DbInfo,
+        doublepir::DoublePirClient,
+        params::Params,
+        pir::PirClient,
+        serializer::{DeserializeSlice, Serialize},
+    };
+
+    #[actix_web::test]
+    async fn restored_server_answers_client_queries() {
+        let num_entries = 1 << 24;
+        let bits_per_entry = 1;
+
+        let vals: Vec<u8> = thread_rng()
+            .sample_iter(Standard)
+            .map(|x: u8| x % 2)
+            .take(num_entries)
+            .collect();
+        let mut server = DoublePirServer::new(num_entries as u64, bits_per_entry);
+        server.load_data(vals.iter().copied());
+
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let dir = std::env::temp_dir().join(format!("doublepir-server-{}", nanos));
+        std::fs::create_dir_all(&dir).unwrap();
+        let fname_base = dir.join("db").to_str().unwrap().to_owned();
+        server.save_to_files(&fname_base).unwrap();
+
+        let state = AppState::from_files(num_entries as u64, bits_per_entry, &fname_base).unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(state))
+                .app_data(web::PayloadConfig::new(1 << 30))
+                .configure(configure),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/meta").to_request();
+        let meta: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(meta["scheme"], "doublepir");
+        assert_eq!(meta["num_entries"], num_entries);
+        let params = Params::from_string(meta["params"].as_str().unwrap());
+        let dbinfo = DbInfo::deserialize(
+            &general_purpose::STANDARD
+                .decode(meta["dbinfo"].as_str().unwrap())
+                .unwrap(),
+        );
+        let mut client = DoublePirClient::with_params(&params, &dbinfo);
+
+        let req = test::TestRequest::get().uri("/hint").to_request();
+        let hint = test::call_and_read_body(&app, req).await;
+        client.load_hint(&hint);
+
+        let index = thread_rng().gen::<u64>() % num_entries as u64;
+        let (queries, client_states, query_plan) = client.generate_query_batch(&[index]);
+        let req = test::TestRequest::post()
+            .uri("/answer")
+            .set_payload(queries.serialize())
+            .to_request();
+        let answer = test::call_and_read_body(&app, req).await;
+
+        let (_, index_in_batch) = query_plan[0].unwrap();
+        let result = client.decode_response_impl(&answer, index_in_batch, 0, &client_states[0]);
+        let result = u64::from_ne_bytes(result.as_slice().try_into().unwrap());
+        assert_eq!(result, vals[index as usize] as u64);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn malformed_queries_are_rejected() {
+        let num_entries = 1 << 10;
+        let mut server = DoublePirServer::new(num_entries, 1);
+        server.load_data((0..num_entries).map(|i| (i % 2) as u8));
+        let state = AppState::new(server);
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(state))
+                .app_data(web::PayloadConfig::new(1 << 30))
+                .configure(configure),
+        )
+        .await;
+
+        for body in [vec![], vec![0; 10], vec![0xff; 1 << 16]] {
+            let req = test::TestRequest::post()
+                .uri("/answer")
+                .set_payload(body)
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        }
+    }
+}
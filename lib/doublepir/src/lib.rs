@@ -1,6 +1,8 @@
 pub mod arith;
 pub mod database;
 pub mod doublepir;
+#[cfg(feature = "server")]
+pub mod http;
 pub mod matrix;
 pub mod params;
 pub mod pir;
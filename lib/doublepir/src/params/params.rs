@@ -34,22 +34,33 @@ impl Params {
         )
     }
 
+    /// Parses parameters written by `to_string`.
+    ///
+    /// Panics if `inp_str` is malformed; see `try_from_string`.
     pub fn from_string(inp_str: &str) -> Self {
+        Self::try_from_string(inp_str).unwrap()
+    }
+
+    /// Parses parameters written by `to_string`, or returns `None` if `inp_str` is malformed.
+    pub fn try_from_string(inp_str: &str) -> Option<Self> {
         let mut inp = inp_str.split(",");
-        let n = inp.next().unwrap().parse().unwrap();
-        let sigma = inp.next().unwrap().parse().unwrap();
-        let l = inp.next().unwrap().parse().unwrap();
-        let m = inp.next().unwrap().parse().unwrap();
-        let logq = inp.next().unwrap().parse().unwrap();
-        let p = inp.next().unwrap().parse().unwrap();
-        Self {
+        let n = inp.next()?.parse().ok()?;
+        let sigma = inp.next()?.parse().ok()?;
+        let l = inp.next()?.parse().ok()?;
+        let m = inp.next()?.parse().ok()?;
+        let logq = inp.next()?.parse().ok()?;
+        let p = inp.next()?.parse().ok()?;
+        if inp.next().is_some() {
+            return None;
+        }
+        Some(Self {
             n,
             sigma,
             l,
             m,
             logq,
             p,
-        }
+        })
     }
 
     pub fn zero() -> Self {
@@ -1,6 +1,6 @@
 pub trait PirServer {
     fn new(num_entries: u64, bits_per_entry: usize) -> Self;
-    fn load_data<'a, I: Iterator<Item = u8>>(&mut self, data: I);
+    fn load_data<T: Into<u64>, I: Iterator<Item = T>>(&mut self, data: I);
     fn get_hint(&self) -> Vec<u8>;
     fn answer(&self, query: &[u8]) -> Vec<u8>;
 }
@@ -0,0 +1,449 @@
+//! The file format for a preprocessed DoublePIR database.
+//!
+//! An artifact is a single file holding everything needed to restore a server, or
+//! to set up a client:
+//!
+//! - a header, with the magic bytes `DPIR`, the format version, and a table giving
+//!   the tag, offset, length and `checksum_fnv64` of each section;
+//! - the `Params`, as written by `Params::to_string`;
+//! - the serialized `DbInfo`;
+//! - the layout of the squished database, and the version of the hint;
+//! - the serialized hint and server state;
+//! - the squished database, as native-endian `u32`s. This section starts at a
+//!   multiple of `PAGE_SIZE`, so that it can be mapped.
+//!
+//! Integers in the header and layout are big-endian, as in the rest of the serializer.
+
+use std::{
+    fmt::Display,
+    fs::File,
+    io::{self, BufWriter, Read, Seek, SeekFrom, Write},
+    path::Path,
+    slice,
+};
+
+use crate::{
+    database::{DbInfo, DbStorage, RawData, PAGE_SIZE},
+    params::Params,
+    util::checksum_fnv64,
+};
+
+use super::{DeserializeSlice, Serialize, State};
+
+pub const ARTIFACT_MAGIC: &[u8; 4] = b"DPIR";
+pub const ARTIFACT_VERSION: u32 = 1;
+
+/// A section of an artifact.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Section {
+    Params,
+    DbInfo,
+    Layout,
+    Hint,
+    ServerState,
+    Data,
+}
+
+const SECTIONS: [Section; 6] = [
+    Section::Params,
+    Section::DbInfo,
+    Section::Layout,
+    Section::Hint,
+    Section::ServerState,
+    Section::Data,
+];
+
+const HEADER_LEN: u64 = 12 + 28 * SECTIONS.len() as u64;
+
+impl Section {
+    fn tag(&self) -> &'static [u8; 4] {
+        match self {
+            Section::Params => b"PRMS",
+            Section::DbInfo => b"INFO",
+            Section::Layout => b"LAYT",
+            Section::Hint => b"HINT",
+            Section::ServerState => b"STAT",
+            Section::Data => b"DATA",
+        }
+    }
+}
+
+impl Display for Section {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Section::Params => "params",
+            Section::DbInfo => "dbinfo",
+            Section::Layout => "layout",
+            Section::Hint => "hint",
+            Section::ServerState => "server state",
+            Section::Data => "data",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// An error reading or writing an artifact.
+#[derive(Debug)]
+pub enum ArtifactError {
+    Io(io::Error),
+    /// The file is not an artifact.
+    BadMagic,
+    UnsupportedVersion(u32),
+    /// The section table is malformed.
+    BadHeader,
+    MissingSection(Section),
+    /// A section extends past the end of the file.
+    Truncated(Section),
+    ChecksumMismatch(Section),
+    /// A section has a valid checksum, but cannot be parsed.
+    Malformed(Section),
+    /// The artifact does not match the database shape or parameters it was loaded for.
+    Mismatch(String),
+}
+
+impl Display for ArtifactError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArtifactError::Io(e) => write!(f, "{}", e),
+            ArtifactError::BadMagic => write!(f, "not a DoublePIR artifact"),
+            ArtifactError::UnsupportedVersion(v) => write!(
+                f,
+                "unsupported artifact version {} (expected {})",
+                v, ARTIFACT_VERSION
+            ),
+            ArtifactError::BadHeader => write!(f, "malformed artifact header"),
+            ArtifactError::MissingSection(s) => write!(f, "missing {} section", s),
+            ArtifactError::Truncated(s) => write!(f, "{} section is truncated", s),
+            ArtifactError::ChecksumMismatch(s) => write!(f, "{} section is corrupted", s),
+            ArtifactError::Malformed(s) => write!(f, "{} section is malformed", s),
+            ArtifactError::Mismatch(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ArtifactError {}
+
+impl From<io::Error> for ArtifactError {
+    fn from(e: io::Error) -> Self {
+        ArtifactError::Io(e)
+    }
+}
+
+/// The shape of the squished database, and the version of the hint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DbLayout {
+    pub rows: usize,
+    pub cols: usize,
+    pub hint_version: u64,
+}
+
+impl DbLayout {
+    fn serialize(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend((self.rows as u64).to_be_bytes());
+        out.extend((self.cols as u64).to_be_bytes());
+        out.extend(self.hint_version.to_be_bytes());
+        out
+    }
+
+    fn deserialize(data: &[u8]) -> Option<Self> {
+        let word = |i: usize| {
+            Some(u64::from_be_bytes(
+                data.get(8 * i..8 * i + 8)?.try_into().ok()?,
+            ))
+        };
+        Some(Self {
+            rows: word(0)? as usize,
+            cols: word(1)? as usize,
+            hint_version: word(2)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct SectionEntry {
+    offset: u64,
+    len: u64,
+    checksum: u64,
+}
+
+fn data_as_bytes(data: &[u32]) -> &[u8] {
+    unsafe { slice::from_raw_parts(data.as_ptr() as *const u8, data.len() * 4) }
+}
+
+/// An artifact opened for reading. Sections are read, and their checksums checked,
+/// only when requested.
+pub struct Artifact {
+    file: File,
+    sections: [SectionEntry; SECTIONS.len()],
+}
+
+impl Artifact {
+    /// Writes an artifact to `path`.
+    pub fn write<P: AsRef<Path>>(
+        path: P,
+        params: &Params,
+        dbinfo: &DbInfo,
+        layout: &DbLayout,
+        hint: &State,
+        server_state: &State,
+        data: &[u32],
+    ) -> Result<(), ArtifactError> {
+        assert_eq!(data.len(), layout.rows * layout.cols);
+        let params = params.to_string().into_bytes();
+        let dbinfo = dbinfo.serialize();
+        let layout = layout.serialize();
+        let hint = hint.serialize();
+        let server_state = server_state.serialize();
+        let contents: [&[u8]; SECTIONS.len()] = [
+            &params,
+            &dbinfo,
+            &layout,
+            &hint,
+            &server_state,
+            data_as_bytes(data),
+        ];
+
+        let mut header = Vec::with_capacity(HEADER_LEN as usize);
+        header.extend(ARTIFACT_MAGIC);
+        header.extend(ARTIFACT_VERSION.to_be_bytes());
+        header.extend((SECTIONS.len() as u32).to_be_bytes());
+        let mut offsets = Vec::with_capacity(SECTIONS.len());
+        let mut offset = HEADER_LEN;
+        for (section, bytes) in SECTIONS.iter().zip(contents) {
+            if *section == Section::Data {
+                offset = (offset + PAGE_SIZE - 1) / PAGE_SIZE * PAGE_SIZE;
+            }
+            header.extend(section.tag());
+            header.extend(offset.to_be_bytes());
+            header.extend((bytes.len() as u64).to_be_bytes());
+            header.extend(checksum_fnv64(bytes).to_be_bytes());
+            offsets.push(offset);
+            offset += bytes.len() as u64;
+        }
+
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(&header)?;
+        let mut at = HEADER_LEN;
+        for (offset, bytes) in offsets.into_iter().zip(contents) {
+            out.write_all(&vec![0u8; (offset - at) as usize])?;
+            out.write_all(bytes)?;
+            at = offset + bytes.len() as u64;
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    /// Opens the artifact at `path`, checking its header.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, ArtifactError> {
+        let mut file = File::open(path)?;
+        let file_len = file.metadata()?.len();
+
+        let mut header = vec![0u8; HEADER_LEN as usize];
+        if file_len < 12 || file.read_exact(&mut header[..12]).is_err() {
+            return Err(ArtifactError::BadMagic);
+        }
+        if &header[..4] != ARTIFACT_MAGIC {
+            return Err(ArtifactError::BadMagic);
+        }
+        let word = |at: usize| u32::from_be_bytes(header[at..at + 4].try_into().unwrap());
+        let version = word(4);
+        if version != ARTIFACT_VERSION {
+            return Err(ArtifactError::UnsupportedVersion(version));
+        }
+        let num_sections = word(8) as usize;
+        if num_sections != SECTIONS.len() || file_len < HEADER_LEN {
+            return Err(ArtifactError::BadHeader);
+        }
+        file.read_exact(&mut header[12..])?;
+
+        let mut sections = [SectionEntry {
+            offset: 0,
+            len: 0,
+            checksum: 0,
+        }; SECTIONS.len()];
+        for (i, section) in SECTIONS.iter().enumerate() {
+            let entry = &header[12 + 28 * i..12 + 28 * (i + 1)];
+            if &entry[..4] != section.tag() {
+                return Err(ArtifactError::MissingSection(*section));
+            }
+            let field = |at: usize| u64::from_be_bytes(entry[at..at + 8].try_into().unwrap());
+            sections[i] = SectionEntry {
+                offset: field(4),
+                len: field(12),
+                checksum: field(20),
+            };
+            if sections[i].offset.saturating_add(sections[i].len) > file_len {
+                return Err(ArtifactError::Truncated(*section));
+            }
+        }
+
+        Ok(Self { file, sections })
+    }
+
+    fn entry(&self, section: Section) -> SectionEntry {
+        self.sections[SECTIONS.iter().position(|s| *s == section).unwrap()]
+    }
+
+    fn check(&self, section: Section, bytes: &[u8]) -> Result<(), ArtifactError> {
+        if checksum_fnv64(bytes) != self.entry(section).checksum {
+            return Err(ArtifactError::ChecksumMismatch(section));
+        }
+        Ok(())
+    }
+
+    fn read_section(&self, section: Section) -> Result<Vec<u8>, ArtifactError> {
+        let entry = self.entry(section);
+        let mut bytes = vec![0u8; entry.len as usize];
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(entry.offset))?;
+        file.read_exact(&mut bytes)?;
+        self.check(section, &bytes)?;
+        Ok(bytes)
+    }
+
+    pub fn params(&self) -> Result<Params, ArtifactError> {
+        let bytes = self.read_section(Section::Params)?;
+        std::str::from_utf8(&bytes)
+            .ok()
+            .and_then(Params::try_from_string)
+            .ok_or(ArtifactError::Malformed(Section::Params))
+    }
+
+    pub fn dbinfo(&self) -> Result<DbInfo, ArtifactError> {
+        Ok(DbInfo::deserialize(&self.read_section(Section::DbInfo)?))
+    }
+
+    pub fn layout(&self) -> Result<DbLayout, ArtifactError> {
+        DbLayout::deserialize(&self.read_section(Section::Layout)?)
+            .ok_or(ArtifactError::Truncated(Section::Layout))
+    }
+
+    pub fn hint(&self) -> Result<State, ArtifactError> {
+        Ok(State::deserialize(&self.read_section(Section::Hint)?))
+    }
+
+    pub fn server_state(&self) -> Result<State, ArtifactError> {
+        Ok(State::deserialize(
+            &self.read_section(Section::ServerState)?,
+        ))
+    }
+
+    /// Loads the squished database.
+    ///
+    /// Its checksum is only checked when it is read into memory: checking a mapped
+    /// database would read all of it, so only its length is checked.
+    pub fn data(&self, storage: DbStorage) -> Result<RawData, ArtifactError> {
+        let entry = self.entry(Section::Data);
+        let data = RawData::load(&self.file, entry.offset, entry.len as usize, storage)?;
+        if storage == DbStorage::Read {
+            self.check(Section::Data, &data)?;
+        }
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::matrix::Matrix;
+
+    use super::*;
+
+    fn write_test_artifact(path: &Path) -> Vec<u32> {
+        let params = Params {
+            n: 4,
+            sigma: 6.4,
+            l: 3,
+            m: 5,
+            logq: 32,
+            p: 991,
+        };
+        let dbinfo = DbInfo::new(10, 8, &params);
+        let data: Vec<u32> = (0..6).collect();
+        let layout = DbLayout {
+            rows: 3,
+            cols: 2,
+            hint_version: 7,
+        };
+        let hint = vec![Matrix::random(4, 4)];
+        let server_state = vec![Matrix::random(2, 3), Matrix::random(3, 2)];
+        Artifact::write(path, &params, &dbinfo, &layout, &hint, &server_state, &data).unwrap();
+        data
+    }
+
+    #[test]
+    fn artifacts_round_trip_and_detect_corruption() {
+        let path = std::env::temp_dir().join(format!("doublepir-artifact-{}", std::process::id()));
+        let data = write_test_artifact(&path);
+
+        let artifact = Artifact::open(&path).unwrap();
+        assert_eq!(artifact.params().unwrap().to_string(), "4,6.4,3,5,32,991");
+        assert_eq!(artifact.dbinfo().unwrap().num_entries, 10);
+        assert_eq!(artifact.layout().unwrap().hint_version, 7);
+        assert_eq!(artifact.hint().unwrap()[0].rows, 4);
+        assert_eq!(artifact.server_state().unwrap().len(), 2);
+        for storage in [DbStorage::Read, DbStorage::Map] {
+            assert_eq!(artifact.data(storage).unwrap().as_u32_slice(), &data[..]);
+        }
+
+        // flip a bit in the last byte of the hint
+        let mut bytes = std::fs::read(&path).unwrap();
+        let hint = artifact.entry(Section::Hint);
+        bytes[(hint.offset + hint.len - 1) as usize] ^= 1;
+        std::fs::write(&path, &bytes).unwrap();
+        let artifact = Artifact::open(&path).unwrap();
+        assert!(artifact.params().is_ok());
+        assert!(matches!(
+            artifact.hint(),
+            Err(ArtifactError::ChecksumMismatch(Section::Hint))
+        ));
+
+        std::fs::write(&path, &bytes[..bytes.len() - 4]).unwrap();
+        assert!(matches!(
+            Artifact::open(&path),
+            Err(ArtifactError::Truncated(Section::Data))
+        ));
+
+        bytes[0] = b'X';
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(matches!(
+            Artifact::open(&path),
+            Err(ArtifactError::BadMagic)
+        ));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn malformed_params_are_rejected() {
+        let path =
+            std::env::temp_dir().join(format!("doublepir-artifact-params-{}", std::process::id()));
+        write_test_artifact(&path);
+        let bytes = std::fs::read(&path).unwrap();
+        let params = Artifact::open(&path).unwrap().entry(Section::Params);
+        let (start, end) = (
+            params.offset as usize,
+            (params.offset + params.len) as usize,
+        );
+
+        // sections with valid checksums, which are not valid parameters
+        for bad_params in [
+            &b"4,6.4,3,5,32,99x"[..],
+            b"4,6.4,3,5,32,9,1",
+            b"\xff,6.4,3,5,32,991",
+        ] {
+            let mut bytes = bytes.clone();
+            bytes[start..end].copy_from_slice(&bad_params[..end - start]);
+            // the checksum of the params section, the first in the header table
+            let checksum = checksum_fnv64(&bytes[start..end]);
+            bytes[32..40].copy_from_slice(&checksum.to_be_bytes());
+            std::fs::write(&path, &bytes).unwrap();
+            assert!(matches!(
+                Artifact::open(&path).unwrap().params(),
+                Err(ArtifactError::Malformed(Section::Params))
+            ));
+        }
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
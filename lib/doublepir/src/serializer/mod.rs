@@ -1,3 +1,5 @@
+mod artifact;
 mod serializer;
 
+pub use artifact::*;
 pub use serializer::*;
@@ -1,6 +1,7 @@
 use std::iter::Copied;
 
 use crate::database::{Db, DbInfo};
+use crate::doublepir::HintDelta;
 use crate::matrix::{Matrix, SquishParams};
 
 pub type State = Vec<Matrix>;
@@ -176,6 +177,39 @@ impl Serialize for Db {
     }
 }
 
+impl Serialize for HintDelta {
+    fn serialize(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend(self.from_version.to_be_bytes());
+        out.extend(self.to_version.to_be_bytes());
+        write_u32(&mut out, self.rows.len() as u32);
+        for row in self.rows.iter() {
+            out.extend(row.to_be_bytes());
+        }
+        out.extend(self.diffs.serialize());
+        out
+    }
+}
+
+impl<'a, I: Iterator<Item = u8>> Deserialize<'a, I> for HintDelta {
+    fn deserialize_iter(iter: &mut I) -> Self {
+        let from_version = u64::from_be_bytes(iter.take_n());
+        let to_version = u64::from_be_bytes(iter.take_n());
+        let len = read_u32_iter(iter);
+        assert!(len < MAX_LEN);
+        let rows = (0..len)
+            .map(|_| u64::from_be_bytes(iter.take_n()))
+            .collect();
+        let diffs = Matrix::deserialize_iter(iter);
+        Self {
+            from_version,
+            to_version,
+            rows,
+            diffs,
+        }
+    }
+}
+
 // impl<'a, I: Iterator<Item = u8>> Deserialize<'a, I> for Db {
 //     fn deserialize_iter(iter: &mut I) -> Self {
 //         let info = DbInfo::deserialize_iter(iter);
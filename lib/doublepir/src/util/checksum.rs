@@ -15,3 +15,24 @@ pub fn checksum_u32(data: &[u32]) -> u32 {
     }
     val
 }
+
+/// 64-bit FNV-1a hash of a byte slice, taken a word at a time.
+///
+/// Unlike the XOR checksums, this detects reordered and zeroed data, so it is
+/// used to check files for corruption.
+pub fn checksum_fnv64(data: &[u8]) -> u64 {
+    const OFFSET: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+
+    let mut val = OFFSET;
+    let mut chunks = data.chunks_exact(8);
+    for chunk in &mut chunks {
+        val ^= u64::from_le_bytes(chunk.try_into().unwrap());
+        val = val.wrapping_mul(PRIME);
+    }
+    for d in chunks.remainder() {
+        val ^= *d as u64;
+        val = val.wrapping_mul(PRIME);
+    }
+    val
+}
@@ -0,0 +1,774 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    future::{ready, Ready},
+    ops::Deref,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+};
+
+use actix_web::{web, FromRequest, HttpRequest};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use spiral_rs::{
+    key_value::KeyLayout,
+    params::{intern_params, Params},
+    params_store::params_from_store_or_search,
+    util::try_params_from_json_obj,
+};
+
+use crate::{
+    db::{
+        aligned_memory::AlignedMemory64,
+        loading::map_preprocessed_db_from_file,
+        persistence::{rebuild_db, Persistence, DEFAULT_SNAPSHOT_INTERVAL},
+        sparse_db::SparseDb,
+        write::{apply_kv_pairs, commit_update, plan_update},
+    },
+    doublepir::{DoublePirBucket, DoublePirConfig},
+    error::Error,
+    pub_params::{PubParamsConfig, PubParamsStore},
+};
+
+/// Name of the bucket served at the root paths (`/meta`, `/private-read`, ...).
+pub const DEFAULT_BUCKET: &str = "default";
+
+const CONFIG_FNAME: &str = "bucket.json";
+
+/// The default number of buckets a server may hold. Each bucket has its own public
+/// parameters store, with its own memory budget, so this bounds their total memory.
+pub const DEFAULT_MAX_BUCKETS: usize = 16;
+
+/// The largest database a bucket may hold, matching the largest entries in the
+/// parameter store: `2^26` items, and `2^42` bytes in total.
+const MAX_NUM_ITEMS_LOG2: usize = 26;
+const MAX_DB_SIZE_BYTES: usize = 1 << 42;
+
+/// How to choose the `Params` for a bucket.
+///
+/// Either `params` (the JSON scheme parameters accepted by `params_from_json`) is given,
+/// or both `num_items_log2` and `item_size_bytes` are, and the parameters are
+/// looked up with `params_from_store_or_search`.
+///
+/// `key_layout` chooses how keys are placed in rows, and defaults to `KeyLayout::Single`.
+///
+/// If `doublepir` is given, the bucket is instead served with DoublePIR from
+/// preprocessed files, and the other fields are ignored. Since it names files on the
+/// server, it can only be given at startup, and not to `POST /buckets/{name}`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BucketConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub params: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub num_items_log2: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub item_size_bytes: Option<usize>,
+    #[serde(default)]
+    pub key_layout: KeyLayout,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub doublepir: Option<DoublePirConfig>,
+}
+
+impl BucketConfig {
+    pub fn from_params_json(params_json: &str) -> Result<Self, Error> {
+        let params = serde_json::from_str(params_json)
+            .map_err(|e| Error::InvalidRequest(format!("bad params: {}", e)))?;
+        Ok(Self {
+            params: Some(params),
+            ..Default::default()
+        })
+    }
+
+    pub fn from_store(num_items_log2: usize, item_size_bytes: usize) -> Self {
+        Self {
+            num_items_log2: Some(num_items_log2),
+            item_size_bytes: Some(item_size_bytes),
+            ..Default::default()
+        }
+    }
+
+    /// Returns the params, and the JSON to report for them in `/meta`.
+    fn to_params(&self) -> Result<(Params, String), Error> {
+        if !self.key_layout.is_valid() {
+            return Err(Error::InvalidRequest(format!(
+                "unsupported key layout: {:?}",
+                self.key_layout
+            )));
+        }
+        let params = match (&self.params, self.num_items_log2, self.item_size_bytes) {
+            (Some(params), _, _) => try_params_from_json_obj(params)?,
+            // checked before the lookup, since searching for huge databases is slow
+            (None, Some(num_items_log2), Some(item_size_bytes))
+                if num_items_log2 > MAX_NUM_ITEMS_LOG2
+                    || item_size_bytes > MAX_DB_SIZE_BYTES >> num_items_log2 =>
+            {
+                return Err(too_large(num_items_log2, item_size_bytes))
+            }
+            (None, Some(num_items_log2), Some(item_size_bytes)) => {
+                params_from_store_or_search(num_items_log2, item_size_bytes)?
+            }
+            _ => {
+                return Err(Error::InvalidRequest(
+                    "need either params, or num_items_log2 and item_size_bytes".to_owned(),
+                ))
+            }
+        };
+        let db_size = params.num_items().saturating_mul(params.db_item_size);
+        if params.num_items() > 1 << MAX_NUM_ITEMS_LOG2 || db_size > MAX_DB_SIZE_BYTES {
+            return Err(too_large(
+                params.db_dim_1 + params.db_dim_2,
+                params.db_item_size,
+            ));
+        }
+        let mut reported = params.to_json_obj();
+        reported["scheme"] = "spiral".into();
+        Ok((params, reported.to_string()))
+    }
+}
+
+fn too_large(num_items_log2: usize, item_size_bytes: usize) -> Error {
+    Error::InvalidRequest(format!(
+        "database of 2^{} items of {} bytes is above the maximum of 2^{} items and {} bytes",
+        num_items_log2, item_size_bytes, MAX_NUM_ITEMS_LOG2, MAX_DB_SIZE_BYTES
+    ))
+}
+
+/// A single database, with its own parameters, data, and client public parameters.
+pub struct Bucket {
+    pub name: String,
+    pub params: Arc<Params>,
+    pub params_json: String,
+    pub key_layout: KeyLayout,
+    pub db: RwLock<SparseDb>,
+    /// A preprocessed database mapped from a file, which is served instead of `db`.
+    /// Buckets serving one are read-only.
+    pub mapped_db: Option<AlignedMemory64>,
+    pub rows: RwLock<Vec<Vec<u8>>>,
+    pub pub_params: PubParamsStore,
+    pub version: RwLock<u64>,
+    persistence: Option<Mutex<Persistence>>,
+}
+
+impl Bucket {
+    /// Creates a bucket. If `data_dir` is given, the bucket's writes are persisted
+    /// there, and any state already in it is recovered.
+    pub fn new(
+        name: &str,
+        config: &BucketConfig,
+        data_dir: Option<&Path>,
+        pub_params_config: PubParamsConfig,
+    ) -> Result<Self, Error> {
+        let (params, params_json) = config.to_params()?;
+        let params = intern_params(params);
+
+        let mut db = SparseDb::new();
+        let mut rows = vec![Vec::new(); params.num_items()];
+        let mut version = 0;
+
+        let mut persistence = None;
+        if let Some(data_dir) = data_dir {
+            let mut p = Persistence::open(data_dir, DEFAULT_SNAPSHOT_INTERVAL)?;
+            let key_layout = config.key_layout;
+            let recovered = p.recover(params.num_items(), |kv_pairs, rows| {
+                apply_kv_pairs(&params, key_layout, kv_pairs, rows).map(|_| ())
+            })?;
+            rows = recovered.rows;
+            version = recovered.version;
+            db = rebuild_db(&params, &rows);
+            persistence = Some(Mutex::new(p));
+        }
+
+        Ok(Self {
+            name: name.to_owned(),
+            pub_params: PubParamsStore::new(params.clone(), pub_params_config)?,
+            params,
+            params_json,
+            key_layout: config.key_layout,
+            db: RwLock::new(db),
+            mapped_db: None,
+            rows: RwLock::new(rows),
+            version: RwLock::new(version),
+            persistence,
+        })
+    }
+
+    /// Creates a read-only bucket serving the preprocessed database in `db_file` (see
+    /// `save_preprocessed_db_to_file`), which is mapped instead of read into memory.
+    pub fn map(
+        name: &str,
+        config: &BucketConfig,
+        db_file: &Path,
+        pub_params_config: PubParamsConfig,
+    ) -> Result<Self, Error> {
+        let (params, params_json) = config.to_params()?;
+        let params = intern_params(params);
+        let mapped_db = map_preprocessed_db_from_file(&params, &File::open(db_file)?)?;
+
+        Ok(Self {
+            name: name.to_owned(),
+            pub_params: PubParamsStore::new(params.clone(), pub_params_config)?,
+            params,
+            params_json,
+            key_layout: config.key_layout,
+            db: RwLock::new(SparseDb::new()),
+            mapped_db: Some(mapped_db),
+            rows: RwLock::new(Vec::new()),
+            version: RwLock::new(0),
+            persistence: None,
+        })
+    }
+
+    /// Returns an error if the bucket serves a mapped database, which cannot be written.
+    pub fn check_writable(&self) -> Result<(), Error> {
+        if self.mapped_db.is_some() {
+            return Err(Error::InvalidRequest(format!(
+                "bucket {} is read-only",
+                self.name
+            )));
+        }
+        Ok(())
+    }
+
+    /// Writes the given key-value pairs, returning the new version of the bucket.
+    ///
+    /// The write is logged before it is applied; if it cannot be logged, the bucket
+    /// is left unchanged.
+    pub fn write(&self, kv_pairs: &[(&str, &[u8])]) -> Result<u64, Error> {
+        self.check_writable()?;
+        let mut rows_mut = self.rows.write()?;
+        let mut db_mut = self.db.write()?;
+        let mut version_mut = self.version.write()?;
+
+        let updated_rows = plan_update(&self.params, self.key_layout, kv_pairs, &rows_mut)?;
+        let new_version = *version_mut + 1;
+
+        let mut persistence = match &self.persistence {
+            Some(persistence) => Some(persistence.lock()?),
+            None => None,
+        };
+        if let Some(persistence) = &mut persistence {
+            persistence.log_write(new_version, kv_pairs)?;
+        }
+
+        commit_update(&self.params, updated_rows, &mut rows_mut, &mut db_mut);
+        *version_mut = new_version;
+
+        if let Some(persistence) = &mut persistence {
+            // the write is already durable in the log, so a failed snapshot only
+            // delays truncating it
+            if persistence.should_snapshot() {
+                if let Err(e) = persistence.snapshot(new_version, &rows_mut) {
+                    println!("failed to snapshot bucket {}: {}", self.name, e);
+                }
+            }
+        }
+
+        Ok(new_version)
+    }
+
+    /// Deletes the given keys, returning the new version of the bucket.
+    /// Keys that are not in the bucket are ignored.
+    pub fn delete(&self, keys: &[&str]) -> Result<u64, Error> {
+        let kv_pairs: Vec<(&str, &[u8])> = keys.iter().map(|key| (*key, &[][..])).collect();
+        self.write(&kv_pairs)
+    }
+}
+
+/// A bucket served with either PIR scheme.
+#[derive(Clone)]
+pub enum AnyBucket {
+    Spiral(Arc<Bucket>),
+    DoublePir(Arc<DoublePirBucket>),
+}
+
+impl AnyBucket {
+    fn open(
+        name: &str,
+        config: &BucketConfig,
+        data_dir: Option<&Path>,
+        pub_params_config: PubParamsConfig,
+    ) -> Result<Self, Error> {
+        match &config.doublepir {
+            Some(doublepir) => Ok(Self::DoublePir(Arc::new(DoublePirBucket::new(
+                name, doublepir,
+            )?))),
+            None => Ok(Self::Spiral(Arc::new(Bucket::new(
+                name,
+                config,
+                data_dir,
+                pub_params_config,
+            )?))),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Spiral(bucket) => &bucket.name,
+            Self::DoublePir(bucket) => &bucket.name,
+        }
+    }
+
+    /// Returns the Spiral bucket, or an error if the bucket is served with DoublePIR.
+    pub fn into_spiral(self) -> Result<Arc<Bucket>, Error> {
+        match self {
+            Self::Spiral(bucket) => Ok(bucket),
+            Self::DoublePir(bucket) => Err(Error::InvalidRequest(format!(
+                "bucket {} is served with doublepir",
+                bucket.name
+            ))),
+        }
+    }
+}
+
+fn is_valid_bucket_name(name: &str) -> bool {
+    !name.is_empty()
+        && name != "buckets"
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+        && !name.starts_with('.')
+}
+
+/// All the buckets hosted by a server.
+///
+/// When a data directory is given, each bucket is stored in a subdirectory
+/// named after it, containing its `bucket.json` configuration. Likewise, when
+/// the public parameters config has a spill directory, each bucket spills to a
+/// subdirectory of it.
+///
+/// The public parameters config applies to each bucket on its own, so its memory budget is
+/// per bucket; there are at most `max_buckets` buckets, which bounds the total.
+pub struct Buckets {
+    data_dir: Option<PathBuf>,
+    pub_params_config: PubParamsConfig,
+    max_buckets: usize,
+    buckets: RwLock<HashMap<String, AnyBucket>>,
+}
+
+impl Buckets {
+    pub fn new(data_dir: Option<PathBuf>) -> Self {
+        Self::with_pub_params_config(data_dir, PubParamsConfig::default())
+    }
+
+    pub fn with_pub_params_config(
+        data_dir: Option<PathBuf>,
+        pub_params_config: PubParamsConfig,
+    ) -> Self {
+        Self {
+            data_dir,
+            pub_params_config,
+            max_buckets: DEFAULT_MAX_BUCKETS,
+            buckets: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Sets the number of buckets the server may hold (`DEFAULT_MAX_BUCKETS` by default).
+    pub fn with_max_buckets(mut self, max_buckets: usize) -> Self {
+        self.max_buckets = max_buckets;
+        self
+    }
+
+    /// Fails if there is no room for another bucket.
+    fn check_room(&self, buckets: &HashMap<String, AnyBucket>) -> Result<(), Error> {
+        if buckets.len() >= self.max_buckets {
+            return Err(Error::InvalidRequest(format!(
+                "a server holds at most {} buckets",
+                self.max_buckets
+            )));
+        }
+        Ok(())
+    }
+
+    fn pub_params_config_for(&self, name: &str) -> PubParamsConfig {
+        PubParamsConfig {
+            spill_dir: self
+                .pub_params_config
+                .spill_dir
+                .as_ref()
+                .map(|d| d.join(name)),
+            ..self.pub_params_config.clone()
+        }
+    }
+
+    /// Loads every bucket found in the data directory.
+    pub fn load_existing(&self) -> Result<(), Error> {
+        let Some(data_dir) = &self.data_dir else {
+            return Ok(());
+        };
+        if !data_dir.exists() {
+            return Ok(());
+        }
+
+        let mut buckets = self.buckets.write()?;
+        for entry in fs::read_dir(data_dir)? {
+            let path = entry?.path();
+            let config_path = path.join(CONFIG_FNAME);
+            if !config_path.exists() {
+                continue;
+            }
+            let name = path.file_name().unwrap().to_string_lossy().to_string();
+            self.check_room(&buckets)?;
+            let config: BucketConfig = serde_json::from_slice(&fs::read(config_path)?)
+                .map_err(|e| Error::Corrupted(format!("bad config for {}: {}", name, e)))?;
+            let bucket = AnyBucket::open(
+                &name,
+                &config,
+                Some(&path),
+                self.pub_params_config_for(&name),
+            )?;
+            match &bucket {
+                AnyBucket::Spiral(spiral) => println!(
+                    "Loaded bucket {} at version {}",
+                    name,
+                    *spiral.version.read()?
+                ),
+                AnyBucket::DoublePir(_) => println!("Loaded doublepir bucket {}", name),
+            }
+            buckets.insert(name, bucket);
+        }
+        Ok(())
+    }
+
+    pub fn create(&self, name: &str, config: &BucketConfig) -> Result<AnyBucket, Error> {
+        if !is_valid_bucket_name(name) {
+            return Err(Error::InvalidRequest(format!("bad bucket name: {}", name)));
+        }
+
+        let mut buckets = self.buckets.write()?;
+        if buckets.contains_key(name) {
+            return Err(Error::AlreadyExists);
+        }
+        self.check_room(&buckets)?;
+
+        let bucket_dir = self.data_dir.as_ref().map(|d| d.join(name));
+        if let Some(bucket_dir) = &bucket_dir {
+            fs::create_dir_all(bucket_dir)?;
+            fs::write(
+                bucket_dir.join(CONFIG_FNAME),
+                serde_json::to_vec(config).unwrap(),
+            )?;
+        }
+
+        let bucket = AnyBucket::open(
+            name,
+            config,
+            bucket_dir.as_deref(),
+            self.pub_params_config_for(name),
+        );
+        let bucket = match bucket {
+            Ok(bucket) => bucket,
+            Err(e) => {
+                if let Some(bucket_dir) = &bucket_dir {
+                    fs::remove_dir_all(bucket_dir)?;
+                }
+                return Err(e);
+            }
+        };
+        buckets.insert(name.to_owned(), bucket.clone());
+        Ok(bucket)
+    }
+
+    /// Creates a read-only bucket serving the preprocessed database in `db_file`, as
+    /// `Bucket::map`. It is not persisted in the data directory.
+    pub fn create_mapped(
+        &self,
+        name: &str,
+        config: &BucketConfig,
+        db_file: &Path,
+    ) -> Result<AnyBucket, Error> {
+        if !is_valid_bucket_name(name) {
+            return Err(Error::InvalidRequest(format!("bad bucket name: {}", name)));
+        }
+
+        let mut buckets = self.buckets.write()?;
+        if buckets.contains_key(name) {
+            return Err(Error::AlreadyExists);
+        }
+        self.check_room(&buckets)?;
+
+        let bucket = Bucket::map(name, config, db_file, self.pub_params_config_for(name))?;
+        let bucket = AnyBucket::Spiral(Arc::new(bucket));
+        buckets.insert(name.to_owned(), bucket.clone());
+        Ok(bucket)
+    }
+
+    pub fn get_any(&self, name: &str) -> Result<AnyBucket, Error> {
+        self.buckets
+            .read()?
+            .get(name)
+            .cloned()
+            .ok_or(Error::NotFound)
+    }
+
+    /// Returns the named bucket, which must be served with Spiral.
+    pub fn get(&self, name: &str) -> Result<Arc<Bucket>, Error> {
+        self.get_any(name)?.into_spiral()
+    }
+
+    /// Removes the bucket, and deletes its persisted data and spilled public parameters.
+    pub fn delete(&self, name: &str) -> Result<(), Error> {
+        self.buckets.write()?.remove(name).ok_or(Error::NotFound)?;
+        let dirs = [
+            self.data_dir.as_ref().map(|d| d.join(name)),
+            self.pub_params_config_for(name).spill_dir,
+        ];
+        for dir in dirs.into_iter().flatten() {
+            if dir.exists() {
+                fs::remove_dir_all(dir)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn names(&self) -> Result<Vec<String>, Error> {
+        let mut names: Vec<_> = self.buckets.read()?.keys().cloned().collect();
+        names.sort();
+        Ok(names)
+    }
+}
+
+/// Extracts the bucket named by the `{bucket}` path segment,
+/// or the default bucket if the route has no such segment.
+pub struct BucketRef(pub Arc<Bucket>);
+
+impl Deref for BucketRef {
+    type Target = Bucket;
+
+    fn deref(&self) -> &Bucket {
+        &self.0
+    }
+}
+
+fn bucket_from_request(req: &HttpRequest) -> Result<AnyBucket, Error> {
+    let name = req.match_info().get("bucket").unwrap_or(DEFAULT_BUCKET);
+    match req.app_data::<web::Data<Buckets>>() {
+        Some(buckets) => buckets.get_any(name),
+        None => Err(Error::Unknown),
+    }
+}
+
+impl FromRequest for BucketRef {
+    type Error = Error;
+    type Future = Ready<Result<Self, Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut actix_http::Payload) -> Self::Future {
+        ready(
+            bucket_from_request(req)
+                .and_then(AnyBucket::into_spiral)
+                .map(BucketRef),
+        )
+    }
+}
+
+/// Like `BucketRef`, but also accepts buckets served with DoublePIR.
+pub struct AnyBucketRef(pub AnyBucket);
+
+impl Deref for AnyBucketRef {
+    type Target = AnyBucket;
+
+    fn deref(&self) -> &AnyBucket {
+        &self.0
+    }
+}
+
+impl FromRequest for AnyBucketRef {
+    type Error = Error;
+    type Future = Ready<Result<Self, Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut actix_http::Payload) -> Self::Future {
+        ready(bucket_from_request(req).map(AnyBucketRef))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    const PARAMS_JSON: &str = r#"{
+        "n": 2,
+        "nu_1": 6,
+        "nu_2": 2,
+        "p": 256,
+        "q2_bits": 20,
+        "t_gsw": 8,
+        "t_conv": 4,
+        "t_exp_left": 8,
+        "t_exp_right": 8,
+        "instances": 1,
+        "db_item_size": 8192
+    }"#;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        std::env::temp_dir().join(format!("spiral-server-{}-{}", name, nanos))
+    }
+
+    #[test]
+    fn buckets_are_independent_and_survive_restart() {
+        let dir = temp_dir("buckets");
+        let config = BucketConfig::from_params_json(PARAMS_JSON).unwrap();
+        let cuckoo_config = BucketConfig {
+            key_layout: KeyLayout::Cuckoo { num_hashes: 2 },
+            ..config.clone()
+        };
+        {
+            let buckets = Buckets::new(Some(dir.clone()));
+            let a = buckets.create("a", &config).unwrap().into_spiral().unwrap();
+            let b = buckets
+                .create("b", &cuckoo_config)
+                .unwrap()
+                .into_spiral()
+                .unwrap();
+            assert!(matches!(
+                buckets.create("a", &config),
+                Err(Error::AlreadyExists)
+            ));
+            assert!(buckets.create("../c", &config).is_err());
+
+            assert_eq!(a.write(&[("k", b"v")]).unwrap(), 1);
+            assert_eq!(a.write(&[("k", b"w")]).unwrap(), 2);
+            assert_eq!(*b.version.read().unwrap(), 0);
+
+            assert_eq!(b.write(&[("k", b"v")]).unwrap(), 1);
+            assert_eq!(b.delete(&["k", "missing"]).unwrap(), 2);
+            assert!(b.rows.read().unwrap().iter().all(|row| row.is_empty()));
+            assert!(b.db.read().unwrap().data.is_empty());
+
+            buckets.create("c", &config).unwrap();
+            buckets.delete("c").unwrap();
+            assert!(matches!(buckets.get("c"), Err(Error::NotFound)));
+        }
+
+        let buckets = Buckets::new(Some(dir.clone()));
+        buckets.load_existing().unwrap();
+        assert_eq!(buckets.names().unwrap(), vec!["a", "b"]);
+        let a = buckets.get("a").unwrap();
+        assert_eq!(*a.version.read().unwrap(), 2);
+        assert_eq!(*buckets.get("b").unwrap().version.read().unwrap(), 2);
+        assert!(buckets.get("b").unwrap().db.read().unwrap().data.is_empty());
+        assert_eq!(a.params_json, buckets.get("b").unwrap().params_json);
+        assert_eq!(
+            buckets.get("b").unwrap().key_layout,
+            KeyLayout::Cuckoo { num_hashes: 2 }
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn invalid_params_are_rejected() {
+        let buckets = Buckets::new(None);
+        let bad_json = PARAMS_JSON.replace(r#""p": 256"#, r#""p": 300"#);
+        let config = BucketConfig::from_params_json(&bad_json).unwrap();
+        assert!(matches!(
+            buckets.create("a", &config),
+            Err(Error::InvalidRequest(_))
+        ));
+
+        let config = BucketConfig::from_params_json(PARAMS_JSON).unwrap();
+        let a = buckets.create("a", &config).unwrap().into_spiral().unwrap();
+        let reported: Value = serde_json::from_str(&a.params_json).unwrap();
+        assert_eq!(reported["scheme"], "spiral");
+        assert_eq!(try_params_from_json_obj(&reported).unwrap(), *a.params);
+    }
+
+    #[test]
+    fn store_buckets_report_their_params() {
+        let buckets = Buckets::new(None);
+        let config = BucketConfig::from_store(10, 200);
+        let a = buckets.create("a", &config).unwrap().into_spiral().unwrap();
+        assert_eq!(a.params.num_items(), 1 << 10);
+        assert!(a.params.db_item_size >= 200);
+        let reported: Value = serde_json::from_str(&a.params_json).unwrap();
+        assert_eq!(try_params_from_json_obj(&reported).unwrap(), *a.params);
+    }
+
+    #[test]
+    fn oversized_databases_are_rejected() {
+        let buckets = Buckets::new(None);
+        for config in [
+            BucketConfig::from_store(27, 256),
+            BucketConfig::from_store(64, 256),
+            BucketConfig::from_store(20, 1 << 30),
+            BucketConfig::from_params_json(&PARAMS_JSON.replace(r#""nu_2": 2"#, r#""nu_2": 40"#))
+                .unwrap(),
+        ] {
+            assert!(matches!(
+                buckets.create("a", &config),
+                Err(Error::InvalidRequest(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn invalid_key_layouts_are_rejected() {
+        let buckets = Buckets::new(None);
+        let config = BucketConfig::from_params_json(PARAMS_JSON).unwrap();
+        for num_hashes in [0, 1, 9, 257] {
+            let config = BucketConfig {
+                key_layout: KeyLayout::Cuckoo { num_hashes },
+                ..config.clone()
+            };
+            assert!(matches!(
+                buckets.create("a", &config),
+                Err(Error::InvalidRequest(_))
+            ));
+        }
+        let config = BucketConfig {
+            key_layout: KeyLayout::Cuckoo { num_hashes: 8 },
+            ..config
+        };
+        buckets.create("a", &config).unwrap();
+    }
+
+    #[test]
+    fn writes_that_cannot_be_logged_are_not_applied() {
+        let dir = temp_dir("unlogged");
+        let config = BucketConfig::from_params_json(PARAMS_JSON).unwrap();
+        {
+            let bucket = Bucket::new("a", &config, Some(&dir), PubParamsConfig::default()).unwrap();
+            assert_eq!(bucket.write(&[("k", b"v")]).unwrap(), 1);
+            let rows = bucket.rows.read().unwrap().clone();
+            let db_idxs = bucket.db.read().unwrap().db_idx_to_vec_idx.clone();
+
+            let set_log_writable = |writable| {
+                let mut persistence = bucket.persistence.as_ref().unwrap().lock().unwrap();
+                persistence.set_log_writable(writable).unwrap();
+            };
+            set_log_writable(false);
+            assert!(bucket.write(&[("k", b"w"), ("other", b"x")]).is_err());
+            assert!(bucket.delete(&["k"]).is_err());
+            assert_eq!(*bucket.version.read().unwrap(), 1);
+            assert_eq!(*bucket.rows.read().unwrap(), rows);
+            assert_eq!(bucket.db.read().unwrap().db_idx_to_vec_idx, db_idxs);
+
+            set_log_writable(true);
+            assert_eq!(bucket.delete(&["k"]).unwrap(), 2);
+        }
+
+        let bucket = Bucket::new("a", &config, Some(&dir), PubParamsConfig::default()).unwrap();
+        assert_eq!(*bucket.version.read().unwrap(), 2);
+        assert!(bucket.rows.read().unwrap().iter().all(|row| row.is_empty()));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn number_of_buckets_is_capped() {
+        let buckets = Buckets::new(None).with_max_buckets(2);
+        let config = BucketConfig::from_params_json(PARAMS_JSON).unwrap();
+        buckets.create("a", &config).unwrap();
+        buckets.create("b", &config).unwrap();
+        assert!(matches!(
+            buckets.create("c", &config),
+            Err(Error::InvalidRequest(_))
+        ));
+
+        buckets.delete("b").unwrap();
+        buckets.create("c", &config).unwrap();
+    }
+}
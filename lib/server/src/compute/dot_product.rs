@@ -1,16 +1,66 @@
-#[cfg(target_feature = "avx2")]
-use std::arch::x86_64::*;
+use std::sync::OnceLock;
 
 use spiral_rs::arith::*;
 use spiral_rs::params::*;
 use spiral_rs::poly::*;
+use spiral_rs::simd::Simd;
 
 use crate::db::sparse_db::*;
 
 pub const MAX_SUMMED: usize = 1 << 6;
 pub const PACKED_OFFSET_2: i32 = 32;
 
-#[cfg(target_feature = "avx2")]
+/// Multiplies the first-dimension query by a sparse database, accumulating into `out`.
+pub type SparseDotKernel =
+    fn(&mut Vec<PolyMatrixNTT>, &SparseDb, &[u64], &Params, usize, usize, usize);
+
+/// Accumulates the products of one database item with both query rows into an output
+/// `[c1_lo, c1_hi, c2_lo, c2_hi]`, without reducing.
+type AccumulateItem = fn(&mut [u64], &[u64], &[u64], &[u64]);
+
+/// Returns the sparse database multiplication path for the given instruction set.
+///
+/// Panics if the running CPU does not support it.
+pub fn multiply_reg_by_sparse_database_kernel(simd: Simd) -> SparseDotKernel {
+    simd.assert_available();
+    match simd {
+        #[cfg(target_arch = "x86_64")]
+        Simd::Avx2 => |out, db, query, params, dim0, num_per, db_idx| {
+            multiply_reg_by_sparse_database_with(
+                out,
+                db,
+                query,
+                params,
+                (dim0, num_per, db_idx),
+                |out, a1, a2, b| unsafe { x86::accumulate_item_avx2(out, a1, a2, b) },
+            )
+        },
+        #[cfg(target_arch = "x86_64")]
+        Simd::Avx512 => |out, db, query, params, dim0, num_per, db_idx| {
+            multiply_reg_by_sparse_database_with(
+                out,
+                db,
+                query,
+                params,
+                (dim0, num_per, db_idx),
+                |out, a1, a2, b| unsafe { x86::accumulate_item_avx512(out, a1, a2, b) },
+            )
+        },
+        #[cfg(target_arch = "aarch64")]
+        Simd::Neon => |out, db, query, params, dim0, num_per, db_idx| {
+            multiply_reg_by_sparse_database_with(
+                out,
+                db,
+                query,
+                params,
+                (dim0, num_per, db_idx),
+                |out, a1, a2, b| unsafe { neon::accumulate_item(out, a1, a2, b) },
+            )
+        },
+        _ => multiply_reg_by_sparse_database_scalar,
+    }
+}
+
 pub fn multiply_reg_by_sparse_database(
     out: &mut Vec<PolyMatrixNTT>,
     db: &SparseDb,
@@ -20,121 +70,13 @@ pub fn multiply_reg_by_sparse_database(
     num_per: usize,
     db_idx: usize,
 ) {
-    //    db:  [inst_trials, num_per, dim0, poly_len]
-    // query:  [dim0, ct_rows, poly_len]
-
-    let poly_len = params.poly_len;
-    let crt_count = params.crt_count;
-    assert_eq!(crt_count, 2);
-
-    let mut adds = 0;
-
-    unsafe {
-        for j in 0..dim0 {
-            for i in 0..num_per {
-                let full_idx = db_idx * (dim0 * num_per) + j * num_per + i;
-                let result = db.get_idx(full_idx);
-                // if (j * num_per) + i == 14706 {
-                //     println!("sparsedb at 14706 (full_idx: {}) is {:?}", full_idx, result);
-                //     println!("moduli {:?} qmod {}", params.moduli, params.modulus);
-                // }
-                if result.is_none() {
-                    continue;
-                }
-                let real_idx = *result.unwrap();
-                // println!("doing full_idx: {}", full_idx);
-                // println!("at real_idx: {}", real_idx);
-
-                let b_poly = db.data[real_idx].as_slice();
-                // if (j * num_per) + i == 14706 {
-                //     println!(
-                //         "sparsedb at 14706 (full_idx: {}) contains data {} {} ...",
-                //         full_idx, b_poly[0], b_poly[1]
-                //     );
-                //     // println!(
-                //     //     "sparsedb at 14706 (full_idx: {}) contains data {:?}",
-                //     //     full_idx, b_poly
-                //     // );
-                // }
-                for z in (0..poly_len).step_by(4) {
-                    let v_a1 = query.get_unchecked((j * 2) * poly_len + z) as *const u64;
-                    let v_a2 = query.get_unchecked((j * 2 + 1) * poly_len + z) as *const u64;
-                    let v_b = b_poly.get_unchecked(z) as *const u64;
-
-                    let a1 = _mm256_load_si256(v_a1 as *const __m256i);
-                    let a2 = _mm256_load_si256(v_a2 as *const __m256i);
-                    let b = _mm256_load_si256(v_b as *const __m256i);
-
-                    let a1_lo = a1;
-                    let a1_hi = _mm256_srli_epi64(a1, PACKED_OFFSET_2);
-                    let a2_lo = a2;
-                    let a2_hi = _mm256_srli_epi64(a2, PACKED_OFFSET_2);
-                    let b_lo = b;
-                    let b_hi = _mm256_srli_epi64(b, PACKED_OFFSET_2);
-
-                    let c1_lo_loc = ((&mut out[i].data[z]) as *mut u64) as *mut __m256i;
-                    let c1_hi_loc = ((&mut out[i].data[poly_len + z]) as *mut u64) as *mut __m256i;
-                    let c2_lo_loc =
-                        ((&mut out[i].data[2 * poly_len + z]) as *mut u64) as *mut __m256i;
-                    let c2_hi_loc =
-                        ((&mut out[i].data[3 * poly_len + z]) as *mut u64) as *mut __m256i;
-
-                    let mut c1_lo = _mm256_load_si256(c1_lo_loc);
-                    let mut c1_hi = _mm256_load_si256(c1_hi_loc);
-                    let mut c2_lo = _mm256_load_si256(c2_lo_loc);
-                    let mut c2_hi = _mm256_load_si256(c2_hi_loc);
-
-                    c1_lo = _mm256_add_epi64(c1_lo, _mm256_mul_epu32(a1_lo, b_lo));
-                    c1_hi = _mm256_add_epi64(c1_hi, _mm256_mul_epu32(a1_hi, b_hi));
-                    c2_lo = _mm256_add_epi64(c2_lo, _mm256_mul_epu32(a2_lo, b_lo));
-                    c2_hi = _mm256_add_epi64(c2_hi, _mm256_mul_epu32(a2_hi, b_hi));
-
-                    _mm256_store_si256(c1_lo_loc, c1_lo);
-                    _mm256_store_si256(c1_hi_loc, c1_hi);
-                    _mm256_store_si256(c2_lo_loc, c2_lo);
-                    _mm256_store_si256(c2_hi_loc, c2_hi);
-
-                    adds += 1;
-
-                    if adds >= MAX_SUMMED {
-                        adds = 0;
-                        for z_in in 0..4 {
-                            out[i].data[z + z_in] =
-                                barrett_coeff_u64(params, out[i].data[z + z_in], 0);
-                            out[i].data[poly_len + z + z_in] =
-                                barrett_coeff_u64(params, out[i].data[poly_len + z + z_in], 1);
-                            out[i].data[2 * poly_len + z + z_in] =
-                                barrett_coeff_u64(params, out[i].data[2 * poly_len + z + z_in], 0);
-                            out[i].data[3 * poly_len + z + z_in] =
-                                barrett_coeff_u64(params, out[i].data[3 * poly_len + z + z_in], 1);
-                        }
-                        // *c1_lo_loc = barrett_coeff_u64(params, *c1_lo_loc, 0);
-                        // *c1_hi_loc = barrett_coeff_u64(params, *c1_hi_loc, 0);
-                        // *c2_lo_loc = barrett_coeff_u64(params, *c2_lo_loc, 1);
-                        // *c2_hi_loc = barrett_coeff_u64(params, *c2_hi_loc, 1);
-                    }
-                }
-            }
-        }
-
-        for i in 0..num_per {
-            for z in (0..poly_len).step_by(4) {
-                for z_in in 0..4 {
-                    out[i].data[z + z_in] = barrett_coeff_u64(params, out[i].data[z + z_in], 0);
-                    out[i].data[poly_len + z + z_in] =
-                        barrett_coeff_u64(params, out[i].data[poly_len + z + z_in], 1);
-                    out[i].data[2 * poly_len + z + z_in] =
-                        barrett_coeff_u64(params, out[i].data[2 * poly_len + z + z_in], 0);
-                    out[i].data[3 * poly_len + z + z_in] =
-                        barrett_coeff_u64(params, out[i].data[3 * poly_len + z + z_in], 1);
-                }
-            }
-        }
-    }
+    static KERNEL: OnceLock<SparseDotKernel> = OnceLock::new();
+    KERNEL.get_or_init(|| multiply_reg_by_sparse_database_kernel(Simd::detected()))(
+        out, db, query, params, dim0, num_per, db_idx,
+    )
 }
 
-#[cfg(not(target_feature = "avx2"))]
-pub fn multiply_reg_by_sparse_database(
+pub fn multiply_reg_by_sparse_database_scalar(
     out: &mut Vec<PolyMatrixNTT>,
     db: &SparseDb,
     query: &[u64],
@@ -142,6 +84,25 @@ pub fn multiply_reg_by_sparse_database(
     dim0: usize,
     num_per: usize,
     db_idx: usize,
+) {
+    multiply_reg_by_sparse_database_with(
+        out,
+        db,
+        query,
+        params,
+        (dim0, num_per, db_idx),
+        accumulate_item_scalar,
+    )
+}
+
+#[inline(always)]
+fn multiply_reg_by_sparse_database_with(
+    out: &mut [PolyMatrixNTT],
+    db: &SparseDb,
+    query: &[u64],
+    params: &Params,
+    (dim0, num_per, db_idx): (usize, usize, usize),
+    accumulate_item: AccumulateItem,
 ) {
     //    db:  [inst_trials, num_per, dim0, poly_len]
     // query:  [dim0, ct_rows, poly_len]
@@ -150,16 +111,14 @@ pub fn multiply_reg_by_sparse_database(
     let crt_count = params.crt_count;
     assert_eq!(crt_count, 2);
 
-    let lo_mask = (1 << PACKED_OFFSET_2) - 1;
+    // number of unreduced products summed into each output
+    let mut adds = vec![0; num_per];
 
     for j in 0..dim0 {
-        let mut adds = 0;
+        let a1 = &query[(j * 2) * poly_len..(j * 2 + 1) * poly_len];
+        let a2 = &query[(j * 2 + 1) * poly_len..(j * 2 + 2) * poly_len];
 
         for i in 0..num_per {
-            let (part_0, part_1) = out[i].data.as_mut_slice().split_at_mut(2 * poly_len);
-            let (out_0, out_1) = part_0.split_at_mut(poly_len);
-            let (out_2, out_3) = part_1.split_at_mut(poly_len);
-
             let full_idx = db_idx * (dim0 * num_per) + j * num_per + i;
             let result = db.get_idx(full_idx);
             if result.is_none() {
@@ -167,59 +126,172 @@ pub fn multiply_reg_by_sparse_database(
             }
             let real_idx = *result.unwrap();
 
-            let b_poly = db.data[real_idx].as_slice();
-
-            for z in 0..poly_len {
-                let a1 = query[(j * 2) * poly_len + z];
-                let a2 = query[(j * 2 + 1) * poly_len + z];
-                let b = b_poly[z];
-
-                let a1_lo = (a1 & lo_mask) as u32;
-                let a1_hi = (a1 >> PACKED_OFFSET_2) as u32;
-                let a2_lo = (a2 & lo_mask) as u32;
-                let a2_hi = (a2 >> PACKED_OFFSET_2) as u32;
-                let b_lo = (b & lo_mask) as u32;
-                let b_hi = (b >> PACKED_OFFSET_2) as u32;
-
-                let c1_lo_loc = &mut out_0[z];
-                let c1_hi_loc = &mut out_1[z];
-                let c2_lo_loc = &mut out_2[z];
-                let c2_hi_loc = &mut out_3[z];
-
-                *c1_lo_loc += (a1_lo as u64) * (b_lo as u64);
-                *c1_hi_loc += (a1_hi as u64) * (b_hi as u64);
-                *c2_lo_loc += (a2_lo as u64) * (b_lo as u64);
-                *c2_hi_loc += (a2_hi as u64) * (b_hi as u64);
-
-                if adds >= MAX_SUMMED {
-                    *c1_lo_loc = barrett_coeff_u64(params, *c1_lo_loc, 0);
-                    *c1_hi_loc = barrett_coeff_u64(params, *c1_hi_loc, 1);
-                    *c2_lo_loc = barrett_coeff_u64(params, *c2_lo_loc, 0);
-                    *c2_hi_loc = barrett_coeff_u64(params, *c2_hi_loc, 1);
-                }
+            let b_poly = &db.data[real_idx].as_slice()[..poly_len];
+            let out_i = &mut out[i].data.as_mut_slice()[..4 * poly_len];
+            accumulate_item(out_i, a1, a2, b_poly);
+
+            adds[i] += 1;
+            if adds[i] >= MAX_SUMMED {
+                adds[i] = 0;
+                reduce_output(params, out_i);
             }
+        }
+    }
+
+    for out_i in out.iter_mut() {
+        reduce_output(params, &mut out_i.data.as_mut_slice()[..4 * poly_len]);
+    }
+}
 
-            adds += 1;
+fn reduce_output(params: &Params, out: &mut [u64]) {
+    let poly_len = params.poly_len;
+    for (n, poly) in out.chunks_exact_mut(poly_len).enumerate() {
+        for x in poly {
+            *x = barrett_coeff_u64(params, *x, n % 2);
+        }
+    }
+}
 
-            if adds >= MAX_SUMMED {
-                adds = 0;
+fn accumulate_item_scalar(out: &mut [u64], a1: &[u64], a2: &[u64], b_poly: &[u64]) {
+    let poly_len = b_poly.len();
+    let lo_mask = (1 << PACKED_OFFSET_2) - 1;
+
+    let (part_0, part_1) = out.split_at_mut(2 * poly_len);
+    let (out_0, out_1) = part_0.split_at_mut(poly_len);
+    let (out_2, out_3) = part_1.split_at_mut(poly_len);
+
+    for z in 0..poly_len {
+        let a1 = a1[z];
+        let a2 = a2[z];
+        let b = b_poly[z];
+
+        let a1_lo = (a1 & lo_mask) as u32;
+        let a1_hi = (a1 >> PACKED_OFFSET_2) as u32;
+        let a2_lo = (a2 & lo_mask) as u32;
+        let a2_hi = (a2 >> PACKED_OFFSET_2) as u32;
+        let b_lo = (b & lo_mask) as u32;
+        let b_hi = (b >> PACKED_OFFSET_2) as u32;
+
+        out_0[z] += (a1_lo as u64) * (b_lo as u64);
+        out_1[z] += (a1_hi as u64) * (b_hi as u64);
+        out_2[z] += (a2_lo as u64) * (b_lo as u64);
+        out_3[z] += (a2_hi as u64) * (b_hi as u64);
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use std::arch::x86_64::*;
+
+    use super::PACKED_OFFSET_2;
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn accumulate_item_avx2(out: &mut [u64], a1: &[u64], a2: &[u64], b_poly: &[u64]) {
+        let poly_len = b_poly.len();
+        assert_eq!(out.len(), 4 * poly_len);
+        assert!(a1.len() == poly_len && a2.len() == poly_len);
+
+        for z in (0..poly_len).step_by(4) {
+            let v_a1 = a1.get_unchecked(z) as *const u64;
+            let v_a2 = a2.get_unchecked(z) as *const u64;
+            let v_b = b_poly.get_unchecked(z) as *const u64;
+
+            let a1 = _mm256_load_si256(v_a1 as *const __m256i);
+            let a2 = _mm256_load_si256(v_a2 as *const __m256i);
+            let b = _mm256_load_si256(v_b as *const __m256i);
+
+            let a1_lo = a1;
+            let a1_hi = _mm256_srli_epi64(a1, PACKED_OFFSET_2);
+            let a2_lo = a2;
+            let a2_hi = _mm256_srli_epi64(a2, PACKED_OFFSET_2);
+            let b_lo = b;
+            let b_hi = _mm256_srli_epi64(b, PACKED_OFFSET_2);
+
+            let c1_lo_loc = out.as_mut_ptr().add(z) as *mut __m256i;
+            let c1_hi_loc = out.as_mut_ptr().add(poly_len + z) as *mut __m256i;
+            let c2_lo_loc = out.as_mut_ptr().add(2 * poly_len + z) as *mut __m256i;
+            let c2_hi_loc = out.as_mut_ptr().add(3 * poly_len + z) as *mut __m256i;
+
+            let mut c1_lo = _mm256_load_si256(c1_lo_loc);
+            let mut c1_hi = _mm256_load_si256(c1_hi_loc);
+            let mut c2_lo = _mm256_load_si256(c2_lo_loc);
+            let mut c2_hi = _mm256_load_si256(c2_hi_loc);
+
+            c1_lo = _mm256_add_epi64(c1_lo, _mm256_mul_epu32(a1_lo, b_lo));
+            c1_hi = _mm256_add_epi64(c1_hi, _mm256_mul_epu32(a1_hi, b_hi));
+            c2_lo = _mm256_add_epi64(c2_lo, _mm256_mul_epu32(a2_lo, b_lo));
+            c2_hi = _mm256_add_epi64(c2_hi, _mm256_mul_epu32(a2_hi, b_hi));
+
+            _mm256_store_si256(c1_lo_loc, c1_lo);
+            _mm256_store_si256(c1_hi_loc, c1_hi);
+            _mm256_store_si256(c2_lo_loc, c2_lo);
+            _mm256_store_si256(c2_hi_loc, c2_hi);
+        }
+    }
+
+    #[target_feature(enable = "avx512f")]
+    pub unsafe fn accumulate_item_avx512(out: &mut [u64], a1: &[u64], a2: &[u64], b_poly: &[u64]) {
+        let poly_len = b_poly.len();
+        assert_eq!(out.len(), 4 * poly_len);
+        assert!(a1.len() == poly_len && a2.len() == poly_len);
+
+        for z in (0..poly_len).step_by(8) {
+            let a1 = _mm512_loadu_si512(a1.as_ptr().add(z) as *const _);
+            let a2 = _mm512_loadu_si512(a2.as_ptr().add(z) as *const _);
+            let b = _mm512_loadu_si512(b_poly.as_ptr().add(z) as *const _);
+
+            let a1_hi = _mm512_srli_epi64(a1, PACKED_OFFSET_2 as u32);
+            let a2_hi = _mm512_srli_epi64(a2, PACKED_OFFSET_2 as u32);
+            let b_hi = _mm512_srli_epi64(b, PACKED_OFFSET_2 as u32);
+
+            let products = [
+                _mm512_mul_epu32(a1, b),
+                _mm512_mul_epu32(a1_hi, b_hi),
+                _mm512_mul_epu32(a2, b),
+                _mm512_mul_epu32(a2_hi, b_hi),
+            ];
+            for (n, product) in products.into_iter().enumerate() {
+                let c_loc = out.as_mut_ptr().add(n * poly_len + z);
+                let c = _mm512_loadu_si512(c_loc as *const _);
+                _mm512_storeu_si512(c_loc as *mut _, _mm512_add_epi64(c, product));
             }
         }
     }
+}
 
-    for i in 0..num_per {
-        for z in 0..poly_len {
-            out[i].data[z] = barrett_coeff_u64(params, out[i].data[z], 0);
-            out[i].data[poly_len + z] = barrett_coeff_u64(params, out[i].data[poly_len + z], 1);
-            out[i].data[2 * poly_len + z] =
-                barrett_coeff_u64(params, out[i].data[2 * poly_len + z], 0);
-            out[i].data[3 * poly_len + z] =
-                barrett_coeff_u64(params, out[i].data[3 * poly_len + z], 1);
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use std::arch::aarch64::*;
+
+    #[target_feature(enable = "neon")]
+    pub unsafe fn accumulate_item(out: &mut [u64], a1: &[u64], a2: &[u64], b_poly: &[u64]) {
+        let poly_len = b_poly.len();
+        assert_eq!(out.len(), 4 * poly_len);
+        assert!(a1.len() == poly_len && a2.len() == poly_len);
+
+        for z in (0..poly_len).step_by(2) {
+            let a1 = vld1q_u64(a1.as_ptr().add(z));
+            let a2 = vld1q_u64(a2.as_ptr().add(z));
+            let b = vld1q_u64(b_poly.as_ptr().add(z));
+
+            let (a1_lo, a1_hi) = (vmovn_u64(a1), vshrn_n_u64::<32>(a1));
+            let (a2_lo, a2_hi) = (vmovn_u64(a2), vshrn_n_u64::<32>(a2));
+            let (b_lo, b_hi) = (vmovn_u64(b), vshrn_n_u64::<32>(b));
+
+            let products = [
+                vmull_u32(a1_lo, b_lo),
+                vmull_u32(a1_hi, b_hi),
+                vmull_u32(a2_lo, b_lo),
+                vmull_u32(a2_hi, b_hi),
+            ];
+            for (n, product) in products.into_iter().enumerate() {
+                let c_loc = out.as_mut_ptr().add(n * poly_len + z);
+                vst1q_u64(c_loc, vaddq_u64(vld1q_u64(c_loc), product));
+            }
         }
     }
 }
 
-#[cfg(not(target_feature = "avx2"))]
 pub fn multiply_reg_by_database(
     out: &mut Vec<PolyMatrixNTT>,
     db: &[u64],
@@ -462,4 +534,53 @@ mod test {
             assert_eq!(dec_rescaled.data[z], corr_item.data[z]);
         }
     }
+
+    #[test]
+    fn multiply_reg_by_sparse_database_kernels_agree() {
+        let cfg = r#"
+            {'n': 4,
+            'nu_1': 9,
+            'nu_2': 5,
+            'p': 256,
+            'q2_bits': 20,
+            't_gsw': 9,
+            't_conv': 4,
+            't_exp_left': 8,
+            't_exp_right': 28,
+            'instances': 1,
+            'db_item_size': 32768 }
+        "#;
+        let params = util::params_from_json(&cfg.replace("'", "\""));
+
+        let mut rng = ChaCha20Rng::from_entropy();
+
+        let dim0 = 1 << params.db_dim_1;
+        let num_per = 1 << params.db_dim_2;
+
+        let (_, db) = generate_fake_sparse_db_and_get_item(&params, 0, params.num_items() / 4);
+
+        let mut query = AlignedMemory64::new(dim0 * 2 * params.poly_len);
+        for x in query.as_mut_slice() {
+            let lo = rng.gen::<u64>() % params.moduli[0];
+            let hi = rng.gen::<u64>() % params.moduli[1];
+            *x = lo | (hi << PACKED_OFFSET_2);
+        }
+
+        let run = |kernel: SparseDotKernel| {
+            let mut out = Vec::with_capacity(num_per);
+            for _ in 0..num_per {
+                out.push(PolyMatrixNTT::zero(&params, 2, 1));
+            }
+            kernel(&mut out, &db, query.as_slice(), &params, dim0, num_per, 0);
+            out
+        };
+
+        let expected = run(multiply_reg_by_sparse_database_scalar);
+        for simd in Simd::available() {
+            let out = run(multiply_reg_by_sparse_database_kernel(simd));
+            for (res, exp) in out.iter().zip(expected.iter()) {
+                assert_eq!(res.as_slice(), exp.as_slice(), "{:?}", simd);
+            }
+        }
+    }
 }
@@ -77,25 +77,14 @@ pub fn coefficient_expansion(
             let mut ct_auto_1_ntt = PolyMatrixNTT::zero(params, 1, 1);
             let mut w_times_ginv_ct = PolyMatrixNTT::zero(params, 2, 1);
 
-            let mut ginv_ct_left = PolyMatrixRaw::zero(params, params.t_exp_left, 1);
-            let mut ginv_ct_left_ntt = PolyMatrixNTT::zero(params, params.t_exp_left, 1);
-            let mut ginv_ct_right = PolyMatrixRaw::zero(params, params.t_exp_right, 1);
-            let mut ginv_ct_right_ntt = PolyMatrixNTT::zero(params, params.t_exp_right, 1);
-
-            let (w, _gadget_dim, gi_ct, gi_ct_ntt) = match (r != 0) && (i % 2 == 0) {
-                true => (
-                    &v_w_left[r],
-                    params.t_exp_left,
-                    &mut ginv_ct_left,
-                    &mut ginv_ct_left_ntt,
-                ),
-                false => (
-                    &v_w_right[r],
-                    params.t_exp_right,
-                    &mut ginv_ct_right,
-                    &mut ginv_ct_right_ntt,
-                ),
+            let w = match (r != 0) && (i % 2 == 0) {
+                true => &v_w_left[r],
+                false => &v_w_right[r],
             };
+            // the right keys of a batch query end with left keys (see `expand_batch_query`),
+            // so the gadget dimension comes from the key
+            let gi_ct = &mut PolyMatrixRaw::zero(params, w.cols, 1);
+            let gi_ct_ntt = &mut PolyMatrixNTT::zero(params, w.cols, 1);
 
             // if i < num_in {
             //     let (src, dest) = v.split_at_mut(num_in);
@@ -247,22 +236,29 @@ pub fn to_per_round_set(params: &Params, indices: &HashSet<usize>) -> HashSet<(u
     to_do
 }
 
+/// Expands a batch query of `num_partitions` partitions (see `Client::generate_batch_query`),
+/// skipping the first-dimension ciphertexts of rows without any of the given `indices`.
+///
+/// Returns the first-dimension ciphertexts, which select one row in each partition, and the
+/// folding ciphertexts of each partition. The GSW ciphertexts of a batch need more expansion
+/// rounds than the right expansion keys cover; those rounds use the left keys.
 pub fn expand_query<'a>(
     params: &'a Params,
     public_params: &PublicParameters<'a>,
     query: &Query<'a>,
+    num_partitions: usize,
     indices: Option<&HashMap<usize, usize>>,
-) -> (AlignedMemory64, Vec<PolyMatrixNTT<'a>>) {
+) -> (AlignedMemory64, Vec<Vec<PolyMatrixNTT<'a>>>) {
     let dim0 = 1 << params.db_dim_1;
     let further_dims = params.db_dim_2;
 
     let mut v_reg_reoriented;
-    let mut v_folding;
 
     let num_bits_to_gen = params.t_gsw * further_dims + dim0;
     let g = log2_ceil_usize(num_bits_to_gen);
-    let right_expanded = params.t_gsw * further_dims;
-    let stop_round = log2_ceil_usize(right_expanded);
+    let right_expanded = num_partitions * params.t_gsw * further_dims;
+    let stop_round = batch_stop_round(params, num_partitions);
+    assert!(further_dims == 0 || stop_round < g);
 
     let mut v = Vec::new();
     for _ in 0..(1 << g) {
@@ -273,6 +269,9 @@ pub fn expand_query<'a>(
     let v_conversion = &public_params.v_conversion.as_ref().unwrap()[0];
     let v_w_left = public_params.v_expansion_left.as_ref().unwrap();
     let v_w_right = public_params.v_expansion_right.as_ref().unwrap_or(v_w_left);
+    let v_w_right: Vec<_> = (0..=stop_round)
+        .map(|r| v_w_right.get(r).unwrap_or(&v_w_left[r]).clone())
+        .collect();
     let v_neg1 = params.get_v_neg1();
 
     let mut v_reg_inp = Vec::with_capacity(dim0);
@@ -315,7 +314,7 @@ pub fn expand_query<'a>(
             &v_w_left,
             &v_w_right,
             &v_neg1,
-            params.t_gsw * params.db_dim_2,
+            right_expanded,
             indices_to_do,
         );
 
@@ -346,14 +345,26 @@ pub fn expand_query<'a>(
     v_reg_reoriented = AlignedMemory64::new(v_reg_sz);
     reorient_reg_ciphertexts(params, v_reg_reoriented.as_mut_slice(), &v_reg_inp);
 
-    v_folding = Vec::new();
-    for _ in 0..params.db_dim_2 {
-        v_folding.push(PolyMatrixNTT::zero(params, 2, 2 * params.t_gsw));
-    }
-
-    regev_to_gsw(&mut v_folding, &v_gsw_inp, &v_conversion, params, 1, 0);
+    let v_foldings = (0..num_partitions)
+        .map(|partition| {
+            let mut v_folding = Vec::new();
+            for _ in 0..params.db_dim_2 {
+                v_folding.push(PolyMatrixNTT::zero(params, 2, 2 * params.t_gsw));
+            }
+            let gsw_per_partition = params.t_gsw * further_dims;
+            regev_to_gsw(
+                &mut v_folding,
+                &v_gsw_inp,
+                &v_conversion,
+                params,
+                1,
+                partition * gsw_per_partition,
+            );
+            v_folding
+        })
+        .collect();
 
-    (v_reg_reoriented, v_folding)
+    (v_reg_reoriented, v_foldings)
 }
 
 #[cfg(test)]
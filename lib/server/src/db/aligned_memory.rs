@@ -1,17 +1,23 @@
 use std::{
     alloc::{alloc_zeroed, dealloc, realloc, Layout},
+    fs::File,
+    io,
     mem::size_of,
     ops::{Index, IndexMut},
     slice::{from_raw_parts, from_raw_parts_mut},
 };
 
+use memmap2::{MmapMut, MmapOptions};
+
 const ALIGN_SIMD: usize = 64; // enough to support AVX-512
 pub type AlignedMemory64 = AlignedMemory<ALIGN_SIMD>;
 
+/// Memory aligned to `ALIGN` bytes, either allocated or mapped from a file.
 pub struct AlignedMemory<const ALIGN: usize> {
     p: *mut u64,
     sz_u64: usize,
     layout: Layout,
+    mapping: Option<MmapMut>,
 }
 
 impl<const ALIGN: usize> AlignedMemory<{ ALIGN }> {
@@ -28,10 +34,39 @@ impl<const ALIGN: usize> AlignedMemory<{ ALIGN }> {
             p: ptr as *mut u64,
             sz_u64,
             layout,
+            mapping: None,
         }
     }
 
+    /// Maps `sz_u64` words from the start of `file`, copy-on-write.
+    ///
+    /// Pages are only read in when first used, and are shared with other processes
+    /// mapping the same file until they are written to. Writes never reach the file.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be modified while it is mapped.
+    pub unsafe fn map_file(file: &File, sz_u64: usize) -> io::Result<Self> {
+        let sz_bytes = sz_u64 * size_of::<u64>();
+        let mut mapping = MmapOptions::new().len(sz_bytes).map_copy(file)?;
+        let p = mapping.as_mut_ptr() as *mut u64;
+        // mappings are page-aligned
+        assert_eq!(p as usize % ALIGN, 0);
+
+        Ok(Self {
+            p,
+            sz_u64,
+            layout: Layout::from_size_align(sz_bytes, ALIGN).unwrap(),
+            mapping: Some(mapping),
+        })
+    }
+
+    pub fn is_mapped(&self) -> bool {
+        self.mapping.is_some()
+    }
+
     pub fn extend(&mut self, new_size: usize) {
+        assert!(!self.is_mapped(), "cannot resize mapped memory");
         self.sz_u64 = new_size;
         let new_size = self.sz_u64 * size_of::<u64>();
         unsafe {
@@ -72,8 +107,11 @@ unsafe impl<const ALIGN: usize> Sync for AlignedMemory<{ ALIGN }> {}
 
 impl<const ALIGN: usize> Drop for AlignedMemory<{ ALIGN }> {
     fn drop(&mut self) {
-        unsafe {
-            dealloc(self.p as *mut u8, self.layout);
+        // mappings are unmapped when dropped
+        if self.mapping.is_none() {
+            unsafe {
+                dealloc(self.p as *mut u8, self.layout);
+            }
         }
     }
 }
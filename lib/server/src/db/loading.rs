@@ -5,6 +5,7 @@ use std::io::BufReader;
 use std::io::Read;
 use std::io::Seek;
 use std::io::SeekFrom;
+use std::io::Write;
 use std::time::Instant;
 
 use rand::thread_rng;
@@ -32,18 +33,20 @@ pub fn get_inv_idx(params: &Params, item_idx: usize) -> usize {
 }
 
 pub fn pack_ntt_poly(poly: &PolyMatrixNTT) -> Vec<u64> {
-    let mut v = vec![0u64; poly.params.poly_len];
-    for z in 0..poly.params.poly_len {
+    let poly_len = poly.get_params().poly_len;
+    let mut v = vec![0u64; poly_len];
+    for z in 0..poly_len {
         v[z] = poly.data[z]
-            | (poly.data[poly.params.poly_len + z] << crate::compute::dot_product::PACKED_OFFSET_2);
+            | (poly.data[poly_len + z] << crate::compute::dot_product::PACKED_OFFSET_2);
     }
     v
 }
 
 pub fn pack_ntt_poly_inplace(poly: &PolyMatrixNTT, out: &mut [u64]) {
-    for z in 0..poly.params.poly_len {
+    let poly_len = poly.get_params().poly_len;
+    for z in 0..poly_len {
         out[z] = poly.data[z]
-            | (poly.data[poly.params.poly_len + z] << crate::compute::dot_product::PACKED_OFFSET_2);
+            | (poly.data[poly_len + z] << crate::compute::dot_product::PACKED_OFFSET_2);
     }
 }
 
@@ -275,6 +278,37 @@ pub fn load_preprocessed_db_from_file(params: &Params, file: &mut File) -> Align
     v
 }
 
+/// Writes a database in the format read by `load_preprocessed_db_from_file` and
+/// `map_preprocessed_db_from_file`.
+pub fn save_preprocessed_db_to_file(db: &AlignedMemory64, file: &mut File) -> Result<(), Error> {
+    let data_as_u8 = unsafe { db.as_slice().align_to::<u8>().1 };
+    file.write_all(data_as_u8)?;
+    Ok(())
+}
+
+/// Maps a preprocessed database from a file, instead of reading it into memory.
+///
+/// This returns immediately regardless of the database size, and the database is
+/// shared with other processes serving the same file. The file must not be
+/// modified while the database is in use.
+pub fn map_preprocessed_db_from_file(
+    params: &Params,
+    file: &File,
+) -> Result<AlignedMemory64, Error> {
+    let instances = params.instances;
+    let trials = params.n * params.n;
+    let dim0 = 1 << params.db_dim_1;
+    let num_per = 1 << params.db_dim_2;
+    let num_items = dim0 * num_per;
+    let db_size_words = instances * trials * num_items * params.poly_len;
+
+    let len = file.metadata()?.len() as usize;
+    if len != db_size_words * 8 {
+        return Err(Error::InvalidLength(len, db_size_words * 8));
+    }
+    Ok(unsafe { AlignedMemory64::map_file(file, db_size_words)? })
+}
+
 pub fn convert_pt_to_poly<'a>(params: &'a Params, data: &[u8]) -> PolyMatrixNTT<'a> {
     let logp = f64::ceil(f64::log2(params.pt_modulus as f64)) as usize;
     let modp_words_per_chunk = params.poly_len; //params.modp_words_per_chunk();
@@ -308,6 +342,9 @@ pub fn update_item(params: &Params, body: &[u8], db: &mut SparseDb) -> Result<u6
     if body.len() > max_update_len {
         return Err(Error::InvalidLength(body.len(), max_update_len));
     }
+    if body.len() < 4 {
+        return Err(Error::InvalidLength(body.len(), 4));
+    }
 
     let db_idx = u32::from_be_bytes(body[..4].try_into().unwrap()) as usize;
 
@@ -325,18 +362,20 @@ pub fn update_item_raw(
     let pt_data_len = params.bytes_per_chunk();
 
     let mut new_bucket = vec![0u8; instances * trials * pt_data_len];
+    if data.len() > new_bucket.len() {
+        return Err(Error::RowOverflow(db_idx, data.len(), new_bucket.len()));
+    }
     new_bucket[..data.len()].copy_from_slice(&data);
     let inp = new_bucket.as_slice();
 
     assert_eq!(inp.len() % pt_data_len, 0);
 
     if db_idx >= params.num_items() {
-        println!(
+        return Err(Error::InvalidRequest(format!(
             "bad db idx {} (expected less than {})",
             db_idx,
             params.num_items()
-        );
-        return Err(Error::Unknown);
+        )));
     }
 
     // set item to bytes
@@ -358,13 +397,27 @@ pub fn update_item_raw(
     Ok(upsert_time as u64)
 }
 
+/// Removes every polynomial of the item at `db_idx` from the database.
+pub fn remove_item_raw(params: &Params, db_idx: usize, db: &mut SparseDb) {
+    let instances = params.instances;
+    let trials = params.n * params.n;
+    for inst_trial in 0..instances * trials {
+        db.remove(inst_trial * params.num_items() + db_idx);
+    }
+}
+
 pub fn update_many_items(params: &Params, body: &[u8], db: &mut SparseDb) -> Result<u64, Error> {
     let mut offs = 0;
     let mut largest_update = 0;
 
     while offs < body.len() {
-        let chunk_len = u32::from_be_bytes(body[offs..offs + 4].try_into().unwrap()) as usize;
-        let data = &body[offs + 4..offs + 4 + chunk_len];
+        let len_bytes = body
+            .get(offs..offs + 4)
+            .ok_or(Error::InvalidLength(body.len(), offs + 4))?;
+        let chunk_len = u32::from_be_bytes(len_bytes.try_into().unwrap()) as usize;
+        let data = body
+            .get(offs + 4..offs + 4 + chunk_len)
+            .ok_or(Error::InvalidLength(body.len(), offs + 4 + chunk_len))?;
         if data.len() > largest_update {
             largest_update = data.len();
         }
@@ -375,3 +428,54 @@ pub fn update_many_items(params: &Params, body: &[u8], db: &mut SparseDb) -> Res
 
     Ok(largest_update as u64)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn mapped_db_matches_loaded_db() {
+        let params = params_from_json(
+            r#"{
+            "n": 2,
+            "nu_1": 6,
+            "nu_2": 2,
+            "p": 256,
+            "q2_bits": 20,
+            "t_gsw": 8,
+            "t_conv": 4,
+            "t_exp_left": 8,
+            "t_exp_right": 8,
+            "instances": 1,
+            "db_item_size": 8192
+        }"#,
+        );
+        let (_, db) = generate_random_db_and_get_item(&params, 0);
+
+        let path = std::env::temp_dir().join(format!("spiral-server-db-{}", std::process::id()));
+        save_preprocessed_db_to_file(&db, &mut File::create(&path).unwrap()).unwrap();
+
+        let loaded = load_preprocessed_db_from_file(&params, &mut File::open(&path).unwrap());
+        let mut mapped =
+            map_preprocessed_db_from_file(&params, &File::open(&path).unwrap()).unwrap();
+        assert!(mapped.is_mapped());
+        assert_eq!(unsafe { mapped.as_ptr() } as usize % 64, 0);
+        assert_eq!(mapped.as_slice(), db.as_slice());
+        assert_eq!(loaded.as_slice(), db.as_slice());
+
+        // writes stay private to the mapping
+        mapped[0] ^= 1;
+        let reloaded = load_preprocessed_db_from_file(&params, &mut File::open(&path).unwrap());
+        assert_eq!(reloaded.as_slice(), db.as_slice());
+
+        drop(mapped);
+        let mut short = File::create(&path).unwrap();
+        short.write_all(&[0u8; 8]).unwrap();
+        assert!(matches!(
+            map_preprocessed_db_from_file(&params, &File::open(&path).unwrap()),
+            Err(Error::InvalidLength(8, _))
+        ));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
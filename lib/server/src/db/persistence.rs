@@ -0,0 +1,389 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufReader, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use spiral_rs::params::Params;
+
+use crate::error::Error;
+
+use super::{sparse_db::SparseDb, write::encode_row};
+
+const LOG_FNAME: &str = "writes.log";
+const SNAPSHOT_FNAME: &str = "rows.snapshot";
+const SNAPSHOT_TMP_FNAME: &str = "rows.snapshot.tmp";
+
+const SNAPSHOT_MAGIC: &[u8; 4] = b"SPRS";
+const LOG_RECORD_MAGIC: &[u8; 4] = b"SPWR";
+
+/// Default number of logged writes between snapshots.
+pub const DEFAULT_SNAPSHOT_INTERVAL: usize = 1024;
+
+/// On-disk state for a single database.
+///
+/// Every call to `/write` is appended to `writes.log` before it is applied.
+/// Every `snapshot_interval` writes, the raw rows and the version are written to
+/// `rows.snapshot`, and the log is truncated. On startup, the snapshot is loaded
+/// and the log is replayed on top of it.
+///
+/// The NTT-encoded `SparseDb` is not stored; it is rebuilt from the raw rows.
+pub struct Persistence {
+    dir: PathBuf,
+    log: File,
+    /// Length of the log up to the end of its last complete record.
+    log_len: u64,
+    writes_since_snapshot: usize,
+    snapshot_interval: usize,
+}
+
+/// The state recovered from a data directory.
+pub struct RecoveredState {
+    pub rows: Vec<Vec<u8>>,
+    pub version: u64,
+}
+
+fn write_u64(out: &mut Vec<u8>, val: u64) {
+    out.extend_from_slice(&val.to_le_bytes());
+}
+
+fn write_bytes(out: &mut Vec<u8>, data: &[u8]) {
+    write_u64(out, data.len() as u64);
+    out.extend_from_slice(data);
+}
+
+/// Cursor over a byte buffer that returns `None` instead of panicking on truncated input.
+struct Reader<'a> {
+    data: &'a [u8],
+    offs: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, offs: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.offs >= self.data.len()
+    }
+
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let end = self.offs.checked_add(len)?;
+        let out = self.data.get(self.offs..end)?;
+        self.offs = end;
+        Some(out)
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn bytes(&mut self) -> Option<&'a [u8]> {
+        let len = self.u64()? as usize;
+        self.take(len)
+    }
+}
+
+fn encode_log_record(version: u64, kv_pairs: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut body = Vec::new();
+    write_u64(&mut body, version);
+    write_u64(&mut body, kv_pairs.len() as u64);
+    for (key, value) in kv_pairs {
+        write_bytes(&mut body, key.as_bytes());
+        write_bytes(&mut body, value);
+    }
+
+    let mut record = LOG_RECORD_MAGIC.to_vec();
+    write_bytes(&mut record, &body);
+    record
+}
+
+type LogRecord = (u64, Vec<(String, Vec<u8>)>);
+
+fn decode_log_record(body: &[u8]) -> Option<LogRecord> {
+    let mut reader = Reader::new(body);
+    let version = reader.u64()?;
+    let num_pairs = reader.u64()?;
+    let mut kv_pairs = Vec::new();
+    for _ in 0..num_pairs {
+        let key = String::from_utf8(reader.bytes()?.to_vec()).ok()?;
+        let value = reader.bytes()?.to_vec();
+        kv_pairs.push((key, value));
+    }
+    Some((version, kv_pairs))
+}
+
+fn encode_snapshot(version: u64, rows: &[Vec<u8>]) -> Vec<u8> {
+    let mut out = SNAPSHOT_MAGIC.to_vec();
+    write_u64(&mut out, version);
+    write_u64(&mut out, rows.len() as u64);
+    let non_empty = rows.iter().filter(|row| !row.is_empty()).count();
+    write_u64(&mut out, non_empty as u64);
+    for (row_id, row) in rows.iter().enumerate() {
+        if !row.is_empty() {
+            write_u64(&mut out, row_id as u64);
+            write_bytes(&mut out, row);
+        }
+    }
+    out
+}
+
+fn decode_snapshot(data: &[u8], num_items: usize) -> Result<RecoveredState, Error> {
+    let mut reader = Reader::new(data);
+    if reader.take(SNAPSHOT_MAGIC.len()) != Some(SNAPSHOT_MAGIC) {
+        return Err(Error::Corrupted("bad snapshot header".to_owned()));
+    }
+    let truncated = || Error::Corrupted("truncated snapshot".to_owned());
+
+    let version = reader.u64().ok_or_else(truncated)?;
+    let num_rows = reader.u64().ok_or_else(truncated)? as usize;
+    if num_rows != num_items {
+        return Err(Error::InvalidLength(num_rows, num_items));
+    }
+
+    let mut rows = vec![Vec::new(); num_items];
+    let non_empty = reader.u64().ok_or_else(truncated)?;
+    for _ in 0..non_empty {
+        let row_id = reader.u64().ok_or_else(truncated)? as usize;
+        let row = reader.bytes().ok_or_else(truncated)?;
+        if row_id >= num_items {
+            return Err(Error::Corrupted(format!("row {} out of range", row_id)));
+        }
+        rows[row_id] = row.to_vec();
+    }
+
+    Ok(RecoveredState { rows, version })
+}
+
+impl Persistence {
+    /// Opens (creating, if needed) the data directory at `dir`.
+    pub fn open<P: AsRef<Path>>(dir: P, snapshot_interval: usize) -> Result<Self, Error> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(LOG_FNAME))?;
+
+        Ok(Self {
+            dir,
+            log_len: log.metadata()?.len(),
+            log,
+            writes_since_snapshot: 0,
+            snapshot_interval: usize::max(snapshot_interval, 1),
+        })
+    }
+
+    /// Loads the latest snapshot and replays the write log on top of it,
+    /// applying each logged write to the rows with `apply`.
+    ///
+    /// A partially-written record at the end of the log (from a crash during
+    /// `log_write`) was never acknowledged, so it is discarded.
+    pub fn recover<F>(&mut self, num_items: usize, mut apply: F) -> Result<RecoveredState, Error>
+    where
+        F: FnMut(&[(&str, &[u8])], &mut [Vec<u8>]) -> Result<(), Error>,
+    {
+        let mut state = match fs::read(self.dir.join(SNAPSHOT_FNAME)) {
+            Ok(data) => decode_snapshot(&data, num_items)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => RecoveredState {
+                rows: vec![Vec::new(); num_items],
+                version: 0,
+            },
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut log_data = Vec::new();
+        BufReader::new(File::open(self.dir.join(LOG_FNAME))?).read_to_end(&mut log_data)?;
+
+        let mut reader = Reader::new(&log_data);
+        let mut valid_len = 0;
+        let mut replayed = 0;
+        while !reader.is_empty() {
+            let record = match (reader.take(LOG_RECORD_MAGIC.len()), reader.bytes()) {
+                (Some(magic), Some(body)) if magic == LOG_RECORD_MAGIC => decode_log_record(body),
+                _ => None,
+            };
+            let Some((version, kv_pairs)) = record else {
+                println!("discarding incomplete record at end of write log");
+                self.log.set_len(valid_len as u64)?;
+                break;
+            };
+            valid_len = reader.offs;
+
+            // records older than the snapshot were already folded into it
+            if version <= state.version {
+                continue;
+            }
+
+            let kv_pairs_slices: Vec<(&str, &[u8])> = kv_pairs
+                .iter()
+                .map(|(key, value)| (key.as_str(), value.as_slice()))
+                .collect();
+            apply(&kv_pairs_slices, &mut state.rows)?;
+            state.version = version;
+            replayed += 1;
+        }
+        self.log_len = valid_len as u64;
+        self.writes_since_snapshot = replayed;
+
+        Ok(state)
+    }
+
+    /// Durably appends a write to the log. Must be called before the write is applied.
+    ///
+    /// If this fails, the partial record is cut off the log (now, or before the next
+    /// record is appended), so that later records are not lost behind it on recovery.
+    pub fn log_write(&mut self, version: u64, kv_pairs: &[(&str, &[u8])]) -> Result<(), Error> {
+        if self.log.metadata()?.len() != self.log_len {
+            self.log.set_len(self.log_len)?;
+        }
+
+        let record = encode_log_record(version, kv_pairs);
+        let result = self
+            .log
+            .write_all(&record)
+            .and_then(|_| self.log.sync_data());
+        if let Err(e) = result {
+            let _ = self.log.set_len(self.log_len);
+            return Err(e.into());
+        }
+
+        self.log_len += record.len() as u64;
+        self.writes_since_snapshot += 1;
+        Ok(())
+    }
+
+    /// Returns whether enough writes have been logged that a snapshot should be taken.
+    pub fn should_snapshot(&self) -> bool {
+        self.writes_since_snapshot >= self.snapshot_interval
+    }
+
+    /// Atomically replaces the snapshot with the given rows, and truncates the write log.
+    pub fn snapshot(&mut self, version: u64, rows: &[Vec<u8>]) -> Result<(), Error> {
+        let tmp_path = self.dir.join(SNAPSHOT_TMP_FNAME);
+        let mut tmp_file = File::create(&tmp_path)?;
+        tmp_file.write_all(&encode_snapshot(version, rows))?;
+        tmp_file.sync_all()?;
+        fs::rename(&tmp_path, self.dir.join(SNAPSHOT_FNAME))?;
+
+        self.log.set_len(0)?;
+        self.log.sync_all()?;
+        self.log_len = 0;
+        self.writes_since_snapshot = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl Persistence {
+    /// Reopens the log read-only (so that logging fails), or for appending again.
+    pub(crate) fn set_log_writable(&mut self, writable: bool) -> Result<(), Error> {
+        let path = self.dir.join(LOG_FNAME);
+        self.log = match writable {
+            true => OpenOptions::new().append(true).open(path)?,
+            false => File::open(path)?,
+        };
+        Ok(())
+    }
+}
+
+/// Builds the NTT-encoded database from a full set of raw rows.
+pub fn rebuild_db(params: &Params, rows: &[Vec<u8>]) -> SparseDb {
+    let mut db = SparseDb::new();
+    for (row_id, row) in rows.iter().enumerate() {
+        if !row.is_empty() {
+            encode_row(params, row_id, row, &mut db);
+        }
+    }
+    db
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use crate::db::write::update_row;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        std::env::temp_dir().join(format!("spiral-server-{}-{}", name, nanos))
+    }
+
+    const NUM_ITEMS: usize = 64;
+
+    /// Places keys by length; recovery only needs the placement to be deterministic.
+    fn apply(kv: &[(&str, &[u8])], rows: &mut [Vec<u8>]) -> Result<(), Error> {
+        for (key, value) in kv {
+            update_row(&mut rows[key.len() % rows.len()], key, value);
+        }
+        Ok(())
+    }
+
+    fn write(p: &mut Persistence, rows: &mut [Vec<u8>], version: u64, kv: &[(&str, &[u8])]) {
+        apply(kv, rows).unwrap();
+        p.log_write(version, kv).unwrap();
+    }
+
+    #[test]
+    fn recovers_rows_and_version_after_restart() {
+        let dir = temp_dir("recover");
+        let mut rows = vec![Vec::new(); NUM_ITEMS];
+        {
+            let mut p = Persistence::open(&dir, 2).unwrap();
+            assert_eq!(p.recover(NUM_ITEMS, apply).unwrap().version, 0);
+
+            write(&mut p, &mut rows, 1, &[("a", b"1"), ("b", b"2")]);
+            write(&mut p, &mut rows, 2, &[("c", b"3")]);
+            assert!(p.should_snapshot());
+            p.snapshot(2, &rows).unwrap();
+            write(&mut p, &mut rows, 3, &[("a", b"4")]);
+        }
+
+        let mut p = Persistence::open(&dir, 2).unwrap();
+        let state = p.recover(NUM_ITEMS, apply).unwrap();
+        assert_eq!(state.version, 3);
+        assert_eq!(state.rows, rows);
+        assert!(!p.should_snapshot());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn ignores_truncated_log_record() {
+        let dir = temp_dir("truncated");
+        let mut rows = vec![Vec::new(); NUM_ITEMS];
+        {
+            let mut p = Persistence::open(&dir, 16).unwrap();
+            write(&mut p, &mut rows, 1, &[("a", b"1")]);
+        }
+
+        let mut record = encode_log_record(2, &[("b", b"2")]);
+        record.truncate(record.len() - 1);
+        let mut log = OpenOptions::new()
+            .append(true)
+            .open(dir.join(LOG_FNAME))
+            .unwrap();
+        log.write_all(&record).unwrap();
+
+        let mut p = Persistence::open(&dir, 16).unwrap();
+        let state = p.recover(NUM_ITEMS, apply).unwrap();
+        assert_eq!(state.version, 1);
+        assert_eq!(state.rows, rows);
+
+        // later writes must not be hidden behind the discarded record
+        write(&mut p, &mut rows, 2, &[("c", b"3")]);
+        let state = Persistence::open(&dir, 16)
+            .unwrap()
+            .recover(NUM_ITEMS, apply)
+            .unwrap();
+        assert_eq!(state.version, 2);
+        assert_eq!(state.rows, rows);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
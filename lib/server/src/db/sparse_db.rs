@@ -8,12 +8,16 @@ pub struct SparseDb {
 
     // db_idx to data vector index
     pub db_idx_to_vec_idx: HashMap<usize, usize>,
+
+    // data vector index to db_idx
+    vec_idx_to_db_idx: Vec<usize>,
 }
 impl SparseDb {
     pub fn new() -> SparseDb {
         SparseDb {
             data: Vec::new(),
             db_idx_to_vec_idx: HashMap::new(),
+            vec_idx_to_db_idx: Vec::new(),
         }
     }
 
@@ -26,6 +30,7 @@ impl SparseDb {
         new_poly.as_mut_slice().copy_from_slice(data);
         self.data.push(new_poly);
         self.db_idx_to_vec_idx.insert(idx, self.data.len() - 1);
+        self.vec_idx_to_db_idx.push(idx);
     }
 
     fn update_impl(&mut self, vec_idx: usize, data: &[u64]) {
@@ -45,4 +50,47 @@ impl SparseDb {
             self.add(idx, data);
         }
     }
+
+    /// Removes the polynomial at `idx`, if any, moving the last polynomial into its place.
+    /// Returns whether a polynomial was removed.
+    pub fn remove(&mut self, idx: usize) -> bool {
+        let Some(vec_idx) = self.db_idx_to_vec_idx.remove(&idx) else {
+            return false;
+        };
+        self.data.swap_remove(vec_idx);
+        self.vec_idx_to_db_idx.swap_remove(vec_idx);
+        if vec_idx < self.data.len() {
+            let moved_idx = self.vec_idx_to_db_idx[vec_idx];
+            self.db_idx_to_vec_idx.insert(moved_idx, vec_idx);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn remove_compacts_data() {
+        let mut db = SparseDb::new();
+        for idx in [3, 5, 7] {
+            db.add(idx, &[idx as u64; 4]);
+        }
+
+        assert!(db.remove(3));
+        assert!(!db.remove(3));
+        assert!(!db.remove(4));
+        assert_eq!(db.data.len(), 2);
+        assert_eq!(db.get_idx(3), None);
+        for idx in [5, 7] {
+            let vec_idx = *db.get_idx(idx).unwrap();
+            assert_eq!(db.data[vec_idx].as_slice(), &[idx as u64; 4]);
+        }
+
+        db.upsert(3, &[1; 4]);
+        assert!(db.remove(7));
+        assert_eq!(db.data[*db.get_idx(3).unwrap()].as_slice(), &[1; 4]);
+        assert_eq!(db.data[*db.get_idx(5).unwrap()].as_slice(), &[5; 4]);
+    }
 }
@@ -1,11 +1,22 @@
-use std::{collections::HashMap, io::Read};
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Read,
+};
 
 use base64::{engine::general_purpose, Engine};
 use bzip2::{read::BzEncoder, Compression};
 use sha2::{Digest, Sha256};
-use spiral_rs::params::Params;
+use spiral_rs::{
+    key_value::{cuckoo_rows_from_hash, key_hash, KeyLayout},
+    params::Params,
+};
 
-use super::{loading::update_item_raw, sparse_db::SparseDb};
+use crate::error::Error;
+
+use super::{
+    loading::{remove_item_raw, update_item_raw},
+    sparse_db::SparseDb,
+};
 
 pub fn row_from_key(num_items: usize, key: &str) -> usize {
     let buckets_log2 = (num_items as f64).log2().ceil() as usize;
@@ -66,117 +77,511 @@ pub fn varint_decode(data: &[u8]) -> (usize, usize) {
 
 const DEFAULT_KEY_HASH_BYTES: u8 = 8;
 
-pub fn update_row(row: &mut Vec<u8>, key: &str, value: &[u8]) {
-    if row.len() == 0 {
-        row.push(DEFAULT_KEY_HASH_BYTES);
+/// Returns the (start, end) byte range of each entry in a raw row.
+/// Each entry is a key hash, followed by the varint length of the value, and the value.
+fn row_entries(row: &[u8]) -> Vec<(usize, usize)> {
+    let mut entries = Vec::new();
+    if row.is_empty() {
+        return entries;
     }
-
     let key_hash_bytes = row[0] as usize;
 
-    let mut target_key_hash = hash_key(key, key_hash_bytes);
-
     let mut i = 1;
-    let mut found_start = false;
-    let mut found_end = false;
-    let mut start = 0;
-    let mut end = 0;
     while i < row.len() {
         // read key
-        let key_hash = &row[i..i + key_hash_bytes];
+        let key_start = i;
         i += key_hash_bytes;
 
-        if key_hash == target_key_hash {
-            found_start = true;
-            start = i;
-        }
-
-        // read len
-        let (value_len, value_len_len) = varint_decode(&row[i..i + VARINT_MAX_BYTES]);
+        // read len (the varint may end less than VARINT_MAX_BYTES before the end of the row)
+        let varint_end = usize::min(i + VARINT_MAX_BYTES, row.len());
+        let (value_len, value_len_len) = varint_decode(&row[i..varint_end]);
         i += value_len_len;
 
         // read value
-        i += value_len as usize;
+        i += value_len;
 
-        if key_hash == target_key_hash {
-            found_end = true;
-            end = i;
-        }
+        entries.push((key_start, i));
     }
+    entries
+}
 
-    if found_start {
-        assert!(found_end);
-    }
+/// Splits the entry at `start..end` of a raw row into its key hash and value.
+fn split_entry(row: &[u8], (start, end): (usize, usize)) -> (&[u8], &[u8]) {
+    let key_hash_bytes = row[0] as usize;
+    let varint_start = start + key_hash_bytes;
+    let varint_end = usize::min(varint_start + VARINT_MAX_BYTES, end);
+    let (_, value_len_len) = varint_decode(&row[varint_start..varint_end]);
+    (
+        &row[start..varint_start],
+        &row[varint_start + value_len_len..end],
+    )
+}
+
+fn find_entry(row: &[u8], key_hash: &[u8]) -> Option<(usize, usize)> {
+    row_entries(row)
+        .into_iter()
+        .find(|entry| split_entry(row, *entry).0 == key_hash)
+}
 
-    let mut new_value = value.to_vec();
+/// Sets the entry with the given key hash to `value` in the raw row. An empty value
+/// deletes the entry; deleting an entry that is not in the row does nothing.
+/// A row left with no entries is cleared entirely.
+pub fn update_row_hash(row: &mut Vec<u8>, key_hash: &[u8], value: &[u8]) {
+    if row.is_empty() {
+        if value.is_empty() {
+            return;
+        }
+        row.push(key_hash.len() as u8);
+    }
 
-    if value.len() == 0 {
+    match (find_entry(row, key_hash), value.is_empty()) {
         // deleting this key, so also delete the key hash
-        assert!(found_start);
-        start -= key_hash_bytes;
-    } else {
-        new_value = varint_encode(value.len() as u64);
-        new_value.append(&mut value.to_vec());
+        (Some((key_start, end)), true) => {
+            row.drain(key_start..end);
+        }
+        (Some((key_start, end)), false) => {
+            let mut new_value = varint_encode(value.len() as u64);
+            new_value.extend_from_slice(value);
+            row.splice(key_start + key_hash.len()..end, new_value);
+        }
+        (None, true) => {}
+        (None, false) => {
+            row.extend_from_slice(key_hash);
+            row.append(&mut varint_encode(value.len() as u64));
+            row.extend_from_slice(value);
+        }
     }
 
-    if found_start {
-        row.splice(start..end, new_value);
-    } else {
-        row.append(&mut target_key_hash);
-        row.append(&mut new_value);
+    if row.len() == 1 {
+        row.clear();
     }
 }
 
-pub fn unwrap_kv_pairs(data: &[u8]) -> Vec<(String, Vec<u8>)> {
-    let mut kv_pairs = Vec::new();
+/// Sets `key` to `value` in the raw row. An empty value deletes the key;
+/// deleting a key that is not in the row does nothing.
+/// A row left with no keys is cleared entirely.
+pub fn update_row(row: &mut Vec<u8>, key: &str, value: &[u8]) {
+    let key_hash_bytes = row.first().copied().unwrap_or(DEFAULT_KEY_HASH_BYTES);
+    update_row_hash(row, &hash_key(key, key_hash_bytes as usize), value);
+}
 
-    // Parse the data as a JSON object
-    if let Ok(json_data) = serde_json::from_slice::<HashMap<String, String>>(data) {
-        for (key, base64_value) in json_data.iter() {
+/// Parses a JSON object of keys to base64-encoded values.
+/// A `null` value is parsed as an empty value, which deletes the key.
+pub fn unwrap_kv_pairs(data: &[u8]) -> Result<Vec<(String, Vec<u8>)>, Error> {
+    let json_data = serde_json::from_slice::<HashMap<String, Option<String>>>(data)
+        .map_err(|e| Error::InvalidRequest(format!("bad JSON: {}", e)))?;
+
+    let mut kv_pairs = Vec::new();
+    for (key, base64_value) in json_data.into_iter() {
+        let value = match base64_value {
             // Decode the Base64-encoded value
-            if let Ok(decoded_value) = base64::decode(base64_value) {
-                kv_pairs.push((key.clone(), decoded_value));
+            Some(base64_value) => general_purpose::STANDARD
+                .decode(base64_value)
+                .map_err(|e| Error::InvalidRequest(format!("bad base64 for {}: {}", key, e)))?,
+            None => Vec::new(),
+        };
+        kv_pairs.push((key, value));
+    }
+
+    Ok(kv_pairs)
+}
+
+/// Groups the given key-value pairs by the row they belong to, preserving their order.
+fn group_by_row<'a>(
+    kv_pairs: &[(&'a str, &'a [u8])],
+    num_rows: usize,
+) -> BTreeMap<usize, Vec<(&'a str, &'a [u8])>> {
+    let mut row_id_to_pairs: BTreeMap<usize, Vec<_>> = BTreeMap::new();
+    for (k, v) in kv_pairs {
+        let row_id = row_from_key(num_rows, k);
+        row_id_to_pairs.entry(row_id).or_default().push((*k, *v));
+    }
+    row_id_to_pairs
+}
+
+/// Number of bytes of compressed row data that fit in a single database item.
+pub fn row_capacity(params: &Params) -> usize {
+    params.instances * params.n * params.n * params.bytes_per_chunk()
+}
+
+fn compress_row(row_data: &[u8]) -> Vec<u8> {
+    let mut compressor = BzEncoder::new(row_data, Compression::best());
+    let mut compressed = Vec::new();
+    compressor.read_to_end(&mut compressed).unwrap();
+    compressed
+}
+
+/// Maximum number of entries moved to place a single key in the cuckoo layout.
+const MAX_CUCKOO_EVICTIONS: usize = 64;
+
+/// Rows changed by a write in progress. Rows are copied on first use, so that the
+/// write can be abandoned without changing the stored rows.
+struct RowChanges<'a> {
+    rows: &'a [Vec<u8>],
+    changed: BTreeMap<usize, Vec<u8>>,
+    /// Compressed contents of changed rows that have not changed again since.
+    compressed: BTreeMap<usize, Vec<u8>>,
+}
+
+impl<'a> RowChanges<'a> {
+    fn new(rows: &'a [Vec<u8>]) -> Self {
+        Self {
+            rows,
+            changed: BTreeMap::new(),
+            compressed: BTreeMap::new(),
+        }
+    }
+
+    fn get(&self, row_id: usize) -> &[u8] {
+        self.changed.get(&row_id).unwrap_or(&self.rows[row_id])
+    }
+
+    fn get_mut(&mut self, row_id: usize) -> &mut Vec<u8> {
+        let rows = self.rows;
+        self.compressed.remove(&row_id);
+        self.changed
+            .entry(row_id)
+            .or_insert_with(|| rows[row_id].clone())
+    }
+}
+
+/// Places an entry in one of its candidate rows, moving other entries to their own
+/// alternate rows when a row would no longer fit in a database item.
+fn cuckoo_insert(
+    params: &Params,
+    num_hashes: usize,
+    changes: &mut RowChanges,
+    key_hash: &[u8],
+    value: &[u8],
+) -> Result<(), Error> {
+    let capacity = row_capacity(params);
+    let num_items = changes.rows.len();
+
+    let mut pending = (key_hash.to_vec(), value.to_vec());
+    let mut evicted_from = None;
+    for _ in 0..MAX_CUCKOO_EVICTIONS {
+        let (key_hash, value) = &pending;
+
+        // prefer the emptiest candidate, other than the row the entry was just evicted from
+        let candidates = cuckoo_rows_from_hash(num_items, key_hash, num_hashes);
+        let row_id = candidates
+            .iter()
+            .copied()
+            .filter(|row_id| Some(*row_id) != evicted_from)
+            .min_by_key(|row_id| changes.get(*row_id).len())
+            .unwrap_or(candidates[0]);
+
+        let row = changes.get_mut(row_id);
+        update_row_hash(row, key_hash, value);
+        let compressed = compress_row(row);
+        let compressed_len = compressed.len();
+        if compressed_len <= capacity {
+            changes.compressed.insert(row_id, compressed);
+            return Ok(());
+        }
+
+        // evict the first other entry in the row
+        let Some(entry) = row_entries(row)
+            .into_iter()
+            .find(|entry| split_entry(row, *entry).0 != key_hash.as_slice())
+        else {
+            return Err(Error::RowOverflow(row_id, compressed_len, capacity));
+        };
+        let (evicted_hash, evicted_value) = split_entry(row, entry);
+        let evicted = (evicted_hash.to_vec(), evicted_value.to_vec());
+        update_row_hash(row, &evicted.0, &[]);
+        pending = evicted;
+        evicted_from = Some(row_id);
+    }
+
+    let row_id = evicted_from.unwrap_or(0);
+    let size = compress_row(changes.get(row_id)).len();
+    Err(Error::RowOverflow(row_id, size, capacity))
+}
+
+/// A changed row: its id, raw contents, and compressed contents (if not empty).
+pub type ChangedRow = (usize, Vec<u8>, Option<Vec<u8>>);
+
+/// Applies the given key-value pairs to copies of the raw rows they affect,
+/// returning each changed row. Neither `rows` nor the database is changed until
+/// the result is passed to `commit_update`.
+///
+/// If any changed row would not fit in a database item once compressed,
+/// `Error::RowOverflow` is returned.
+pub fn plan_update(
+    params: &Params,
+    layout: KeyLayout,
+    kv_pairs: &[(&str, &[u8])],
+    rows: &[Vec<u8>],
+) -> Result<Vec<ChangedRow>, Error> {
+    let mut changes = RowChanges::new(rows);
+    match layout {
+        KeyLayout::Single => {
+            for (row_id, pairs) in group_by_row(kv_pairs, rows.len()) {
+                let row = changes.get_mut(row_id);
+                for (key, value) in pairs {
+                    update_row(row, key, value);
+                }
             }
         }
+        KeyLayout::Cuckoo { num_hashes } => {
+            for (key, value) in kv_pairs {
+                // the key is removed from wherever it is, and placed again if not deleted
+                let key_hash = key_hash(key);
+                for row_id in cuckoo_rows_from_hash(rows.len(), &key_hash, num_hashes) {
+                    if find_entry(changes.get(row_id), &key_hash).is_some() {
+                        update_row_hash(changes.get_mut(row_id), &key_hash, &[]);
+                    }
+                }
+                if !value.is_empty() {
+                    cuckoo_insert(params, num_hashes, &mut changes, &key_hash, value)?;
+                }
+            }
+        }
+    }
+
+    let capacity = row_capacity(params);
+    let mut updated_rows = Vec::new();
+    for (row_id, row) in changes.changed {
+        let compressed = if row.is_empty() {
+            None
+        } else {
+            let compressed = match changes.compressed.remove(&row_id) {
+                Some(compressed) => compressed,
+                None => compress_row(&row),
+            };
+            if compressed.len() > capacity {
+                return Err(Error::RowOverflow(row_id, compressed.len(), capacity));
+            }
+            Some(compressed)
+        };
+        updated_rows.push((row_id, row, compressed));
+    }
+    Ok(updated_rows)
+}
+
+/// Applies the given key-value pairs to the raw rows, returning the ids of the
+/// rows that changed, in ascending order.
+///
+/// If any changed row would not fit in a database item once compressed, nothing is
+/// changed, and `Error::RowOverflow` is returned.
+pub fn apply_kv_pairs(
+    params: &Params,
+    layout: KeyLayout,
+    kv_pairs: &[(&str, &[u8])],
+    rows: &mut [Vec<u8>],
+) -> Result<Vec<usize>, Error> {
+    let updated_rows = plan_update(params, layout, kv_pairs, rows)?;
+    let mut row_ids = Vec::new();
+    for (row_id, row, _) in updated_rows {
+        rows[row_id] = row;
+        row_ids.push(row_id);
+    }
+    Ok(row_ids)
+}
+
+/// Compresses a raw row and writes it into the database at `row_id`.
+/// An empty row is removed from the database.
+pub fn encode_row(params: &Params, row_id: usize, row_data: &[u8], db: &mut SparseDb) {
+    if row_data.is_empty() {
+        remove_item_raw(params, row_id, db);
+        return;
     }
-    // print KV pairs
-    println!("kv_pairs: {:?}", kv_pairs);
 
-    kv_pairs
+    update_item_raw(params, row_id, &compress_row(row_data), db).unwrap();
 }
 
+/// Applies the given key-value pairs to the raw rows and the database.
+///
+/// If any changed row would not fit in a database item once compressed, nothing is
+/// changed, and `Error::RowOverflow` is returned.
 pub fn update_database(
     params: &Params,
+    layout: KeyLayout,
     kv_pairs: &[(&str, &[u8])],
     rows: &mut [Vec<u8>],
     db: &mut SparseDb,
+) -> Result<(), Error> {
+    let updated_rows = plan_update(params, layout, kv_pairs, rows)?;
+    commit_update(params, updated_rows, rows, db);
+    Ok(())
+}
+
+/// Stores rows planned by `plan_update` in the raw rows and the database.
+///
+/// `plan_update` has already checked that every row fits, so this cannot fail.
+pub fn commit_update(
+    params: &Params,
+    updated_rows: Vec<ChangedRow>,
+    rows: &mut [Vec<u8>],
+    db: &mut SparseDb,
 ) {
-    let mut row_id_to_keys = HashMap::new();
-    let mut keys_to_values = HashMap::new();
-    for (k, v) in kv_pairs {
-        keys_to_values.insert(*k, *v);
-        let row_id = row_from_key(rows.len(), k);
-        if !row_id_to_keys.contains_key(&row_id) {
-            row_id_to_keys.insert(row_id, Vec::new());
+    for (row_id, row, compressed) in updated_rows {
+        match compressed {
+            Some(compressed) => {
+                update_item_raw(params, row_id, &compressed, db).expect("planned row fits");
+            }
+            None => remove_item_raw(params, row_id, db),
         }
-        row_id_to_keys.get_mut(&row_id).unwrap().push(*k);
+        rows[row_id] = row;
     }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
 
-    let mut row_ids_to_update = row_id_to_keys.keys().collect::<Vec<_>>();
-    row_ids_to_update.sort();
+    use rand::{rngs::SmallRng, RngCore, SeedableRng};
 
-    for row_id in row_ids_to_update {
-        let row_data = &mut rows[*row_id];
+    #[test]
+    fn update_row_sets_and_deletes_keys() {
+        let mut row = Vec::new();
+        update_row(&mut row, "missing", &[]);
+        assert!(row.is_empty());
 
-        let keys_to_update = row_id_to_keys.get(row_id).unwrap();
-        for key in keys_to_update {
-            let value = keys_to_values.get(key).unwrap();
-            update_row(row_data, *key, *value);
+        update_row(&mut row, "a", b"1");
+        update_row(&mut row, "b", b"22");
+        let after_insert = row.clone();
+        update_row(&mut row, "c", &[]);
+        assert_eq!(row, after_insert);
+
+        update_row(&mut row, "a", b"333");
+        update_row(&mut row, "b", &[]);
+        let mut expected = Vec::new();
+        update_row(&mut expected, "a", b"333");
+        assert_eq!(row, expected);
+
+        update_row(&mut row, "a", &[]);
+        assert!(row.is_empty());
+    }
+
+    #[test]
+    fn malformed_kv_pairs_are_rejected() {
+        let kv_pairs = unwrap_kv_pairs(br#"{"a": "MQ==", "b": null}"#).unwrap();
+        let mut kv_pairs: Vec<_> = kv_pairs.into_iter().collect();
+        kv_pairs.sort();
+        assert_eq!(
+            kv_pairs,
+            vec![("a".to_owned(), b"1".to_vec()), ("b".to_owned(), vec![])]
+        );
+
+        for body in [&b"not json"[..], br#"["a"]"#, br#"{"a": "not base64!"}"#] {
+            assert!(matches!(
+                unwrap_kv_pairs(body),
+                Err(Error::InvalidRequest(_))
+            ));
         }
+    }
+
+    fn get_params() -> Params {
+        spiral_rs::util::params_from_json(
+            r#"{
+            "n": 2,
+            "nu_1": 6,
+            "nu_2": 2,
+            "p": 256,
+            "q2_bits": 20,
+            "t_gsw": 8,
+            "t_conv": 4,
+            "t_exp_left": 8,
+            "t_exp_right": 8,
+            "instances": 1,
+            "db_item_size": 8192
+        }"#,
+        )
+    }
 
-        let mut compressor = BzEncoder::new(row_data.as_slice(), Compression::best());
-        let mut compressed = Vec::new();
-        compressor.read_to_end(&mut compressed).unwrap();
+    fn random_bytes(rng: &mut SmallRng, len: usize) -> Vec<u8> {
+        let mut bytes = vec![0u8; len];
+        rng.fill_bytes(&mut bytes);
+        bytes
+    }
 
-        update_item_raw(params, *row_id, &compressed, db).unwrap();
+    #[test]
+    fn overflowing_write_is_rejected() {
+        let params = get_params();
+        let mut rng = SmallRng::seed_from_u64(0);
+        let big = random_bytes(&mut rng, 2 * row_capacity(&params));
+
+        for layout in [KeyLayout::Single, KeyLayout::Cuckoo { num_hashes: 2 }] {
+            let mut rows = vec![Vec::new(); params.num_items()];
+            let mut db = SparseDb::new();
+
+            update_database(&params, layout, &[("small", b"value")], &mut rows, &mut db).unwrap();
+            let rows_before = rows.clone();
+            let db_len_before = db.data.len();
+
+            let result = update_database(
+                &params,
+                layout,
+                &[("other", b"value"), ("big", &big)],
+                &mut rows,
+                &mut db,
+            );
+
+            assert!(matches!(result, Err(Error::RowOverflow(..))));
+            assert_eq!(rows, rows_before);
+            assert_eq!(db.data.len(), db_len_before);
+        }
+    }
+
+    #[test]
+    fn cuckoo_layout_spreads_hot_rows() {
+        let params = get_params();
+        let num_hashes = 2;
+        let mut rng = SmallRng::seed_from_u64(0);
+
+        // about two values fit in each row, so some rows overflow with a single hash
+        let values: Vec<_> = (0..200)
+            .map(|_| random_bytes(&mut rng, row_capacity(&params) / 3))
+            .collect();
+        let keys: Vec<_> = (0..values.len()).map(|i| format!("key{}", i)).collect();
+
+        let mut single_rows = vec![Vec::new(); params.num_items()];
+        let mut cuckoo_rows = vec![Vec::new(); params.num_items()];
+        let mut replayed_rows = vec![Vec::new(); params.num_items()];
+        let mut db = SparseDb::new();
+        let mut single_overflowed = false;
+        for (key, value) in keys.iter().zip(values.iter()) {
+            let kv_pairs = [(key.as_str(), value.as_slice())];
+            single_overflowed |=
+                apply_kv_pairs(&params, KeyLayout::Single, &kv_pairs, &mut single_rows).is_err();
+
+            let layout = KeyLayout::Cuckoo { num_hashes };
+            update_database(&params, layout, &kv_pairs, &mut cuckoo_rows, &mut db).unwrap();
+            apply_kv_pairs(&params, layout, &kv_pairs, &mut replayed_rows).unwrap();
+        }
+        assert!(single_overflowed);
+        assert_eq!(cuckoo_rows, replayed_rows);
+
+        let layout = KeyLayout::Cuckoo { num_hashes };
+        let deleted = keys[0].as_str();
+        update_database(
+            &params,
+            layout,
+            &[(deleted, &[])],
+            &mut cuckoo_rows,
+            &mut db,
+        )
+        .unwrap();
+
+        for (key, value) in keys.iter().zip(values.iter()) {
+            let key_hash = key_hash(key);
+            // a key's candidate rows may coincide
+            let mut candidates = layout.candidate_rows(&params, key);
+            candidates.dedup();
+            let found: Vec<_> = candidates
+                .into_iter()
+                .filter_map(|row_id| {
+                    let row = &cuckoo_rows[row_id];
+                    find_entry(row, &key_hash).map(|entry| split_entry(row, entry).1)
+                })
+                .collect();
+            if key == deleted {
+                assert!(found.is_empty());
+            } else {
+                assert_eq!(found, vec![value.as_slice()]);
+            }
+        }
     }
 }
@@ -0,0 +1,59 @@
+//! Buckets served with DoublePIR instead of Spiral.
+//!
+//! DoublePIR answers queries much faster than Spiral, but clients first download a
+//! large hint, and the database is read-only: it is loaded from the artifact written by
+//! `DoublePirServer::save_to_files` (e.g. by doublepir's `preprocess` binary).
+
+use std::path::PathBuf;
+
+use doublepir_rs::{doublepir::DoublePirServer, http::AppState};
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DoublePirConfig {
+    pub num_entries: u64,
+    pub bits_per_entry: usize,
+    /// The base name of the preprocessed `.dpir` artifact.
+    pub files: PathBuf,
+}
+
+pub struct DoublePirBucket {
+    pub name: String,
+    state: AppState,
+}
+
+impl DoublePirBucket {
+    pub fn new(name: &str, config: &DoublePirConfig) -> Result<Self, Error> {
+        let fname_base = config
+            .files
+            .to_str()
+            .ok_or_else(|| Error::InvalidRequest("bad doublepir files path".to_owned()))?;
+        let artifact = DoublePirServer::artifact_file_name(fname_base);
+        if !PathBuf::from(&artifact).exists() {
+            return Err(Error::InvalidRequest(format!("missing {}", artifact)));
+        }
+
+        let state = AppState::from_files(config.num_entries, config.bits_per_entry, fname_base)?;
+
+        Ok(Self {
+            name: name.to_owned(),
+            state,
+        })
+    }
+
+    pub fn hint(&self) -> &[u8] {
+        self.state.hint()
+    }
+
+    /// The JSON reported as `pir_scheme` in `/meta`.
+    pub fn pir_scheme_json(&self) -> &str {
+        self.state.meta()
+    }
+
+    /// Answers a serialized batch of DoublePIR queries, rejecting queries of the wrong shape.
+    pub fn answer(&self, query: &[u8]) -> Result<Vec<u8>, Error> {
+        Ok(self.state.answer(query)?)
+    }
+}
@@ -1,13 +1,23 @@
 use std::{fmt::Display, sync::PoisonError};
 
-use actix_http::body::BoxBody;
-use actix_web::{HttpResponse, ResponseError};
+use actix_http::{body::BoxBody, StatusCode};
+use actix_web::{error::BlockingError, HttpResponse, ResponseError};
+use doublepir_rs::{http::InvalidQuery, serializer::ArtifactError};
+use spiral_rs::{client::DeserializeError, params::ParamsError};
 
 #[derive(Debug)]
 pub enum Error {
     InvalidLength(usize, usize),
+    /// A row (id, compressed size) that does not fit in an item (capacity).
+    RowOverflow(usize, usize, usize),
     IoError(std::io::Error),
+    Corrupted(String),
+    InvalidRequest(String),
     NotFound,
+    /// A setup UUID with no public parameters, e.g. because they were evicted.
+    /// Reported as 410 Gone, so clients can tell it apart from a missing bucket.
+    UnknownUuid(String),
+    AlreadyExists,
     Unknown,
 }
 
@@ -15,8 +25,17 @@ impl Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Error::IoError(io_error) => write!(f, "{}", io_error),
+            Error::Corrupted(reason) => write!(f, "corrupted data: {}", reason),
+            Error::InvalidRequest(reason) => write!(f, "invalid request: {}", reason),
             Error::NotFound => write!(f, "not found"),
+            Error::UnknownUuid(uuid) => write!(f, "unknown setup uuid: {}", uuid),
+            Error::AlreadyExists => write!(f, "already exists"),
             Error::Unknown => write!(f, "unknown err"),
+            Error::RowOverflow(row_id, size, capacity) => write!(
+                f,
+                "row {} overflows: compressed size {} exceeds capacity {}",
+                row_id, size, capacity
+            ),
             Error::InvalidLength(got, expected) => {
                 write!(f, "bad length: got {}, expected {}", got, expected)
             }
@@ -26,13 +45,70 @@ impl Display for Error {
 
 impl std::error::Error for Error {}
 
-// TODO: add status_code() implementation to give better error info
 impl ResponseError for Error {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Error::InvalidLength(_, _) | Error::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+            Error::RowOverflow(_, _, _) => StatusCode::PAYLOAD_TOO_LARGE,
+            Error::NotFound => StatusCode::NOT_FOUND,
+            Error::UnknownUuid(_) => StatusCode::GONE,
+            Error::AlreadyExists => StatusCode::CONFLICT,
+            Error::IoError(_) | Error::Corrupted(_) | Error::Unknown => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
     fn error_response(&self) -> HttpResponse<BoxBody> {
         HttpResponse::with_body(self.status_code(), self.to_string()).map_into_boxed_body()
     }
 }
 
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::IoError(e)
+    }
+}
+
+impl From<InvalidQuery> for Error {
+    fn from(e: InvalidQuery) -> Self {
+        Error::InvalidRequest(e.0)
+    }
+}
+
+impl From<ArtifactError> for Error {
+    fn from(e: ArtifactError) -> Self {
+        match e {
+            ArtifactError::Io(io_error) => Error::IoError(io_error),
+            // the artifact is fine, but the bucket is configured for a different database
+            ArtifactError::Mismatch(reason) => Error::InvalidRequest(reason),
+            e => Error::Corrupted(e.to_string()),
+        }
+    }
+}
+
+impl From<DeserializeError> for Error {
+    fn from(e: DeserializeError) -> Self {
+        match e {
+            DeserializeError::InvalidLength { got, expected } => {
+                Error::InvalidLength(got, expected)
+            }
+        }
+    }
+}
+
+impl From<ParamsError> for Error {
+    fn from(e: ParamsError) -> Self {
+        Error::InvalidRequest(e.to_string())
+    }
+}
+
+impl From<BlockingError> for Error {
+    fn from(_: BlockingError) -> Self {
+        Error::Unknown
+    }
+}
+
 impl<T> From<PoisonError<T>> for Error {
     fn from(_: PoisonError<T>) -> Self {
         Error::Unknown
@@ -1,5 +1,10 @@
+pub mod bucket;
+pub mod doublepir;
 pub mod error;
+pub mod pub_params;
+pub mod routes;
 pub mod server;
+pub mod wire;
 
 pub mod compute {
     pub mod dot_product;
@@ -11,6 +16,7 @@ pub mod compute {
 pub mod db {
     pub mod aligned_memory;
     pub mod loading;
+    pub mod persistence;
     pub mod sparse_db;
     pub mod write;
 }
@@ -0,0 +1,370 @@
+//! Storage for the public parameters clients upload to `/setup`.
+//!
+//! Public parameters are several MB each, so the store is bounded: entries
+//! unused for longer than the TTL expire, and when the memory budget is
+//! exceeded the least recently used entries are evicted. If a spill directory
+//! is configured, evicted entries are written there in serialized form, and
+//! reloaded the next time their client makes a query, instead of requiring the
+//! client to set up again. Spilled entries do not survive a restart.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use spiral_rs::{client::PublicParameters, params::Params, poly::PolyMatrixNTT};
+
+use crate::error::Error;
+
+pub const DEFAULT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+pub const DEFAULT_MAX_BYTES: usize = 4 << 30;
+
+#[derive(Debug, Clone)]
+pub struct PubParamsConfig {
+    /// How long an entry may go unused before it expires.
+    pub ttl: Duration,
+    /// The approximate memory budget for deserialized public parameters. Every bucket has
+    /// its own store, so this budget is per bucket (see `Buckets`).
+    pub max_bytes: usize,
+    /// Where evicted entries are spilled, if anywhere.
+    pub spill_dir: Option<PathBuf>,
+}
+
+impl Default for PubParamsConfig {
+    fn default() -> Self {
+        Self {
+            ttl: DEFAULT_TTL,
+            max_bytes: DEFAULT_MAX_BYTES,
+            spill_dir: None,
+        }
+    }
+}
+
+struct Entry {
+    pub_params: Arc<PublicParameters<'static>>,
+    bytes: usize,
+    last_used: Instant,
+}
+
+#[derive(Default)]
+struct Inner {
+    entries: HashMap<String, Entry>,
+    /// Spilled entries, and when they were last used.
+    spilled: HashMap<String, Instant>,
+    total_bytes: usize,
+}
+
+fn matrices_bytes(v: &[PolyMatrixNTT]) -> usize {
+    v.iter().map(|m| m.data.len() * 8).sum()
+}
+
+/// The memory used by the matrices of the given public parameters.
+fn pub_params_bytes(pub_params: &PublicParameters) -> usize {
+    let optional = [
+        &pub_params.v_expansion_left,
+        &pub_params.v_expansion_right,
+        &pub_params.v_conversion,
+    ];
+    matrices_bytes(&pub_params.v_packing)
+        + optional
+            .iter()
+            .filter_map(|v| v.as_deref())
+            .map(matrices_bytes)
+            .sum::<usize>()
+}
+
+/// The public parameters of a bucket's clients, keyed by UUID.
+///
+/// Entries borrow `params` as `'static` (see `entry_params`), which is sound because:
+/// - entries are only handed out for as long as a borrow of the store;
+/// - `Drop` drops every entry before `params`, so the parameters outlive them.
+pub struct PubParamsStore {
+    inner: Mutex<Inner>,
+    config: PubParamsConfig,
+    params: Arc<Params>,
+}
+
+impl PubParamsStore {
+    /// Creates an empty store. Any entries left in the spill directory are removed.
+    pub fn new(params: Arc<Params>, config: PubParamsConfig) -> Result<Self, Error> {
+        if let Some(spill_dir) = &config.spill_dir {
+            if spill_dir.exists() {
+                fs::remove_dir_all(spill_dir)?;
+            }
+            fs::create_dir_all(spill_dir)?;
+        }
+        Ok(Self {
+            params,
+            config,
+            inner: Mutex::new(Inner::default()),
+        })
+    }
+
+    /// The parameters, borrowed for the entries of the store.
+    fn entry_params(&self) -> &'static Params {
+        // SAFETY: see the invariants on `PubParamsStore`.
+        unsafe { &*Arc::as_ptr(&self.params) }
+    }
+
+    fn spill_path(&self, uuid: &str) -> Option<PathBuf> {
+        self.config.spill_dir.as_ref().map(|d| d.join(uuid))
+    }
+
+    fn remove_spilled(&self, inner: &mut Inner, uuid: &str) -> Result<bool, Error> {
+        if inner.spilled.remove(uuid).is_none() {
+            return Ok(false);
+        }
+        if let Some(path) = self.spill_path(uuid) {
+            fs::remove_file(path)?;
+        }
+        Ok(true)
+    }
+
+    /// Drops every entry, in memory or spilled, that has not been used within the TTL.
+    fn purge_expired(&self, inner: &mut Inner, now: Instant) -> Result<(), Error> {
+        let ttl = self.config.ttl;
+        let expired: Vec<_> = inner
+            .entries
+            .iter()
+            .filter(|(_, e)| now.duration_since(e.last_used) > ttl)
+            .map(|(uuid, _)| uuid.clone())
+            .collect();
+        for uuid in expired {
+            let entry = inner.entries.remove(&uuid).unwrap();
+            inner.total_bytes -= entry.bytes;
+        }
+
+        let expired: Vec<_> = inner
+            .spilled
+            .iter()
+            .filter(|(_, last_used)| now.duration_since(**last_used) > ttl)
+            .map(|(uuid, _)| uuid.clone())
+            .collect();
+        for uuid in expired {
+            self.remove_spilled(inner, &uuid)?;
+        }
+        Ok(())
+    }
+
+    /// Evicts least recently used entries until `extra_bytes` more fit in the budget.
+    fn make_room(&self, inner: &mut Inner, extra_bytes: usize) -> Result<(), Error> {
+        while inner.total_bytes + extra_bytes > self.config.max_bytes {
+            let Some(uuid) = inner
+                .entries
+                .iter()
+                .min_by_key(|(_, e)| e.last_used)
+                .map(|(uuid, _)| uuid.clone())
+            else {
+                break;
+            };
+            let entry = inner.entries.remove(&uuid).unwrap();
+            inner.total_bytes -= entry.bytes;
+            if let Some(path) = self.spill_path(&uuid) {
+                fs::write(path, entry.pub_params.serialize())?;
+                inner.spilled.insert(uuid, entry.last_used);
+            }
+        }
+        Ok(())
+    }
+
+    fn insert_entry(
+        &self,
+        inner: &mut Inner,
+        uuid: &str,
+        pub_params: Arc<PublicParameters<'static>>,
+        now: Instant,
+    ) -> Result<(), Error> {
+        let bytes = pub_params_bytes(&pub_params);
+        self.make_room(inner, bytes)?;
+        inner.total_bytes += bytes;
+        let entry = Entry {
+            pub_params,
+            bytes,
+            last_used: now,
+        };
+        if let Some(old) = inner.entries.insert(uuid.to_owned(), entry) {
+            inner.total_bytes -= old.bytes;
+        }
+        Ok(())
+    }
+
+    /// Deserializes and stores the given public parameters for the given UUID.
+    pub fn insert(&self, uuid: &str, data: &[u8]) -> Result<(), Error> {
+        let pub_params = PublicParameters::try_deserialize(self.entry_params(), data)?;
+        let now = Instant::now();
+        let mut inner = self.inner.lock()?;
+        self.purge_expired(&mut inner, now)?;
+        self.remove_spilled(&mut inner, uuid)?;
+        self.insert_entry(&mut inner, uuid, Arc::new(pub_params), now)
+    }
+
+    /// Returns the public parameters for the given UUID, reloading them if they were spilled.
+    pub fn get(&self, uuid: &str) -> Result<Option<Arc<PublicParameters<'_>>>, Error> {
+        let now = Instant::now();
+        let mut inner = self.inner.lock()?;
+        self.purge_expired(&mut inner, now)?;
+
+        if let Some(entry) = inner.entries.get_mut(uuid) {
+            entry.last_used = now;
+            return Ok(Some(entry.pub_params.clone()));
+        }
+
+        let Some(path) = self.spill_path(uuid) else {
+            return Ok(None);
+        };
+        if !inner.spilled.contains_key(uuid) {
+            return Ok(None);
+        }
+        let data = fs::read(&path)?;
+        if data.len() != self.params.setup_bytes() {
+            return Err(Error::Corrupted(format!("bad spilled setup for {}", uuid)));
+        }
+        self.remove_spilled(&mut inner, uuid)?;
+        let pub_params = Arc::new(PublicParameters::deserialize(self.entry_params(), &data));
+        self.insert_entry(&mut inner, uuid, pub_params.clone(), now)?;
+        Ok(Some(pub_params))
+    }
+
+    /// Removes the public parameters for the given UUID, returning whether there were any.
+    pub fn remove(&self, uuid: &str) -> Result<bool, Error> {
+        let mut inner = self.inner.lock()?;
+        let in_memory = match inner.entries.remove(uuid) {
+            Some(entry) => {
+                inner.total_bytes -= entry.bytes;
+                true
+            }
+            None => false,
+        };
+        let spilled = self.remove_spilled(&mut inner, uuid)?;
+        Ok(in_memory || spilled)
+    }
+
+    /// The number of entries held in memory, and the memory they use.
+    pub fn usage(&self) -> Result<(usize, usize), Error> {
+        let inner = self.inner.lock()?;
+        Ok((inner.entries.len(), inner.total_bytes))
+    }
+}
+
+impl Drop for PubParamsStore {
+    fn drop(&mut self) {
+        let inner = self.inner.get_mut().unwrap_or_else(PoisonError::into_inner);
+        inner.entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use spiral_rs::{client::Client, params::intern_params, util::params_from_json};
+
+    fn get_params() -> Arc<Params> {
+        let params = params_from_json(
+            r#"{
+            "n": 2,
+            "nu_1": 6,
+            "nu_2": 2,
+            "p": 256,
+            "q2_bits": 20,
+            "t_gsw": 8,
+            "t_conv": 4,
+            "t_exp_left": 8,
+            "t_exp_right": 8,
+            "instances": 1,
+            "db_item_size": 8192
+        }"#,
+        );
+        intern_params(params)
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        std::env::temp_dir().join(format!("spiral-server-{}-{}", name, nanos))
+    }
+
+    #[test]
+    fn least_recently_used_entries_are_spilled_and_reloaded() {
+        let params = get_params();
+        let mut client = Client::init(&params);
+        let setup = client.generate_keys().serialize();
+        let bytes = pub_params_bytes(&PublicParameters::deserialize(&params, &setup));
+
+        let dir = temp_dir("pub-params");
+        let store = PubParamsStore::new(
+            params.clone(),
+            PubParamsConfig {
+                max_bytes: 2 * bytes,
+                spill_dir: Some(dir.clone()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        for uuid in ["a", "b", "c"] {
+            store.insert(uuid, &setup).unwrap();
+        }
+        assert_eq!(store.usage().unwrap(), (2, 2 * bytes));
+        assert!(dir.join("a").exists());
+
+        // reloading "a" spills "b", the least recently used
+        let a = store.get("a").unwrap().unwrap();
+        assert_eq!(a.serialize(), setup);
+        assert!(!dir.join("a").exists());
+        assert!(dir.join("b").exists());
+        assert_eq!(store.usage().unwrap().0, 2);
+
+        assert!(store.remove("b").unwrap());
+        assert!(!dir.join("b").exists());
+        assert!(store.get("b").unwrap().is_none());
+        assert!(!store.remove("b").unwrap());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unused_entries_expire() {
+        let params = get_params();
+        let mut client = Client::init(&params);
+        let setup = client.generate_keys().serialize();
+
+        let store = PubParamsStore::new(
+            params.clone(),
+            PubParamsConfig {
+                ttl: Duration::from_millis(100),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        store.insert("a", &setup).unwrap();
+        assert!(store.get("a").unwrap().is_some());
+        std::thread::sleep(Duration::from_millis(200));
+        assert!(store.get("a").unwrap().is_none());
+        assert_eq!(store.usage().unwrap(), (0, 0));
+    }
+
+    #[test]
+    fn store_keeps_params_until_dropped() {
+        // not interned, so that the store holds the only reference
+        let params = Arc::new(get_params().as_ref().clone());
+        let weak = Arc::downgrade(&params);
+        let mut client = Client::init(&params);
+        let setup = client.generate_keys().serialize();
+        drop(client);
+
+        let store = PubParamsStore::new(params, PubParamsConfig::default()).unwrap();
+        store.insert("a", &setup).unwrap();
+        assert_eq!(store.get("a").unwrap().unwrap().serialize(), setup);
+
+        assert!(weak.upgrade().is_some());
+        drop(store);
+        assert!(weak.upgrade().is_none());
+    }
+}
@@ -0,0 +1,738 @@
+use std::time::Instant;
+
+use actix_web::{
+    delete, get,
+    http::header::{self, HeaderName},
+    post, web, HttpRequest, HttpResponse,
+};
+use base64::{engine::general_purpose, Engine as _};
+use serde::{Deserialize, Serialize};
+use spiral_rs::{client::*, key_value::KeyLayout};
+use uuid::Uuid;
+
+use crate::{
+    bucket::*,
+    db::{loading::update_many_items, write::unwrap_kv_pairs},
+    error::Error,
+    server::*,
+    wire::*,
+};
+
+fn header_str(req: &HttpRequest, name: HeaderName) -> Option<&str> {
+    req.headers().get(name).and_then(|v| v.to_str().ok())
+}
+
+fn is_binary(req: &HttpRequest) -> bool {
+    header_str(req, header::CONTENT_TYPE).is_some_and(|c| c.starts_with(CONTENT_TYPE_BINARY))
+}
+
+#[post("/update-row")]
+async fn update_row(body: web::Bytes, bucket: BucketRef) -> Result<String, Error> {
+    let now = Instant::now();
+
+    bucket.check_writable()?;
+    let mut db_mut = bucket.db.write()?;
+    let largest_update = update_many_items(&bucket.params, &body, &mut db_mut)?;
+
+    Ok(format!(
+        "{{\"status\":\"done updating\", \"loading_time_us\":{}, \"largest_update\":{}}}",
+        now.elapsed().as_micros(),
+        largest_update
+    ))
+}
+
+#[post("/write")]
+async fn write(body: web::Bytes, bucket: BucketRef) -> Result<String, Error> {
+    let now = Instant::now();
+
+    let kv_pairs = unwrap_kv_pairs(&body)?;
+    let kv_pairs_slices: Vec<(&str, &[u8])> = kv_pairs
+        .iter()
+        .map(|(key, value)| (key.as_str(), value.as_slice()))
+        .collect();
+    let version = bucket.write(&kv_pairs_slices)?;
+
+    Ok(format!(
+        "{{\"status\":\"done updating\", \"loading_time_us\":{}, \"version\":{}}}",
+        now.elapsed().as_micros(),
+        version
+    ))
+}
+
+/// Deletes the keys in the JSON list in the body.
+#[post("/delete")]
+async fn delete_keys(body: web::Bytes, bucket: BucketRef) -> Result<String, Error> {
+    let now = Instant::now();
+
+    let keys: Vec<String> = serde_json::from_slice(&body)
+        .map_err(|e| Error::InvalidRequest(format!("bad JSON: {}", e)))?;
+    let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+    let version = bucket.delete(&keys)?;
+
+    Ok(format!(
+        "{{\"status\":\"done deleting\", \"loading_time_us\":{}, \"version\":{}}}",
+        now.elapsed().as_micros(),
+        version
+    ))
+}
+
+#[derive(Serialize)]
+pub struct UuidResponse {
+    pub uuid: String,
+}
+
+#[post("/setup")]
+async fn setup(req: HttpRequest, body: web::Bytes, bucket: BucketRef) -> Result<String, Error> {
+    let client_pub_params = if is_binary(&req) {
+        body.to_vec()
+    } else {
+        // parse body as json str
+        let body_str = serde_json::from_slice::<String>(&body)
+            .map_err(|e| Error::InvalidRequest(format!("bad JSON: {}", e)))?;
+        // decode body from base64
+        general_purpose::STANDARD
+            .decode(&body_str)
+            .map_err(|e| Error::InvalidRequest(format!("bad base64: {}", e)))?
+    };
+    let uuid = Uuid::new_v4();
+    bucket
+        .pub_params
+        .insert(&uuid.to_string(), &client_pub_params)?;
+
+    // return uuid as JSON string
+    let uuid_json = serde_json::to_string(&UuidResponse {
+        uuid: uuid.to_string(),
+    })
+    .unwrap();
+
+    Ok(uuid_json)
+}
+
+#[derive(Deserialize)]
+struct SetupPath {
+    uuid: String,
+}
+
+/// Drops the public parameters uploaded to `/setup`, ending the client's session.
+#[delete("/setup/{uuid}")]
+async fn delete_setup(path: web::Path<SetupPath>, bucket: BucketRef) -> Result<String, Error> {
+    if !bucket.pub_params.remove(&path.uuid)? {
+        return Err(Error::UnknownUuid(path.uuid.clone()));
+    }
+    Ok(format!(
+        "{{\"status\":\"deleted\", \"uuid\":\"{}\"}}",
+        path.uuid
+    ))
+}
+
+const UUID_V4_STR_BYTES: usize = 36;
+
+/// Checks that a request is exactly `expected` bytes long.
+fn check_length(request_bytes: &[u8], expected: usize) -> Result<(), Error> {
+    if request_bytes.len() != expected {
+        return Err(Error::InvalidLength(request_bytes.len(), expected));
+    }
+    Ok(())
+}
+
+async fn private_read_impl(body: &[u8], bucket: &Bucket) -> Result<Vec<u8>, Error> {
+    let prefix_bytes = if bucket.params.expand_queries {
+        UUID_V4_STR_BYTES
+    } else {
+        bucket.params.setup_bytes()
+    };
+    check_length(body, prefix_bytes + bucket.params.query_bytes())?;
+    let (prefix, query_bytes) = body.split_at(prefix_bytes);
+    Ok(answer_batch_query(prefix, query_bytes, 1, bucket)
+        .await?
+        .pop()
+        .unwrap())
+}
+
+/// Answers a batch query: the setup UUID (or the serialized `PublicParameters`, for
+/// parameters that do not expand queries), the number of partitions as a u64 LE, and the
+/// `Query`.
+async fn private_read_batch_impl(body: &[u8], bucket: &Bucket) -> Result<Vec<Vec<u8>>, Error> {
+    let prefix_bytes = if bucket.params.expand_queries {
+        UUID_V4_STR_BYTES
+    } else {
+        bucket.params.setup_bytes()
+    };
+    let query_bytes = bucket.params.query_bytes();
+    check_length(body, prefix_bytes + 8 + query_bytes)?;
+    let (prefix, rest) = body.split_at(prefix_bytes);
+    let (num_partitions, query_bytes) = rest.split_at(8);
+    let num_partitions = u64::from_le_bytes(num_partitions.try_into().unwrap());
+    answer_batch_query(prefix, query_bytes, num_partitions, bucket).await
+}
+
+/// Answers a batch query of `num_partitions` partitions, whose length has been checked.
+async fn answer_batch_query(
+    prefix: &[u8],
+    query_bytes: &[u8],
+    num_partitions: u64,
+    bucket: &Bucket,
+) -> Result<Vec<Vec<u8>>, Error> {
+    let db = bucket.db.read()?;
+
+    let now = Instant::now();
+    let num_partitions = usize::try_from(num_partitions)
+        .ok()
+        .filter(|num_partitions| is_valid_batch_size(&bucket.params, *num_partitions))
+        .ok_or_else(|| {
+            Error::InvalidRequest(format!("{} partitions cannot be batched", num_partitions))
+        })?;
+    let query = Query::try_deserialize(&bucket.params, query_bytes)?;
+
+    let process = |pub_params: &PublicParameters| match &bucket.mapped_db {
+        Some(mapped_db) => process_batch_query_preprocessed(
+            &bucket.params,
+            pub_params,
+            &query,
+            num_partitions,
+            mapped_db.as_slice(),
+        ),
+        None => process_batch_query(&bucket.params, pub_params, &query, num_partitions, &db),
+    };
+    let result = if bucket.params.expand_queries {
+        // Parse the UUID
+        let uuid = std::str::from_utf8(prefix)
+            .map_err(|_| Error::InvalidRequest("bad UUID".to_owned()))?;
+
+        // Look up UUID and get public parameters
+        let pub_params = bucket
+            .pub_params
+            .get(uuid)?
+            .ok_or_else(|| Error::UnknownUuid(uuid.to_owned()))?;
+
+        process(&pub_params)
+    } else {
+        // Here, we get the public parameters in the query
+        let pub_params = PublicParameters::try_deserialize(&bucket.params, prefix)?;
+
+        process(&pub_params)
+    };
+    println!(
+        "Query batch of {} partitions processed. ({} ms)",
+        num_partitions,
+        now.elapsed().as_millis()
+    );
+
+    Ok(result)
+}
+
+/// Serves a batch of queries, in the format described in [`crate::wire`].
+#[post("/private-read")]
+async fn private_read(
+    req: HttpRequest,
+    body: web::Bytes,
+    bucket: AnyBucketRef,
+) -> Result<HttpResponse, Error> {
+    let (format, queries) = decode_read_request(header_str(&req, header::CONTENT_TYPE), &body)?;
+
+    let mut results = Vec::new();
+    for query_bytes in queries {
+        let result = match &*bucket {
+            AnyBucket::Spiral(bucket) => private_read_impl(&query_bytes, bucket).await?,
+            AnyBucket::DoublePir(bucket) => {
+                let bucket = bucket.clone();
+                web::block(move || bucket.answer(&query_bytes)).await??
+            }
+        };
+        results.push(result);
+    }
+
+    let format = format.for_response(header_str(&req, header::ACCEPT));
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .body(encode_read_response(format, &results)))
+}
+
+/// Serves a list of batch queries, in the format described in [`crate::wire`].
+///
+/// Each batch query is answered with one pass over the database, and produces one result
+/// per partition; the results of all batches are returned in order.
+#[post("/private-read-batch")]
+async fn private_read_batch(
+    req: HttpRequest,
+    body: web::Bytes,
+    bucket: BucketRef,
+) -> Result<HttpResponse, Error> {
+    let (format, batches) = decode_read_request(header_str(&req, header::CONTENT_TYPE), &body)?;
+
+    let mut results = Vec::new();
+    for batch_bytes in batches.iter() {
+        results.extend(private_read_batch_impl(batch_bytes, &bucket).await?);
+    }
+
+    let format = format.for_response(header_str(&req, header::ACCEPT));
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .body(encode_read_response(format, &results)))
+}
+
+/// Reports the bucket's `pir_scheme`: the Spiral parameters, or for DoublePIR
+/// buckets, `"scheme": "doublepir"` and the DoublePIR parameters.
+#[get("/meta")]
+async fn meta(bucket: AnyBucketRef) -> Result<String, Error> {
+    let (pir_scheme, key_layout, version) = match &*bucket {
+        AnyBucket::Spiral(bucket) => (
+            bucket.params_json.as_str(),
+            bucket.key_layout,
+            *bucket.version.read()?,
+        ),
+        AnyBucket::DoublePir(bucket) => (bucket.pir_scheme_json(), KeyLayout::Single, 0),
+    };
+
+    Ok(format!(
+        r#"{{
+            "id": 0,
+            "name": "{}",
+            "owner_id": 0,
+            "open_access": true,
+            "pir_scheme": {},
+            "key_layout": {},
+            "global_version": {}
+        }}"#,
+        bucket.name(),
+        pir_scheme,
+        serde_json::to_string(&key_layout).unwrap(),
+        version
+    ))
+}
+
+/// Serves the hint that DoublePIR clients download before making queries.
+#[get("/hint")]
+async fn hint(bucket: AnyBucketRef) -> Result<HttpResponse, Error> {
+    match &*bucket {
+        AnyBucket::Spiral(bucket) => Err(Error::InvalidRequest(format!(
+            "bucket {} is served with spiral, which has no hint",
+            bucket.name
+        ))),
+        AnyBucket::DoublePir(bucket) => Ok(HttpResponse::Ok()
+            .content_type(CONTENT_TYPE_BINARY)
+            .body(bucket.hint().to_vec())),
+    }
+}
+
+#[get("/")]
+async fn index(bucket: BucketRef) -> String {
+    format!("Hello {}!", bucket.params.poly_len)
+}
+
+#[get("/buckets")]
+async fn list_buckets(buckets: web::Data<Buckets>) -> Result<String, Error> {
+    Ok(serde_json::to_string(&buckets.names()?).unwrap())
+}
+
+#[post("/buckets/{name}")]
+async fn create_bucket(
+    name: web::Path<String>,
+    config: web::Json<BucketConfig>,
+    buckets: web::Data<Buckets>,
+) -> Result<String, Error> {
+    if config.doublepir.is_some() {
+        return Err(Error::InvalidRequest(
+            "doublepir buckets can only be configured at startup".to_owned(),
+        ));
+    }
+    buckets.create(&name, &config)?;
+    Ok(format!("{{\"status\":\"created\", \"name\":\"{}\"}}", name))
+}
+
+#[delete("/buckets/{name}")]
+async fn delete_bucket(
+    name: web::Path<String>,
+    buckets: web::Data<Buckets>,
+) -> Result<String, Error> {
+    buckets.delete(&name)?;
+    Ok(format!("{{\"status\":\"deleted\", \"name\":\"{}\"}}", name))
+}
+
+/// Registers all routes. Routes at the root are served by the default bucket,
+/// and routes under `/{bucket}` by the named bucket.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_buckets)
+        .service(create_bucket)
+        .service(delete_bucket)
+        .service(private_read)
+        .service(private_read_batch)
+        .service(index)
+        .service(meta)
+        .service(hint)
+        .service(update_row)
+        .service(setup)
+        .service(delete_setup)
+        .service(write)
+        .service(delete_keys)
+        .service(
+            web::scope("/{bucket}")
+                .service(private_read)
+                .service(private_read_batch)
+                .service(meta)
+                .service(hint)
+                .service(update_row)
+                .service(setup)
+                .service(delete_setup)
+                .service(write)
+                .service(delete_keys),
+        );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::{
+        fs,
+        path::PathBuf,
+        time::{SystemTime, UNIX_EPOCH},
+    };
+
+    use actix_web::{http::StatusCode, test, App};
+    use doublepir_rs::{
+        database::DbInfo,
+        doublepir::{DoublePirClient, DoublePirServer},
+        pir::{PirClient, PirServer},
+        serializer::{DeserializeSlice, Serialize as _},
+    };
+    use rand::{distributions::Standard, thread_rng, Rng};
+    use serde_json::Value;
+    use spiral_rs::{arith::log2_ceil, util::params_from_json};
+
+    use crate::{
+        db::loading::{generate_random_db_and_get_item, save_preprocessed_db_to_file},
+        doublepir::DoublePirConfig,
+    };
+
+    const PARAMS_JSON: &str = r#"{
+        "n": 2,
+        "nu_1": 6,
+        "nu_2": 2,
+        "p": 256,
+        "q2_bits": 20,
+        "t_gsw": 8,
+        "t_conv": 4,
+        "t_exp_left": 8,
+        "t_exp_right": 8,
+        "instances": 1,
+        "db_item_size": 8192
+    }"#;
+
+    #[actix_web::test]
+    async fn malformed_requests_get_client_errors() {
+        let buckets = Buckets::new(None);
+        let config = BucketConfig::from_params_json(PARAMS_JSON).unwrap();
+        buckets.create(DEFAULT_BUCKET, &config).unwrap();
+        let (dir, doublepir_config, _) = save_doublepir_db(1 << 20);
+        buckets.create("dp", &doublepir_config).unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(buckets))
+                .app_data(web::PayloadConfig::new(1 << 30))
+                .configure(configure),
+        )
+        .await;
+
+        let requests = [
+            ("/setup", CONTENT_TYPE_JSON, b"not json".to_vec()),
+            ("/setup", CONTENT_TYPE_JSON, b"\"not base64!\"".to_vec()),
+            ("/setup", CONTENT_TYPE_BINARY, vec![0; 10]),
+            ("/private-read", CONTENT_TYPE_BINARY, vec![0; 10]),
+            (
+                "/private-read",
+                CONTENT_TYPE_BINARY,
+                serialize_chunks(&[vec![0; 10]]),
+            ),
+            (
+                "/private-read-batch",
+                CONTENT_TYPE_BINARY,
+                serialize_chunks(&[vec![0; 10]]),
+            ),
+            (
+                "/write",
+                CONTENT_TYPE_JSON,
+                br#"{"a": "not base64!"}"#.to_vec(),
+            ),
+            ("/update-row", CONTENT_TYPE_BINARY, vec![0, 0, 0, 8, 1]),
+            (
+                "/dp/private-read",
+                CONTENT_TYPE_BINARY,
+                serialize_chunks(&[vec![0; 10]]),
+            ),
+            (
+                "/dp/private-read",
+                CONTENT_TYPE_BINARY,
+                serialize_chunks(&[vec![0xff; 1 << 16]]),
+            ),
+        ];
+        for (path, content_type, body) in requests {
+            let req = test::TestRequest::post()
+                .uri(path)
+                .insert_header((header::CONTENT_TYPE, content_type))
+                .set_payload(body)
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{}", path);
+        }
+
+        // a query of the right length, but with the wrong shape
+        let req = test::TestRequest::get().uri("/dp/meta").to_request();
+        let metadata: Value = test::call_and_read_body_json(&app, req).await;
+        let client = doublepir_client(&metadata["pir_scheme"]);
+        let (queries, _, _) = client.generate_query_batch(&[0]);
+        let mut query = queries.serialize();
+        query[8..12].copy_from_slice(&1u32.to_be_bytes());
+        let req = test::TestRequest::post()
+            .uri("/dp/private-read")
+            .insert_header((header::CONTENT_TYPE, CONTENT_TYPE_BINARY))
+            .set_payload(serialize_chunks(&[query]))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let req = test::TestRequest::get().uri("/missing/meta").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let req = test::TestRequest::delete()
+            .uri("/setup/missing")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::GONE);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn doublepir_buckets_cannot_be_created_over_http() {
+        let (dir, doublepir_config, _) = save_doublepir_db(1 << 10);
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(Buckets::new(None)))
+                .configure(configure),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/buckets/dp")
+            .set_json(&doublepir_config)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let req = test::TestRequest::get().uri("/buckets").to_request();
+        let names: Vec<String> = test::call_and_read_body_json(&app, req).await;
+        assert!(names.is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Saves a DoublePIR database of random bits to a new directory, returning the
+    /// directory, the bucket config serving it, and the bits.
+    fn save_doublepir_db(num_entries: usize) -> (PathBuf, BucketConfig, Vec<u8>) {
+        let bits_per_entry = 1;
+        let vals: Vec<u8> = thread_rng()
+            .sample_iter(Standard)
+            .map(|x: u8| x % 2)
+            .take(num_entries)
+            .collect();
+        let mut server = DoublePirServer::new(num_entries as u64, bits_per_entry);
+        server.load_data(vals.iter().copied());
+
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let dir = std::env::temp_dir().join(format!("spiral-server-doublepir-{}", nanos));
+        fs::create_dir_all(&dir).unwrap();
+        server
+            .save_to_files(dir.join("db").to_str().unwrap())
+            .unwrap();
+
+        let config = BucketConfig {
+            doublepir: Some(DoublePirConfig {
+                num_entries: num_entries as u64,
+                bits_per_entry,
+                files: dir.join("db"),
+            }),
+            ..Default::default()
+        };
+        (dir, config, vals)
+    }
+
+    /// A DoublePIR client for the `pir_scheme` reported in `/meta`.
+    fn doublepir_client(pir_scheme: &Value) -> DoublePirClient {
+        let params =
+            doublepir_rs::params::Params::from_string(pir_scheme["params"].as_str().unwrap());
+        let dbinfo = DbInfo::deserialize(
+            &general_purpose::STANDARD
+                .decode(pir_scheme["dbinfo"].as_str().unwrap())
+                .unwrap(),
+        );
+        DoublePirClient::with_params(&params, &dbinfo)
+    }
+
+    #[actix_web::test]
+    async fn doublepir_buckets_share_routes_with_spiral() {
+        let num_entries = 1 << 24;
+        let (dir, doublepir_config, vals) = save_doublepir_db(num_entries);
+
+        let buckets = Buckets::new(None);
+        let config = BucketConfig::from_params_json(PARAMS_JSON).unwrap();
+        buckets.create("spiral", &config).unwrap();
+        buckets.create("dp", &doublepir_config).unwrap();
+        let missing_config = BucketConfig {
+            doublepir: Some(DoublePirConfig {
+                files: dir.join("missing"),
+                ..doublepir_config.doublepir.clone().unwrap()
+            }),
+            ..Default::default()
+        };
+        assert!(matches!(
+            buckets.create("missing", &missing_config),
+            Err(Error::InvalidRequest(_))
+        ));
+        let mismatched_config = BucketConfig {
+            doublepir: Some(DoublePirConfig {
+                num_entries: num_entries as u64 / 2,
+                ..doublepir_config.doublepir.clone().unwrap()
+            }),
+            ..Default::default()
+        };
+        assert!(matches!(
+            buckets.create("mismatched", &mismatched_config),
+            Err(Error::InvalidRequest(_))
+        ));
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(buckets))
+                .app_data(web::PayloadConfig::new(1 << 30))
+                .configure(configure),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/spiral/meta").to_request();
+        let metadata: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(metadata["pir_scheme"]["scheme"], "spiral");
+        let req = test::TestRequest::get().uri("/spiral/hint").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let req = test::TestRequest::post()
+            .uri("/dp/write")
+            .insert_header((header::CONTENT_TYPE, CONTENT_TYPE_JSON))
+            .set_payload("{}")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let req = test::TestRequest::get().uri("/dp/meta").to_request();
+        let metadata: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(metadata["pir_scheme"]["scheme"], "doublepir");
+        let mut client = doublepir_client(&metadata["pir_scheme"]);
+
+        let req = test::TestRequest::get().uri("/dp/hint").to_request();
+        client.load_hint(&test::call_and_read_body(&app, req).await);
+
+        let index_to_query = thread_rng().gen::<u64>() % num_entries as u64;
+        let (queries, client_states, query_plan) = client.generate_query_batch(&[index_to_query]);
+        let req = test::TestRequest::post()
+            .uri("/dp/private-read")
+            .insert_header((header::CONTENT_TYPE, CONTENT_TYPE_BINARY))
+            .set_payload(serialize_chunks(&[queries.serialize()]))
+            .to_request();
+        let results = deserialize_chunks(&test::call_and_read_body(&app, req).await).unwrap();
+
+        let (_, index_in_batch) = query_plan[0].unwrap();
+        let result = client.decode_response_impl(&results[0], index_in_batch, 0, &client_states[0]);
+        let result = u64::from_ne_bytes(result.as_slice().try_into().unwrap());
+        assert_eq!(result, vals[index_to_query as usize] as u64);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn mapped_buckets_serve_preprocessed_databases() {
+        let config = BucketConfig::from_params_json(PARAMS_JSON).unwrap();
+        let params = params_from_json(PARAMS_JSON);
+        let target_idx = thread_rng().gen::<usize>() % params.num_items();
+        let (item, db) = generate_random_db_and_get_item(&params, target_idx);
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let path = std::env::temp_dir().join(format!("spiral-server-mapped-{}", nanos));
+        save_preprocessed_db_to_file(&db, &mut fs::File::create(&path).unwrap()).unwrap();
+
+        let buckets = Buckets::new(None);
+        buckets
+            .create_mapped(DEFAULT_BUCKET, &config, &path)
+            .unwrap();
+        let larger_config =
+            BucketConfig::from_params_json(&PARAMS_JSON.replace(r#""nu_2": 2"#, r#""nu_2": 3"#))
+                .unwrap();
+        assert!(matches!(
+            buckets.create_mapped("larger", &larger_config, &path),
+            Err(Error::InvalidLength(..))
+        ));
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(buckets))
+                .app_data(web::PayloadConfig::new(1 << 30))
+                .configure(configure),
+        )
+        .await;
+
+        let mut client = Client::init(&params);
+        let req = test::TestRequest::post()
+            .uri("/setup")
+            .insert_header((header::CONTENT_TYPE, CONTENT_TYPE_BINARY))
+            .set_payload(client.generate_keys().serialize())
+            .to_request();
+        let setup_resp: Value = test::call_and_read_body_json(&app, req).await;
+        let uuid = setup_resp["uuid"].as_str().unwrap();
+
+        let query = client.generate_query(target_idx);
+        let req = test::TestRequest::post()
+            .uri("/private-read")
+            .insert_header((header::CONTENT_TYPE, CONTENT_TYPE_BINARY))
+            .set_payload(serialize_chunks(&[
+                [uuid.as_bytes(), &query.serialize()].concat()
+            ]))
+            .to_request();
+        let results = deserialize_chunks(&test::call_and_read_body(&app, req).await).unwrap();
+
+        let p_bits = log2_ceil(params.pt_modulus) as usize;
+        let result = client
+            .decode_response(&results[0])
+            .to_vec(p_bits, params.modp_words_per_chunk());
+        assert_eq!(result, item.to_vec(p_bits, params.modp_words_per_chunk()));
+
+        // a UUID without public parameters is reported as gone
+        let unknown_uuid = Uuid::new_v4().to_string();
+        let req = test::TestRequest::post()
+            .uri("/private-read")
+            .insert_header((header::CONTENT_TYPE, CONTENT_TYPE_BINARY))
+            .set_payload(serialize_chunks(&[[
+                unknown_uuid.as_bytes(),
+                &query.serialize(),
+            ]
+            .concat()]))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::GONE);
+
+        // the mapped database cannot be written
+        for path in ["/write", "/update-row"] {
+            let req = test::TestRequest::post()
+                .uri(path)
+                .insert_header((header::CONTENT_TYPE, CONTENT_TYPE_JSON))
+                .set_payload("{}")
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{}", path);
+        }
+
+        fs::remove_file(&path).unwrap();
+    }
+}
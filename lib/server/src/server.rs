@@ -1,4 +1,5 @@
 use spiral_rs::arith::*;
+use spiral_rs::client::is_valid_batch_size;
 use spiral_rs::client::PublicParameters;
 use spiral_rs::client::Query;
 use spiral_rs::params::*;
@@ -20,82 +21,152 @@ pub fn process_query(
     query: &Query,
     db: &SparseDb,
 ) -> Vec<u8> {
+    process_batch_query(params, public_params, query, 1, db)
+        .pop()
+        .unwrap()
+}
+
+/// Answers a batch query of `num_partitions` partitions with a single pass over the database.
+///
+/// The first dimension is split into `num_partitions` equal partitions of rows, and the query
+/// selects one item in each (see `spiral_rs::client::Client::generate_batch_query`). The query
+/// is expanded once, and each partition is only multiplied by its own rows, then folded with
+/// its own ciphertexts. Returns one response per partition.
+pub fn process_batch_query(
+    params: &Params,
+    public_params: &PublicParameters,
+    query: &Query,
+    num_partitions: usize,
+    db: &SparseDb,
+) -> Vec<Vec<u8>> {
     // println!("Processing query");
 
+    assert!(is_valid_batch_size(params, num_partitions));
+
     let dim0 = 1 << params.db_dim_1;
     let num_per = 1 << params.db_dim_2;
+    let rows_per_partition = dim0 / num_partitions;
+    let partition_words = rows_per_partition * 2 * params.poly_len;
 
     let v_packing = public_params.v_packing.as_ref();
 
-    let mut v_reg_reoriented;
-    let v_folding;
+    let (v_reg_reoriented, v_foldings) =
+        get_query_vectors(params, public_params, query, num_partitions, db);
+    let v_foldings_neg: Vec<_> = v_foldings
+        .iter()
+        .map(|v_folding| get_v_folding_neg(params, v_folding))
+        .collect();
+
+    let trials = params.n * params.n;
+    let v_cts: Vec<Vec<PolyMatrixRaw>> = (0..(params.instances * trials))
+        .into_par_iter()
+        .map(|instance_trial| {
+            // every partition covers its own rows, so together they make one pass
+            (0..num_partitions)
+                .map(|partition| {
+                    let v_reg_partition = &v_reg_reoriented.as_slice()
+                        [partition * partition_words..(partition + 1) * partition_words];
+
+                    let mut intermediate = Vec::with_capacity(num_per);
+                    let mut intermediate_raw = Vec::with_capacity(num_per);
+                    for _ in 0..num_per {
+                        intermediate.push(PolyMatrixNTT::zero(params, 2, 1));
+                        intermediate_raw.push(PolyMatrixRaw::zero(params, 2, 1));
+                    }
+
+                    // let now = Instant::now();
+                    multiply_reg_by_sparse_database(
+                        &mut intermediate,
+                        db,
+                        v_reg_partition,
+                        params,
+                        rows_per_partition,
+                        num_per,
+                        instance_trial * num_partitions + partition,
+                    );
+                    // println!("mul took {} us", now.elapsed().as_micros());
+
+                    // let now = Instant::now();
+                    for i in 0..intermediate.len() {
+                        from_ntt(&mut intermediate_raw[i], &intermediate[i]);
+                    }
+
+                    fold_ciphertexts(
+                        params,
+                        &mut intermediate_raw,
+                        &v_foldings[partition],
+                        &v_foldings_neg[partition],
+                    );
+                    // println!("fold took {} us", now.elapsed().as_micros());
+
+                    intermediate_raw[0].clone()
+                })
+                .collect()
+        })
+        .collect();
+
+    (0..num_partitions)
+        .map(|partition| {
+            let v_cts_partition: Vec<PolyMatrixRaw> =
+                v_cts.iter().map(|cts| cts[partition].clone()).collect();
+            let v_packed_ct = v_cts_partition
+                .par_chunks_exact(trials)
+                .map(|chunk: &[PolyMatrixRaw]| {
+                    let packed_ct = pack(params, chunk, v_packing);
+                    packed_ct.raw()
+                })
+                .collect();
+
+            encode(params, &v_packed_ct)
+        })
+        .collect()
+}
+
+/// Answers a batch query, as `process_batch_query`, from a preprocessed database
+/// (see `crate::db::loading::load_preprocessed_db_from_file`).
+pub fn process_batch_query_preprocessed(
+    params: &Params,
+    public_params: &PublicParameters,
+    query: &Query,
+    num_partitions: usize,
+    db: &[u64],
+) -> Vec<Vec<u8>> {
+    assert!(is_valid_batch_size(params, num_partitions));
+    spiral_rs::server::process_batch_query(params, public_params, query, num_partitions, db)
+}
+
+/// Returns the first-dimension ciphertexts, in the layout of `reorient_reg_ciphertexts`, and
+/// the folding ciphertexts of each partition of a query, expanding it if needed.
+fn get_query_vectors<'a>(
+    params: &'a Params,
+    public_params: &PublicParameters<'a>,
+    query: &Query<'a>,
+    num_partitions: usize,
+    db: &SparseDb,
+) -> (AlignedMemory64, Vec<Vec<PolyMatrixNTT<'a>>>) {
     if params.expand_queries {
-        (v_reg_reoriented, v_folding) =
-            expand_query(params, public_params, query, Some(&db.db_idx_to_vec_idx));
+        expand_query(
+            params,
+            public_params,
+            query,
+            num_partitions,
+            Some(&db.db_idx_to_vec_idx),
+        )
     } else {
-        v_reg_reoriented = AlignedMemory64::new(query.v_buf.as_ref().unwrap().len());
+        let mut v_reg_reoriented = AlignedMemory64::new(query.v_buf.as_ref().unwrap().len());
         v_reg_reoriented
             .as_mut_slice()
             .copy_from_slice(query.v_buf.as_ref().unwrap());
 
-        v_folding = query
+        let v_folding = query
             .v_ct
             .as_ref()
             .unwrap()
             .iter()
             .map(|x| x.ntt())
             .collect();
+        (v_reg_reoriented, vec![v_folding])
     }
-
-    let v_folding_neg = get_v_folding_neg(params, &v_folding);
-
-    let trials = params.n * params.n;
-    let v_cts: Vec<PolyMatrixRaw> = (0..(params.instances * trials))
-        .into_par_iter()
-        .map(|instance_trial| {
-            let instance = instance_trial / trials;
-            let trial = instance_trial % trials;
-
-            let mut intermediate = Vec::with_capacity(num_per);
-            let mut intermediate_raw = Vec::with_capacity(num_per);
-            for _ in 0..num_per {
-                intermediate.push(PolyMatrixNTT::zero(params, 2, 1));
-                intermediate_raw.push(PolyMatrixRaw::zero(params, 2, 1));
-            }
-
-            // let now = Instant::now();
-            multiply_reg_by_sparse_database(
-                &mut intermediate,
-                db,
-                v_reg_reoriented.as_slice(),
-                params,
-                dim0,
-                num_per,
-                instance * trials + trial,
-            );
-            // println!("mul took {} us", now.elapsed().as_micros());
-
-            // let now = Instant::now();
-            for i in 0..intermediate.len() {
-                from_ntt(&mut intermediate_raw[i], &intermediate[i]);
-            }
-
-            fold_ciphertexts(params, &mut intermediate_raw, &v_folding, &v_folding_neg);
-            // println!("fold took {} us", now.elapsed().as_micros());
-
-            intermediate_raw[0].clone()
-        })
-        .collect();
-
-    let v_packed_ct = v_cts
-        .par_chunks_exact(trials)
-        .map(|chunk: &[PolyMatrixRaw]| {
-            let packed_ct = pack(params, chunk, &v_packing);
-            packed_ct.raw()
-        })
-        .collect();
-
-    encode(params, &v_packed_ct)
 }
 
 pub fn encode(params: &Params, v_packed_ct: &Vec<PolyMatrixRaw>) -> Vec<u8> {
@@ -188,9 +259,11 @@ mod test {
         println!("processing took {} us", now.elapsed().as_micros());
         println!("response: {} bytes", response.len());
 
-        let result = client.decode_response(response.as_slice());
-
         let p_bits = log2_ceil(params.pt_modulus) as usize;
+        let result = client
+            .decode_response(response.as_slice())
+            .to_vec(p_bits, params.modp_words_per_chunk());
+
         let corr_result = corr_db_item.to_vec(p_bits, params.modp_words_per_chunk());
 
         assert_eq!(result.len(), corr_result.len());
@@ -204,4 +277,90 @@ mod test {
     fn full_protocol_is_correct() {
         full_protocol_is_correct_for_params(&get_params());
     }
+
+    #[test]
+    fn batch_query_matches_single_queries() {
+        let params = util::get_expansion_testing_params();
+        let num_partitions = 4;
+        assert!(is_valid_batch_size(&params, num_partitions));
+        let p_bits = log2_ceil(params.pt_modulus) as usize;
+
+        let mut client = Client::init(&params);
+        let public_params = client.generate_keys();
+
+        let target_idx = 3;
+        let (corr_db_item, db) = generate_fake_sparse_db_and_get_item(&params, target_idx, 1000);
+
+        // fetch some stored item from every other partition
+        let mut targets = vec![None; num_partitions];
+        targets[0] = Some(target_idx);
+        for idx in db.db_idx_to_vec_idx.keys() {
+            if *idx < params.num_items() {
+                let partition = batch_partition(&params, num_partitions, *idx);
+                targets[partition].get_or_insert(*idx);
+            }
+        }
+
+        let query = client.generate_batch_query(&targets);
+        let responses = process_batch_query(&params, &public_params, &query, num_partitions, &db);
+        assert_eq!(responses.len(), num_partitions);
+
+        let decode = |response: &[u8]| {
+            client
+                .decode_response(response)
+                .to_vec(p_bits, params.modp_words_per_chunk())
+        };
+        assert_eq!(
+            decode(&responses[0]),
+            corr_db_item.to_vec(p_bits, params.modp_words_per_chunk())
+        );
+        for (target, response) in targets.iter().zip(responses.iter()).skip(1) {
+            let query = client.generate_query(target.unwrap());
+            let single_response = process_query(&params, &public_params, &query, &db);
+            assert_eq!(decode(response), decode(&single_response));
+        }
+    }
+
+    #[test]
+    fn preprocessed_batch_query_matches_single_queries() {
+        let params = util::get_fast_expansion_testing_params();
+        let num_partitions = 4;
+        let p_bits = log2_ceil(params.pt_modulus) as usize;
+
+        let mut client = Client::init(&params);
+        let public_params = client.generate_keys();
+
+        let items_per_partition = params.num_items() / num_partitions;
+        let targets: Vec<_> = (0..num_partitions)
+            .map(|partition| Some(partition * items_per_partition + 5))
+            .collect();
+        let (corr_db_item, db) =
+            spiral_rs::server::generate_random_db_and_get_item(&params, targets[1].unwrap());
+
+        let query = client.generate_batch_query(&targets);
+        let responses = process_batch_query_preprocessed(
+            &params,
+            &public_params,
+            &query,
+            num_partitions,
+            db.as_slice(),
+        );
+        assert_eq!(responses.len(), num_partitions);
+
+        let decode = |response: &[u8]| {
+            client
+                .decode_response(response)
+                .to_vec(p_bits, params.modp_words_per_chunk())
+        };
+        assert_eq!(
+            decode(&responses[1]),
+            corr_db_item.to_vec(p_bits, params.modp_words_per_chunk())
+        );
+        for (target, response) in targets.iter().zip(responses.iter()) {
+            let query = client.generate_query(target.unwrap());
+            let single_response =
+                spiral_rs::server::process_query(&params, &public_params, &query, db.as_slice());
+            assert_eq!(decode(response), decode(&single_response));
+        }
+    }
 }
@@ -0,0 +1,242 @@
+//! Wire format for `/private-read` and `/private-read-batch`.
+//!
+//! The request carries a list of queries, and the response a list of results, one per query.
+//! Each query is the 36-byte setup UUID followed by the serialized `Query`, or, for parameters
+//! that do not expand queries, the serialized `PublicParameters` followed by the `Query`.
+//! Each result is a serialized response, to be passed to `Client::decode_response`.
+//! For DoublePIR buckets, each query is instead a serialized DoublePIR query batch, and
+//! each result the serialized answer.
+//!
+//! `/private-read-batch` uses the same format, but each query is a batch: the UUID (or
+//! `PublicParameters`), the number of partitions of the first dimension as a u64 LE, and a
+//! single `Query` that selects one item per partition, as made by
+//! `Client::generate_full_batch_query`. A batch yields one result per partition.
+//!
+//! Two encodings of version 1 are supported, chosen by `Content-Type`:
+//!
+//! - `application/octet-stream`: all integers are u64 LE
+//!   - 8 bytes: wire version (1)
+//!   - 8 bytes: number of chunks
+//!   - for each chunk:
+//!     - 8 bytes: chunk length
+//!     - (chunk data)
+//! - `application/json`: `{"version": 1, "queries": ["<base64>", ...]}` for requests, and
+//!   `{"version": 1, "results": ["<base64>", ...]}` for responses.
+//!
+//! The response uses the encoding named by the `Accept` header, if it names one,
+//! and otherwise the encoding of the request. For older clients, a request body that is a bare
+//! JSON list of base64 queries is also accepted, and answered with a bare JSON list.
+
+use base64::{engine::general_purpose, Engine as _};
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+pub const WIRE_VERSION: u64 = 1;
+
+pub const CONTENT_TYPE_BINARY: &str = "application/octet-stream";
+pub const CONTENT_TYPE_JSON: &str = "application/json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WireFormat {
+    Binary,
+    Json,
+    LegacyJson,
+}
+
+impl WireFormat {
+    /// Picks the response format from the `Accept` header, falling back to the request format.
+    pub fn for_response(self, accept: Option<&str>) -> Self {
+        match accept {
+            Some(a) if a.contains(CONTENT_TYPE_BINARY) => WireFormat::Binary,
+            Some(a) if a.contains(CONTENT_TYPE_JSON) && self == WireFormat::Binary => {
+                WireFormat::Json
+            }
+            _ => self,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            WireFormat::Binary => CONTENT_TYPE_BINARY,
+            WireFormat::Json | WireFormat::LegacyJson => CONTENT_TYPE_JSON,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonReadRequest {
+    version: u64,
+    queries: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonReadResponse {
+    version: u64,
+    results: Vec<String>,
+}
+
+fn read_u64(data: &[u8], offset: &mut usize) -> Result<u64, Error> {
+    let bytes = data
+        .get(*offset..*offset + 8)
+        .ok_or(Error::InvalidLength(data.len(), *offset + 8))?;
+    *offset += 8;
+    Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+/// Serialize a list of chunks, prefixed by the wire version.
+pub fn serialize_chunks(chunks: &[Vec<u8>]) -> Vec<u8> {
+    let total: usize = chunks.iter().map(|c| c.len() + 8).sum();
+    let mut out = Vec::with_capacity(16 + total);
+    out.extend(WIRE_VERSION.to_le_bytes());
+    out.extend((chunks.len() as u64).to_le_bytes());
+    for chunk in chunks {
+        out.extend((chunk.len() as u64).to_le_bytes());
+        out.extend(chunk);
+    }
+    out
+}
+
+/// Deserialize a list of chunks, checking the wire version.
+pub fn deserialize_chunks(data: &[u8]) -> Result<Vec<Vec<u8>>, Error> {
+    let mut offset = 0;
+    let version = read_u64(data, &mut offset)?;
+    if version != WIRE_VERSION {
+        return Err(Error::InvalidRequest(format!(
+            "unsupported wire version {}",
+            version
+        )));
+    }
+    let num_chunks = read_u64(data, &mut offset)?;
+    let mut chunks = Vec::new();
+    for _ in 0..num_chunks {
+        let chunk_len = read_u64(data, &mut offset)? as usize;
+        let end = offset
+            .checked_add(chunk_len)
+            .ok_or(Error::InvalidLength(data.len(), usize::MAX))?;
+        let chunk = data
+            .get(offset..end)
+            .ok_or(Error::InvalidLength(data.len(), end))?;
+        chunks.push(chunk.to_vec());
+        offset = end;
+    }
+    if offset != data.len() {
+        return Err(Error::InvalidLength(data.len(), offset));
+    }
+    Ok(chunks)
+}
+
+fn decode_base64_list(strs: &[String]) -> Result<Vec<Vec<u8>>, Error> {
+    strs.iter()
+        .map(|s| {
+            general_purpose::STANDARD
+                .decode(s)
+                .map_err(|e| Error::InvalidRequest(format!("bad base64: {}", e)))
+        })
+        .collect()
+}
+
+fn encode_base64_list(chunks: &[Vec<u8>]) -> Vec<String> {
+    chunks
+        .iter()
+        .map(|c| general_purpose::STANDARD.encode(c))
+        .collect()
+}
+
+/// Decode a `/private-read` request body into its queries.
+pub fn decode_read_request(
+    content_type: Option<&str>,
+    body: &[u8],
+) -> Result<(WireFormat, Vec<Vec<u8>>), Error> {
+    if content_type.is_some_and(|c| c.starts_with(CONTENT_TYPE_BINARY)) {
+        return Ok((WireFormat::Binary, deserialize_chunks(body)?));
+    }
+
+    let value: serde_json::Value = serde_json::from_slice(body)
+        .map_err(|e| Error::InvalidRequest(format!("bad JSON: {}", e)))?;
+    if value.is_array() {
+        let strs: Vec<String> = serde_json::from_value(value)
+            .map_err(|e| Error::InvalidRequest(format!("bad JSON: {}", e)))?;
+        return Ok((WireFormat::LegacyJson, decode_base64_list(&strs)?));
+    }
+
+    let request: JsonReadRequest = serde_json::from_value(value)
+        .map_err(|e| Error::InvalidRequest(format!("bad JSON: {}", e)))?;
+    if request.version != WIRE_VERSION {
+        return Err(Error::InvalidRequest(format!(
+            "unsupported wire version {}",
+            request.version
+        )));
+    }
+    Ok((WireFormat::Json, decode_base64_list(&request.queries)?))
+}
+
+/// Encode the results of a `/private-read` request.
+pub fn encode_read_response(format: WireFormat, results: &[Vec<u8>]) -> Vec<u8> {
+    match format {
+        WireFormat::Binary => serialize_chunks(results),
+        WireFormat::Json => serde_json::to_vec(&JsonReadResponse {
+            version: WIRE_VERSION,
+            results: encode_base64_list(results),
+        })
+        .unwrap(),
+        WireFormat::LegacyJson => serde_json::to_vec(&encode_base64_list(results)).unwrap(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sample() -> Vec<Vec<u8>> {
+        vec![vec![1, 2, 3], vec![], vec![0xff; 100]]
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let body = serialize_chunks(&sample());
+        let (format, chunks) = decode_read_request(Some(CONTENT_TYPE_BINARY), &body).unwrap();
+        assert_eq!(format, WireFormat::Binary);
+        assert_eq!(chunks, sample());
+
+        assert!(decode_read_request(Some(CONTENT_TYPE_BINARY), &body[..body.len() - 1]).is_err());
+        let mut bad_version = body.clone();
+        bad_version[0] = 2;
+        assert!(deserialize_chunks(&bad_version).is_err());
+
+        let mut huge_chunk = serialize_chunks(&[vec![1, 2, 3]]);
+        huge_chunk[16..24].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(matches!(
+            deserialize_chunks(&huge_chunk),
+            Err(Error::InvalidLength(_, _))
+        ));
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let results = encode_read_response(WireFormat::Json, &sample());
+        let response: JsonReadResponse = serde_json::from_slice(&results).unwrap();
+        assert_eq!(response.version, WIRE_VERSION);
+
+        let body = serde_json::to_vec(&JsonReadRequest {
+            version: WIRE_VERSION,
+            queries: response.results,
+        })
+        .unwrap();
+        let (format, chunks) = decode_read_request(Some(CONTENT_TYPE_JSON), &body).unwrap();
+        assert_eq!(format, WireFormat::Json);
+        assert_eq!(chunks, sample());
+    }
+
+    #[test]
+    fn legacy_json_is_accepted() {
+        let body = encode_read_response(WireFormat::LegacyJson, &sample());
+        let (format, chunks) = decode_read_request(None, &body).unwrap();
+        assert_eq!(format, WireFormat::LegacyJson);
+        assert_eq!(chunks, sample());
+        assert_eq!(
+            format.for_response(Some(CONTENT_TYPE_BINARY)),
+            WireFormat::Binary
+        );
+    }
+}
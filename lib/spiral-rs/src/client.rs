@@ -1,12 +1,24 @@
 use crate::{
-    arith::*, discrete_gaussian::*, gadget::*, number_theory::*, params::*, poly::*, util::*,
+    arith::*,
+    discrete_gaussian::*,
+    gadget::*,
+    noise_estimate::NoiseEstimator,
+    number_theory::*,
+    param_search::{estimate_batch_server_ops, CostModel},
+    params::*,
+    poly::*,
+    util::*,
 };
 use either::{Either, Left, Right};
 use rand::seq::SliceRandom;
 use rand::{Rng, SeedableRng};
 use rand_chacha::ChaCha20Rng;
 use serde::{Deserialize, Serialize};
-use std::{iter::once, mem::size_of};
+use std::{
+    iter::once,
+    mem::{size_of, ManuallyDrop},
+    sync::Arc,
+};
 use subtle::ConditionallySelectable;
 use subtle::ConstantTimeEq;
 
@@ -33,6 +45,34 @@ pub const DEFAULT_PARAMS: &'static str = r#"
 
 const UUID_V4_LEN: usize = 36;
 
+/// Why untrusted bytes could not be deserialized.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeserializeError {
+    InvalidLength { got: usize, expected: usize },
+}
+
+impl std::fmt::Display for DeserializeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeserializeError::InvalidLength { got, expected } => {
+                write!(f, "bad length: got {}, expected {}", got, expected)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DeserializeError {}
+
+fn check_length(data: &[u8], expected: usize) -> Result<(), DeserializeError> {
+    if data.len() != expected {
+        return Err(DeserializeError::InvalidLength {
+            got: data.len(),
+            expected,
+        });
+    }
+    Ok(())
+}
+
 fn new_vec_raw<'a>(
     params: &'a Params,
     num: usize,
@@ -225,6 +265,12 @@ impl<'a> PublicParameters<'a> {
         data
     }
 
+    /// Like `deserialize`, but returns an error instead of panicking on malformed input.
+    pub fn try_deserialize(params: &'a Params, data: &[u8]) -> Result<Self, DeserializeError> {
+        check_length(data, params.setup_bytes())?;
+        Ok(Self::deserialize(params, data))
+    }
+
     pub fn deserialize(params: &'a Params, data: &[u8]) -> Self {
         assert_eq!(params.setup_bytes(), data.len());
 
@@ -317,6 +363,12 @@ impl<'a> Query<'a> {
         data
     }
 
+    /// Like `deserialize`, but returns an error instead of panicking on malformed input.
+    pub fn try_deserialize(params: &'a Params, data: &[u8]) -> Result<Self, DeserializeError> {
+        check_length(data, params.query_bytes())?;
+        Ok(Self::deserialize(params, data))
+    }
+
     pub fn deserialize(params: &'a Params, mut data: &[u8]) -> Self {
         assert_eq!(params.query_bytes(), data.len());
 
@@ -380,6 +432,168 @@ fn params_with_moduli(params: &Params, moduli: &Vec<u64>) -> Params {
     )
 }
 
+/// The largest probability, as a log2, that a batch query fails: either because its targets
+/// do not fit the batches (see `choose_batch_shape`), or because of the extra noise of packing
+/// several queries into one ciphertext (see `max_batch_partitions`).
+const BATCH_LOG2_FAILURE_PROB: f64 = -40.0;
+
+/// The last expansion round that produces GSW ciphertexts, for a batch query that packs the
+/// GSW ciphertexts of `num_partitions` queries.
+pub fn batch_stop_round(params: &Params, num_partitions: usize) -> usize {
+    if params.db_dim_2 == 0 {
+        return 0;
+    }
+    log2_ceil_usize(num_partitions * params.t_gsw * params.db_dim_2)
+}
+
+/// Returns the largest number of partitions a batch query can be split into.
+///
+/// A batch query is a single ciphertext that selects one row in each partition of the first
+/// dimension, and carries the GSW ciphertexts of every partition. This only fits when the
+/// GSW ciphertexts are expanded within the expansion rounds of the params, and when their
+/// extra noise keeps the probability of a decryption error below that of the params, or below
+/// `BATCH_LOG2_FAILURE_PROB`. Params that do not expand queries cannot be batched.
+pub fn max_batch_partitions(params: &Params) -> usize {
+    if !params.expand_queries {
+        return 1;
+    }
+    let max_log2_err_prob = f64::max(params.estimate_log2_err_prob(), BATCH_LOG2_FAILURE_PROB);
+    let mut num_partitions = 1;
+    while num_partitions < (1 << params.db_dim_1) {
+        let next = 2 * num_partitions;
+        if batch_stop_round(params, next) >= params.g()
+            || params.estimate_batch_log2_err_prob(next) > max_log2_err_prob
+        {
+            break;
+        }
+        num_partitions = next;
+    }
+    num_partitions
+}
+
+/// Returns whether a batch query can be split into `num_partitions` partitions: the first
+/// dimension must split evenly into that many partitions of rows.
+pub fn is_valid_batch_size(params: &Params, num_partitions: usize) -> bool {
+    num_partitions.is_power_of_two() && num_partitions <= max_batch_partitions(params)
+}
+
+/// Returns the partition holding `idx_target`, when the first dimension is split into
+/// `num_partitions` equal ranges of rows for a batch query.
+pub fn batch_partition(params: &Params, num_partitions: usize, idx_target: usize) -> usize {
+    assert!(num_partitions.is_power_of_two() && num_partitions <= (1 << params.db_dim_1));
+    let idx_dim0 = idx_target >> params.db_dim_2;
+    idx_dim0 / ((1 << params.db_dim_1) / num_partitions)
+}
+
+/// `ln(a + b)`, given `ln(a)` and `ln(b)`.
+fn ln_add_exp(ln_a: f64, ln_b: f64) -> f64 {
+    let (hi, lo) = if ln_a > ln_b {
+        (ln_a, ln_b)
+    } else {
+        (ln_b, ln_a)
+    };
+    if lo == f64::NEG_INFINITY {
+        return hi;
+    }
+    hi + f64::ln_1p(f64::exp(lo - hi))
+}
+
+/// Returns the number of batches needed so that `num_targets` targets, each in a uniformly
+/// random partition out of `num_partitions`, fit with one target per partition per batch,
+/// except with probability `2^BATCH_LOG2_FAILURE_PROB`.
+///
+/// A partition overflows when more targets fall into it than there are batches; the number of
+/// targets in a partition is binomial, and the probability that any partition overflows is
+/// bounded by `num_partitions` times that of one partition.
+fn batches_needed(num_targets: usize, num_partitions: usize) -> usize {
+    if num_partitions == 1 {
+        return num_targets;
+    }
+    let mut ln_factorials = vec![0f64; num_targets + 1];
+    for i in 1..=num_targets {
+        ln_factorials[i] = ln_factorials[i - 1] + f64::ln(i as f64);
+    }
+    let ln_p = -f64::ln(num_partitions as f64);
+    let ln_not_p = f64::ln_1p(-1. / num_partitions as f64);
+    let ln_pmf = |i: usize| {
+        ln_factorials[num_targets] - ln_factorials[i] - ln_factorials[num_targets - i]
+            + (i as f64) * ln_p
+            + ((num_targets - i) as f64) * ln_not_p
+    };
+
+    let ln_bound = BATCH_LOG2_FAILURE_PROB * std::f64::consts::LN_2 + ln_p;
+    // ln of the probability that more than `num_batches` targets fall into one partition
+    let mut ln_tail = f64::NEG_INFINITY;
+    for num_batches in (0..num_targets).rev() {
+        ln_tail = ln_add_exp(ln_tail, ln_pmf(num_batches + 1));
+        if ln_tail > ln_bound {
+            return num_batches + 1;
+        }
+    }
+    0
+}
+
+/// Picks the shape of the batch queries used to fetch `num_targets` targets: the number of
+/// batches, and the number of partitions to split the first dimension into.
+///
+/// The shape depends only on `num_targets` and the params, so it reveals nothing about which
+/// targets are fetched. For each number of partitions, there are just enough batches for
+/// targets spread at random (like the rows of hashed keys) to fit, except with probability
+/// `2^BATCH_LOG2_FAILURE_PROB`; see `plan_batches`. Of those shapes, this picks the cheapest
+/// under the default `CostModel`: a batch costs one query and one pass over the database, but
+/// one response, fold and packing per partition. With a single partition, this is one query
+/// per target.
+pub fn choose_batch_shape(params: &Params, num_targets: usize) -> (usize, usize) {
+    let cost_model = CostModel::default();
+    let cost = |num_batches: usize, num_partitions: usize| {
+        let per_batch = cost_model.query_byte * params.query_bytes() as f64
+            + cost_model.response_byte * (num_partitions * params.response_bytes()) as f64
+            + cost_model.server_op * estimate_batch_server_ops(params, num_partitions);
+        num_batches as f64 * per_batch
+    };
+
+    let max_partitions = max_batch_partitions(params);
+    let mut best = (num_targets, 1);
+    let mut num_partitions = 2;
+    while num_partitions <= max_partitions {
+        let num_batches = batches_needed(num_targets, num_partitions);
+        if cost(num_batches, num_partitions) < cost(best.0, best.1) {
+            best = (num_batches, num_partitions);
+        }
+        num_partitions *= 2;
+    }
+    best
+}
+
+/// Assigns targets to exactly `num_batches` batches, so that every batch has at most one target
+/// per partition.
+///
+/// Returns, for each batch, the target of each partition, if any. Duplicate targets are only
+/// fetched once. Returns `None` if more targets fall into some partition than there are batches.
+/// Callers must then fail rather than fetch the targets some other way, since that would reveal
+/// that the targets are clustered; for the shapes picked by `choose_batch_shape`, this only
+/// happens with probability `2^BATCH_LOG2_FAILURE_PROB`.
+pub fn plan_batches(
+    params: &Params,
+    num_batches: usize,
+    num_partitions: usize,
+    idx_targets: &[usize],
+) -> Option<Vec<Vec<Option<usize>>>> {
+    let mut targets = idx_targets.to_vec();
+    targets.sort_unstable();
+    targets.dedup();
+
+    let mut batches = vec![vec![None; num_partitions]; num_batches];
+    for idx_target in targets {
+        let partition = batch_partition(params, num_partitions, idx_target);
+        let batch = batches
+            .iter_mut()
+            .find(|batch| batch[partition].is_none())?;
+        batch[partition] = Some(idx_target);
+    }
+    Some(batches)
+}
+
 pub struct Client<'a> {
     params: &'a Params,
     sk_gsw: PolyMatrixRaw<'a>,
@@ -637,36 +851,40 @@ impl<'a> Client<'a> {
         pp
     }
 
-    pub fn generate_query(&self, idx_target: usize) -> Query<'a> {
+    /// The plaintext of a query that is expanded by the server, selecting `idx_targets[p]` in
+    /// partition `p` of the first dimension (see `batch_partition`).
+    ///
+    /// The even coefficients select one row per partition, and the odd coefficients hold the
+    /// GSW bits of each partition in turn.
+    fn expansion_plaintext(&self, idx_targets: &[usize]) -> PolyMatrixRaw<'a> {
         let params = self.params;
         let further_dims = params.db_dim_2;
-        let idx_dim0 = idx_target / (1 << further_dims);
-        let idx_further = idx_target % (1 << further_dims);
         let scale_k = params.modulus / params.pt_modulus;
         let bits_per = get_bits_per(params, params.t_gsw);
 
-        let mut rng = ChaCha20Rng::from_entropy();
-
-        let mut query = Query::empty();
-        let query_seed = ChaCha20Rng::from_entropy().gen();
-        query.seed = Some(query_seed);
-        let mut rng_pub = ChaCha20Rng::from_seed(query_seed);
-        if params.expand_queries {
-            // pack query into single ciphertext
-            let mut sigma = PolyMatrixRaw::zero(params, 1, 1);
-            let inv_2_g_first = invert_uint_mod(1 << params.g(), params.modulus).unwrap();
-            let inv_2_g_rest =
-                invert_uint_mod(1 << (params.stop_round() + 1), params.modulus).unwrap();
-
-            if params.db_dim_2 == 0 {
+        let mut sigma = PolyMatrixRaw::zero(params, 1, 1);
+        let inv_2_g_first = invert_uint_mod(1 << params.g(), params.modulus).unwrap();
+        let inv_2_g_rest = invert_uint_mod(
+            1 << (batch_stop_round(params, idx_targets.len()) + 1),
+            params.modulus,
+        )
+        .unwrap();
+
+        if params.db_dim_2 == 0 {
+            for idx_target in idx_targets {
+                let idx_dim0 = idx_target / (1 << further_dims);
                 for i in 0..(1 << params.db_dim_1) {
                     sigma.data[i].conditional_assign(&scale_k, (i as u64).ct_eq(&(idx_dim0 as u64)))
                 }
+            }
 
-                for i in 0..params.poly_len {
-                    sigma.data[i] = multiply_uint_mod(sigma.data[i], inv_2_g_first, params.modulus);
-                }
-            } else {
+            for i in 0..params.poly_len {
+                sigma.data[i] = multiply_uint_mod(sigma.data[i], inv_2_g_first, params.modulus);
+            }
+        } else {
+            for (partition, idx_target) in idx_targets.iter().enumerate() {
+                let idx_dim0 = idx_target / (1 << further_dims);
+                let idx_further = idx_target % (1 << further_dims);
                 for i in 0..(1 << params.db_dim_1) {
                     sigma.data[2 * i]
                         .conditional_assign(&scale_k, (i as u64).ct_eq(&(idx_dim0 as u64)))
@@ -677,19 +895,39 @@ impl<'a> Client<'a> {
                     let bit = ((idx_further as u64) & mask).ct_eq(&mask);
                     for j in 0..params.t_gsw {
                         let val = u64::conditional_select(&0, &(1u64 << (bits_per * j)), bit);
-                        let idx = (i as usize) * params.t_gsw + (j as usize);
+                        let idx = (partition * further_dims + i as usize) * params.t_gsw + j;
                         sigma.data[2 * idx + 1] = val;
                     }
                 }
+            }
 
-                for i in 0..params.poly_len / 2 {
-                    sigma.data[2 * i] =
-                        multiply_uint_mod(sigma.data[2 * i], inv_2_g_first, params.modulus);
-                    sigma.data[2 * i + 1] =
-                        multiply_uint_mod(sigma.data[2 * i + 1], inv_2_g_rest, params.modulus);
-                }
+            for i in 0..params.poly_len / 2 {
+                sigma.data[2 * i] =
+                    multiply_uint_mod(sigma.data[2 * i], inv_2_g_first, params.modulus);
+                sigma.data[2 * i + 1] =
+                    multiply_uint_mod(sigma.data[2 * i + 1], inv_2_g_rest, params.modulus);
             }
+        }
+        sigma
+    }
 
+    pub fn generate_query(&self, idx_target: usize) -> Query<'a> {
+        let params = self.params;
+        let further_dims = params.db_dim_2;
+        let idx_dim0 = idx_target / (1 << further_dims);
+        let idx_further = idx_target % (1 << further_dims);
+        let scale_k = params.modulus / params.pt_modulus;
+        let bits_per = get_bits_per(params, params.t_gsw);
+
+        let mut rng = ChaCha20Rng::from_entropy();
+
+        let mut query = Query::empty();
+        let query_seed = ChaCha20Rng::from_entropy().gen();
+        query.seed = Some(query_seed);
+        let mut rng_pub = ChaCha20Rng::from_seed(query_seed);
+        if params.expand_queries {
+            // pack query into single ciphertext
+            let sigma = self.expansion_plaintext(&[idx_target]);
             query.ct = Some(from_ntt_alloc(&self.encrypt_matrix_reg(
                 &to_ntt_alloc(&sigma),
                 &mut rng,
@@ -751,6 +989,61 @@ impl<'a> Client<'a> {
         full_query_buf
     }
 
+    /// Generates a batch query, that selects one item in each partition of the first dimension.
+    ///
+    /// `targets[p]` must lie in partition `p` (see `batch_partition`), and `targets.len()` must
+    /// be a valid batch size (see `is_valid_batch_size`). Partitions without a target select a
+    /// random item in the partition, so the server cannot tell them apart. The batch is a single
+    /// ciphertext, which the server expands and multiplies by the database once, and answers
+    /// with one response per partition.
+    pub fn generate_batch_query(&self, targets: &[Option<usize>]) -> Query<'a> {
+        let params = self.params;
+        let num_partitions = targets.len();
+        assert!(is_valid_batch_size(params, num_partitions));
+        let items_per_partition = params.num_items() / num_partitions;
+
+        let mut rng = ChaCha20Rng::from_entropy();
+        let idx_targets: Vec<_> = targets
+            .iter()
+            .enumerate()
+            .map(|(partition, target)| match target {
+                Some(idx_target) => {
+                    assert_eq!(
+                        batch_partition(params, num_partitions, *idx_target),
+                        partition
+                    );
+                    *idx_target
+                }
+                None => partition * items_per_partition + rng.gen_range(0..items_per_partition),
+            })
+            .collect();
+        if num_partitions == 1 {
+            return self.generate_query(idx_targets[0]);
+        }
+
+        let mut query = Query::empty();
+        let query_seed = ChaCha20Rng::from_entropy().gen();
+        query.seed = Some(query_seed);
+        let mut rng_pub = ChaCha20Rng::from_seed(query_seed);
+        let sigma = self.expansion_plaintext(&idx_targets);
+        query.ct = Some(from_ntt_alloc(&self.encrypt_matrix_reg(
+            &to_ntt_alloc(&sigma),
+            &mut rng,
+            &mut rng_pub,
+        )));
+        query
+    }
+
+    /// Serializes a batch query (see `generate_batch_query`) for `/private-read-batch`: the
+    /// setup UUID, the number of partitions as a u64 LE, and the query.
+    pub fn generate_full_batch_query(&self, id: &str, targets: &[Option<usize>]) -> Vec<u8> {
+        assert_eq!(id.len(), UUID_V4_LEN);
+        let mut full_query_buf = id.as_bytes().to_vec();
+        full_query_buf.extend_from_slice(&(targets.len() as u64).to_le_bytes());
+        full_query_buf.append(&mut self.generate_batch_query(targets).serialize());
+        full_query_buf
+    }
+
     pub fn decode_response(&self, data: &[u8]) -> PolyMatrixRaw<'a> {
         /*
             0. NTT over q2 the secret key
@@ -951,6 +1244,65 @@ impl<'a> Client<'a> {
     }
 }
 
+/// A `Client` that holds its own reference to its shared parameters (see `intern_params`),
+/// so it can be kept without borrowing them.
+///
+/// The client borrows the parameters as `'static`, which is sound because:
+/// - the borrow stays private: the client, and everything it creates, is only handed
+///   out for as long as a borrow of `self`;
+/// - `Drop` drops the client before `params`, so the parameters outlive it.
+pub struct SharedClient {
+    client: ManuallyDrop<Client<'static>>,
+    params: Arc<Params>,
+}
+
+impl SharedClient {
+    pub fn init(params: Arc<Params>) -> Self {
+        // SAFETY: see the invariants on `SharedClient`.
+        let borrowed: &'static Params = unsafe { &*Arc::as_ptr(&params) };
+        Self {
+            client: ManuallyDrop::new(Client::init(borrowed)),
+            params,
+        }
+    }
+
+    pub fn params(&self) -> &Params {
+        &self.params
+    }
+
+    pub fn client(&self) -> &Client<'_> {
+        &self.client
+    }
+
+    pub fn generate_keys_from_seed(&mut self, seed: Seed) -> PublicParameters<'_> {
+        self.client.generate_keys_from_seed(seed)
+    }
+
+    pub fn generate_keys(&mut self) -> PublicParameters<'_> {
+        self.client.generate_keys()
+    }
+
+    pub fn generate_secret_keys_from_seed(&mut self, seed: Seed) {
+        self.client.generate_secret_keys_from_seed(seed)
+    }
+
+    pub fn generate_keys_optional(
+        &mut self,
+        seed: Seed,
+        generate_pub_params: bool,
+    ) -> Option<Vec<u8>> {
+        self.client
+            .generate_keys_optional(seed, generate_pub_params)
+    }
+}
+
+impl Drop for SharedClient {
+    fn drop(&mut self) {
+        // SAFETY: `client` is not used again; `params` is only dropped after this.
+        unsafe { ManuallyDrop::drop(&mut self.client) }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -982,6 +1334,21 @@ mod test {
         }
     }
 
+    #[test]
+    fn shared_client_keeps_params_until_dropped() {
+        let params = Arc::new(get_params());
+        let weak = Arc::downgrade(&params);
+        let mut client = SharedClient::init(params);
+        let seed = [7u8; SEED_LENGTH];
+
+        let setup = client.generate_keys_from_seed(seed).serialize();
+        assert_eq!(setup.len(), client.params().setup_bytes());
+
+        assert!(weak.upgrade().is_some());
+        drop(client);
+        assert!(weak.upgrade().is_none());
+    }
+
     fn get_vec(v: &Vec<PolyMatrixNTT>) -> Vec<u64> {
         v.iter().map(|d| d.as_slice().to_vec()).flatten().collect()
     }
@@ -1085,6 +1452,26 @@ mod test {
         }
     }
 
+    #[test]
+    fn try_deserialize_rejects_bad_lengths() {
+        let params = get_params();
+        let mut client = Client::init(&params);
+        let setup = client.generate_keys().serialize();
+        let query = client.generate_query(1).serialize();
+
+        assert!(PublicParameters::try_deserialize(&params, &setup).is_ok());
+        assert!(Query::try_deserialize(&params, &query).is_ok());
+        assert_eq!(
+            PublicParameters::try_deserialize(&params, &setup[1..]).err(),
+            Some(DeserializeError::InvalidLength {
+                got: setup.len() - 1,
+                expected: setup.len()
+            })
+        );
+        assert!(Query::try_deserialize(&params, &[]).is_err());
+        assert!(Query::try_deserialize(&params, &[query.clone(), vec![0]].concat()).is_err());
+    }
+
     #[test]
     fn query_serialization_is_correct() {
         query_serialization_is_correct_for_params(get_params())
@@ -1094,4 +1481,102 @@ mod test {
     fn no_expansion_query_serialization_is_correct() {
         query_serialization_is_correct_for_params(get_no_expansion_testing_params())
     }
+
+    #[test]
+    fn batch_shape_depends_only_on_number_of_targets() {
+        let params = get_expansion_testing_params();
+        let num_targets = 64;
+        let (num_batches, num_partitions) = choose_batch_shape(&params, num_targets);
+        assert_eq!((num_batches, num_partitions), (32, 8));
+        assert_eq!(choose_batch_shape(&params, 1), (1, 1));
+        assert_eq!(choose_batch_shape(&params, 20), (20, 1));
+        assert_eq!(choose_batch_shape(&params, 0), (0, 1));
+
+        // targets spread over all partitions, and targets clustered in half of them
+        let partition_size = params.num_items() / num_partitions;
+        let spread: Vec<_> = (0..num_targets).map(|i| i * partition_size / 8).collect();
+        let clustered: Vec<_> = (0..num_targets).map(|i| i * partition_size / 16).collect();
+        let mut shapes = Vec::new();
+        for targets in [&spread, &clustered] {
+            let batches = plan_batches(&params, num_batches, num_partitions, targets).unwrap();
+            let shape: Vec<_> = batches.iter().map(|batch| batch.len()).collect();
+            shapes.push(shape);
+        }
+        assert_eq!(shapes[0], vec![num_partitions; num_batches]);
+        assert_eq!(shapes[0], shapes[1]);
+
+        // targets that overflow the batches fail, rather than change the shape
+        let all_in_one: Vec<_> = (0..num_targets).collect();
+        assert_eq!(
+            plan_batches(&params, num_batches, num_partitions, &all_in_one),
+            None
+        );
+    }
+
+    #[test]
+    fn batches_are_sized_for_random_targets() {
+        assert_eq!(batches_needed(64, 1), 64);
+        assert_eq!(batches_needed(0, 8), 0);
+        for num_partitions in [2, 8, 64] {
+            for num_targets in [1, 20, 64, 1000] {
+                let num_batches = batches_needed(num_targets, num_partitions);
+                assert!(num_batches >= num_targets.div_ceil(num_partitions));
+                assert!(num_batches <= num_targets);
+            }
+        }
+        assert_eq!(batches_needed(64, 8), 32);
+        assert_eq!(batches_needed(1024, 16), 129);
+    }
+
+    #[test]
+    fn batch_queries_cost_less_than_single_queries() {
+        let params = get_expansion_testing_params();
+        let num_targets = 256;
+        let (num_batches, num_partitions) = choose_batch_shape(&params, num_targets);
+        assert!(num_partitions > 1);
+
+        let mut client = Client::init(&params);
+        client.generate_keys();
+        let id = "00000000-0000-4000-8000-000000000000";
+        let batch = vec![None; num_partitions];
+        let batch_bytes = client.generate_full_batch_query(id, &batch).len();
+        let single_bytes = client.generate_full_query(id, 0).len();
+        assert_eq!(batch_bytes, single_bytes + 8);
+        assert!(num_batches * batch_bytes < num_targets * single_bytes);
+
+        let batch_ops = estimate_batch_server_ops(&params, num_partitions);
+        let single_ops = estimate_batch_server_ops(&params, 1);
+        assert!(num_batches as f64 * batch_ops < num_targets as f64 * single_ops);
+    }
+
+    #[test]
+    fn batches_hold_one_target_per_partition() {
+        let params = get_expansion_testing_params();
+        assert!(is_valid_batch_size(&params, 4));
+        assert!(!is_valid_batch_size(&params, 3));
+        assert!(!is_valid_batch_size(&params, 1 << params.db_dim_1));
+        assert_eq!(max_batch_partitions(&get_no_expansion_testing_params()), 1);
+
+        let targets = [5, 8192, 0, 30000, 1, 0];
+        let batches = plan_batches(&params, 3, 4, &targets).unwrap();
+        assert_eq!(batches.len(), 3);
+        for batch in batches.iter() {
+            assert_eq!(batch.len(), 4);
+            for (partition, target) in batch.iter().enumerate() {
+                if let Some(idx_target) = target {
+                    assert_eq!(batch_partition(&params, 4, *idx_target), partition);
+                }
+            }
+        }
+        let mut fetched: Vec<_> = batches.iter().flatten().flatten().copied().collect();
+        fetched.sort_unstable();
+        assert_eq!(fetched, vec![0, 1, 5, 8192, 30000]);
+        assert_eq!(plan_batches(&params, 2, 4, &targets), None);
+
+        let mut client = Client::init(&params);
+        client.generate_keys();
+        let id = "00000000-0000-4000-8000-000000000000";
+        let full_query = client.generate_full_batch_query(id, &batches[0]);
+        assert_eq!(full_query.len(), id.len() + 8 + params.query_bytes());
+    }
 }
@@ -1,9 +1,25 @@
 use crate::params::Params;
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 
 const VARINT_MAX_BYTES: usize = 8;
 const MAX_VARINT_BITS: u64 = 63;
 
+pub fn varint_encode(mut number: u64) -> Vec<u8> {
+    let mut buf = Vec::new();
+    loop {
+        let to_write = (number & 0x7F) as u8;
+        number >>= 7;
+        if number != 0 {
+            buf.push(to_write | 0x80);
+        } else {
+            buf.push(to_write);
+            break;
+        }
+    }
+    buf
+}
+
 pub fn varint_decode(data: &[u8]) -> (usize, usize) {
     let mut shift = 0u64;
     let mut result = 0u64;
@@ -22,12 +38,10 @@ pub fn varint_decode(data: &[u8]) -> (usize, usize) {
     (result as usize, j)
 }
 
-pub fn row_from_key(params: &Params, key: &str) -> usize {
-    let num_items = params.num_items();
+/// Maps the leading bits of `hash` to an index in `0..num_items`.
+fn index_from_hash(num_items: usize, hash: &[u8]) -> usize {
     let buckets_log2 = (num_items as f64).log2().ceil() as usize;
 
-    let hash = Sha256::digest(key.as_bytes());
-
     // let idx = read_arbitrary_bits(&hash, 0, buckets_log2) as usize;
     let mut idx = 0;
     for i in 0..buckets_log2 {
@@ -39,6 +53,77 @@ pub fn row_from_key(params: &Params, key: &str) -> usize {
     idx
 }
 
+pub fn row_from_key(params: &Params, key: &str) -> usize {
+    let hash = Sha256::digest(key.as_bytes());
+    index_from_hash(params.num_items(), &hash)
+}
+
+/// Number of bytes of the key hash stored alongside each value in a row.
+pub const KEY_HASH_BYTES: usize = 8;
+
+/// Default number of candidate rows per key in the cuckoo layout.
+pub const DEFAULT_CUCKOO_HASHES: usize = 2;
+
+/// Supported numbers of candidate rows per key in the cuckoo layout.
+pub const CUCKOO_HASHES_RANGE: (usize, usize) = (2, 8);
+
+/// The hash of `key` that is stored alongside its value in a row.
+pub fn key_hash(key: &str) -> [u8; KEY_HASH_BYTES] {
+    let hash = Sha256::digest(key.as_bytes());
+    hash[hash.len() - KEY_HASH_BYTES..].try_into().unwrap()
+}
+
+/// The candidate rows, in `0..num_items`, of a key with the given stored hash.
+///
+/// These depend only on the stored hash, so that a server can move a value
+/// between its candidate rows without knowing its key.
+pub fn cuckoo_rows_from_hash(num_items: usize, key_hash: &[u8], num_hashes: usize) -> Vec<usize> {
+    (0..num_hashes)
+        .map(|i| {
+            let hash = Sha256::new()
+                .chain_update([i as u8])
+                .chain_update(key_hash)
+                .finalize();
+            index_from_hash(num_items, &hash)
+        })
+        .collect()
+}
+
+/// How keys are placed in the rows of a database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum KeyLayout {
+    /// Each key is in the single row given by `row_from_key`.
+    #[default]
+    Single,
+    /// Each key is in one of `num_hashes` candidate rows, given by `cuckoo_rows_from_hash`.
+    Cuckoo { num_hashes: usize },
+}
+
+impl KeyLayout {
+    /// Whether the layout is supported: the cuckoo layout needs a number of candidate
+    /// rows in `CUCKOO_HASHES_RANGE`.
+    pub fn is_valid(&self) -> bool {
+        match self {
+            KeyLayout::Single => true,
+            KeyLayout::Cuckoo { num_hashes } => {
+                (CUCKOO_HASHES_RANGE.0..=CUCKOO_HASHES_RANGE.1).contains(num_hashes)
+            }
+        }
+    }
+
+    /// The rows that may hold `key`. A client must query all of them, even if some coincide,
+    /// so that the number of queries does not depend on the key.
+    pub fn candidate_rows(&self, params: &Params, key: &str) -> Vec<usize> {
+        match self {
+            KeyLayout::Single => vec![row_from_key(params, key)],
+            KeyLayout::Cuckoo { num_hashes } => {
+                cuckoo_rows_from_hash(params.num_items(), &key_hash(key), *num_hashes)
+            }
+        }
+    }
+}
+
 pub fn extract_result_impl(key: &str, result: &[u8]) -> Result<Vec<u8>, &'static str> {
     let hash_bytes = result[0] as usize;
     let hash = Sha256::digest(key.as_bytes());
@@ -49,8 +134,9 @@ pub fn extract_result_impl(key: &str, result: &[u8]) -> Result<Vec<u8>, &'static
         let key_hash = &result[i..i + hash_bytes];
         i += hash_bytes;
 
-        // read len
-        let (value_len, value_len_len) = varint_decode(&result[i..i + VARINT_MAX_BYTES]);
+        // read len (the varint may end less than VARINT_MAX_BYTES before the end of the row)
+        let varint_end = usize::min(i + VARINT_MAX_BYTES, result.len());
+        let (value_len, value_len_len) = varint_decode(&result[i..varint_end]);
         i += value_len_len;
 
         // read value
@@ -89,6 +175,32 @@ mod test {
         )
     }
 
+    #[test]
+    fn varint_round_trip() {
+        for number in [0, 1, 127, 128, 300, 1 << 40] {
+            let encoded = varint_encode(number);
+            assert_eq!(varint_decode(&encoded), (number as usize, encoded.len()));
+        }
+    }
+
+    #[test]
+    fn cuckoo_rows_are_deterministic_and_in_range() {
+        let params = get_params();
+        let layout = KeyLayout::Cuckoo { num_hashes: 3 };
+        let rows = layout.candidate_rows(&params, "CA");
+        assert_eq!(rows.len(), 3);
+        assert!(rows.iter().all(|row| *row < params.num_items()));
+        assert_eq!(rows, layout.candidate_rows(&params, "CA"));
+        assert_eq!(
+            KeyLayout::Single.candidate_rows(&params, "CA"),
+            vec![row_from_key(&params, "CA")]
+        );
+
+        let json = serde_json::to_string(&layout).unwrap();
+        assert_eq!(json, r#"{"type":"cuckoo","num_hashes":3}"#);
+        assert_eq!(serde_json::from_str::<KeyLayout>(&json).unwrap(), layout);
+    }
+
     #[test]
     fn row_from_key_is_correct() {
         let params = get_params();
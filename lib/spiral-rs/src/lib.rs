@@ -3,16 +3,18 @@ pub mod arith;
 pub mod discrete_gaussian;
 pub mod noise_estimate;
 pub mod number_theory;
+pub mod param_search;
 pub mod util;
 
 pub mod gadget;
 pub mod ntt;
 pub mod params;
+pub mod params_store;
 pub mod poly;
+pub mod simd;
 
 pub mod client;
 pub mod key_value;
 
 #[cfg(feature = "server")]
 pub mod server;
-
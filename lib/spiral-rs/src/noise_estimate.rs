@@ -51,6 +51,14 @@ fn gadget_exp_factor(s: &Paramset, t: usize, z: f64) -> f64 {
 }
 
 pub fn get_noise_from_paramset(s: &Paramset) -> f64 {
+    get_batch_noise_from_paramset(s, 1)
+}
+
+/// The noise of a batch query that packs `num_partitions` queries into one ciphertext.
+///
+/// The GSW ciphertexts of the batch take `num_partitions` times as many expansion rounds'
+/// worth of slots, and the rounds past those of a single query use the left expansion keys.
+pub fn get_batch_noise_from_paramset(s: &Paramset, num_partitions: usize) -> f64 {
     let nu1 = s.db_dim_1 as i32;
     let nu2 = s.db_dim_2 as i32;
 
@@ -75,10 +83,16 @@ pub fn get_noise_from_paramset(s: &Paramset) -> f64 {
         // NB: above, we exclude a factor of s.d; this is bad according to the paper, but
         //     in practice, it seems to model the noise accurately
 
-        let num_exp_gsw = f64::ceil(f64::log2((s.t_gsw as f64) * (nu2 as f64))) as i32 + 1;
-        sigma_gsw_2 = 4f64.powi(num_exp_gsw)
-            * s.sigma.powi(2)
-            * (1.0 + ((s.t_exp_right) as f64 * z_exp_right.powi(2) / 3.));
+        let num_exp_gsw_single = f64::ceil(f64::log2((s.t_gsw as f64) * (nu2 as f64))) as i32 + 1;
+        let num_exp_gsw = f64::ceil(f64::log2(
+            (num_partitions as f64) * (s.t_gsw as f64) * (nu2 as f64),
+        )) as i32
+            + 1;
+        let mut exp_gsw_factor = (s.t_exp_right) as f64 * z_exp_right.powi(2) / 3.;
+        if num_exp_gsw > num_exp_gsw_single {
+            exp_gsw_factor = exp_gsw_factor.max((s.t_exp_left) as f64 * z_exp_left.powi(2) / 3.);
+        }
+        sigma_gsw_2 = 4f64.powi(num_exp_gsw) * s.sigma.powi(2) * (1.0 + exp_gsw_factor);
         sigma_gsw_2 = sigma_gsw_2 * 2. * (HAMMING_WEIGHT as f64)
             + 2. * gadget_exp_factor(s, s.t_conv, z_conv);
     }
@@ -120,6 +134,9 @@ pub fn get_p_err(s: &Paramset, s_e: f64, q_prime: u64) -> f64 {
 pub trait NoiseEstimator {
     fn estimate_noise(&self) -> f64;
     fn estimate_log2_err_prob(&self) -> f64;
+    /// The probability of a decryption error, as a log2, for a batch query of
+    /// `num_partitions` partitions (see `client::max_batch_partitions`).
+    fn estimate_batch_log2_err_prob(&self, num_partitions: usize) -> f64;
 }
 
 impl NoiseEstimator for Params {
@@ -133,6 +150,13 @@ impl NoiseEstimator for Params {
         let s_e = self.estimate_noise();
         get_p_err(&paramset, s_e, q2)
     }
+
+    fn estimate_batch_log2_err_prob(&self, num_partitions: usize) -> f64 {
+        let q2 = Q2_VALUES[self.q2_bits as usize];
+        let paramset = extract_paramset(self);
+        let s_e = get_batch_noise_from_paramset(&paramset, num_partitions);
+        get_p_err(&paramset, s_e, q2)
+    }
 }
 
 #[cfg(test)]
@@ -168,4 +192,12 @@ mod test {
         // assert!(noise_log2 < 87.0);
         assert!(p_err <= -40.0);
     }
+
+    #[test]
+    fn batch_noise_grows_with_partitions() {
+        let params = get_expansion_testing_params();
+        let p_err = params.estimate_log2_err_prob();
+        assert_eq!(params.estimate_batch_log2_err_prob(1), p_err);
+        assert!(params.estimate_batch_log2_err_prob(16) > p_err);
+    }
 }
@@ -1,7 +1,6 @@
-#[cfg(target_feature = "avx2")]
-use std::arch::x86_64::*;
+use std::sync::OnceLock;
 
-use crate::{arith::*, number_theory::*, params::*};
+use crate::{arith::*, number_theory::*, params::*, simd::Simd};
 
 pub fn powers_of_primitive_root(root: u64, modulus: u64, poly_len_log2: usize) -> Vec<u64> {
     let poly_len = 1usize << poly_len_log2;
@@ -64,8 +63,99 @@ pub fn build_ntt_tables(poly_len: usize, moduli: &[u64]) -> Vec<Vec<Vec<u64>>> {
     output
 }
 
-#[cfg(not(target_feature = "avx2"))]
+/// A forward or inverse NTT, applied in place to every CRT modulus of an operand.
+pub type NttKernel = fn(&Params, &mut [u64]);
+
+/// Returns the forward NTT path for the given instruction set.
+///
+/// Panics if the running CPU does not support it.
+pub fn ntt_forward_kernel(simd: Simd) -> NttKernel {
+    simd.assert_available();
+    match simd {
+        #[cfg(target_arch = "x86_64")]
+        Simd::Avx2 => |params, operand| unsafe { avx2::ntt_forward(params, operand) },
+        #[cfg(target_arch = "x86_64")]
+        Simd::Avx512 => |params, operand| unsafe { avx512::ntt_forward(params, operand) },
+        #[cfg(target_arch = "aarch64")]
+        Simd::Neon => |params, operand| unsafe { neon::ntt_forward(params, operand) },
+        _ => ntt_forward_scalar,
+    }
+}
+
+/// Returns the inverse NTT path for the given instruction set.
+///
+/// Panics if the running CPU does not support it.
+pub fn ntt_inverse_kernel(simd: Simd) -> NttKernel {
+    simd.assert_available();
+    match simd {
+        #[cfg(target_arch = "x86_64")]
+        Simd::Avx2 => |params, operand| unsafe { avx2::ntt_inverse(params, operand) },
+        #[cfg(target_arch = "x86_64")]
+        Simd::Avx512 => |params, operand| unsafe { avx512::ntt_inverse(params, operand) },
+        #[cfg(target_arch = "aarch64")]
+        Simd::Neon => |params, operand| unsafe { neon::ntt_inverse(params, operand) },
+        _ => ntt_inverse_scalar,
+    }
+}
+
 pub fn ntt_forward(params: &Params, operand_overall: &mut [u64]) {
+    static KERNEL: OnceLock<NttKernel> = OnceLock::new();
+    KERNEL.get_or_init(|| ntt_forward_kernel(Simd::detected()))(params, operand_overall)
+}
+
+pub fn ntt_inverse(params: &Params, operand_overall: &mut [u64]) {
+    static KERNEL: OnceLock<NttKernel> = OnceLock::new();
+    KERNEL.get_or_init(|| ntt_inverse_kernel(Simd::detected()))(params, operand_overall)
+}
+
+/// Forward NTT butterflies for one block of `2 * t` coefficients.
+#[inline(always)]
+fn forward_butterflies(op: &mut [u64], t: usize, w: u64, w_prime: u64, modulus_small: u32) {
+    let two_times_modulus_small: u32 = 2 * modulus_small;
+    for j in 0..t {
+        let x: u32 = op[j] as u32;
+        let y: u32 = op[t + j] as u32;
+
+        let curr_x: u32 = x - (two_times_modulus_small * ((x >= two_times_modulus_small) as u32));
+        let q_tmp: u64 = ((y as u64) * w_prime) >> 32u64;
+        let q_new = w * (y as u64) - q_tmp * (modulus_small as u64);
+
+        op[j] = curr_x as u64 + q_new;
+        op[t + j] = curr_x as u64 + ((two_times_modulus_small as u64) - q_new);
+    }
+}
+
+/// Inverse NTT butterflies for one block of `2 * t` coefficients.
+#[inline(always)]
+fn inverse_butterflies(op: &mut [u64], t: usize, w: u64, w_prime: u64, modulus: u64) {
+    let two_times_modulus: u64 = 2 * modulus;
+    for j in 0..t {
+        let x = op[j];
+        let y = op[t + j];
+
+        let t_tmp = two_times_modulus - y + x;
+        let curr_x = x + y - (two_times_modulus * (((x << 1) >= t_tmp) as u64));
+        let h_tmp = (t_tmp * w_prime) >> 32;
+
+        let res_x = (curr_x + (modulus * (t_tmp & 1))) >> 1;
+        let res_y = w * t_tmp - h_tmp * modulus;
+
+        op[j] = res_x;
+        op[t + j] = res_y;
+    }
+}
+
+/// Reduces every coefficient from `[0, 4 * modulus)` to `[0, modulus)`.
+#[inline(always)]
+fn reduce_coeffs(operand: &mut [u64], modulus: u64) {
+    let two_times_modulus = 2 * modulus;
+    for x in operand {
+        *x -= ((*x >= two_times_modulus) as u64) * two_times_modulus;
+        *x -= ((*x >= modulus) as u64) * modulus;
+    }
+}
+
+pub fn ntt_forward_scalar(params: &Params, operand_overall: &mut [u64]) {
     let log_n = params.poly_len_log2;
     let n = 1 << log_n;
 
@@ -75,7 +165,6 @@ pub fn ntt_forward(params: &Params, operand_overall: &mut [u64]) {
         let forward_table = params.get_ntt_forward_table(coeff_mod);
         let forward_table_prime = params.get_ntt_forward_prime_table(coeff_mod);
         let modulus_small = params.moduli[coeff_mod] as u32;
-        let two_times_modulus_small: u32 = 2 * modulus_small;
 
         for mm in 0..log_n {
             let m = 1 << mm;
@@ -88,79 +177,89 @@ pub fn ntt_forward(params: &Params, operand_overall: &mut [u64]) {
                 let w_prime = forward_table_prime[m + i];
 
                 let op = it.next().unwrap();
-
-                for j in 0..t {
-                    let x: u32 = op[j] as u32;
-                    let y: u32 = op[t + j] as u32;
-
-                    let curr_x: u32 =
-                        x - (two_times_modulus_small * ((x >= two_times_modulus_small) as u32));
-                    let q_tmp: u64 = ((y as u64) * (w_prime as u64)) >> 32u64;
-                    let q_new = w * (y as u64) - q_tmp * (modulus_small as u64);
-
-                    op[j] = curr_x as u64 + q_new;
-                    op[t + j] = curr_x as u64 + ((two_times_modulus_small as u64) - q_new);
-                }
+                forward_butterflies(op, t, w, w_prime, modulus_small);
             }
         }
 
-        for i in 0..n {
-            operand[i] -= ((operand[i] >= two_times_modulus_small as u64) as u64)
-                * two_times_modulus_small as u64;
-            operand[i] -= ((operand[i] >= modulus_small as u64) as u64) * modulus_small as u64;
-        }
+        reduce_coeffs(operand, modulus_small as u64);
     }
 }
 
-#[cfg(target_feature = "avx2")]
-pub fn ntt_forward(params: &Params, operand_overall: &mut [u64]) {
-    let log_n = params.poly_len_log2;
-    let n = 1 << log_n;
-
+pub fn ntt_inverse_scalar(params: &Params, operand_overall: &mut [u64]) {
     for coeff_mod in 0..params.crt_count {
+        let n = params.poly_len;
+
         let operand = &mut operand_overall[coeff_mod * n..coeff_mod * n + n];
 
-        let forward_table = params.get_ntt_forward_table(coeff_mod);
-        let forward_table_prime = params.get_ntt_forward_prime_table(coeff_mod);
-        let modulus_small = params.moduli[coeff_mod] as u32;
-        let two_times_modulus_small: u32 = 2 * modulus_small;
+        let inverse_table = params.get_ntt_inverse_table(coeff_mod);
+        let inverse_table_prime = params.get_ntt_inverse_prime_table(coeff_mod);
+        let modulus = params.moduli[coeff_mod];
 
-        for mm in 0..log_n {
-            let m = 1 << mm;
+        for mm in (0..params.poly_len_log2).rev() {
+            let h = 1 << mm;
             let t = n >> (mm + 1);
 
             let mut it = operand.chunks_exact_mut(2 * t);
 
-            for i in 0..m {
-                let w = forward_table[m + i];
-                let w_prime = forward_table_prime[m + i];
+            for i in 0..h {
+                let w = inverse_table[h + i];
+                let w_prime = inverse_table_prime[h + i];
 
                 let op = it.next().unwrap();
+                inverse_butterflies(op, t, w, w_prime, modulus);
+            }
+        }
 
-                if t < 4 {
-                    for j in 0..t {
-                        let x: u32 = op[j] as u32;
-                        let y: u32 = op[t + j] as u32;
+        reduce_coeffs(operand, modulus);
+    }
+}
 
-                        let curr_x: u32 =
-                            x - (two_times_modulus_small * ((x >= two_times_modulus_small) as u32));
-                        let q_tmp: u64 = ((y as u64) * (w_prime as u64)) >> 32u64;
-                        let q_new = w * (y as u64) - q_tmp * (modulus_small as u64);
+#[cfg(target_arch = "x86_64")]
+mod avx2 {
+    use std::arch::x86_64::*;
 
-                        op[j] = curr_x as u64 + q_new;
-                        op[t + j] = curr_x as u64 + ((two_times_modulus_small as u64) - q_new);
-                    }
-                } else {
-                    unsafe {
+    use super::*;
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn ntt_forward(params: &Params, operand_overall: &mut [u64]) {
+        let log_n = params.poly_len_log2;
+        let n = 1 << log_n;
+
+        for coeff_mod in 0..params.crt_count {
+            let operand = &mut operand_overall[coeff_mod * n..coeff_mod * n + n];
+
+            let forward_table = params.get_ntt_forward_table(coeff_mod);
+            let forward_table_prime = params.get_ntt_forward_prime_table(coeff_mod);
+            let modulus_small = params.moduli[coeff_mod] as u32;
+            let two_times_modulus_small: u32 = 2 * modulus_small;
+
+            for mm in 0..log_n {
+                let m = 1 << mm;
+                let t = n >> (mm + 1);
+
+                let mut it = operand.chunks_exact_mut(2 * t);
+
+                for i in 0..m {
+                    let w = forward_table[m + i];
+                    let w_prime = forward_table_prime[m + i];
+
+                    let op = it.next().unwrap();
+
+                    if t < 4 {
+                        forward_butterflies(op, t, w, w_prime, modulus_small);
+                    } else {
                         for j in (0..t).step_by(4) {
-                            // Use AVX2 here
                             let p_x = &mut op[j] as *mut u64;
                             let p_y = &mut op[j + t] as *mut u64;
                             let x = _mm256_load_si256(p_x as *const __m256i);
                             let y = _mm256_load_si256(p_y as *const __m256i);
 
+                            // x >= 2 * modulus_small
                             let cmp_val = _mm256_set1_epi64x(two_times_modulus_small as i64);
-                            let gt_mask = _mm256_cmpgt_epi64(x, cmp_val);
+                            let gt_mask = _mm256_cmpgt_epi64(
+                                x,
+                                _mm256_sub_epi64(cmp_val, _mm256_set1_epi64x(1)),
+                            );
 
                             let to_subtract = _mm256_and_si256(gt_mask, cmp_val);
                             let curr_x = _mm256_sub_epi64(x, to_subtract);
@@ -187,118 +286,54 @@ pub fn ntt_forward(params: &Params, operand_overall: &mut [u64]) {
                     }
                 }
             }
-        }
 
-        for i in (0..n).step_by(4) {
-            unsafe {
+            for i in (0..n).step_by(4) {
                 let p_x = &mut operand[i] as *mut u64;
 
+                // subtract when x > 2 * modulus_small - 1, and then when x > modulus_small - 1
                 let cmp_val1 = _mm256_set1_epi64x(two_times_modulus_small as i64);
                 let mut x = _mm256_load_si256(p_x as *const __m256i);
-                let mut gt_mask = _mm256_cmpgt_epi64(x, cmp_val1);
+                let mut gt_mask =
+                    _mm256_cmpgt_epi64(x, _mm256_sub_epi64(cmp_val1, _mm256_set1_epi64x(1)));
                 let mut to_subtract = _mm256_and_si256(gt_mask, cmp_val1);
                 x = _mm256_sub_epi64(x, to_subtract);
 
                 let cmp_val2 = _mm256_set1_epi64x(modulus_small as i64);
-                gt_mask = _mm256_cmpgt_epi64(x, cmp_val2);
+                gt_mask = _mm256_cmpgt_epi64(x, _mm256_sub_epi64(cmp_val2, _mm256_set1_epi64x(1)));
                 to_subtract = _mm256_and_si256(gt_mask, cmp_val2);
                 x = _mm256_sub_epi64(x, to_subtract);
                 _mm256_store_si256(p_x as *mut __m256i, x);
             }
         }
     }
-}
-
-#[cfg(not(target_feature = "avx2"))]
-pub fn ntt_inverse(params: &Params, operand_overall: &mut [u64]) {
-    for coeff_mod in 0..params.crt_count {
-        let n = params.poly_len;
-
-        let operand = &mut operand_overall[coeff_mod * n..coeff_mod * n + n];
-
-        let inverse_table = params.get_ntt_inverse_table(coeff_mod);
-        let inverse_table_prime = params.get_ntt_inverse_prime_table(coeff_mod);
-        let modulus = params.moduli[coeff_mod];
-        let two_times_modulus: u64 = 2 * modulus;
-
-        for mm in (0..params.poly_len_log2).rev() {
-            let h = 1 << mm;
-            let t = n >> (mm + 1);
-
-            let mut it = operand.chunks_exact_mut(2 * t);
-
-            for i in 0..h {
-                let w = inverse_table[h + i];
-                let w_prime = inverse_table_prime[h + i];
-
-                let op = it.next().unwrap();
-
-                for j in 0..t {
-                    let x = op[j];
-                    let y = op[t + j];
-
-                    let t_tmp = two_times_modulus - y + x;
-                    let curr_x = x + y - (two_times_modulus * (((x << 1) >= t_tmp) as u64));
-                    let h_tmp = (t_tmp * w_prime) >> 32;
-
-                    let res_x = (curr_x + (modulus * ((t_tmp & 1) as u64))) >> 1;
-                    let res_y = w * t_tmp - h_tmp * modulus;
-
-                    op[j] = res_x;
-                    op[t + j] = res_y;
-                }
-            }
-        }
-
-        for i in 0..n {
-            operand[i] -= ((operand[i] >= two_times_modulus) as u64) * two_times_modulus;
-            operand[i] -= ((operand[i] >= modulus) as u64) * modulus;
-        }
-    }
-}
-
-#[cfg(target_feature = "avx2")]
-pub fn ntt_inverse(params: &Params, operand_overall: &mut [u64]) {
-    for coeff_mod in 0..params.crt_count {
-        let n = params.poly_len;
 
-        let operand = &mut operand_overall[coeff_mod * n..coeff_mod * n + n];
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn ntt_inverse(params: &Params, operand_overall: &mut [u64]) {
+        for coeff_mod in 0..params.crt_count {
+            let n = params.poly_len;
 
-        let inverse_table = params.get_ntt_inverse_table(coeff_mod);
-        let inverse_table_prime = params.get_ntt_inverse_prime_table(coeff_mod);
-        let modulus = params.moduli[coeff_mod];
-        let two_times_modulus: u64 = 2 * modulus;
-        for mm in (0..params.poly_len_log2).rev() {
-            let h = 1 << mm;
-            let t = n >> (mm + 1);
-
-            let mut it = operand.chunks_exact_mut(2 * t);
-
-            for i in 0..h {
-                let w = inverse_table[h + i];
-                let w_prime = inverse_table_prime[h + i];
+            let operand = &mut operand_overall[coeff_mod * n..coeff_mod * n + n];
 
-                let op = it.next().unwrap();
+            let inverse_table = params.get_ntt_inverse_table(coeff_mod);
+            let inverse_table_prime = params.get_ntt_inverse_prime_table(coeff_mod);
+            let modulus = params.moduli[coeff_mod];
+            let two_times_modulus: u64 = 2 * modulus;
+            for mm in (0..params.poly_len_log2).rev() {
+                let h = 1 << mm;
+                let t = n >> (mm + 1);
 
-                if t < 4 {
-                    for j in 0..t {
-                        let x = op[j];
-                        let y = op[t + j];
+                let mut it = operand.chunks_exact_mut(2 * t);
 
-                        let t_tmp = two_times_modulus - y + x;
-                        let curr_x = x + y - (two_times_modulus * (((x << 1) >= t_tmp) as u64));
-                        let h_tmp = (t_tmp * w_prime) >> 32;
+                for i in 0..h {
+                    let w = inverse_table[h + i];
+                    let w_prime = inverse_table_prime[h + i];
 
-                        let res_x = (curr_x + (modulus * ((t_tmp & 1) as u64))) >> 1;
-                        let res_y = w * t_tmp - h_tmp * modulus;
+                    let op = it.next().unwrap();
 
-                        op[j] = res_x;
-                        op[t + j] = res_y;
-                    }
-                } else {
-                    unsafe {
+                    if t < 4 {
+                        inverse_butterflies(op, t, w, w_prime, modulus);
+                    } else {
                         for j in (0..t).step_by(4) {
-                            // Use AVX2 here
                             let p_x = &mut op[j] as *mut u64;
                             let p_y = &mut op[j + t] as *mut u64;
                             let x = _mm256_load_si256(p_x as *const __m256i);
@@ -310,8 +345,9 @@ pub fn ntt_inverse(params: &Params, operand_overall: &mut [u64]) {
                             let mut t_tmp = _mm256_set1_epi64x(two_times_modulus as i64);
                             t_tmp = _mm256_sub_epi64(t_tmp, y);
                             t_tmp = _mm256_add_epi64(t_tmp, x);
-                            let gt_mask = _mm256_cmpgt_epi64(_mm256_slli_epi64(x, 1), t_tmp);
-                            let to_subtract = _mm256_and_si256(gt_mask, two_times_modulus_vec);
+                            // subtract when (x << 1) >= t_tmp, that is, unless t_tmp > (x << 1)
+                            let lt_mask = _mm256_cmpgt_epi64(t_tmp, _mm256_slli_epi64(x, 1));
+                            let to_subtract = _mm256_andnot_si256(lt_mask, two_times_modulus_vec);
                             let mut curr_x = _mm256_add_epi64(x, y);
                             curr_x = _mm256_sub_epi64(curr_x, to_subtract);
 
@@ -337,30 +373,296 @@ pub fn ntt_inverse(params: &Params, operand_overall: &mut [u64]) {
                     }
                 }
             }
+
+            reduce_coeffs(operand, modulus);
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx512 {
+    use std::arch::x86_64::*;
+
+    use super::*;
+
+    #[target_feature(enable = "avx512f")]
+    pub unsafe fn ntt_forward(params: &Params, operand_overall: &mut [u64]) {
+        let log_n = params.poly_len_log2;
+        let n = 1 << log_n;
+
+        for coeff_mod in 0..params.crt_count {
+            let operand = &mut operand_overall[coeff_mod * n..coeff_mod * n + n];
+
+            let forward_table = params.get_ntt_forward_table(coeff_mod);
+            let forward_table_prime = params.get_ntt_forward_prime_table(coeff_mod);
+            let modulus_small = params.moduli[coeff_mod] as u32;
+            let modulus_small_vec = _mm512_set1_epi64(modulus_small as i64);
+            let two_times_modulus_small_vec = _mm512_set1_epi64(2 * modulus_small as i64);
+
+            for mm in 0..log_n {
+                let m = 1 << mm;
+                let t = n >> (mm + 1);
+
+                let mut it = operand.chunks_exact_mut(2 * t);
+
+                for i in 0..m {
+                    let w = forward_table[m + i];
+                    let w_prime = forward_table_prime[m + i];
+
+                    let op = it.next().unwrap();
+
+                    if t < 8 {
+                        forward_butterflies(op, t, w, w_prime, modulus_small);
+                        continue;
+                    }
+                    let w_vec = _mm512_set1_epi64(w as i64);
+                    let w_prime_vec = _mm512_set1_epi64(w_prime as i64);
+                    for j in (0..t).step_by(8) {
+                        let p_x = op.as_mut_ptr().add(j) as *mut __m512i;
+                        let p_y = op.as_mut_ptr().add(j + t) as *mut __m512i;
+                        let x = _mm512_loadu_si512(p_x as *const _);
+                        let y = _mm512_loadu_si512(p_y as *const _);
+
+                        let ge_mask = _mm512_cmpge_epu64_mask(x, two_times_modulus_small_vec);
+                        let curr_x =
+                            _mm512_mask_sub_epi64(x, ge_mask, x, two_times_modulus_small_vec);
+
+                        let q_val = _mm512_srli_epi64(_mm512_mul_epu32(y, w_prime_vec), 32);
+                        let q_final = _mm512_sub_epi64(
+                            _mm512_mul_epu32(y, w_vec),
+                            _mm512_mul_epu32(q_val, modulus_small_vec),
+                        );
+
+                        let new_x = _mm512_add_epi64(curr_x, q_final);
+                        let new_y = _mm512_add_epi64(
+                            curr_x,
+                            _mm512_sub_epi64(two_times_modulus_small_vec, q_final),
+                        );
+
+                        _mm512_storeu_si512(p_x as *mut _, new_x);
+                        _mm512_storeu_si512(p_y as *mut _, new_y);
+                    }
+                }
+            }
+
+            reduce_coeffs_avx512(operand, modulus_small as u64);
         }
+    }
+
+    #[target_feature(enable = "avx512f")]
+    pub unsafe fn ntt_inverse(params: &Params, operand_overall: &mut [u64]) {
+        for coeff_mod in 0..params.crt_count {
+            let n = params.poly_len;
 
-        for i in 0..n {
-            operand[i] -= ((operand[i] >= two_times_modulus) as u64) * two_times_modulus;
-            operand[i] -= ((operand[i] >= modulus) as u64) * modulus;
+            let operand = &mut operand_overall[coeff_mod * n..coeff_mod * n + n];
+
+            let inverse_table = params.get_ntt_inverse_table(coeff_mod);
+            let inverse_table_prime = params.get_ntt_inverse_prime_table(coeff_mod);
+            let modulus = params.moduli[coeff_mod];
+            let modulus_vec = _mm512_set1_epi64(modulus as i64);
+            let two_times_modulus_vec = _mm512_set1_epi64(2 * modulus as i64);
+            let one = _mm512_set1_epi64(1);
+
+            for mm in (0..params.poly_len_log2).rev() {
+                let h = 1 << mm;
+                let t = n >> (mm + 1);
+
+                let mut it = operand.chunks_exact_mut(2 * t);
+
+                for i in 0..h {
+                    let w = inverse_table[h + i];
+                    let w_prime = inverse_table_prime[h + i];
+
+                    let op = it.next().unwrap();
+
+                    if t < 8 {
+                        inverse_butterflies(op, t, w, w_prime, modulus);
+                        continue;
+                    }
+                    let w_vec = _mm512_set1_epi64(w as i64);
+                    let w_prime_vec = _mm512_set1_epi64(w_prime as i64);
+                    for j in (0..t).step_by(8) {
+                        let p_x = op.as_mut_ptr().add(j) as *mut __m512i;
+                        let p_y = op.as_mut_ptr().add(j + t) as *mut __m512i;
+                        let x = _mm512_loadu_si512(p_x as *const _);
+                        let y = _mm512_loadu_si512(p_y as *const _);
+
+                        let t_tmp = _mm512_add_epi64(_mm512_sub_epi64(two_times_modulus_vec, y), x);
+                        let sum = _mm512_add_epi64(x, y);
+                        let ge_mask = _mm512_cmpge_epu64_mask(_mm512_slli_epi64(x, 1), t_tmp);
+                        let curr_x =
+                            _mm512_mask_sub_epi64(sum, ge_mask, sum, two_times_modulus_vec);
+
+                        let h_tmp = _mm512_srli_epi64(_mm512_mul_epu32(t_tmp, w_prime_vec), 32);
+
+                        let odd_mask = _mm512_test_epi64_mask(t_tmp, one);
+                        let to_halve = _mm512_mask_add_epi64(curr_x, odd_mask, curr_x, modulus_vec);
+                        let new_x = _mm512_srli_epi64(to_halve, 1);
+
+                        let new_y = _mm512_sub_epi64(
+                            _mm512_mul_epu32(t_tmp, w_vec),
+                            _mm512_mul_epu32(h_tmp, modulus_vec),
+                        );
+
+                        _mm512_storeu_si512(p_x as *mut _, new_x);
+                        _mm512_storeu_si512(p_y as *mut _, new_y);
+                    }
+                }
+            }
+
+            reduce_coeffs_avx512(operand, modulus);
         }
+    }
 
-        // for i in (0..n).step_by(4) {
-        //     unsafe {
-        //         let p_x = &mut operand[i] as *mut u64;
-
-        //         let cmp_val1 = _mm256_set1_epi64x(two_times_modulus as i64);
-        //         let mut x = _mm256_load_si256(p_x as *const __m256i);
-        //         let mut gt_mask = _mm256_cmpgt_epi64(x, cmp_val1);
-        //         let mut to_subtract = _mm256_and_si256(gt_mask, cmp_val1);
-        //         x = _mm256_sub_epi64(x, to_subtract);
-
-        //         let cmp_val2 = _mm256_set1_epi64x(modulus as i64);
-        //         gt_mask = _mm256_cmpgt_epi64(x, cmp_val2);
-        //         to_subtract = _mm256_and_si256(gt_mask, cmp_val2);
-        //         x = _mm256_sub_epi64(x, to_subtract);
-        //         _mm256_store_si256(p_x as *mut __m256i, x);
-        //     }
-        // }
+    #[target_feature(enable = "avx512f")]
+    unsafe fn reduce_coeffs_avx512(operand: &mut [u64], modulus: u64) {
+        let modulus_vec = _mm512_set1_epi64(modulus as i64);
+        let two_times_modulus_vec = _mm512_set1_epi64(2 * modulus as i64);
+        let mut chunks = operand.chunks_exact_mut(8);
+        for chunk in &mut chunks {
+            let p_x = chunk.as_mut_ptr() as *mut __m512i;
+            let mut x = _mm512_loadu_si512(p_x as *const _);
+            let ge_mask = _mm512_cmpge_epu64_mask(x, two_times_modulus_vec);
+            x = _mm512_mask_sub_epi64(x, ge_mask, x, two_times_modulus_vec);
+            let ge_mask = _mm512_cmpge_epu64_mask(x, modulus_vec);
+            x = _mm512_mask_sub_epi64(x, ge_mask, x, modulus_vec);
+            _mm512_storeu_si512(p_x as *mut _, x);
+        }
+        reduce_coeffs(chunks.into_remainder(), modulus);
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use std::arch::aarch64::*;
+
+    use super::*;
+
+    #[target_feature(enable = "neon")]
+    pub unsafe fn ntt_forward(params: &Params, operand_overall: &mut [u64]) {
+        let log_n = params.poly_len_log2;
+        let n = 1 << log_n;
+
+        for coeff_mod in 0..params.crt_count {
+            let operand = &mut operand_overall[coeff_mod * n..coeff_mod * n + n];
+
+            let forward_table = params.get_ntt_forward_table(coeff_mod);
+            let forward_table_prime = params.get_ntt_forward_prime_table(coeff_mod);
+            let modulus_small = params.moduli[coeff_mod] as u32;
+            let modulus_small_vec = vdup_n_u32(modulus_small);
+            let two_times_modulus_small_vec = vdupq_n_u64(2 * modulus_small as u64);
+
+            for mm in 0..log_n {
+                let m = 1 << mm;
+                let t = n >> (mm + 1);
+
+                let mut it = operand.chunks_exact_mut(2 * t);
+
+                for i in 0..m {
+                    let w = forward_table[m + i];
+                    let w_prime = forward_table_prime[m + i];
+
+                    let op = it.next().unwrap();
+
+                    if t < 2 {
+                        forward_butterflies(op, t, w, w_prime, modulus_small);
+                        continue;
+                    }
+                    let w_vec = vdup_n_u32(w as u32);
+                    let w_prime_vec = vdup_n_u32(w_prime as u32);
+                    for j in (0..t).step_by(2) {
+                        let p_x = op.as_mut_ptr().add(j);
+                        let p_y = op.as_mut_ptr().add(j + t);
+                        let x = vld1q_u64(p_x);
+                        let y = vmovn_u64(vld1q_u64(p_y));
+
+                        let ge_mask = vcgeq_u64(x, two_times_modulus_small_vec);
+                        let curr_x = vsubq_u64(x, vandq_u64(ge_mask, two_times_modulus_small_vec));
+
+                        let q_val = vshrq_n_u64::<32>(vmull_u32(y, w_prime_vec));
+                        let q_final = vsubq_u64(
+                            vmull_u32(y, w_vec),
+                            vmull_u32(vmovn_u64(q_val), modulus_small_vec),
+                        );
+
+                        let new_x = vaddq_u64(curr_x, q_final);
+                        let new_y =
+                            vaddq_u64(curr_x, vsubq_u64(two_times_modulus_small_vec, q_final));
+
+                        vst1q_u64(p_x, new_x);
+                        vst1q_u64(p_y, new_y);
+                    }
+                }
+            }
+
+            reduce_coeffs(operand, modulus_small as u64);
+        }
+    }
+
+    #[target_feature(enable = "neon")]
+    pub unsafe fn ntt_inverse(params: &Params, operand_overall: &mut [u64]) {
+        for coeff_mod in 0..params.crt_count {
+            let n = params.poly_len;
+
+            let operand = &mut operand_overall[coeff_mod * n..coeff_mod * n + n];
+
+            let inverse_table = params.get_ntt_inverse_table(coeff_mod);
+            let inverse_table_prime = params.get_ntt_inverse_prime_table(coeff_mod);
+            let modulus = params.moduli[coeff_mod];
+            let modulus_vec = vdupq_n_u64(modulus);
+            let modulus_small_vec = vdup_n_u32(modulus as u32);
+            let two_times_modulus_vec = vdupq_n_u64(2 * modulus);
+            let one = vdupq_n_u64(1);
+
+            for mm in (0..params.poly_len_log2).rev() {
+                let h = 1 << mm;
+                let t = n >> (mm + 1);
+
+                let mut it = operand.chunks_exact_mut(2 * t);
+
+                for i in 0..h {
+                    let w = inverse_table[h + i];
+                    let w_prime = inverse_table_prime[h + i];
+
+                    let op = it.next().unwrap();
+
+                    if t < 2 {
+                        inverse_butterflies(op, t, w, w_prime, modulus);
+                        continue;
+                    }
+                    let w_vec = vdup_n_u32(w as u32);
+                    let w_prime_vec = vdup_n_u32(w_prime as u32);
+                    for j in (0..t).step_by(2) {
+                        let p_x = op.as_mut_ptr().add(j);
+                        let p_y = op.as_mut_ptr().add(j + t);
+                        let x = vld1q_u64(p_x);
+                        let y = vld1q_u64(p_y);
+
+                        let t_tmp = vaddq_u64(vsubq_u64(two_times_modulus_vec, y), x);
+                        let ge_mask = vcgeq_u64(vshlq_n_u64::<1>(x), t_tmp);
+                        let curr_x =
+                            vsubq_u64(vaddq_u64(x, y), vandq_u64(ge_mask, two_times_modulus_vec));
+
+                        let t_tmp_small = vmovn_u64(t_tmp);
+                        let h_tmp = vshrq_n_u64::<32>(vmull_u32(t_tmp_small, w_prime_vec));
+
+                        let odd_mask = vceqq_u64(vandq_u64(t_tmp, one), one);
+                        let to_add = vandq_u64(odd_mask, modulus_vec);
+                        let new_x = vshrq_n_u64::<1>(vaddq_u64(curr_x, to_add));
+
+                        let new_y = vsubq_u64(
+                            vmull_u32(t_tmp_small, w_vec),
+                            vmull_u32(vmovn_u64(h_tmp), modulus_small_vec),
+                        );
+
+                        vst1q_u64(p_x, new_x);
+                        vst1q_u64(p_y, new_y);
+                    }
+                }
+            }
+
+            reduce_coeffs(operand, modulus);
+        }
     }
 }
 
@@ -442,6 +744,35 @@ mod test {
         }
     }
 
+    #[test]
+    fn ntt_kernels_agree() {
+        let params = get_params();
+        let mut v1 = AlignedMemory64::new(params.crt_count * params.poly_len);
+        let mut rng = rand::thread_rng();
+        for i in 0..params.crt_count {
+            for j in 0..params.poly_len {
+                let idx = calc_index(&[i, j], &[params.crt_count, params.poly_len]);
+                let val: u64 = rng.gen();
+                v1[idx] = val % params.moduli[i];
+            }
+        }
+
+        let mut forward_ref = v1.clone();
+        ntt_forward_scalar(&params, forward_ref.as_mut_slice());
+        let mut inverse_ref = v1.clone();
+        ntt_inverse_scalar(&params, inverse_ref.as_mut_slice());
+
+        for simd in Simd::available() {
+            let mut forward = v1.clone();
+            ntt_forward_kernel(simd)(&params, forward.as_mut_slice());
+            assert_eq!(forward.as_slice(), forward_ref.as_slice(), "{:?}", simd);
+
+            let mut inverse = v1.clone();
+            ntt_inverse_kernel(simd)(&params, inverse.as_mut_slice());
+            assert_eq!(inverse.as_slice(), inverse_ref.as_slice(), "{:?}", simd);
+        }
+    }
+
     #[test]
     fn calc_index_correct() {
         assert_eq!(calc_index(&[2, 3, 4], &[10, 10, 100]), 2304);
@@ -0,0 +1,330 @@
+//! Automatic selection of Spiral parameters.
+//!
+//! Given the shape of a database (number of items and item size) and a target
+//! `estimate_log2_err_prob`, `search_params` enumerates candidate parameter sets,
+//! keeps those that meet the target, and ranks them by a `CostModel` of
+//! query bytes, response bytes, setup bytes and estimated server work.
+
+use serde_json::Value;
+
+use crate::{
+    arith::*,
+    noise_estimate::{extract_paramset, get_p_err, NoiseEstimator},
+    params::*,
+    util::{get_empty_params, params_from_json_obj},
+};
+
+/// The parameter version produced by the search.
+const SEARCH_VERSION: usize = 1;
+
+const N_RANGE: (usize, usize) = (2, 4);
+const PT_MODULUS_BITS_RANGE: (u64, u64) = (4, 16);
+const MAX_DB_DIM_1: usize = 12;
+const T_GSW_RANGE: (usize, usize) = (2, 12);
+const T_CONV_RANGE: (usize, usize) = (2, 8);
+const T_EXP_RANGE: (usize, usize) = (2, 16);
+const MAX_Q2_BITS: u64 = (Q2_VALUES.len() - 1) as u64;
+
+/// Weights applied to the metrics of a candidate; the cost of a candidate is
+/// the weighted sum of its metrics. Bytes and server operations are weighted
+/// separately, so that the same search can favor bandwidth or compute.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CostModel {
+    pub query_byte: f64,
+    pub response_byte: f64,
+    /// Setup happens once per client, so it is usually weighted much lower than a query.
+    pub setup_byte: f64,
+    pub server_op: f64,
+}
+
+impl Default for CostModel {
+    fn default() -> Self {
+        Self {
+            query_byte: 1.0,
+            response_byte: 1.0,
+            setup_byte: 0.01,
+            server_op: 0.005,
+        }
+    }
+}
+
+/// A parameter set found by `search_params`, along with the metrics it was ranked by.
+#[derive(Debug, Clone)]
+pub struct ParamCandidate {
+    /// The parameters, without NTT tables; use `to_params` to get usable `Params`.
+    shape: Params,
+    pub log2_err_prob: f64,
+    pub query_bytes: usize,
+    pub response_bytes: usize,
+    pub setup_bytes: usize,
+    /// Estimated number of word multiply-adds the server performs to answer one query.
+    pub server_ops: f64,
+    pub cost: f64,
+}
+
+impl ParamCandidate {
+    /// Evaluates an existing set of parameters under the given cost model.
+    pub fn from_params(params: &Params, cost_model: &CostModel) -> Self {
+        let shape = Params {
+            ntt_tables: Vec::new(),
+            scratch: Vec::new(),
+            ..params.clone()
+        };
+        let log2_err_prob = shape.estimate_log2_err_prob();
+        Self::with_err_prob(shape, log2_err_prob, cost_model)
+    }
+
+    fn with_err_prob(shape: Params, log2_err_prob: f64, cost_model: &CostModel) -> Self {
+        let query_bytes = shape.query_bytes();
+        let response_bytes = shape.response_bytes();
+        let setup_bytes = shape.setup_bytes();
+        let server_ops = estimate_server_ops(&shape);
+        let cost = cost_model.query_byte * query_bytes as f64
+            + cost_model.response_byte * response_bytes as f64
+            + cost_model.setup_byte * setup_bytes as f64
+            + cost_model.server_op * server_ops;
+        Self {
+            shape,
+            log2_err_prob,
+            query_bytes,
+            response_bytes,
+            setup_bytes,
+            server_ops,
+            cost,
+        }
+    }
+
+    /// The parameters in the JSON form read by `params_from_json_obj`.
+    pub fn to_json_obj(&self) -> Value {
+        self.shape.to_json_obj()
+    }
+
+    pub fn to_params(&self) -> Params {
+        params_from_json_obj(&self.to_json_obj())
+    }
+}
+
+/// Estimates the number of word multiply-adds needed to answer a single query,
+/// counting an NTT as `poly_len * log2(poly_len)` operations.
+fn estimate_server_ops(p: &Params) -> f64 {
+    estimate_batch_server_ops(p, 1)
+}
+
+/// Estimates the number of word multiply-adds needed to answer a batch query of
+/// `num_partitions` partitions (see `client::generate_batch_query`).
+///
+/// The batch is expanded and multiplied by the database once, but every partition is folded
+/// and packed on its own.
+pub fn estimate_batch_server_ops(p: &Params, num_partitions: usize) -> f64 {
+    let poly_ops = (p.poly_len * p.crt_count) as f64;
+    let ntt_ops = poly_ops * p.poly_len_log2 as f64;
+    let inst_trials = (p.instances * p.n * p.n) as f64;
+    let dim0 = (1usize << p.db_dim_1) as f64;
+    let num_per = (1usize << p.db_dim_2) as f64;
+
+    // first dimension: each plaintext coefficient is multiplied into both ciphertext rows
+    let first_dim = inst_trials * dim0 * num_per * poly_ops * 2.;
+
+    // folding: two external products per pair of ciphertexts
+    let t_gsw = p.t_gsw as f64;
+    let partitions = num_partitions as f64;
+    let fold = partitions
+        * inst_trials
+        * (num_per - 1.)
+        * 2.
+        * (2. * t_gsw * ntt_ops + 4. * t_gsw * poly_ops);
+
+    let mut expansion = 0.;
+    if p.expand_queries {
+        let t_exp = usize::max(p.t_exp_left, p.t_exp_right) as f64;
+        let expanded = (1usize << p.g()) as f64;
+        let t_conv = p.t_conv as f64;
+        let conversions = (num_partitions * p.t_gsw * p.db_dim_2) as f64;
+        expansion = expanded * t_exp * (ntt_ops + 2. * poly_ops)
+            + conversions * 2. * t_conv * (ntt_ops + 2. * poly_ops);
+    }
+
+    let t_conv = p.t_conv as f64;
+    let n = p.n as f64;
+    let pack = partitions * p.instances as f64 * n * n * t_conv * (ntt_ops + (n + 1.) * poly_ops);
+
+    first_dim + fold + expansion + pack
+}
+
+/// Parameters with the given dimensions, but no NTT tables, for cheap metric and noise estimates.
+fn shape(
+    n: usize,
+    pt_modulus: u64,
+    db_dim_1: usize,
+    db_dim_2: usize,
+    instances: usize,
+    db_item_size: usize,
+) -> Params {
+    let modulus = DEFAULT_MODULI.iter().product::<u64>();
+    let mut moduli = [0u64; MAX_MODULI];
+    moduli[..DEFAULT_MODULI.len()].copy_from_slice(&DEFAULT_MODULI);
+    Params {
+        poly_len: DEFAULT_POLY_LEN,
+        poly_len_log2: log2(DEFAULT_POLY_LEN as u64) as usize,
+        crt_count: DEFAULT_MODULI.len(),
+        moduli,
+        modulus,
+        modulus_log2: log2_ceil(modulus),
+        noise_width: DEFAULT_NOISE_WIDTH,
+        n,
+        pt_modulus,
+        q2_bits: MIN_Q2_BITS,
+        expand_queries: true,
+        db_dim_1,
+        db_dim_2,
+        instances,
+        db_item_size,
+        version: SEARCH_VERSION,
+        ..get_empty_params()
+    }
+}
+
+/// Finds the cheapest gadget parameters and `q2_bits` for the given dimensions that
+/// meet the target error probability, if any.
+fn best_for_shape(
+    base: Params,
+    target_log2_err_prob: f64,
+    cost_model: &CostModel,
+) -> Option<ParamCandidate> {
+    let mut best: Option<ParamCandidate> = None;
+    for t_gsw in T_GSW_RANGE.0..=T_GSW_RANGE.1 {
+        // coefficient expansion can generate at most poly_len ciphertexts
+        if t_gsw * base.db_dim_2 + base.num_expanded() > base.poly_len {
+            break;
+        }
+        for t_conv in T_CONV_RANGE.0..=T_CONV_RANGE.1 {
+            for t_exp in T_EXP_RANGE.0..=T_EXP_RANGE.1 {
+                let mut s = Params {
+                    t_gsw,
+                    t_conv,
+                    t_exp_left: t_exp,
+                    t_exp_right: t_exp,
+                    ..base.clone()
+                };
+                let paramset = extract_paramset(&s);
+                let s_e = s.estimate_noise();
+                let q2_bits = (MIN_Q2_BITS..=MAX_Q2_BITS).find(|&q2_bits| {
+                    get_p_err(&paramset, s_e, Q2_VALUES[q2_bits as usize]) <= target_log2_err_prob
+                });
+                let Some(q2_bits) = q2_bits else {
+                    continue;
+                };
+                s.q2_bits = q2_bits;
+                let log2_err_prob = get_p_err(&paramset, s_e, Q2_VALUES[q2_bits as usize]);
+                let candidate = ParamCandidate::with_err_prob(s, log2_err_prob, cost_model);
+                if best.as_ref().map_or(true, |b| candidate.cost < b.cost) {
+                    best = Some(candidate);
+                }
+            }
+        }
+    }
+    best
+}
+
+/// Searches for parameters that hold at least `num_items` items of `item_size` bytes
+/// each, with an estimated log2 error probability of at most `target_log2_err_prob`
+/// (e.g. `-40.0`).
+///
+/// Returns one candidate per choice of `n`, `p` and database dimensions, ranked
+/// from cheapest to most expensive under the cost model. The list is empty if no
+/// candidate meets the target.
+pub fn search_params(
+    num_items: usize,
+    item_size: usize,
+    target_log2_err_prob: f64,
+    cost_model: &CostModel,
+) -> Vec<ParamCandidate> {
+    let total_dims = usize::max(log2_ceil_usize(num_items), 2);
+    let mut candidates = Vec::new();
+    for n in N_RANGE.0..=N_RANGE.1 {
+        for pt_bits in PT_MODULUS_BITS_RANGE.0..=PT_MODULUS_BITS_RANGE.1 {
+            let bytes_per_instance = n * n * DEFAULT_POLY_LEN * pt_bits as usize / 8;
+            let instances =
+                usize::max((item_size + bytes_per_instance - 1) / bytes_per_instance, 1);
+            for db_dim_1 in 1..usize::min(total_dims, MAX_DB_DIM_1 + 1) {
+                let db_dim_2 = total_dims - db_dim_1;
+                let base = shape(n, 1 << pt_bits, db_dim_1, db_dim_2, instances, item_size);
+                if let Some(c) = best_for_shape(base, target_log2_err_prob, cost_model) {
+                    candidates.push(c);
+                }
+            }
+        }
+    }
+    candidates.sort_by(|a, b| a.cost.total_cmp(&b.cost));
+    candidates
+}
+
+/// The cheapest parameters under the default cost model, if any meet the target.
+pub fn best_params(
+    num_items: usize,
+    item_size: usize,
+    target_log2_err_prob: f64,
+) -> Option<Params> {
+    search_params(
+        num_items,
+        item_size,
+        target_log2_err_prob,
+        &CostModel::default(),
+    )
+    .first()
+    .map(|c| c.to_params())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::util::params_from_json;
+
+    use super::*;
+
+    #[test]
+    fn search_params_meets_target_and_is_ranked() {
+        let (num_items, item_size) = (1 << 14, 32768);
+        let candidates = search_params(num_items, item_size, -40.0, &CostModel::default());
+        assert!(!candidates.is_empty());
+        for c in &candidates {
+            assert!(c.log2_err_prob <= -40.0);
+            assert!(c.shape.num_items() >= num_items);
+            assert!(c.shape.item_size() >= item_size);
+        }
+        assert!(candidates.windows(2).all(|w| w[0].cost <= w[1].cost));
+
+        // the full parameters agree with the estimate made without NTT tables
+        let best = &candidates[0];
+        let params = best.to_params();
+        assert_eq!(params.estimate_log2_err_prob(), best.log2_err_prob);
+        assert_eq!(params.response_bytes(), best.response_bytes);
+
+        // hand-picked parameters for the same shape are no cheaper
+        let hand_picked = params_from_json(
+            r#"{
+            "n": 2,
+            "nu_1": 9,
+            "nu_2": 5,
+            "p": 256,
+            "q2_bits": 22,
+            "t_gsw": 7,
+            "t_conv": 3,
+            "t_exp_left": 5,
+            "t_exp_right": 5,
+            "instances": 4,
+            "db_item_size": 32768,
+            "version": 1
+        }"#,
+        );
+        let hand_picked = ParamCandidate::from_params(&hand_picked, &CostModel::default());
+        assert!(best.cost <= hand_picked.cost);
+    }
+
+    #[test]
+    fn stricter_target_is_no_cheaper() {
+        let loose = search_params(1 << 10, 1024, -20.0, &CostModel::default());
+        let strict = search_params(1 << 10, 1024, -80.0, &CostModel::default());
+        assert!(strict[0].log2_err_prob <= -80.0);
+        assert!(loose[0].cost <= strict[0].cost);
+    }
+}
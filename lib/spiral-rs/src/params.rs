@@ -1,11 +1,20 @@
+use std::collections::HashMap;
 use std::mem::size_of;
+use std::sync::{Arc, LazyLock, Mutex, PoisonError, Weak};
 
 use crate::{arith::*, client::SEED_LENGTH, ntt::*, number_theory::*, poly::*};
 
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
 
 pub const MAX_MODULI: usize = 4;
 
+/// The ring dimension, moduli and noise width used by every parameter set in this crate.
+pub const DEFAULT_POLY_LEN: usize = 2048;
+pub const DEFAULT_MODULI: [u64; 2] = [268369921u64, 249561089u64];
+pub const DEFAULT_NOISE_WIDTH: f64 = 6.4;
+
 pub static MIN_Q2_BITS: u64 = 14;
 pub static Q2_VALUES: [u64; 37] = [
     0,
@@ -47,6 +56,88 @@ pub static Q2_VALUES: [u64; 37] = [
     68718428161,
 ];
 
+/// Why a set of scheme parameters was rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParamsError {
+    /// The parameters are not valid JSON.
+    Json(String),
+    /// A required field is missing, or is not an unsigned integer.
+    InvalidField(&'static str),
+    /// The plaintext modulus `p` is not a power of two.
+    PtModulusNotPowerOfTwo(u64),
+    /// There is no modulus in `Q2_VALUES` with this many bits.
+    UnsupportedQ2Bits(u64),
+    /// A field is outside of its supported range.
+    OutOfRange {
+        field: &'static str,
+        value: usize,
+        min: usize,
+        max: usize,
+    },
+    /// Query expansion would need more ciphertexts than there are coefficients.
+    TooManyExpanded { needed: usize, poly_len: usize },
+    /// No known parameters hold a database of this shape.
+    NoParams {
+        num_items_log2: usize,
+        item_size: usize,
+    },
+}
+
+impl std::fmt::Display for ParamsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParamsError::Json(reason) => write!(f, "bad params JSON: {}", reason),
+            ParamsError::InvalidField(field) => {
+                write!(f, "missing or non-integer field '{}'", field)
+            }
+            ParamsError::PtModulusNotPowerOfTwo(p) => {
+                write!(f, "p must be a power of two, got {}", p)
+            }
+            ParamsError::UnsupportedQ2Bits(q2_bits) => {
+                write!(f, "unsupported q2_bits {}", q2_bits)
+            }
+            ParamsError::OutOfRange {
+                field,
+                value,
+                min,
+                max,
+            } => write!(f, "{} must be in {}..={}, got {}", field, min, max, value),
+            ParamsError::TooManyExpanded { needed, poly_len } => write!(
+                f,
+                "query expansion needs {} ciphertexts, but poly_len is {}",
+                needed, poly_len
+            ),
+            ParamsError::NoParams {
+                num_items_log2,
+                item_size,
+            } => write!(
+                f,
+                "no parameters for 2^{} items of {} bytes",
+                num_items_log2, item_size
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ParamsError {}
+
+fn check_range(
+    field: &'static str,
+    value: usize,
+    min: usize,
+    max: usize,
+) -> Result<(), ParamsError> {
+    if value < min || value > max {
+        return Err(ParamsError::OutOfRange {
+            field,
+            value,
+            min,
+            max,
+        });
+    }
+    Ok(())
+}
+
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct Params {
     pub poly_len: usize,
@@ -125,7 +216,10 @@ impl Params {
 
     pub fn item_size(&self) -> usize {
         let logp = log2(self.pt_modulus) as usize;
-        self.instances * self.n * self.n * self.poly_len * logp / 8
+        [self.instances, self.n, self.n, self.poly_len, logp]
+            .into_iter()
+            .fold(1usize, usize::saturating_mul)
+            / 8
     }
 
     pub fn g(&self) -> usize {
@@ -183,6 +277,15 @@ impl Params {
         SEED_LENGTH + sz_bytes
     }
 
+    pub fn response_bytes(&self) -> usize {
+        let q1_bits = log2_ceil(4 * self.pt_modulus) as usize;
+        let q2_bits = self.q2_bits as usize;
+        let num_bits = self.instances
+            * ((q2_bits * self.n * self.poly_len) + (q1_bits * self.n * self.n * self.poly_len));
+        let round_to = 64;
+        ((num_bits + round_to - 1) / round_to) * round_to / 8
+    }
+
     pub fn query_v_buf_bytes(&self) -> usize {
         self.num_expanded() * self.poly_len * size_of::<u64>()
     }
@@ -223,6 +326,85 @@ impl Params {
         }
     }
 
+    /// Checks that the scheme parameters are consistent with each other and with the ring.
+    pub fn validate(&self) -> Result<(), ParamsError> {
+        if self.q2_bits < MIN_Q2_BITS
+            || self.q2_bits as usize >= Q2_VALUES.len()
+            || self.q2_bits > self.modulus_log2
+        {
+            return Err(ParamsError::UnsupportedQ2Bits(self.q2_bits));
+        }
+        if self.pt_modulus < 2 || !self.pt_modulus.is_power_of_two() {
+            return Err(ParamsError::PtModulusNotPowerOfTwo(self.pt_modulus));
+        }
+
+        let max_t = self.modulus_log2 as usize;
+        check_range("t_gsw", self.t_gsw, 1, max_t)?;
+        check_range("t_conv", self.t_conv, 1, max_t)?;
+        check_range("t_exp_left", self.t_exp_left, 1, max_t)?;
+        check_range("t_exp_right", self.t_exp_right, 1, max_t)?;
+
+        check_range("n", self.n, 1, self.poly_len)?;
+        check_range("instances", self.instances, 1, usize::MAX)?;
+        check_range("version", self.version, 0, 1)?;
+        check_range("nu_1", self.db_dim_1, 0, self.poly_len_log2)?;
+        // the number of items must fit in a usize; expansion bounds nu_2 further below
+        let max_db_dim_2 = usize::BITS as usize - 1 - self.db_dim_1;
+        check_range("nu_2", self.db_dim_2, 0, max_db_dim_2)?;
+
+        if self.expand_queries {
+            // coefficient expansion can generate at most poly_len ciphertexts
+            let needed = self.t_gsw * self.db_dim_2 + self.num_expanded();
+            if needed > self.poly_len {
+                return Err(ParamsError::TooManyExpanded {
+                    needed,
+                    poly_len: self.poly_len,
+                });
+            }
+        }
+
+        check_range("db_item_size", self.db_item_size, 1, self.item_size())
+    }
+
+    /// The scheme parameters in the JSON form read by `params_from_json_obj`.
+    ///
+    /// The ring (`poly_len`, `moduli` and `noise_width`) is not included; parameters
+    /// read from JSON always use the default ring.
+    pub fn to_json_obj(&self) -> Value {
+        let mut v = json!({
+            "n": self.n,
+            "nu_1": self.db_dim_1,
+            "nu_2": self.db_dim_2,
+            "p": self.pt_modulus,
+            "q2_bits": self.q2_bits,
+            "t_gsw": self.t_gsw,
+            "t_conv": self.t_conv,
+            "t_exp_left": self.t_exp_left,
+            "t_exp_right": self.t_exp_right,
+            "instances": self.instances,
+            "db_item_size": self.db_item_size,
+            "version": self.version,
+        });
+        if !self.expand_queries {
+            v["direct_upload"] = true.into();
+        }
+        v
+    }
+
+    /// The scheme parameters as a JSON string, with keys in a fixed order.
+    pub fn to_json(&self) -> String {
+        self.to_json_obj().to_string()
+    }
+
+    /// A hex SHA-256 hash of `to_json`, so that clients and servers can cheaply check
+    /// that they agree on the parameters.
+    pub fn hash(&self) -> String {
+        Sha256::digest(self.to_json().as_bytes())
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
     pub fn init(
         poly_len: usize,
         moduli: &[u64],
@@ -297,3 +479,63 @@ impl Params {
         }
     }
 }
+
+/// Returns a shared copy of `params`.
+///
+/// Each distinct parameter set is kept once while any client or bucket holds it, so
+/// creating clients for the same parameters again does not make another copy. It is
+/// freed when the last holder is dropped.
+///
+/// Parameter sets are looked up by `hash`, which does not cover the ring dimension,
+/// moduli or noise width; a set that differs from the interned one with the same hash
+/// only in those is returned unshared.
+pub fn intern_params(params: Params) -> Arc<Params> {
+    static INTERNED: LazyLock<Mutex<HashMap<String, Weak<Params>>>> =
+        LazyLock::new(Default::default);
+
+    let mut interned = INTERNED.lock().unwrap_or_else(PoisonError::into_inner);
+    let key = params.hash();
+    if let Some(shared) = interned.get(&key).and_then(Weak::upgrade) {
+        if *shared != params {
+            return Arc::new(params);
+        }
+        return shared;
+    }
+
+    // only sets that are no longer held are dropped, so the map stays as small as the
+    // number of sets in use
+    interned.retain(|_, shared| shared.strong_count() > 0);
+    let shared = Arc::new(params);
+    interned.insert(key, Arc::downgrade(&shared));
+    shared
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::*;
+
+    #[test]
+    fn interned_params_are_shared() {
+        let a = intern_params(get_test_params());
+        let b = intern_params(get_test_params());
+        let c = intern_params(get_short_keygen_params());
+        assert!(Arc::ptr_eq(&a, &b));
+        assert!(!Arc::ptr_eq(&a, &c));
+        assert_eq!(*c, get_short_keygen_params());
+
+        // a set that only differs outside the hash is not confused with the interned one
+        let mut other_noise = get_test_params();
+        other_noise.noise_width += 1.0;
+        let d = intern_params(other_noise.clone());
+        assert!(!Arc::ptr_eq(&a, &d));
+        assert_eq!(*d, other_noise);
+
+        // dropping the last holder frees the parameters
+        let weak = Arc::downgrade(&a);
+        drop(a);
+        assert!(weak.upgrade().is_some());
+        drop(b);
+        assert!(weak.upgrade().is_none());
+    }
+}
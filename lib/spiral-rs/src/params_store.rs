@@ -0,0 +1,314 @@
+//! Parameters for common database shapes, compiled in so that looking them up does
+//! not depend on the working directory.
+//!
+//! The entries were found with `param_search::best_params` for a log2 error
+//! probability of at most `STORE_LOG2_ERR_PROB`, under the default cost model.
+
+use serde_json::json;
+
+use crate::{
+    param_search::best_params,
+    params::{Params, ParamsError},
+    util::try_params_from_json_obj,
+};
+
+/// The log2 error probability the store was generated for.
+pub const STORE_LOG2_ERR_PROB: f64 = -40.0;
+
+/// The parameter version of every entry in the store.
+const STORE_VERSION: usize = 1;
+
+/// An entry of the store:
+/// (log2(num_items), item_size, n, nu_1, nu_2, p, q2_bits, t_gsw, t_conv, t_exp_left, t_exp_right, instances)
+pub type StoreEntry = (
+    usize,
+    usize,
+    usize,
+    usize,
+    usize,
+    u64,
+    u64,
+    usize,
+    usize,
+    usize,
+    usize,
+    usize,
+);
+
+/// Store of parameters in simple array-of-tuples; see `StoreEntry` for the fields.
+#[rustfmt::skip]
+pub const PARAMS_STORE: [StoreEntry; 153] = [
+    (10, 256, 2, 7, 3, 16, 16, 4, 4, 4, 4, 1),
+    (10, 512, 2, 7, 3, 16, 16, 4, 4, 4, 4, 1),
+    (10, 1024, 2, 7, 3, 16, 16, 4, 4, 4, 4, 1),
+    (10, 2048, 2, 7, 3, 16, 16, 4, 4, 4, 4, 1),
+    (10, 4096, 2, 7, 3, 16, 16, 4, 4, 4, 4, 1),
+    (10, 8192, 2, 7, 3, 256, 20, 7, 3, 4, 4, 1),
+    (10, 16384, 2, 7, 3, 256, 22, 5, 4, 5, 5, 2),
+    (10, 32768, 2, 7, 3, 2048, 23, 5, 5, 7, 7, 3),
+    (10, 65536, 2, 8, 2, 2048, 24, 4, 7, 7, 7, 6),
+    (11, 256, 2, 7, 4, 16, 17, 4, 4, 4, 4, 1),
+    (11, 512, 2, 7, 4, 16, 17, 4, 4, 4, 4, 1),
+    (11, 1024, 2, 7, 4, 16, 17, 4, 4, 4, 4, 1),
+    (11, 2048, 2, 7, 4, 16, 17, 4, 4, 4, 4, 1),
+    (11, 4096, 2, 7, 4, 16, 17, 4, 4, 4, 4, 1),
+    (11, 8192, 2, 7, 4, 256, 22, 7, 3, 4, 4, 1),
+    (11, 16384, 2, 8, 3, 256, 22, 7, 3, 4, 4, 2),
+    (11, 32768, 2, 8, 3, 2048, 24, 5, 5, 7, 7, 3),
+    (11, 65536, 2, 8, 3, 2048, 24, 5, 5, 7, 7, 6),
+    (12, 256, 2, 8, 4, 16, 17, 4, 4, 4, 4, 1),
+    (12, 512, 2, 8, 4, 16, 17, 4, 4, 4, 4, 1),
+    (12, 1024, 2, 8, 4, 16, 17, 4, 4, 4, 4, 1),
+    (12, 2048, 2, 8, 4, 16, 17, 4, 4, 4, 4, 1),
+    (12, 4096, 2, 8, 4, 16, 17, 4, 4, 4, 4, 1),
+    (12, 8192, 2, 8, 4, 256, 22, 7, 3, 4, 4, 1),
+    (12, 16384, 2, 8, 4, 256, 22, 7, 3, 4, 4, 2),
+    (12, 32768, 2, 8, 4, 2048, 24, 5, 5, 8, 8, 3),
+    (12, 65536, 2, 8, 4, 8192, 25, 5, 7, 14, 14, 5),
+    (13, 256, 2, 8, 5, 16, 16, 5, 3, 4, 4, 1),
+    (13, 512, 2, 8, 5, 16, 16, 5, 3, 4, 4, 1),
+    (13, 1024, 2, 8, 5, 16, 16, 5, 3, 4, 4, 1),
+    (13, 2048, 2, 8, 5, 16, 16, 5, 3, 4, 4, 1),
+    (13, 4096, 2, 8, 5, 16, 16, 5, 3, 4, 4, 1),
+    (13, 8192, 2, 8, 5, 256, 20, 5, 4, 6, 6, 1),
+    (13, 16384, 2, 8, 5, 256, 20, 4, 5, 7, 7, 2),
+    (13, 32768, 2, 8, 5, 2048, 23, 5, 6, 8, 8, 3),
+    (13, 65536, 2, 8, 5, 8192, 25, 5, 7, 14, 14, 5),
+    (14, 256, 2, 9, 5, 16, 16, 5, 3, 4, 4, 1),
+    (14, 512, 2, 9, 5, 16, 16, 5, 3, 4, 4, 1),
+    (14, 1024, 2, 9, 5, 16, 16, 5, 3, 4, 4, 1),
+    (14, 2048, 2, 9, 5, 16, 16, 5, 3, 4, 4, 1),
+    (14, 4096, 2, 9, 5, 16, 16, 5, 3, 4, 4, 1),
+    (14, 8192, 2, 8, 6, 256, 22, 4, 5, 7, 7, 1),
+    (14, 16384, 2, 9, 5, 256, 20, 5, 4, 6, 6, 2),
+    (14, 32768, 2, 9, 5, 2048, 25, 5, 6, 8, 8, 3),
+    (14, 65536, 2, 8, 6, 8192, 25, 5, 7, 14, 14, 5),
+    (15, 256, 2, 9, 6, 16, 17, 5, 3, 4, 4, 1),
+    (15, 512, 2, 9, 6, 16, 17, 5, 3, 4, 4, 1),
+    (15, 1024, 2, 9, 6, 16, 17, 5, 3, 4, 4, 1),
+    (15, 2048, 2, 9, 6, 16, 17, 5, 3, 4, 4, 1),
+    (15, 4096, 2, 9, 6, 16, 17, 5, 3, 4, 4, 1),
+    (15, 8192, 2, 9, 6, 256, 22, 5, 4, 6, 6, 1),
+    (15, 16384, 2, 9, 6, 256, 22, 4, 5, 7, 7, 2),
+    (15, 32768, 2, 9, 6, 2048, 24, 4, 7, 12, 12, 3),
+    (15, 65536, 2, 9, 6, 2048, 24, 4, 7, 12, 12, 6),
+    (16, 256, 2, 10, 6, 16, 17, 5, 3, 4, 4, 1),
+    (16, 512, 2, 10, 6, 16, 17, 5, 3, 4, 4, 1),
+    (16, 1024, 2, 10, 6, 16, 17, 5, 3, 4, 4, 1),
+    (16, 2048, 2, 10, 6, 16, 17, 5, 3, 4, 4, 1),
+    (16, 4096, 2, 10, 6, 16, 17, 5, 3, 4, 4, 1),
+    (16, 8192, 2, 9, 7, 256, 22, 4, 5, 7, 7, 1),
+    (16, 16384, 2, 10, 6, 256, 22, 5, 4, 6, 6, 2),
+    (16, 32768, 2, 10, 6, 2048, 26, 6, 6, 10, 10, 3),
+    (16, 65536, 2, 10, 6, 2048, 25, 4, 6, 14, 14, 6),
+    (17, 256, 2, 10, 7, 16, 16, 4, 4, 5, 5, 1),
+    (17, 512, 2, 10, 7, 16, 16, 4, 4, 5, 5, 1),
+    (17, 1024, 2, 10, 7, 16, 16, 4, 4, 5, 5, 1),
+    (17, 2048, 2, 10, 7, 16, 16, 4, 4, 5, 5, 1),
+    (17, 4096, 2, 10, 7, 16, 16, 4, 4, 5, 5, 1),
+    (17, 8192, 2, 10, 7, 256, 22, 4, 5, 7, 7, 1),
+    (17, 16384, 2, 10, 7, 256, 22, 4, 5, 7, 7, 2),
+    (17, 32768, 2, 10, 7, 2048, 23, 4, 7, 14, 14, 3),
+    (17, 65536, 2, 10, 7, 2048, 23, 4, 7, 14, 14, 6),
+    (18, 256, 2, 10, 8, 16, 16, 4, 4, 5, 5, 1),
+    (18, 512, 2, 10, 8, 16, 16, 4, 4, 5, 5, 1),
+    (18, 1024, 2, 10, 8, 16, 16, 4, 4, 5, 5, 1),
+    (18, 2048, 2, 10, 8, 16, 16, 4, 4, 5, 5, 1),
+    (18, 4096, 2, 10, 8, 16, 16, 4, 4, 5, 5, 1),
+    (18, 8192, 2, 10, 8, 256, 22, 4, 5, 7, 7, 1),
+    (18, 16384, 2, 10, 8, 256, 22, 4, 5, 7, 7, 2),
+    (18, 32768, 2, 10, 8, 2048, 23, 4, 7, 14, 14, 3),
+    (18, 65536, 2, 10, 8, 2048, 23, 4, 7, 14, 14, 6),
+    (19, 256, 2, 10, 9, 16, 16, 3, 6, 8, 8, 1),
+    (19, 512, 2, 10, 9, 16, 16, 3, 6, 8, 8, 1),
+    (19, 1024, 2, 10, 9, 16, 16, 3, 6, 8, 8, 1),
+    (19, 2048, 2, 10, 9, 16, 16, 3, 6, 8, 8, 1),
+    (19, 4096, 2, 10, 9, 16, 16, 3, 6, 8, 8, 1),
+    (19, 8192, 2, 10, 9, 256, 22, 4, 6, 8, 8, 1),
+    (19, 16384, 2, 10, 9, 256, 22, 4, 6, 8, 8, 2),
+    (19, 32768, 2, 10, 9, 2048, 24, 5, 6, 12, 12, 3),
+    (19, 65536, 2, 10, 9, 2048, 24, 5, 6, 12, 12, 6),
+    (20, 256, 2, 10, 10, 16, 17, 3, 6, 8, 8, 1),
+    (20, 512, 2, 10, 10, 16, 17, 3, 6, 8, 8, 1),
+    (20, 1024, 2, 10, 10, 16, 17, 3, 6, 8, 8, 1),
+    (20, 2048, 2, 10, 10, 16, 17, 3, 6, 8, 8, 1),
+    (20, 4096, 2, 10, 10, 16, 17, 3, 6, 8, 8, 1),
+    (20, 8192, 2, 10, 10, 256, 22, 4, 6, 8, 8, 1),
+    (20, 16384, 2, 10, 10, 256, 22, 4, 6, 8, 8, 2),
+    (20, 32768, 2, 10, 10, 2048, 24, 5, 6, 12, 12, 3),
+    (20, 65536, 2, 10, 10, 2048, 24, 5, 6, 12, 12, 6),
+    (21, 256, 2, 10, 11, 16, 19, 3, 7, 10, 10, 1),
+    (21, 512, 2, 10, 11, 16, 19, 3, 7, 10, 10, 1),
+    (21, 1024, 2, 10, 11, 16, 19, 3, 7, 10, 10, 1),
+    (21, 2048, 2, 10, 11, 16, 19, 3, 7, 10, 10, 1),
+    (21, 4096, 2, 10, 11, 16, 19, 3, 7, 10, 10, 1),
+    (21, 8192, 2, 10, 11, 256, 20, 4, 5, 10, 10, 1),
+    (21, 16384, 2, 10, 11, 256, 20, 4, 5, 10, 10, 2),
+    (21, 32768, 2, 10, 11, 2048, 24, 5, 6, 12, 12, 3),
+    (21, 65536, 2, 10, 11, 2048, 24, 5, 6, 12, 12, 6),
+    (22, 256, 2, 10, 12, 16, 16, 3, 6, 12, 12, 1),
+    (22, 512, 2, 10, 12, 16, 16, 3, 6, 12, 12, 1),
+    (22, 1024, 2, 10, 12, 16, 16, 3, 6, 12, 12, 1),
+    (22, 2048, 2, 10, 12, 16, 16, 3, 6, 12, 12, 1),
+    (22, 4096, 2, 10, 12, 16, 16, 3, 6, 12, 12, 1),
+    (22, 8192, 2, 10, 12, 256, 22, 4, 5, 10, 10, 1),
+    (22, 16384, 2, 10, 12, 256, 22, 4, 5, 10, 10, 2),
+    (22, 32768, 2, 10, 12, 2048, 24, 5, 6, 12, 12, 3),
+    (22, 65536, 2, 10, 12, 2048, 24, 5, 6, 12, 12, 6),
+    (23, 256, 2, 10, 13, 16, 16, 3, 6, 12, 12, 1),
+    (23, 512, 2, 10, 13, 16, 16, 3, 6, 12, 12, 1),
+    (23, 1024, 2, 10, 13, 16, 16, 3, 6, 12, 12, 1),
+    (23, 2048, 2, 10, 13, 16, 16, 3, 6, 12, 12, 1),
+    (23, 4096, 2, 10, 13, 16, 16, 3, 6, 12, 12, 1),
+    (23, 8192, 2, 10, 13, 256, 22, 4, 5, 10, 10, 1),
+    (23, 16384, 2, 10, 13, 256, 22, 4, 5, 10, 10, 2),
+    (23, 32768, 2, 10, 13, 2048, 24, 5, 6, 14, 14, 3),
+    (23, 65536, 2, 10, 13, 2048, 24, 5, 6, 14, 14, 6),
+    (24, 256, 2, 10, 14, 16, 16, 3, 6, 12, 12, 1),
+    (24, 512, 2, 10, 14, 16, 16, 3, 6, 12, 12, 1),
+    (24, 1024, 2, 10, 14, 16, 16, 3, 6, 12, 12, 1),
+    (24, 2048, 2, 10, 14, 16, 16, 3, 6, 12, 12, 1),
+    (24, 4096, 2, 10, 14, 16, 16, 3, 6, 12, 12, 1),
+    (24, 8192, 2, 10, 14, 256, 22, 4, 5, 10, 10, 1),
+    (24, 16384, 2, 10, 14, 256, 22, 4, 5, 10, 10, 2),
+    (24, 32768, 2, 10, 14, 2048, 24, 5, 6, 14, 14, 3),
+    (24, 65536, 2, 10, 14, 2048, 24, 5, 6, 14, 14, 6),
+    (25, 256, 2, 10, 15, 16, 16, 3, 6, 12, 12, 1),
+    (25, 512, 2, 10, 15, 16, 16, 3, 6, 12, 12, 1),
+    (25, 1024, 2, 10, 15, 16, 16, 3, 6, 12, 12, 1),
+    (25, 2048, 2, 10, 15, 16, 16, 3, 6, 12, 12, 1),
+    (25, 4096, 2, 10, 15, 16, 16, 3, 6, 12, 12, 1),
+    (25, 8192, 2, 10, 15, 256, 22, 4, 5, 10, 10, 1),
+    (25, 16384, 2, 10, 15, 256, 22, 4, 5, 10, 10, 2),
+    (25, 32768, 2, 10, 15, 2048, 25, 5, 6, 14, 14, 3),
+    (25, 65536, 2, 10, 15, 2048, 25, 5, 6, 14, 14, 6),
+    (26, 256, 2, 10, 16, 16, 16, 3, 6, 12, 12, 1),
+    (26, 512, 2, 10, 16, 16, 16, 3, 6, 12, 12, 1),
+    (26, 1024, 2, 10, 16, 16, 16, 3, 6, 12, 12, 1),
+    (26, 2048, 2, 10, 16, 16, 16, 3, 6, 12, 12, 1),
+    (26, 4096, 2, 10, 16, 16, 16, 3, 6, 12, 12, 1),
+    (26, 8192, 2, 10, 16, 256, 22, 4, 5, 10, 10, 1),
+    (26, 16384, 2, 10, 16, 256, 22, 4, 5, 10, 10, 2),
+    (26, 32768, 2, 10, 16, 2048, 24, 5, 7, 14, 14, 3),
+    (26, 65536, 2, 10, 16, 2048, 24, 5, 7, 14, 14, 6),
+];
+
+fn entry_to_params(entry: &StoreEntry) -> Result<Params, ParamsError> {
+    let (
+        _,
+        item_size,
+        n,
+        nu_1,
+        nu_2,
+        p,
+        q2_bits,
+        t_gsw,
+        t_conv,
+        t_exp_left,
+        t_exp_right,
+        instances,
+    ) = *entry;
+    try_params_from_json_obj(&json!({
+        "n": n,
+        "nu_1": nu_1,
+        "nu_2": nu_2,
+        "p": p,
+        "q2_bits": q2_bits,
+        "t_gsw": t_gsw,
+        "t_conv": t_conv,
+        "t_exp_left": t_exp_left,
+        "t_exp_right": t_exp_right,
+        "instances": instances,
+        "db_item_size": item_size,
+        "version": STORE_VERSION,
+    }))
+}
+
+/// Looks up parameters for a database of `2^num_items_log2` items of `item_size` bytes.
+///
+/// Returns the nearest entry that covers the database: the one with the fewest items,
+/// and then the smallest items, among those with at least as many items, each at least
+/// as large.
+pub fn params_from_store(num_items_log2: usize, item_size: usize) -> Result<Params, ParamsError> {
+    let entry = PARAMS_STORE
+        .iter()
+        .filter(|e| e.0 >= num_items_log2 && e.1 >= item_size)
+        .min_by_key(|e| (e.0, e.1))
+        .ok_or(ParamsError::NoParams {
+            num_items_log2,
+            item_size,
+        })?;
+    entry_to_params(entry)
+}
+
+/// Like `params_from_store`, but if no entry covers the database, searches for
+/// parameters with `best_params` instead.
+pub fn params_from_store_or_search(
+    num_items_log2: usize,
+    item_size: usize,
+) -> Result<Params, ParamsError> {
+    match params_from_store(num_items_log2, item_size) {
+        Err(ParamsError::NoParams { .. }) => u32::try_from(num_items_log2)
+            .ok()
+            .and_then(|log2| 1usize.checked_shl(log2))
+            .and_then(|num_items| best_params(num_items, item_size, STORE_LOG2_ERR_PROB))
+            .ok_or(ParamsError::NoParams {
+                num_items_log2,
+                item_size,
+            }),
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn store_entries_are_valid() {
+        for entry in PARAMS_STORE.iter() {
+            let params = entry_to_params(entry).unwrap();
+            assert_eq!(params.num_items(), 1 << entry.0);
+            assert_eq!(params.db_item_size, entry.1);
+        }
+    }
+
+    #[test]
+    fn lookup_uses_nearest_covering_entry() {
+        let params = params_from_store(14, 3000).unwrap();
+        assert_eq!(params.num_items(), 1 << 14);
+        assert_eq!(params.db_item_size, 4096);
+
+        let params = params_from_store(9, 100).unwrap();
+        assert_eq!(params.num_items(), 1 << 10);
+        assert_eq!(params.db_item_size, 256);
+
+        assert_eq!(
+            params_from_store(14, 1 << 17),
+            Err(ParamsError::NoParams {
+                num_items_log2: 14,
+                item_size: 1 << 17
+            })
+        );
+    }
+
+    #[test]
+    fn lookup_falls_back_to_search() {
+        let params = params_from_store_or_search(14, 1 << 17).unwrap();
+        assert_eq!(params.num_items(), 1 << 14);
+        assert_eq!(params.db_item_size, 1 << 17);
+        assert_eq!(
+            params_from_store_or_search(14, 4096).unwrap(),
+            params_from_store(14, 4096).unwrap()
+        );
+        for num_items_log2 in [64, usize::MAX] {
+            assert_eq!(
+                params_from_store_or_search(num_items_log2, 256),
+                Err(ParamsError::NoParams {
+                    num_items_log2,
+                    item_size: 256
+                })
+            );
+        }
+    }
+}
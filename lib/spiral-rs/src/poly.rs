@@ -1,4 +1,4 @@
-#[cfg(target_feature = "avx2")]
+#[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::*;
 
 use rand::distributions::Standard;
@@ -6,8 +6,11 @@ use rand::Rng;
 use rand_chacha::ChaCha20Rng;
 use std::cell::RefCell;
 use std::ops::{Add, Mul, Neg};
+use std::sync::OnceLock;
 
-use crate::{aligned_memory::*, arith::*, discrete_gaussian::*, ntt::*, params::*, util::*};
+use crate::{
+    aligned_memory::*, arith::*, discrete_gaussian::*, ntt::*, params::*, simd::Simd, util::*,
+};
 
 use either::{Either, Left, Right};
 use serde::{
@@ -525,27 +528,77 @@ pub fn automorph_poly(params: &Params, res: &mut [u64], a: &[u64], t: usize) {
     }
 }
 
-#[cfg(target_feature = "avx2")]
-pub fn multiply_add_poly_avx(params: &Params, res: &mut [u64], a: &[u64], b: &[u64]) {
+/// Accumulates the raw products `a[i] * b[i]` into `res`, without reducing.
+///
+/// # Safety
+///
+/// The running CPU must support AVX2, and every slice must be 32-byte aligned.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+pub unsafe fn multiply_add_poly_avx(params: &Params, res: &mut [u64], a: &[u64], b: &[u64]) {
     for c in 0..params.crt_count {
         for i in (0..params.poly_len).step_by(4) {
-            unsafe {
-                let p_x = &a[c * params.poly_len + i] as *const u64;
-                let p_y = &b[c * params.poly_len + i] as *const u64;
-                let p_z = &mut res[c * params.poly_len + i] as *mut u64;
-                let x = _mm256_load_si256(p_x as *const __m256i);
-                let y = _mm256_load_si256(p_y as *const __m256i);
-                let z = _mm256_load_si256(p_z as *const __m256i);
-
-                let product = _mm256_mul_epu32(x, y);
-                let out = _mm256_add_epi64(z, product);
-
-                _mm256_store_si256(p_z as *mut __m256i, out);
-            }
+            let p_x = &a[c * params.poly_len + i] as *const u64;
+            let p_y = &b[c * params.poly_len + i] as *const u64;
+            let p_z = &mut res[c * params.poly_len + i] as *mut u64;
+            let x = _mm256_load_si256(p_x as *const __m256i);
+            let y = _mm256_load_si256(p_y as *const __m256i);
+            let z = _mm256_load_si256(p_z as *const __m256i);
+
+            let product = _mm256_mul_epu32(x, y);
+            let out = _mm256_add_epi64(z, product);
+
+            _mm256_store_si256(p_z as *mut __m256i, out);
         }
     }
 }
 
+/// Accumulates the raw products `a[i] * b[i]` into `res`, without reducing.
+///
+/// # Safety
+///
+/// The running CPU must support AVX-512F.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx512f")]
+pub unsafe fn multiply_add_poly_avx512(params: &Params, res: &mut [u64], a: &[u64], b: &[u64]) {
+    let len = params.crt_count * params.poly_len;
+    for i in (0..len).step_by(8) {
+        let p_x = a[i..i + 8].as_ptr();
+        let p_y = b[i..i + 8].as_ptr();
+        let p_z = res[i..i + 8].as_mut_ptr();
+        let x = _mm512_loadu_si512(p_x as *const _);
+        let y = _mm512_loadu_si512(p_y as *const _);
+        let z = _mm512_loadu_si512(p_z as *const _);
+
+        let out = _mm512_add_epi64(z, _mm512_mul_epu32(x, y));
+
+        _mm512_storeu_si512(p_z as *mut _, out);
+    }
+}
+
+/// Accumulates the raw products `a[i] * b[i]` into `res`, without reducing.
+///
+/// # Safety
+///
+/// The running CPU must support NEON.
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "neon")]
+pub unsafe fn multiply_add_poly_neon(params: &Params, res: &mut [u64], a: &[u64], b: &[u64]) {
+    use std::arch::aarch64::*;
+
+    let len = params.crt_count * params.poly_len;
+    for i in (0..len).step_by(2) {
+        let p_x = a[i..i + 2].as_ptr();
+        let p_y = b[i..i + 2].as_ptr();
+        let p_z = res[i..i + 2].as_mut_ptr();
+        let x = vmovn_u64(vld1q_u64(p_x));
+        let y = vmovn_u64(vld1q_u64(p_y));
+        let z = vld1q_u64(p_z);
+
+        vst1q_u64(p_z, vaddq_u64(z, vmull_u32(x, y)));
+    }
+}
+
 pub fn modular_reduce(params: &Params, res: &mut [u64]) {
     for c in 0..params.crt_count {
         for i in 0..params.poly_len {
@@ -555,8 +608,43 @@ pub fn modular_reduce(params: &Params, res: &mut [u64]) {
     }
 }
 
-#[cfg(not(target_feature = "avx2"))]
+/// A matrix product of NTT-form operands, `res = a * b`.
+pub type MultiplyKernel = fn(&mut PolyMatrixNTT, &PolyMatrixNTT, &PolyMatrixNTT);
+
+/// Returns the matrix product path for the given instruction set.
+///
+/// Panics if the running CPU does not support it.
+pub fn multiply_kernel(simd: Simd) -> MultiplyKernel {
+    simd.assert_available();
+    match simd {
+        #[cfg(target_arch = "x86_64")]
+        Simd::Avx2 => |res, a, b| {
+            multiply_accumulate(res, a, b, |params, res_poly, pol1, pol2| unsafe {
+                multiply_add_poly_avx(params, res_poly, pol1, pol2)
+            })
+        },
+        #[cfg(target_arch = "x86_64")]
+        Simd::Avx512 => |res, a, b| {
+            multiply_accumulate(res, a, b, |params, res_poly, pol1, pol2| unsafe {
+                multiply_add_poly_avx512(params, res_poly, pol1, pol2)
+            })
+        },
+        #[cfg(target_arch = "aarch64")]
+        Simd::Neon => |res, a, b| {
+            multiply_accumulate(res, a, b, |params, res_poly, pol1, pol2| unsafe {
+                multiply_add_poly_neon(params, res_poly, pol1, pol2)
+            })
+        },
+        _ => multiply_scalar,
+    }
+}
+
 pub fn multiply(res: &mut PolyMatrixNTT, a: &PolyMatrixNTT, b: &PolyMatrixNTT) {
+    static KERNEL: OnceLock<MultiplyKernel> = OnceLock::new();
+    KERNEL.get_or_init(|| multiply_kernel(Simd::detected()))(res, a, b)
+}
+
+pub fn multiply_scalar(res: &mut PolyMatrixNTT, a: &PolyMatrixNTT, b: &PolyMatrixNTT) {
     assert!(res.rows == a.rows);
     assert!(res.cols == b.cols);
     assert!(a.cols == b.rows);
@@ -584,8 +672,14 @@ pub fn multiply(res: &mut PolyMatrixNTT, a: &PolyMatrixNTT, b: &PolyMatrixNTT) {
     }
 }
 
-#[cfg(target_feature = "avx2")]
-pub fn multiply(res: &mut PolyMatrixNTT, a: &PolyMatrixNTT, b: &PolyMatrixNTT) {
+/// Accumulates unreduced products with `multiply_add`, reducing once per output polynomial.
+#[inline(always)]
+fn multiply_accumulate(
+    res: &mut PolyMatrixNTT,
+    a: &PolyMatrixNTT,
+    b: &PolyMatrixNTT,
+    multiply_add: impl Fn(&Params, &mut [u64], &[u64], &[u64]),
+) {
     assert_eq!(res.rows, a.rows);
     assert_eq!(res.cols, b.cols);
     assert_eq!(a.cols, b.rows);
@@ -603,7 +697,7 @@ pub fn multiply(res: &mut PolyMatrixNTT, a: &PolyMatrixNTT, b: &PolyMatrixNTT) {
             for k in 0..a.cols {
                 let pol1 = a.get_poly(i, k);
                 let pol2 = b.get_poly(k, j);
-                multiply_add_poly_avx(params, res_poly, pol1, pol2);
+                multiply_add(params, res_poly, pol1, pol2);
             }
             modular_reduce(params, res_poly);
         }
@@ -926,6 +1020,20 @@ mod test {
         assert_eq!(m3.get_poly(0, 0)[2], 700);
     }
 
+    #[test]
+    fn multiply_kernels_agree() {
+        let params = get_params();
+        let m1 = PolyMatrixNTT::random(&params, 2, 3);
+        let m2 = PolyMatrixNTT::random(&params, 3, 2);
+        let mut expected = PolyMatrixNTT::zero(&params, 2, 2);
+        multiply_scalar(&mut expected, &m1, &m2);
+        for simd in Simd::available() {
+            let mut res = PolyMatrixNTT::zero(&params, 2, 2);
+            multiply_kernel(simd)(&mut res, &m1, &m2);
+            assert_eq!(res.as_slice(), expected.as_slice(), "{:?}", simd);
+        }
+    }
+
     #[test]
     fn to_vec_correctness() {
         let params = get_params();
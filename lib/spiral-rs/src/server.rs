@@ -3,9 +3,11 @@ use std::io::BufReader;
 use std::io::Read;
 use std::io::Seek;
 use std::io::SeekFrom;
+use std::ops::Range;
 
 use crate::aligned_memory::*;
 use crate::arith::*;
+use crate::client::batch_stop_round;
 use crate::client::PublicParameters;
 use crate::client::Query;
 use crate::client::CLIENT_TEST;
@@ -56,25 +58,14 @@ pub fn coefficient_expansion(
             let mut ct_auto_1_ntt = PolyMatrixNTT::zero(params, 1, 1);
             let mut w_times_ginv_ct = PolyMatrixNTT::zero(params, 2, 1);
 
-            let mut ginv_ct_left = PolyMatrixRaw::zero(params, params.t_exp_left, 1);
-            let mut ginv_ct_left_ntt = PolyMatrixNTT::zero(params, params.t_exp_left, 1);
-            let mut ginv_ct_right = PolyMatrixRaw::zero(params, params.t_exp_right, 1);
-            let mut ginv_ct_right_ntt = PolyMatrixNTT::zero(params, params.t_exp_right, 1);
-
-            let (w, _gadget_dim, gi_ct, gi_ct_ntt) = match (r != 0) && (i % 2 == 0) {
-                true => (
-                    &v_w_left[r],
-                    params.t_exp_left,
-                    &mut ginv_ct_left,
-                    &mut ginv_ct_left_ntt,
-                ),
-                false => (
-                    &v_w_right[r],
-                    params.t_exp_right,
-                    &mut ginv_ct_right,
-                    &mut ginv_ct_right_ntt,
-                ),
+            let w = match (r != 0) && (i % 2 == 0) {
+                true => &v_w_left[r],
+                false => &v_w_right[r],
             };
+            // the right keys of a batch query end with left keys (see `expand_batch_query`),
+            // so the gadget dimension comes from the key
+            let gi_ct = &mut PolyMatrixRaw::zero(params, w.cols, 1);
+            let gi_ct_ntt = &mut PolyMatrixNTT::zero(params, w.cols, 1);
 
             // if i < num_in {
             //     let (src, dest) = v.split_at_mut(num_in);
@@ -163,6 +154,20 @@ pub fn multiply_reg_by_database(
     params: &Params,
     dim0: usize,
     num_per: usize,
+) {
+    multiply_reg_by_database_rows(out, db, v_firstdim, params, dim0, num_per, 0..dim0);
+}
+
+/// Multiplies the first-dimension ciphertexts by the given range of rows of the database, as
+/// `multiply_reg_by_database`; used to answer each partition of a batch query.
+pub fn multiply_reg_by_database_rows(
+    out: &mut Vec<PolyMatrixNTT>,
+    db: &[u64],
+    v_firstdim: &[u64],
+    params: &Params,
+    dim0: usize,
+    num_per: usize,
+    rows: Range<usize>,
 ) {
     let ct_rows = 2;
     let ct_cols = 1;
@@ -171,7 +176,7 @@ pub fn multiply_reg_by_database(
 
     for z in 0..params.poly_len {
         let idx_a_base = z * (ct_cols * dim0 * ct_rows);
-        let mut idx_b_base = z * (num_per * pt_cols * dim0 * pt_rows);
+        let idx_b_base = z * (num_per * pt_cols * dim0 * pt_rows);
 
         for i in 0..num_per {
             for c in 0..pt_cols {
@@ -180,9 +185,8 @@ pub fn multiply_reg_by_database(
                 let mut sums_out_n1_0 = 0u128;
                 let mut sums_out_n1_1 = 0u128;
 
-                for jm in 0..(dim0 * pt_rows) {
-                    let b = db[idx_b_base];
-                    idx_b_base += 1;
+                for jm in (rows.start * pt_rows)..(rows.end * pt_rows) {
+                    let b = db[idx_b_base + (i * pt_cols + c) * dim0 * pt_rows + jm];
 
                     let v_a0 = v_firstdim[idx_a_base + jm * ct_rows];
                     let v_a1 = v_firstdim[idx_a_base + jm * ct_rows + 1];
@@ -477,13 +481,7 @@ pub fn encode(params: &Params, v_packed_ct: &Vec<PolyMatrixRaw>) -> Vec<u8> {
     let q2 = Q2_VALUES[params.q2_bits as usize];
     let q2_bits = params.q2_bits as usize;
 
-    let num_bits = params.instances
-        * ((q2_bits * params.n * params.poly_len)
-            + (q1_bits * params.n * params.n * params.poly_len));
-    let round_to = 64;
-    let num_bytes_rounded_up = ((num_bits + round_to - 1) / round_to) * round_to / 8;
-
-    let mut result = vec![0u8; num_bytes_rounded_up];
+    let mut result = vec![0u8; params.response_bytes()];
     let mut bit_offs = 0;
     for instance in 0..params.instances {
         let packed_ct = &v_packed_ct[instance];
@@ -531,16 +529,31 @@ pub fn expand_query<'a>(
     public_params: &PublicParameters<'a>,
     query: &Query<'a>,
 ) -> (AlignedMemory64, Vec<PolyMatrixNTT<'a>>) {
+    let (v_reg_reoriented, mut v_foldings) = expand_batch_query(params, public_params, query, 1);
+    (v_reg_reoriented, v_foldings.pop().unwrap())
+}
+
+/// Expands a batch query of `num_partitions` partitions (see `Client::generate_batch_query`).
+///
+/// Returns the first-dimension ciphertexts, which select one row in each partition, and the
+/// folding ciphertexts of each partition. The GSW ciphertexts of a batch need more expansion
+/// rounds than the right expansion keys cover; those rounds use the left keys.
+pub fn expand_batch_query<'a>(
+    params: &'a Params,
+    public_params: &PublicParameters<'a>,
+    query: &Query<'a>,
+    num_partitions: usize,
+) -> (AlignedMemory64, Vec<Vec<PolyMatrixNTT<'a>>>) {
     let dim0 = 1 << params.db_dim_1;
     let further_dims = params.db_dim_2;
 
     let mut v_reg_reoriented;
-    let mut v_folding;
 
     let num_bits_to_gen = params.t_gsw * further_dims + dim0;
     let g = log2_ceil_usize(num_bits_to_gen);
-    let right_expanded = params.t_gsw * further_dims;
-    let stop_round = log2_ceil_usize(right_expanded);
+    let right_expanded = num_partitions * params.t_gsw * further_dims;
+    let stop_round = batch_stop_round(params, num_partitions);
+    assert!(further_dims == 0 || stop_round < g);
 
     let mut v = Vec::new();
     for _ in 0..(1 << g) {
@@ -551,6 +564,9 @@ pub fn expand_query<'a>(
     let v_conversion = &public_params.v_conversion.as_ref().unwrap()[0];
     let v_w_left = public_params.v_expansion_left.as_ref().unwrap();
     let v_w_right = public_params.v_expansion_right.as_ref().unwrap_or(v_w_left);
+    let v_w_right: Vec<_> = (0..=stop_round)
+        .map(|r| v_w_right.get(r).unwrap_or(&v_w_left[r]).clone())
+        .collect();
     let v_neg1 = params.get_v_neg1();
 
     let mut v_reg_inp = Vec::with_capacity(dim0);
@@ -564,7 +580,7 @@ pub fn expand_query<'a>(
             &v_w_left,
             &v_w_right,
             &v_neg1,
-            params.t_gsw * params.db_dim_2,
+            right_expanded,
         );
 
         for i in 0..dim0 {
@@ -584,14 +600,26 @@ pub fn expand_query<'a>(
     v_reg_reoriented = AlignedMemory64::new(v_reg_sz);
     reorient_reg_ciphertexts(params, v_reg_reoriented.as_mut_slice(), &v_reg_inp);
 
-    v_folding = Vec::new();
-    for _ in 0..params.db_dim_2 {
-        v_folding.push(PolyMatrixNTT::zero(params, 2, 2 * params.t_gsw));
-    }
-
-    regev_to_gsw(&mut v_folding, &v_gsw_inp, &v_conversion, params, 1, 0);
+    let v_foldings = (0..num_partitions)
+        .map(|partition| {
+            let mut v_folding = Vec::new();
+            for _ in 0..params.db_dim_2 {
+                v_folding.push(PolyMatrixNTT::zero(params, 2, 2 * params.t_gsw));
+            }
+            let gsw_per_partition = params.t_gsw * further_dims;
+            regev_to_gsw(
+                &mut v_folding,
+                &v_gsw_inp,
+                &v_conversion,
+                params,
+                1,
+                partition * gsw_per_partition,
+            );
+            v_folding
+        })
+        .collect();
 
-    (v_reg_reoriented, v_folding)
+    (v_reg_reoriented, v_foldings)
 }
 
 pub fn variance(v: Vec<i64>) -> f64 {
@@ -657,33 +685,56 @@ pub fn process_query(
     query: &Query,
     db: &[u64],
 ) -> Vec<u8> {
+    process_batch_query(params, public_params, query, 1, db)
+        .pop()
+        .unwrap()
+}
+
+/// Answers a batch query of `num_partitions` partitions (see `Client::generate_batch_query`)
+/// with a single pass over the database.
+///
+/// The first dimension is split into `num_partitions` equal partitions of rows, and each
+/// partition is folded with its own ciphertexts. Returns one response per partition.
+pub fn process_batch_query(
+    params: &Params,
+    public_params: &PublicParameters,
+    query: &Query,
+    num_partitions: usize,
+    db: &[u64],
+) -> Vec<Vec<u8>> {
     let dim0 = 1 << params.db_dim_1;
     let num_per = 1 << params.db_dim_2;
     let db_slice_sz = dim0 * num_per * params.poly_len;
+    let rows_per_partition = dim0 / num_partitions;
 
     let v_packing = public_params.v_packing.as_ref();
 
     let mut v_reg_reoriented;
-    let v_folding;
+    let v_foldings;
     if params.expand_queries {
-        (v_reg_reoriented, v_folding) = expand_query(params, public_params, query);
+        (v_reg_reoriented, v_foldings) =
+            expand_batch_query(params, public_params, query, num_partitions);
     } else {
+        assert_eq!(num_partitions, 1);
         v_reg_reoriented = AlignedMemory64::new(query.v_buf.as_ref().unwrap().len());
         v_reg_reoriented
             .as_mut_slice()
             .copy_from_slice(query.v_buf.as_ref().unwrap());
 
-        v_folding = query
+        v_foldings = vec![query
             .v_ct
             .as_ref()
             .unwrap()
             .iter()
             .map(|x| x.ntt())
-            .collect();
+            .collect()];
     }
-    let v_folding_neg = get_v_folding_neg(params, &v_folding);
+    let v_foldings_neg: Vec<_> = v_foldings
+        .iter()
+        .map(|v_folding| get_v_folding_neg(params, v_folding))
+        .collect();
 
-    let v_packed_ct: Vec<PolyMatrixRaw> = (0..params.instances)
+    let v_packed_cts: Vec<Vec<PolyMatrixRaw>> = (0..params.instances)
         .into_par_iter()
         .map(|instance| {
             let mut intermediate = Vec::with_capacity(num_per);
@@ -693,55 +744,73 @@ pub fn process_query(
                 intermediate_raw.push(PolyMatrixRaw::zero(params, 2, 1));
             }
 
-            let mut v_ct = Vec::new();
+            let mut v_cts = vec![Vec::new(); num_partitions];
 
             for trial in 0..(params.n * params.n) {
                 let idx = (instance * (params.n * params.n) + trial) * db_slice_sz;
                 let cur_db = &db[idx..(idx + db_slice_sz)];
 
-                multiply_reg_by_database(
-                    &mut intermediate,
-                    cur_db,
-                    v_reg_reoriented.as_slice(),
-                    params,
-                    dim0,
-                    num_per,
-                );
-
-                for i in 0..intermediate.len() {
-                    from_ntt(&mut intermediate_raw[i], &intermediate[i]);
-                }
+                // every partition covers its own rows, so together they make one pass
+                for partition in 0..num_partitions {
+                    multiply_reg_by_database_rows(
+                        &mut intermediate,
+                        cur_db,
+                        v_reg_reoriented.as_slice(),
+                        params,
+                        dim0,
+                        num_per,
+                        partition * rows_per_partition..(partition + 1) * rows_per_partition,
+                    );
+
+                    for i in 0..intermediate.len() {
+                        from_ntt(&mut intermediate_raw[i], &intermediate[i]);
+                    }
+
+                    fold_ciphertexts(
+                        params,
+                        &mut intermediate_raw,
+                        &v_foldings[partition],
+                        &v_foldings_neg[partition],
+                    );
 
-                fold_ciphertexts(params, &mut intermediate_raw, &v_folding, &v_folding_neg);
-
-                if instance == 0 && trial == 0 {
-                    unsafe {
-                        if let Some((sk_reg, target)) = &CLIENT_TEST {
-                            let ct = intermediate_raw[0].ntt();
-                            let ct_subset = ct.submatrix(0, 0, 2, 1);
-                            let dec = (&sk_reg.ntt() * &ct_subset).raw();
-                            let dec_raw = dec_to_raw(params, &dec, target);
-                            for i in 0..params.poly_len {
-                                assert_eq!(
-                                    dec_raw.data[i], target.data[i],
-                                    "{} != {} at {}",
-                                    dec_raw.data[i], target.data[i], i
-                                );
+                    if instance == 0 && trial == 0 && num_partitions == 1 {
+                        unsafe {
+                            if let Some((sk_reg, target)) = &CLIENT_TEST {
+                                let ct = intermediate_raw[0].ntt();
+                                let ct_subset = ct.submatrix(0, 0, 2, 1);
+                                let dec = (&sk_reg.ntt() * &ct_subset).raw();
+                                let dec_raw = dec_to_raw(params, &dec, target);
+                                for i in 0..params.poly_len {
+                                    assert_eq!(
+                                        dec_raw.data[i], target.data[i],
+                                        "{} != {} at {}",
+                                        dec_raw.data[i], target.data[i], i
+                                    );
+                                }
                             }
                         }
                     }
-                }
 
-                v_ct.push(intermediate_raw[0].clone());
+                    v_cts[partition].push(intermediate_raw[0].clone());
+                }
             }
 
-            let packed_ct = pack(params, &v_ct, &v_packing);
-
-            packed_ct.raw()
+            v_cts
+                .iter()
+                .map(|v_ct| pack(params, v_ct, &v_packing).raw())
+                .collect()
         })
         .collect();
 
-    encode(params, &v_packed_ct)
+    (0..num_partitions)
+        .map(|partition| {
+            let v_packed_ct = v_packed_cts
+                .iter()
+                .map(|cts| cts[partition].clone())
+                .collect();
+            encode(params, &v_packed_ct)
+        })
+        .collect()
 }
 
 #[cfg(test)]
@@ -1013,7 +1082,7 @@ mod test {
         let (corr_item, db) = generate_random_db_and_get_item(params, target_idx);
 
         unsafe {
-            let params_static = Box::leak(Box::new(params.clone()));
+            let params_static: &'static Params = Box::leak(Box::new(params.clone()));
             let mut corr_item_static =
                 PolyMatrixRaw::zero(params_static, corr_item.rows, corr_item.cols);
             corr_item_static
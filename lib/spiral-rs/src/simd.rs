@@ -0,0 +1,84 @@
+//! Runtime selection of the instruction set used by the compute kernels.
+//!
+//! Kernels with SIMD paths (`ntt::ntt_forward`, `ntt::ntt_inverse`, `poly::multiply`) pick the
+//! fastest path the running CPU supports the first time they are called, so a single binary
+//! runs the vectorized code on every machine, without building for a specific `target-cpu`.
+//!
+//! There is no separate AVX-512 IFMA path. IFMA multiplies 52-bit operands, which only pays
+//! off for moduli wider than 32 bits. Every multiplication in these kernels has operands
+//! under 32 bits: NTT butterflies and their Shoup quotients, and `poly::multiply` of
+//! residues. AVX-512F's `vpmuludq` already does each of those in one instruction per 8 lanes,
+//! the same width IFMA has, so an IFMA path would save no multiplications.
+
+use std::sync::OnceLock;
+
+/// An instruction set that compute kernels have a path for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Simd {
+    /// Portable scalar code, available everywhere.
+    Scalar,
+    /// AVX2 on x86-64.
+    Avx2,
+    /// AVX-512F on x86-64. CPUs with AVX-512 IFMA also use this path (see the module docs).
+    Avx512,
+    /// NEON on AArch64.
+    Neon,
+}
+
+impl Simd {
+    /// Every instruction set, from the slowest to the fastest.
+    pub const ALL: [Simd; 4] = [Simd::Scalar, Simd::Neon, Simd::Avx2, Simd::Avx512];
+
+    /// Returns whether the running CPU supports this instruction set.
+    pub fn is_available(self) -> bool {
+        match self {
+            Simd::Scalar => true,
+            #[cfg(target_arch = "x86_64")]
+            Simd::Avx2 => is_x86_feature_detected!("avx2"),
+            #[cfg(target_arch = "x86_64")]
+            Simd::Avx512 => is_x86_feature_detected!("avx512f"),
+            #[cfg(target_arch = "aarch64")]
+            Simd::Neon => std::arch::is_aarch64_feature_detected!("neon"),
+            #[allow(unreachable_patterns)]
+            _ => false,
+        }
+    }
+
+    /// Returns the instruction sets the running CPU supports, from the slowest to the fastest.
+    pub fn available() -> impl Iterator<Item = Simd> {
+        Self::ALL.into_iter().filter(|simd| simd.is_available())
+    }
+
+    /// Returns the fastest instruction set the running CPU supports.
+    ///
+    /// Detection only runs once.
+    pub fn detected() -> Simd {
+        static DETECTED: OnceLock<Simd> = OnceLock::new();
+        *DETECTED.get_or_init(|| Self::available().last().unwrap())
+    }
+
+    /// Panics unless the running CPU supports this instruction set.
+    ///
+    /// Kernel selectors call this before handing out a path, which makes calling it sound.
+    pub fn assert_available(self) {
+        assert!(
+            self.is_available(),
+            "{:?} is not supported by this CPU",
+            self
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn detected_is_available() {
+        assert!(Simd::Scalar.is_available());
+        assert!(Simd::detected().is_available());
+        assert_eq!(Simd::available().next(), Some(Simd::Scalar));
+        #[cfg(target_feature = "avx2")]
+        assert!(Simd::Avx2.is_available());
+    }
+}
@@ -2,7 +2,6 @@ use crate::{arith::*, client::Seed, params::*, poly::*};
 use rand::{prelude::SmallRng, thread_rng, Rng, SeedableRng};
 use rand_chacha::ChaCha20Rng;
 use serde_json::Value;
-use std::fs;
 
 pub fn compose_bytes(bytes: &[u8]) -> u64 {
     assert_eq!(bytes.len(), 8);
@@ -227,35 +226,54 @@ pub const fn get_empty_params() -> Params {
 }
 
 pub fn params_from_json(cfg: &str) -> Params {
-    let v: Value = serde_json::from_str(cfg).unwrap();
-    params_from_json_obj(&v)
+    try_params_from_json(cfg).unwrap_or_else(|e| panic!("{}", e))
 }
 
 pub fn params_from_json_obj(v: &Value) -> Params {
-    let n = v["n"].as_u64().unwrap() as usize;
-    let db_dim_1 = v["nu_1"].as_u64().unwrap() as usize;
-    let db_dim_2 = v["nu_2"].as_u64().unwrap() as usize;
-    let instances = v["instances"].as_u64().unwrap_or(1) as usize;
-    let p = v["p"].as_u64().unwrap();
-    let q2_bits = u64::max(v["q2_bits"].as_u64().unwrap(), MIN_Q2_BITS);
-    let t_gsw = v["t_gsw"].as_u64().unwrap() as usize;
-    let t_conv = v["t_conv"].as_u64().unwrap() as usize;
-    let t_exp_left = v["t_exp_left"].as_u64().unwrap() as usize;
-    let t_exp_right = v["t_exp_right"].as_u64().unwrap() as usize;
-    let do_expansion = v.get("direct_upload").is_none();
+    try_params_from_json_obj(v).unwrap_or_else(|e| panic!("{}", e))
+}
+
+/// Like `params_from_json`, but returns an error for malformed or inconsistent parameters.
+pub fn try_params_from_json(cfg: &str) -> Result<Params, ParamsError> {
+    let v: Value = serde_json::from_str(cfg).map_err(|e| ParamsError::Json(e.to_string()))?;
+    try_params_from_json_obj(&v)
+}
 
-    let mut db_item_size = v["db_item_size"].as_u64().unwrap_or(0) as usize;
-    if db_item_size == 0 {
-        db_item_size = instances * n * n;
-        db_item_size = db_item_size * 2048 * log2_ceil(p) as usize / 8;
+fn get_field(v: &Value, field: &'static str) -> Result<u64, ParamsError> {
+    v[field].as_u64().ok_or(ParamsError::InvalidField(field))
+}
+
+fn get_field_or(v: &Value, field: &'static str, default: u64) -> Result<u64, ParamsError> {
+    match v.get(field) {
+        Some(_) => get_field(v, field),
+        None => Ok(default),
     }
+}
 
-    let version = v["version"].as_u64().unwrap_or(0) as usize;
+/// Like `params_from_json_obj`, but returns an error for malformed or inconsistent parameters.
+pub fn try_params_from_json_obj(v: &Value) -> Result<Params, ParamsError> {
+    let n = get_field(v, "n")? as usize;
+    let db_dim_1 = get_field(v, "nu_1")? as usize;
+    let db_dim_2 = get_field(v, "nu_2")? as usize;
+    let instances = get_field_or(v, "instances", 1)? as usize;
+    let p = get_field(v, "p")?;
+    let q2_bits = get_field(v, "q2_bits")?;
+    // `Params::init` asserts this, so it can't wait for `validate`
+    if q2_bits < MIN_Q2_BITS {
+        return Err(ParamsError::UnsupportedQ2Bits(q2_bits));
+    }
+    let t_gsw = get_field(v, "t_gsw")? as usize;
+    let t_conv = get_field(v, "t_conv")? as usize;
+    let t_exp_left = get_field(v, "t_exp_left")? as usize;
+    let t_exp_right = get_field(v, "t_exp_right")? as usize;
+    let do_expansion = v.get("direct_upload").is_none();
+    let db_item_size = get_field_or(v, "db_item_size", 0)? as usize;
+    let version = get_field_or(v, "version", 0)? as usize;
 
-    Params::init(
-        2048,
-        &vec![268369921u64, 249561089u64],
-        6.4,
+    let mut params = Params::init(
+        DEFAULT_POLY_LEN,
+        &DEFAULT_MODULI,
+        DEFAULT_NOISE_WIDTH,
         n,
         p,
         q2_bits,
@@ -269,31 +287,13 @@ pub fn params_from_json_obj(v: &Value) -> Params {
         instances,
         db_item_size,
         version,
-    )
-}
-
-static ALL_PARAMS_STORE_FNAME: &str = "../params_store.json";
-
-pub fn get_params_from_store(target_num_log2: usize, item_size: usize) -> Params {
-    let params_store_str = fs::read_to_string(ALL_PARAMS_STORE_FNAME).unwrap();
-    let v: Value = serde_json::from_str(&params_store_str).unwrap();
-    let nearest_target_num = target_num_log2;
-    let nearest_item_size = 1 << usize::max(log2_ceil_usize(item_size), 8);
-    println!(
-        "Starting with parameters for 2^{} x {} bytes...",
-        nearest_target_num, nearest_item_size
     );
-    let target = v
-        .as_array()
-        .unwrap()
-        .iter()
-        .map(|x| x.as_object().unwrap())
-        .filter(|x| x.get("target_num").unwrap().as_u64().unwrap() == (nearest_target_num as u64))
-        .filter(|x| x.get("item_size").unwrap().as_u64().unwrap() == (nearest_item_size as u64))
-        .map(|x| x.get("params").unwrap())
-        .next()
-        .unwrap();
-    params_from_json_obj(target)
+    // an item size of 0 means items fill the whole plaintext
+    if params.db_item_size == 0 && p.is_power_of_two() {
+        params.db_item_size = params.item_size();
+    }
+    params.validate()?;
+    Ok(params)
 }
 
 pub fn read_arbitrary_bits(data: &[u8], bit_offs: usize, num_bits: usize) -> u64 {
@@ -407,6 +407,73 @@ mod test {
         assert_eq!(b, c);
     }
 
+    #[test]
+    fn params_json_round_trips() {
+        let all_params = [
+            get_expansion_testing_params(),
+            get_fast_expansion_testing_params(),
+            get_no_expansion_testing_params(),
+        ];
+        for params in all_params.iter() {
+            let json = params.to_json();
+            assert_eq!(params_from_json(&json), *params);
+            assert_eq!(params_from_json(&json).hash(), params.hash());
+        }
+        assert_ne!(all_params[0].hash(), all_params[1].hash());
+        assert_ne!(all_params[0].hash(), all_params[2].hash());
+    }
+
+    #[test]
+    fn invalid_params_are_rejected() {
+        let base = get_expansion_testing_params().to_json_obj();
+        let with = |field: &str, value: Value| {
+            let mut v = base.clone();
+            v[field] = value;
+            try_params_from_json_obj(&v)
+        };
+
+        assert_eq!(
+            with("p", 300.into()),
+            Err(ParamsError::PtModulusNotPowerOfTwo(300))
+        );
+        assert_eq!(
+            with("q2_bits", 40.into()),
+            Err(ParamsError::UnsupportedQ2Bits(40))
+        );
+        assert_eq!(
+            with("q2_bits", 13.into()),
+            Err(ParamsError::UnsupportedQ2Bits(13))
+        );
+        assert!(matches!(
+            with("t_gsw", 0.into()),
+            Err(ParamsError::OutOfRange { field: "t_gsw", .. })
+        ));
+        assert!(matches!(
+            with("t_exp_right", 57.into()),
+            Err(ParamsError::OutOfRange {
+                field: "t_exp_right",
+                ..
+            })
+        ));
+        assert!(matches!(
+            with("db_item_size", 8193.into()),
+            Err(ParamsError::OutOfRange {
+                field: "db_item_size",
+                ..
+            })
+        ));
+        assert!(with("nu_2", 12.into()).is_ok());
+        assert!(matches!(
+            with("nu_1", 11.into()),
+            Err(ParamsError::TooManyExpanded { .. })
+        ));
+        assert_eq!(with("n", "2".into()), Err(ParamsError::InvalidField("n")));
+        assert!(matches!(
+            try_params_from_json("{"),
+            Err(ParamsError::Json(_))
+        ));
+    }
+
     #[test]
     fn test_decompose_calc_correct() {
         let lengths = [5, 4, 3];
@@ -2,21 +2,17 @@ use pyo3::prelude::*;
 
 use spiral_rs::client::*;
 use spiral_rs::key_value::*;
-use spiral_rs::params::Params;
+use spiral_rs::params::intern_params;
 use spiral_rs::util::*;
 
 use std::convert::TryInto;
 
-pub struct ApiClientObj<'a> {
-    pub params: &'a Params,
-    pub c: Client<'a>,
-}
-
-// Container class for a static lifetime ApiClientObj
-// Avoids a lifetime in the return signature of bound Rust functions
+// Container class for a client holding its own parameters
+// Avoids a lifetime in the return signature of bound Rust functions;
+// the parameters are interned, so dropping the last client frees them
 #[pyclass]
 pub struct ApiClient {
-    client: &'static mut ApiClientObj<'static>,
+    client: SharedClient,
 }
 
 #[pyfunction]
@@ -26,11 +22,8 @@ pub fn initialize_client(json_params: Option<String>) -> ApiClient {
         cfg = json_params.unwrap();
     }
 
-    let params = Box::leak(Box::new(params_from_json(&cfg)));
-    let client = Box::leak(Box::new(ApiClientObj {
-        params,
-        c: Client::init(params),
-    }));
+    let params = intern_params(params_from_json(&cfg));
+    let client = SharedClient::init(params);
 
     ApiClient { client }
 }
@@ -44,24 +37,23 @@ pub fn generate_keys(
     let seed_val = (*seed).try_into().unwrap();
     Some(
         c.client
-            .c
             .generate_keys_optional(seed_val, generate_pub_params)?,
     )
 }
 
 #[pyfunction]
 pub fn generate_query(c: &mut ApiClient, id: &str, idx_target: usize) -> Vec<u8> {
-    c.client.c.generate_full_query(id, idx_target)
+    c.client.client().generate_full_query(id, idx_target)
 }
 
 #[pyfunction]
 pub fn decode_response(c: &mut ApiClient, data: Vec<u8>) -> Vec<u8> {
-    c.client.c.decode_response(&*data)
+    c.client.client().decode_response(&*data)
 }
 
 #[pyfunction]
 pub fn get_row(c: &mut ApiClient, key: &str) -> u32 {
-    row_from_key(c.client.params, key) as u32
+    row_from_key(c.client.params(), key) as u32
 }
 
 #[pyfunction]